@@ -1,8 +1,27 @@
-fn main() {
-    windows::build!(
-        windows::win32::debug::GetLastError,
-        windows::win32::dns::{DNS_SERVICE_REGISTER_REQUEST, DnsServiceConstructInstance, DnsServiceRegister, DnsServiceFreeInstance},
-        windows::win32::system_services::DNS_REQUEST_PENDING,
-        windows::win32::windows_programming::{COMPUTER_NAME_FORMAT, GetComputerNameExW},
-    );
+fn main() {
+    if std::env::var("CARGO_CFG_WINDOWS").is_ok() {
+        generate_bindings();
+    }
+}
+
+#[cfg(windows)]
+fn generate_bindings() {
+    windows::build!(
+        windows::win32::debug::GetLastError,
+        windows::win32::file_system::MoveFileExW,
+        windows::win32::dns::{DNS_SERVICE_REGISTER_REQUEST, DnsServiceConstructInstance, DnsServiceDeRegister, DnsServiceRegister, DnsServiceFreeInstance},
+        windows::win32::security::{
+            CloseServiceHandle, NETRESOURCEW, OpenSCManagerW, RegisterServiceCtrlHandlerExW, SERVICE_STATUS,
+            SERVICE_TABLE_ENTRYW, SetServiceStatus, StartServiceCtrlDispatcherW,
+        },
+        windows::win32::services::CreateServiceW,
+        windows::win32::system_services::{CreateEventW, DNS_REQUEST_PENDING, OpenEventW, RegisterEventSourceW, ReportEventW, SetEvent, WaitForSingleObject},
+        windows::win32::windows_networking::WNetAddConnection2W,
+        windows::win32::windows_programming::{CloseHandle, COMPUTER_NAME_FORMAT, GetComputerNameExW},
+    );
+}
+
+#[cfg(not(windows))]
+fn generate_bindings() {
+    panic!("The Windows API bindings can only be generated on a Windows host");
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+use std::{error, net::IpAddr, str::FromStr};
+
+use ipnet::IpNet;
+
+#[derive(Default)]
+pub struct AccessList {
+    allowed: Vec<IpNet>,
+    denied: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(allowed: &[String], denied: &[String]) -> Result<AccessList, Box<dyn error::Error>> {
+        Ok(AccessList { allowed: parse_networks(allowed)?, denied: parse_networks(denied)? })
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !self.denied.iter().any(|network| network.contains(&ip))
+            && (self.allowed.is_empty() || self.allowed.iter().any(|network| network.contains(&ip)))
+    }
+}
+
+pub fn parse_networks(networks: &[String]) -> Result<Vec<IpNet>, Box<dyn error::Error>> {
+    networks.iter()
+        .map(|network| {
+            IpNet::from_str(network)
+                .or_else(|_| IpAddr::from_str(network).map(IpNet::from))
+                .map_err(|_| format!("Invalid network {}, expected an address or a CIDR range such as 192.168.1.0/24", network).into())
+        })
+        .collect()
+}
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, error, time::Duration};
+
+use hyper::HeaderMap;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::config::{Account, Role};
+use crate::state;
+
+const SECRET_LENGTH: usize = 32;
+const BEARER_SCHEME: &str = "Bearer ";
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: Role,
+    #[serde(default)]
+    pub profile: String,
+    pub exp: u64,
+}
+
+pub struct Accounts {
+    accounts: HashMap<String, Account>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    lifetime: Duration,
+}
+
+impl Accounts {
+    pub fn new(accounts: HashMap<String, Account>, secret: Option<&str>, lifetime: Duration) -> Result<Accounts, Box<dyn error::Error>> {
+        for (username, account) in &accounts {
+            auth::check_password_hash(&account.password_hash).map_err(|err| format!("Invalid password hash of the account {}: {}", username, err))?;
+        }
+
+        let secret = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut secret = vec![0; SECRET_LENGTH];
+                getrandom::getrandom(&mut secret)?;
+                secret
+            }
+        };
+        Ok(Accounts {
+            accounts,
+            encoding_key: EncodingKey::from_secret(&secret),
+            decoding_key: DecodingKey::from_secret(&secret),
+            lifetime,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn login(&self, username: &str, password: &str) -> Result<Option<(String, Claims)>, Box<dyn error::Error>> {
+        let account = match self.accounts.get(username) {
+            Some(account) if auth::verify_password(&account.password_hash, password) => account,
+            _ => return Ok(None),
+        };
+
+        let claims = Claims {
+            sub: username.to_string(),
+            role: account.role,
+            profile: account.profile.clone().unwrap_or_default(),
+            exp: state::now() + self.lifetime.as_secs(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)?;
+        Ok(Some((token, claims)))
+    }
+
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Claims>, String> {
+        let token = headers.get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_SCHEME))
+            .map(str::trim)
+            .filter(|token| token.matches('.').count() == 2);
+        let token = match token {
+            Some(token) if !self.is_empty() => token,
+            _ => return Ok(None),
+        };
+
+        let mut claims = jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &Validation::new(Algorithm::HS256))
+            .map_err(|err| format!("Invalid login token: {}", err))?
+            .claims;
+        let account = self.accounts.get(&claims.sub).ok_or_else(|| format!("There's no account named {}", claims.sub))?;
+        claims.role = account.role;
+        claims.profile = account.profile.clone().unwrap_or_default();
+        Ok(Some(claims))
+    }
+}
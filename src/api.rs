@@ -0,0 +1,1024 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error, fmt,
+    path::Path,
+};
+
+use hyper::{body::HttpBody, http::HeaderValue, Body, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{error, warn};
+
+use crate::auth;
+use crate::byte_range::ByteRange;
+use crate::cast;
+use crate::config::UserProfile;
+use crate::events::Event;
+use crate::manifest;
+use crate::query::ItemQuery;
+use crate::scanner::{is_video, item_id};
+use crate::server::{self, State};
+use crate::tokens;
+use crate::trakt;
+use crate::thumbnails::Thumbnails;
+use crate::state::{self, MetadataOverride, PlayStats, Playlist, Progress, ProgressUpdate, RatingSummary, StateSnapshot, MAX_RATING, MIN_RATING};
+use crate::viewer::Viewer;
+
+const CONTINUE_WATCHING_LIMIT: usize = 20;
+const MAX_JSON_BODY: usize = 64 * 1024;
+const MAX_STATE_SNAPSHOT: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum InvalidBody {
+    TooLarge,
+    Malformed(String),
+}
+
+impl fmt::Display for InvalidBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidBody::TooLarge => write!(f, "The request body is too large"),
+            InvalidBody::Malformed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub fn serve_progress(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let progress = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.progress(&viewer.profile, id),
+        None => Ok(None),
+    });
+
+    match progress {
+        Ok(Some(progress)) => write_json(&progress, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't read the progress", err, response),
+    }
+}
+
+pub fn serve_all_progress(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    let data = state.user_state.recent_progress(&viewer.profile).and_then(|recent| Ok((recent, state.user_state.watched(&viewer.profile)?)));
+    let (recent, mut watched) = match data {
+        Ok(data) => data,
+        Err(err) => return internal_error("Couldn't read the progress", err, response),
+    };
+
+    let mut unstarted: Vec<_> = watched.iter().filter(|id| !recent.iter().any(|(recent, _)| recent == *id)).cloned().collect();
+    unstarted.sort();
+    let entries = recent.into_iter().map(|(id, progress)| (id, Some(progress))).chain(unstarted.into_iter().map(|id| (id, None)));
+
+    let mut listing = Vec::new();
+    for (id, progress) in entries {
+        match visible_item(state, viewer, &id) {
+            Ok(Some(_)) => listing.push(serde_json::json!({ "id": id, "progress": progress, "watched": watched.remove(&id) })),
+            Ok(None) => continue,
+            Err(err) => return internal_error("Couldn't look up the item", err, response),
+        }
+    }
+
+    write_json(&listing, response);
+}
+
+pub async fn update_progress(state: &State, viewer: &Viewer, id: &str, body: Body, response: &mut Response<Body>) {
+    match visible_item(state, viewer, id) {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+        Err(err) => return internal_error("Couldn't look up the item", err, response),
+    }
+
+    let report = match read_json::<ProgressReport>(body).await {
+        Ok(report) => report,
+        Err(err) => return rejected_body("Invalid progress", err, response),
+    };
+
+    let progress = Progress { position: report.position, duration: report.duration, player_id: report.player_id, updated: 0, sequence: 0 };
+    match store_progress(state, viewer, id, progress, report.sequence) {
+        Ok(ProgressUpdate::Stored(stored)) => write_json(&stored, response),
+        Ok(ProgressUpdate::Stale(current)) => {
+            write_json(&current, response);
+            *response.status_mut() = StatusCode::CONFLICT;
+        }
+        Err(err) => internal_error("Couldn't store the progress", err, response),
+    }
+}
+
+pub fn store_progress(state: &State, viewer: &Viewer, id: &str, progress: Progress, base: Option<u64>) -> Result<ProgressUpdate, Box<dyn error::Error>> {
+    let progress = Progress { updated: state::now(), ..progress };
+    let previous = state.user_state.progress(&viewer.profile, id)?;
+    let stored = match state.user_state.set_progress(&viewer.profile, id, &progress, base)? {
+        ProgressUpdate::Stored(stored) => stored,
+        stale => return Ok(stale),
+    };
+    let viewing_time = previous.as_ref().map_or(0, |previous| state::viewing_time(previous, &stored));
+    if viewing_time > 0 {
+        state.user_state.record_viewing(&viewer.profile, id, stored.updated, viewing_time)?;
+    }
+    if state::is_nearly_finished(&stored) {
+        state.user_state.set_watched(&viewer.profile, id, true)?;
+    }
+    if let Some(ref trakt) = state.trakt {
+        if let Some(action) = trakt::crossed_threshold(previous.as_ref(), &stored) {
+            if let Some(item) = visible_item(state, viewer, id)? {
+                trakt.scrobble(&viewer.profile, action, &item, &stored);
+            }
+        }
+    }
+
+    state.events.publish(Event {
+        profile: viewer.profile.clone(),
+        player_id: Some(stored.player_id.clone()),
+        name: "progress",
+        data: serde_json::json!({ "id": id, "progress": stored }),
+    });
+    Ok(ProgressUpdate::Stored(stored))
+}
+
+pub fn update_watched(state: &State, viewer: &Viewer, id: &str, watched: bool, response: &mut Response<Body>) {
+    let result = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.set_watched(&viewer.profile, id, watched).map(|()| true),
+        None => Ok(false),
+    });
+
+    match result {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't store the watched state", err, response),
+    }
+}
+
+pub fn serve_continue_watching(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    let recent = match state.user_state.recent_progress(&viewer.profile) {
+        Ok(recent) => recent,
+        Err(err) => return internal_error("Couldn't read the progress", err, response),
+    };
+
+    let mut entries = Vec::new();
+    for (id, progress) in recent {
+        if progress.position == 0 || state::is_nearly_finished(&progress) { continue; }
+
+        match visible_item(state, viewer, &id) {
+            Ok(Some(item)) => entries.push(serde_json::json!({ "item": item, "progress": progress })),
+            Ok(None) => continue,
+            Err(err) => return internal_error("Couldn't look up the item", err, response),
+        }
+        if entries.len() == CONTINUE_WATCHING_LIMIT { break; }
+    }
+
+    write_json(&entries, response);
+}
+
+pub fn serve_up_next(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    let data = state.store.items().and_then(|items| {
+        let progress: HashMap<_, _> = state.user_state.recent_progress(&viewer.profile)?.into_iter().collect();
+        Ok((items, progress, Annotations::load(state, viewer)?))
+    });
+    let (items, progress, annotations) = match data {
+        Ok(data) => data,
+        Err(err) => return internal_error("Couldn't list the items", err, response),
+    };
+
+    let mut shows = BTreeMap::<String, Vec<(u64, u64, Value)>>::new();
+    for item in items.into_iter().filter(|item| can_see_item(viewer, item)) {
+        let episode = match item.get("episode") {
+            Some(episode) => episode,
+            None => continue,
+        };
+        let show = episode.get("show").and_then(Value::as_str).unwrap_or_default().to_string();
+        let number = |key| episode.get(key).and_then(Value::as_u64).unwrap_or_default();
+        let (season, number) = (number("season"), number("episode"));
+        shows.entry(show).or_default().push((season, number, item));
+    }
+
+    let mut up_next = Vec::new();
+    for (show, mut episodes) in shows {
+        episodes.sort_by_key(|(season, episode, _)| (*season, *episode));
+
+        let id_of = |item: &Value| item.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+        let started = |id: &str| progress.get(id).is_some_and(|progress| progress.position > 0 && !state::is_nearly_finished(progress));
+        let activity = |id: &str| {
+            let updated = progress.get(id).map_or(0, |progress| progress.updated);
+            updated.max(annotations.plays.get(id).and_then(|stats| stats.last_played).unwrap_or_default())
+        };
+
+        let last_seen = episodes.iter().rposition(|(.., item)| {
+            let id = id_of(item);
+            annotations.watched.contains(&id) || started(&id)
+        });
+        let last_seen = match last_seen {
+            Some(last_seen) => last_seen,
+            None => continue,
+        };
+
+        let next = episodes[last_seen..].iter().position(|(.., item)| !annotations.watched.contains(&id_of(item)));
+        if let Some(next) = next {
+            let last_activity = episodes.iter().map(|(.., item)| activity(&id_of(item))).max().unwrap_or_default();
+            let item = episodes.swap_remove(last_seen + next).2;
+            up_next.push((last_activity, show, annotate_item(state, viewer, item, &annotations)));
+        }
+    }
+
+    up_next.sort_by_key(|(last_activity, ..)| Reverse(*last_activity));
+    let up_next: Vec<_> = up_next.into_iter().map(|(_, show, item)| serde_json::json!({ "show": show, "item": item })).collect();
+    write_json(&up_next, response);
+}
+
+pub fn serve_history(state: &State, viewer: &Viewer, limit: usize, response: &mut Response<Body>) {
+    let data = state.user_state.recent_progress(&viewer.profile).and_then(|progress| Ok((progress, Annotations::load(state, viewer)?)));
+    let (progress, annotations) = match data {
+        Ok(data) => data,
+        Err(err) => return internal_error("Couldn't read the history", err, response),
+    };
+
+    let mut entries: HashMap<String, (u64, Option<u64>)> = annotations.plays
+        .iter()
+        .filter_map(|(id, stats)| Some((id.clone(), (stats.last_played?, None))))
+        .collect();
+    for (id, progress) in progress {
+        let entry = entries.entry(id).or_insert((progress.updated, None));
+        entry.0 = entry.0.max(progress.updated);
+        entry.1 = Some(progress.position);
+    }
+
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by_key(|(id, (played, _))| (Reverse(*played), id.clone()));
+
+    let mut history = Vec::new();
+    for (id, (played, position)) in entries {
+        if history.len() == limit { break; }
+
+        match visible_item(state, viewer, &id) {
+            Ok(Some(item)) => history.push(serde_json::json!({
+                "item": annotate_item(state, viewer, item, &annotations),
+                "played": played,
+                "position": position,
+            })),
+            Ok(None) => continue,
+            Err(err) => return internal_error("Couldn't look up the item", err, response),
+        }
+    }
+
+    write_json(&history, response);
+}
+
+pub fn serve_viewing_stats(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    match *state.viewing_stats.lock().unwrap() {
+        Some(ref stats) => write_json(&stats.visible_to(viewer), response),
+        None => {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            *response.body_mut() = Body::from("The viewing statistics haven't been aggregated yet");
+        }
+    }
+}
+
+pub fn serve_item(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let item = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(item) => Ok(Some(annotate_item(state, viewer, item, &Annotations::load(state, viewer)?))),
+        None => Ok(None),
+    });
+
+    match item {
+        Ok(Some(item)) => write_json(&item, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't look up the item", err, response),
+    }
+}
+
+pub fn serve_cast(state: &State, viewer: &Viewer, id: &str, file_url: &dyn Fn(&str) -> String, response: &mut Response<Body>) {
+    let request = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(item) => Ok(Some(cast::load_request(&item, state.user_state.progress(&viewer.profile, id)?.as_ref(), file_url))),
+        None => Ok(None),
+    });
+
+    match request {
+        Ok(Some(request)) => write_json(&request, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't assemble the cast request", err, response),
+    }
+}
+
+pub fn serve_items(state: &State, viewer: &Viewer, query: Option<&str>, response: &mut Response<Body>) {
+    let query = match ItemQuery::parse(query) {
+        Ok(query) => query,
+        Err(err) => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err);
+            return;
+        }
+    };
+
+    let items = state.store.items().and_then(|items| Ok((items, state.user_state.metadata_overrides()?, Annotations::load(state, viewer)?)));
+    match items {
+        Ok((items, overrides, annotations)) => {
+            let items = items
+                .into_iter()
+                .filter(|item| can_see_item(viewer, item))
+                .map(|mut item| {
+                    if let Some(fields) = item.as_object_mut() {
+                        let metadata = fields.get("id").and_then(Value::as_str).and_then(|id| overrides.get(id));
+                        if let Some(metadata) = metadata {
+                            apply_override(fields, metadata);
+                        }
+                    }
+                    item
+                })
+                .collect();
+            let items: Vec<_> = query.apply(items, &annotations.plays, &annotations.rating_summaries, state::now())
+                .into_iter()
+                .map(|item| annotate_item(state, viewer, item, &annotations))
+                .collect();
+            write_json(&items, response);
+        }
+        Err(err) => internal_error("Couldn't list the items", err, response),
+    }
+}
+
+pub fn serve_favorites(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    let favorites = Annotations::load(state, viewer).and_then(|annotations| {
+        let mut favorites = Vec::new();
+        for id in &annotations.favorites {
+            if let Some(item) = visible_item(state, viewer, id)? {
+                favorites.push(annotate_item(state, viewer, item, &annotations));
+            }
+        }
+        Ok(favorites)
+    });
+
+    match favorites {
+        Ok(mut favorites) => {
+            favorites.sort_by_key(|item| item.get("title").and_then(Value::as_str).map(str::to_lowercase));
+            write_json(&favorites, response);
+        }
+        Err(err) => internal_error("Couldn't read the favorites", err, response),
+    }
+}
+
+pub fn update_favorite(state: &State, viewer: &Viewer, id: &str, favorite: bool, response: &mut Response<Body>) {
+    let result = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.set_favorite(&viewer.profile, id, favorite).map(|()| true),
+        None => Ok(false),
+    });
+
+    match result {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't store the favorite", err, response),
+    }
+}
+
+pub fn serve_bookmarks(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let bookmarks = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.bookmarks(&viewer.profile, id).map(Some),
+        None => Ok(None),
+    });
+
+    match bookmarks {
+        Ok(Some(bookmarks)) => write_json(&bookmarks, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't read the bookmarks", err, response),
+    }
+}
+
+pub async fn create_bookmark(state: &State, viewer: &Viewer, id: &str, body: Body, response: &mut Response<Body>) {
+    match visible_item(state, viewer, id) {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+        Err(err) => return internal_error("Couldn't look up the item", err, response),
+    }
+
+    let bookmark = match read_json::<NewBookmark>(body).await {
+        Ok(bookmark) => bookmark,
+        Err(err) => return rejected_body("Invalid bookmark", err, response),
+    };
+
+    match state.user_state.add_bookmark(&viewer.profile, id, bookmark.position, &bookmark.label) {
+        Ok(bookmark) => {
+            *response.status_mut() = StatusCode::CREATED;
+            write_json(&bookmark, response);
+        }
+        Err(err) => internal_error("Couldn't store the bookmark", err, response),
+    }
+}
+
+pub fn delete_bookmark(state: &State, viewer: &Viewer, id: &str, bookmark_id: &str, response: &mut Response<Body>) {
+    let deleted = match bookmark_id.parse() {
+        Ok(bookmark_id) => state.user_state.delete_bookmark(&viewer.profile, id, bookmark_id),
+        Err(_) => Ok(false),
+    };
+
+    match deleted {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't delete the bookmark", err, response),
+    }
+}
+
+pub async fn update_rating(state: &State, viewer: &Viewer, id: &str, body: Body, response: &mut Response<Body>) {
+    match visible_item(state, viewer, id) {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+        Err(err) => return internal_error("Couldn't look up the item", err, response),
+    }
+
+    let rating = match read_json::<NewRating>(body).await {
+        Ok(NewRating { rating }) if (MIN_RATING..=MAX_RATING).contains(&rating) => rating,
+        Ok(_) => return bad_request(&format!("A rating goes from {} to {}", MIN_RATING, MAX_RATING), response),
+        Err(err) => return rejected_body("Invalid rating", err, response),
+    };
+
+    match state.user_state.set_rating(&viewer.profile, id, Some(rating)) {
+        Ok(()) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Err(err) => internal_error("Couldn't store the rating", err, response),
+    }
+}
+
+pub fn delete_rating(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let result = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.set_rating(&viewer.profile, id, None).map(|()| true),
+        None => Ok(false),
+    });
+
+    match result {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't delete the rating", err, response),
+    }
+}
+
+pub fn annotate_manifest(state: &State, viewer: &Viewer, manifest: &mut Value) -> Result<(), Box<dyn error::Error>> {
+    ManifestAnnotations::load(state, viewer)?.apply(state, manifest);
+    Ok(())
+}
+
+pub struct ManifestAnnotations {
+    annotations: Annotations,
+    overrides: HashMap<String, MetadataOverride>,
+    resume_positions: HashMap<String, u64>,
+}
+
+impl ManifestAnnotations {
+    pub fn load(state: &State, viewer: &Viewer) -> Result<ManifestAnnotations, Box<dyn error::Error>> {
+        let resume_positions = state.user_state.recent_progress(&viewer.profile)?
+            .into_iter()
+            .filter(|(_, progress)| progress.position > 0 && !state::is_nearly_finished(progress))
+            .map(|(id, progress)| (id, progress.position))
+            .collect();
+        Ok(ManifestAnnotations {
+            annotations: Annotations::load(state, viewer)?,
+            overrides: state.user_state.metadata_overrides()?,
+            resume_positions,
+        })
+    }
+
+    pub fn fingerprint(&self) -> String {
+        let inputs = json!({
+            "watched": self.annotations.watched.iter().collect::<BTreeSet<_>>(),
+            "favorites": self.annotations.favorites.iter().collect::<BTreeSet<_>>(),
+            "ratings": self.annotations.ratings.iter().collect::<BTreeMap<_, _>>(),
+            "rating-summaries": self.annotations.rating_summaries.iter().collect::<BTreeMap<_, _>>(),
+            "overrides": self.overrides.iter().collect::<BTreeMap<_, _>>(),
+            "resume-positions": self.resume_positions.iter().collect::<BTreeMap<_, _>>(),
+        });
+        sha1_smol::Sha1::from(inputs.to_string()).digest().to_string()
+    }
+
+    pub fn apply(&self, state: &State, manifest: &mut Value) {
+        manifest::for_each_file(manifest, &mut |file| {
+            let id = file.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+            if let Some(metadata) = self.overrides.get(&id) {
+                apply_override(file, metadata);
+            }
+            file.insert("resume-position".to_string(), self.resume_positions.get(&id).copied().into());
+            file.insert("watched".to_string(), Value::Bool(self.annotations.watched.contains(&id)));
+            file.insert("favorite".to_string(), Value::Bool(self.annotations.favorites.contains(&id)));
+            annotate_rating(file, &id, &self.annotations);
+            annotate_thumbnails(state, file);
+            if let Some(ref signer) = state.url_signer {
+                signer.sign_file(server::PATH_FILE_PREFIX, file);
+            }
+        });
+    }
+}
+
+fn annotate_thumbnails(state: &State, file: &mut Map<String, Value>) {
+    if state.thumbnails.is_none() || file.contains_key("thumbnails") {
+        return;
+    }
+    let path = match file.get("path").and_then(Value::as_str) {
+        Some(path) if state.has_local_files(path) => path.to_string(),
+        _ => return,
+    };
+    let names = Thumbnails::names().into_iter().map(|name| Value::String(format!("{}/{}", path, name))).collect();
+    file.insert("generated-thumbnails".to_string(), Value::Array(names));
+}
+
+pub fn serve_sessions(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    match state.user_state.sessions(&viewer.profile) {
+        Ok(sessions) => write_json(&sessions, response),
+        Err(err) => internal_error("Couldn't read the sessions", err, response),
+    }
+}
+
+pub fn serve_transcodes(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    let transcodes = state.transcodes.as_ref().unwrap();
+    write_json(&json!({ "profiles": transcodes.profiles(), "sessions": transcodes.sessions(&viewer.profile) }), response);
+}
+
+pub fn stop_transcode(state: &State, viewer: &Viewer, session: &str, response: &mut Response<Body>) {
+    let stopped = state.transcodes.as_ref().is_some_and(|transcodes| transcodes.stop(&viewer.profile, session));
+    *response.status_mut() = if stopped { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND };
+}
+
+pub async fn create_session(state: &State, viewer: &Viewer, body: Body, response: &mut Response<Body>) {
+    let device = match read_json::<NewSession>(body).await {
+        Ok(NewSession { device }) => device,
+        Err(err) => return rejected_body("Invalid session", err, response),
+    };
+
+    match state.user_state.create_session(&viewer.profile, &device) {
+        Ok(session) => {
+            let mut created = serde_json::to_value(&session).unwrap_or_default();
+            created["token"] = Value::String(session.token);
+
+            *response.status_mut() = StatusCode::CREATED;
+            write_json(&created, response);
+        }
+        Err(err) => internal_error("Couldn't store the session", err, response),
+    }
+}
+
+pub fn delete_session(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    match state.user_state.delete_session(&viewer.profile, id) {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't delete the session", err, response),
+    }
+}
+
+pub fn serve_api_tokens(state: &State, profiles: &HashMap<String, UserProfile>, response: &mut Response<Body>) {
+    match tokens::list(state.user_state.as_ref(), profiles) {
+        Ok(tokens) => write_json(&tokens, response),
+        Err(err) => internal_error("Couldn't read the API tokens", err, response),
+    }
+}
+
+pub async fn create_api_token(state: &State, profiles: &HashMap<String, UserProfile>, body: Body, response: &mut Response<Body>) {
+    let token = match read_json::<NewApiToken>(body).await {
+        Ok(token) => token,
+        Err(err) => return rejected_body("Invalid API token", err, response),
+    };
+    if !token.profile.is_empty() && !profiles.contains_key(&token.profile) {
+        return bad_request(&format!("There's no profile named {}", token.profile), response);
+    }
+
+    match tokens::create(state.user_state.as_ref(), profiles, &token.profile, &token.name) {
+        Ok(session) => {
+            let mut created = serde_json::to_value(&session).unwrap_or_default();
+            created["token"] = Value::String(session.token);
+
+            *response.status_mut() = StatusCode::CREATED;
+            write_json(&created, response);
+        }
+        Err(err) => internal_error("Couldn't store the API token", err, response),
+    }
+}
+
+pub async fn login(state: &State, attempt: &[String], body: Body, response: &mut Response<Body>) {
+    let login = match read_json::<Login>(body).await {
+        Ok(login) => login,
+        Err(err) => return rejected_body("Invalid login", err, response),
+    };
+
+    let attempt: Vec<_> = attempt.iter().cloned().chain(Some(auth::user_key(&login.username))).collect();
+    if let Some(retry_after) = state.throttle.locked_for(&attempt) {
+        return auth::too_many_attempts(retry_after, response);
+    }
+
+    match state.accounts.login(&login.username, &login.password) {
+        Ok(Some((token, claims))) => {
+            state.throttle.record_success(&attempt);
+            write_json(&serde_json::json!({ "token": token, "role": claims.role, "profile": claims.profile, "expires": claims.exp }), response);
+        }
+        Ok(None) => {
+            state.throttle.record_failure(&attempt);
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            *response.body_mut() = Body::from("Wrong username or password");
+        }
+        Err(err) => internal_error("Couldn't sign the login token", err, response),
+    }
+}
+
+pub fn revoke_api_token(state: &State, profiles: &HashMap<String, UserProfile>, id: &str, response: &mut Response<Body>) {
+    match tokens::revoke(state.user_state.as_ref(), profiles, id) {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't revoke the API token", err, response),
+    }
+}
+
+pub fn serve_metadata(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let metadata = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.metadata_override(id),
+        None => Ok(None),
+    });
+
+    match metadata {
+        Ok(Some(metadata)) => write_json(&metadata, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't read the metadata override", err, response),
+    }
+}
+
+pub async fn update_metadata(state: &State, viewer: &Viewer, id: &str, body: Body, response: &mut Response<Body>) {
+    match visible_item(state, viewer, id) {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+        Err(err) => return internal_error("Couldn't look up the item", err, response),
+    }
+
+    let metadata = match read_json::<MetadataOverride>(body).await {
+        Ok(metadata) => metadata,
+        Err(err) => return rejected_body("Invalid metadata", err, response),
+    };
+
+    match state.user_state.set_metadata_override(id, Some(&metadata)) {
+        Ok(_) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Err(err) => internal_error("Couldn't store the metadata override", err, response),
+    }
+}
+
+pub fn delete_metadata(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let deleted = visible_item(state, viewer, id).and_then(|item| match item {
+        Some(_) => state.user_state.set_metadata_override(id, None),
+        None => Ok(false),
+    });
+
+    match deleted {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't delete the metadata override", err, response),
+    }
+}
+
+pub fn serve_missing(state: &State, response: &mut Response<Body>) {
+    match state.store.missing_items() {
+        Ok(items) => write_json(&items, response),
+        Err(err) => internal_error("Couldn't list the missing items", err, response),
+    }
+}
+
+pub fn serve_scans(state: &State, response: &mut Response<Body>) {
+    match state.user_state.scans() {
+        Ok(scans) => write_json(&scans, response),
+        Err(err) => internal_error("Couldn't read the scan history", err, response),
+    }
+}
+
+pub fn export_state(state: &State, response: &mut Response<Body>) {
+    match state.user_state.export_state() {
+        Ok(snapshot) => {
+            response.headers_mut().insert("Content-Disposition", HeaderValue::from_static("attachment; filename=\"movie-nexus-state.json\""));
+            write_json(&snapshot, response);
+        }
+        Err(err) => internal_error("Couldn't export the state", err, response),
+    }
+}
+
+pub async fn import_state(state: &State, body: Body, response: &mut Response<Body>) {
+    let snapshot = match read_json_within::<StateSnapshot>(body, MAX_STATE_SNAPSHOT).await {
+        Ok(snapshot) => snapshot,
+        Err(err) => return rejected_body("Invalid state", err, response),
+    };
+
+    match state.user_state.import_state(&snapshot) {
+        Ok(()) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Err(err) => internal_error("Couldn't import the state", err, response),
+    }
+}
+
+pub fn serve_playlists(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    match visible_playlists(state, viewer) {
+        Ok(playlists) => write_json(&playlists, response),
+        Err(err) => internal_error("Couldn't read the playlists", err, response),
+    }
+}
+
+pub fn serve_playlist(state: &State, viewer: &Viewer, id: &str, response: &mut Response<Body>) {
+    let playlist = visible_playlists(state, viewer).map(|playlists| {
+        playlists.into_iter().find(|playlist| Some(playlist.id) == id.parse().ok())
+    });
+
+    match playlist {
+        Ok(Some(playlist)) => write_json(&playlist, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't read the playlists", err, response),
+    }
+}
+
+pub async fn create_playlist(state: &State, body: Body, response: &mut Response<Body>) {
+    let changes = match read_playlist_changes(state, body).await {
+        Ok(PlaylistChanges { name: Some(name), items }) => (name, items.unwrap_or_default()),
+        Ok(_) => return bad_request("A playlist needs a name", response),
+        Err(err) => return rejected_body("Invalid playlist", err, response),
+    };
+
+    match state.user_state.create_playlist(&changes.0, &changes.1) {
+        Ok(playlist) => {
+            *response.status_mut() = StatusCode::CREATED;
+            write_json(&playlist, response);
+        }
+        Err(err) => internal_error("Couldn't store the playlist", err, response),
+    }
+}
+
+pub async fn update_playlist(state: &State, id: &str, body: Body, response: &mut Response<Body>) {
+    let id = match id.parse() {
+        Ok(id) => id,
+        Err(_) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let changes = match read_playlist_changes(state, body).await {
+        Ok(changes) => changes,
+        Err(err) => return rejected_body("Invalid playlist", err, response),
+    };
+
+    match state.user_state.update_playlist(id, changes.name.as_deref(), changes.items.as_deref()) {
+        Ok(Some(playlist)) => write_json(&playlist, response),
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't store the playlist", err, response),
+    }
+}
+
+pub fn delete_playlist(state: &State, id: &str, response: &mut Response<Body>) {
+    let deleted = match id.parse() {
+        Ok(id) => state.user_state.delete_playlist(id),
+        Err(_) => Ok(false),
+    };
+
+    match deleted {
+        Ok(true) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Ok(false) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => internal_error("Couldn't delete the playlist", err, response),
+    }
+}
+
+pub fn playlist_entries(state: &State, viewer: &Viewer) -> Result<Vec<Value>, Box<dyn error::Error>> {
+    let mut entries = Vec::new();
+    for playlist in visible_playlists(state, viewer)? {
+        let mut contents = Vec::with_capacity(playlist.items.len());
+        for id in &playlist.items {
+            contents.extend(overridden_item(state, id)?);
+        }
+
+        entries.push(serde_json::json!({
+            "type": "playlist",
+            "id": playlist.id,
+            "title": playlist.name,
+            "contents": contents,
+        }));
+    }
+    Ok(entries)
+}
+
+pub fn starts_playback(path: &Path, ranges: &[ByteRange]) -> bool {
+    let starts_at_beginning = match ranges.first() {
+        None => true,
+        Some(ByteRange::StartingAt(start)) | Some(ByteRange::FromToIncluding(start, _)) => *start == 0,
+        Some(ByteRange::Last(_)) => false,
+    };
+    starts_at_beginning && is_video(path)
+}
+
+pub fn record_play(state: &State, viewer: &Viewer, requested_path: &str) {
+    let id = item_id(Path::new(requested_path));
+    if let Err(err) = state.user_state.record_play(&viewer.profile, &id, state::now()) {
+        warn!("Couldn't record playing {}: {}", requested_path, err);
+    }
+}
+
+pub fn write_json(value: &impl Serialize, response: &mut Response<Body>) {
+    match serde_json::to_string(value) {
+        Ok(json) => {
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(json);
+        }
+        Err(err) => internal_error("Couldn't encode the response", err.into(), response),
+    }
+}
+
+pub fn internal_error(context: &str, err: Box<dyn error::Error>, response: &mut Response<Body>) {
+    error!("{}: {}", context, err);
+    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+}
+
+struct Annotations {
+    watched: HashSet<String>,
+    plays: HashMap<String, PlayStats>,
+    favorites: HashSet<String>,
+    ratings: HashMap<String, u8>,
+    rating_summaries: HashMap<String, RatingSummary>,
+}
+
+impl Annotations {
+    fn load(state: &State, viewer: &Viewer) -> Result<Annotations, Box<dyn error::Error>> {
+        Ok(Annotations {
+            watched: state.user_state.watched(&viewer.profile)?,
+            plays: state.user_state.play_stats(&viewer.profile)?,
+            favorites: state.user_state.favorites(&viewer.profile)?,
+            ratings: state.user_state.ratings(&viewer.profile)?,
+            rating_summaries: state.user_state.rating_summaries()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProgressReport {
+    position: u64,
+    duration: u64,
+    player_id: String,
+    #[serde(default)]
+    sequence: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct NewSession {
+    device: String,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct NewApiToken {
+    name: String,
+    #[serde(default)]
+    profile: String,
+}
+
+#[derive(Deserialize)]
+struct NewRating {
+    rating: u8,
+}
+
+#[derive(Deserialize)]
+struct NewBookmark {
+    position: u64,
+    #[serde(default)]
+    label: String,
+}
+
+#[derive(Deserialize)]
+struct PlaylistChanges {
+    name: Option<String>,
+    items: Option<Vec<String>>,
+}
+
+async fn read_playlist_changes(state: &State, body: Body) -> Result<PlaylistChanges, InvalidBody> {
+    let changes = read_json::<PlaylistChanges>(body).await?;
+    for id in changes.items.iter().flatten() {
+        match state.store.item(id) {
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(InvalidBody::Malformed(format!("There's no item with ID {}", id))),
+            Err(err) => return Err(InvalidBody::Malformed(err.to_string())),
+        }
+    }
+    Ok(changes)
+}
+
+fn visible_playlists(state: &State, viewer: &Viewer) -> Result<Vec<Playlist>, Box<dyn error::Error>> {
+    let mut playlists = state.user_state.playlists()?;
+    for playlist in &mut playlists {
+        let mut visible = Vec::with_capacity(playlist.items.len());
+        for id in playlist.items.drain(..) {
+            if visible_item(state, viewer, &id)?.is_some() {
+                visible.push(id);
+            }
+        }
+        playlist.items = visible;
+    }
+    Ok(playlists)
+}
+
+fn annotate_rating(item: &mut Map<String, Value>, id: &str, annotations: &Annotations) {
+    let summary = annotations.rating_summaries.get(id).copied().unwrap_or_default();
+    item.insert("rating".to_string(), annotations.ratings.get(id).copied().into());
+    item.insert("average-rating".to_string(), if summary.count > 0 { summary.average.into() } else { Value::Null });
+    item.insert("rating-count".to_string(), summary.count.into());
+}
+
+pub async fn read_json<T: DeserializeOwned>(body: Body) -> Result<T, InvalidBody> {
+    read_json_within(body, MAX_JSON_BODY).await
+}
+
+async fn read_json_within<T: DeserializeOwned>(mut body: Body, limit: usize) -> Result<T, InvalidBody> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(InvalidBody::TooLarge);
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| InvalidBody::Malformed(err.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(InvalidBody::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&bytes).map_err(|err| InvalidBody::Malformed(err.to_string()))
+}
+
+pub fn rejected_body(context: &str, err: InvalidBody, response: &mut Response<Body>) {
+    match err {
+        InvalidBody::TooLarge => {
+            *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+            *response.body_mut() = Body::from(err.to_string());
+        }
+        InvalidBody::Malformed(message) => bad_request(&format!("{}: {}", context, message), response),
+    }
+}
+
+pub fn bad_request(message: &str, response: &mut Response<Body>) {
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    *response.body_mut() = Body::from(message.to_string());
+}
+
+fn can_see_item(viewer: &Viewer, item: &Value) -> bool {
+    item.get("path").and_then(Value::as_str).is_some_and(|path| viewer.can_see(path))
+}
+
+fn visible_item(state: &State, viewer: &Viewer, id: &str) -> Result<Option<Value>, Box<dyn error::Error>> {
+    Ok(overridden_item(state, id)?.filter(|item| can_see_item(viewer, item)))
+}
+
+fn overridden_item(state: &State, id: &str) -> Result<Option<Value>, Box<dyn error::Error>> {
+    let mut item = state.store.item(id)?;
+    if let Some(fields) = item.as_mut().and_then(Value::as_object_mut) {
+        if let Some(metadata) = state.user_state.metadata_override(id)? {
+            apply_override(fields, &metadata);
+        }
+    }
+    Ok(item)
+}
+
+fn apply_override(item: &mut Map<String, Value>, metadata: &MetadataOverride) {
+    if let Some(ref title) = metadata.title {
+        item.insert("title".to_string(), Value::String(title.clone()));
+    }
+    if let Some(ref subtitle) = metadata.subtitle {
+        item.insert("subtitle".to_string(), Value::String(subtitle.clone()));
+    }
+    if let Some(duration) = metadata.duration {
+        item.insert("duration".to_string(), duration.into());
+    }
+}
+
+fn annotate_item(state: &State, viewer: &Viewer, mut item: Value, annotations: &Annotations) -> Value {
+    let id = item.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+    let stats = annotations.plays.get(&id).copied().unwrap_or_default();
+    let progress = match state.user_state.progress(&viewer.profile, &id) {
+        Ok(progress) => progress,
+        Err(err) => {
+            warn!("Couldn't read the progress of {}: {}", id, err);
+            None
+        }
+    };
+    let bookmarks = match state.user_state.bookmarks(&viewer.profile, &id) {
+        Ok(bookmarks) => bookmarks,
+        Err(err) => {
+            warn!("Couldn't read the bookmarks of {}: {}", id, err);
+            Vec::new()
+        }
+    };
+
+    if let Some(item) = item.as_object_mut() {
+        item.insert("watched".to_string(), Value::Bool(annotations.watched.contains(&id)));
+        item.insert("favorite".to_string(), Value::Bool(annotations.favorites.contains(&id)));
+        annotate_rating(item, &id, annotations);
+        annotate_thumbnails(state, item);
+        item.insert("play-count".to_string(), stats.play_count.into());
+        item.insert("last-played".to_string(), stats.last_played.into());
+        item.insert("progress".to_string(), serde_json::to_value(progress).unwrap_or_default());
+        item.insert("bookmarks".to_string(), serde_json::to_value(bookmarks).unwrap_or_default());
+        if let Some(ref signer) = state.url_signer {
+            signer.sign_file(server::PATH_FILE_PREFIX, item);
+        }
+    }
+    item
+}
\ No newline at end of file
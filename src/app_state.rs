@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    error,
+    path::PathBuf,
+    slice,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::file_stream::{OpenFiles, SmallFiles};
+use crate::jellyfin::MediaIndex;
+use crate::manifest::{self, EncodedManifest, EncodedResponses};
+use crate::scanner::CatalogueItem;
+use crate::store::{self, CatalogueStore};
+use crate::viewer::Restriction;
+
+const PARTIAL_PUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct AppState {
+    pub manifest: EncodedManifest,
+    pub manifest_responses: EncodedResponses,
+    pub files: HashMap<String, PathBuf>,
+    folded_files: HashMap<String, String>,
+    pub open_files: OpenFiles,
+    pub small_files: SmallFiles,
+    pub restrictions: Vec<Restriction>,
+    jellyfin_media: OnceLock<MediaIndex>,
+}
+
+impl AppState {
+    pub fn load(store: &dyn CatalogueStore) -> Result<AppState, Box<dyn error::Error>> {
+        Ok(AppState::new(EncodedManifest::new(&store.manifest()?)?, store.served_files()?))
+    }
+
+    pub fn new(manifest: EncodedManifest, files: HashMap<String, PathBuf>) -> AppState {
+        AppState {
+            restrictions: manifest::restrictions(&manifest.value),
+            manifest,
+            manifest_responses: EncodedResponses::default(),
+            files,
+            folded_files: HashMap::new(),
+            open_files: OpenFiles::default(),
+            small_files: SmallFiles::default(),
+            jellyfin_media: OnceLock::new(),
+        }
+    }
+
+    pub fn with_case_insensitive_paths(mut self) -> AppState {
+        self.folded_files = self.files.keys().map(|key| (key.to_lowercase(), key.clone())).collect();
+        self
+    }
+
+    pub fn jellyfin_media(&self) -> &MediaIndex {
+        self.jellyfin_media.get_or_init(|| MediaIndex::new(&self.manifest.value))
+    }
+
+    pub fn served_file<'a>(&'a self, key: &'a str) -> Option<(&'a str, &'a PathBuf)> {
+        if let Some(path) = self.files.get(key) {
+            return Some((key, path));
+        }
+        if self.folded_files.is_empty() {
+            return None;
+        }
+
+        let key = self.folded_files.get(&key.to_lowercase())?;
+        Some((key, &self.files[key]))
+    }
+}
+
+pub struct PartialCatalogue {
+    manifest: Value,
+    files: HashMap<String, PathBuf>,
+    published: Instant,
+}
+
+impl PartialCatalogue {
+    pub fn new() -> PartialCatalogue {
+        PartialCatalogue { manifest: Value::Array(Vec::new()), files: HashMap::new(), published: Instant::now() }
+    }
+
+    pub fn add(&mut self, video: &CatalogueItem) -> Result<(), serde_json::Error> {
+        let path = match video {
+            CatalogueItem::Video { path, .. } => path,
+            _ => return Ok(()),
+        };
+
+        let mut items = &mut self.manifest;
+        for directory in path.relative_path.parent().into_iter().flat_map(|parent| parent.iter()) {
+            items = directory_contents(items, &directory.to_string_lossy());
+        }
+        if let Some(items) = items.as_array_mut() {
+            items.push(serde_json::to_value(video)?);
+        }
+
+        self.files.extend(store::served_files_by_key(slice::from_ref(video)));
+        Ok(())
+    }
+
+    pub fn due(&self) -> bool {
+        self.published.elapsed() >= PARTIAL_PUBLISH_INTERVAL
+    }
+
+    pub fn publish(&mut self) -> Result<AppState, Box<dyn error::Error>> {
+        self.published = Instant::now();
+        Ok(AppState::new(EncodedManifest::from_value(self.manifest.clone())?, self.files.clone()))
+    }
+}
+
+fn directory_contents<'a>(items: &'a mut Value, name: &str) -> &'a mut Value {
+    if !items.is_array() {
+        return items;
+    }
+    let items = items.as_array_mut().unwrap();
+
+    let position = items.iter().position(|item| item["type"] == "directory" && item["title"] == name);
+    let position = position.unwrap_or_else(|| {
+        items.push(json!({ "type": "directory", "title": name, "contents": [] }));
+        items.len() - 1
+    });
+    &mut items[position]["contents"]
+}
\ No newline at end of file
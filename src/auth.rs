@@ -0,0 +1,211 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{http::HeaderValue, Body, HeaderMap, Response, StatusCode};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use crate::config::Credentials;
+
+const BASIC_SCHEME: &str = "Basic ";
+const BEARER_SCHEME: &str = "Bearer ";
+const QUERY_TOKEN: &str = "token=";
+const SALT_LENGTH: usize = 16;
+const CHALLENGE: &str = "Basic realm=\"Movie Nexus\", charset=\"UTF-8\"";
+const BEARER_CHALLENGE: &str = "Bearer realm=\"Movie Nexus\"";
+const FREE_ATTEMPTS: u32 = 3;
+const INITIAL_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+pub struct BasicAuth {
+    username: String,
+    password_hash: String,
+    verified: Mutex<HashSet<String>>,
+}
+
+impl BasicAuth {
+    pub fn new(credentials: &Credentials) -> Result<BasicAuth, Box<dyn error::Error>> {
+        check_password_hash(&credentials.password_hash).map_err(|err| format!("Invalid basic-auth password hash: {}", err))?;
+        Ok(BasicAuth {
+            username: credentials.username.clone(),
+            password_hash: credentials.password_hash.clone(),
+            verified: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn permits(&self, headers: &HeaderMap) -> bool {
+        let encoded = match headers.get("Authorization").and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix(BASIC_SCHEME)) {
+            Some(encoded) => encoded.trim(),
+            None => return false,
+        };
+
+        let digest = sha1_smol::Sha1::from(encoded).digest().to_string();
+        if self.verified.lock().unwrap().contains(&digest) {
+            return true;
+        }
+
+        let decoded = match STANDARD.decode(encoded).ok().and_then(|decoded| String::from_utf8(decoded).ok()) {
+            Some(decoded) => decoded,
+            None => return false,
+        };
+        let permitted = match decoded.split_once(':') {
+            Some((username, password)) => self.verifies(username, password),
+            None => false,
+        };
+
+        if permitted {
+            self.verified.lock().unwrap().insert(digest);
+        }
+        permitted
+    }
+
+    pub fn verifies(&self, username: &str, password: &str) -> bool {
+        username == self.username && verify_password(&self.password_hash, password)
+    }
+}
+
+#[derive(Default)]
+pub struct ApiKeys {
+    keys: Vec<String>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: &[String]) -> Result<ApiKeys, Box<dyn error::Error>> {
+        if keys.iter().any(|key| key.trim().is_empty()) {
+            return Err("API keys can't be empty".into());
+        }
+        Ok(ApiKeys { keys: keys.to_vec() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.iter().fold(false, |found, expected| found | tokens_match(key, expected))
+    }
+
+    pub fn permits(&self, headers: &HeaderMap, query: Option<&str>) -> bool {
+        let bearer = headers.get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_SCHEME))
+            .map(str::trim);
+        let queried = query.into_iter().flat_map(|query| query.split('&')).find_map(|parameter| parameter.strip_prefix(QUERY_TOKEN));
+        bearer.into_iter().chain(queried).any(|key| self.contains(key))
+    }
+}
+
+#[derive(Default)]
+pub struct Throttle {
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn locked_for(&self, keys: &[String]) -> Option<Duration> {
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap();
+        keys.iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+            .map(|until| until - now)
+    }
+
+    pub fn record_failure(&self, keys: &[String]) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, failures| now - failures.last < FAILURE_MEMORY);
+
+        for key in keys {
+            let failures = failures.entry(key.clone()).or_insert(Failures { count: 0, last: now, locked_until: None });
+            failures.count += 1;
+            failures.last = now;
+            if failures.count > FREE_ATTEMPTS {
+                let lockout = INITIAL_LOCKOUT.saturating_mul(1 << (failures.count - FREE_ATTEMPTS - 1).min(16)).min(MAX_LOCKOUT);
+                failures.locked_until = Some(now + lockout);
+                warn!("Locking out {} for {} s after {} failed authentication attempts", key, lockout.as_secs(), failures.count);
+            } else {
+                warn!("Failed authentication attempt {} from {}", failures.count, key);
+            }
+        }
+    }
+
+    pub fn record_success(&self, keys: &[String]) {
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys {
+            failures.remove(key);
+        }
+    }
+}
+
+pub fn attempt_keys(remote: Option<IpAddr>, headers: &HeaderMap) -> Vec<String> {
+    let username = basic_credentials(headers).and_then(|credentials| Some(credentials.split_once(':')?.0.to_string()));
+    remote.map(|ip| ip.to_canonical().to_string())
+        .into_iter()
+        .chain(username.as_deref().map(user_key))
+        .collect()
+}
+
+pub fn user_key(username: &str) -> String {
+    format!("user {}", username)
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<String> {
+    let encoded = headers.get("Authorization")?.to_str().ok()?.strip_prefix(BASIC_SCHEME)?;
+    STANDARD.decode(encoded.trim()).ok().and_then(|decoded| String::from_utf8(decoded).ok())
+}
+
+pub fn too_many_attempts(retry_after: Duration, response: &mut Response<Body>) {
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response.headers_mut().insert("Retry-After", HeaderValue::from(retry_after.as_secs().max(1)));
+    *response.body_mut() = Body::from("Too many failed authentication attempts");
+}
+
+pub fn tokens_match(token: &str, expected: &str) -> bool {
+    token.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+pub fn check_password_hash(password_hash: &str) -> Result<(), String> {
+    PasswordHash::new(password_hash).map(|_| ()).map_err(|err| err.to_string())
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+pub fn hash_password(password: &str) -> Result<String, Box<dyn error::Error>> {
+    let mut salt = [0; SALT_LENGTH];
+    getrandom::getrandom(&mut salt)?;
+    let salt = SaltString::encode_b64(&salt).map_err(|err| format!("Couldn't encode the salt: {}", err))?;
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt).map_err(|err| format!("Couldn't hash the password: {}", err))?;
+    Ok(hash.to_string())
+}
+
+pub fn challenge(response: &mut Response<Body>) {
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response.headers_mut().insert("WWW-Authenticate", HeaderValue::from_static(CHALLENGE));
+    *response.body_mut() = Body::from("Authentication required");
+}
+
+pub fn bearer_challenge(response: &mut Response<Body>) {
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response.headers_mut().insert("WWW-Authenticate", HeaderValue::from_static(BEARER_CHALLENGE));
+    *response.body_mut() = Body::from("Authentication required");
+}
\ No newline at end of file
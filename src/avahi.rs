@@ -0,0 +1,41 @@
+use tracing::{info, warn};
+use zbus::{blocking::{Connection, Proxy}, zvariant::OwnedObjectPath};
+
+const AVAHI_DESTINATION: &str = "org.freedesktop.Avahi";
+const AVAHI_SERVER_INTERFACE: &str = "org.freedesktop.Avahi.Server";
+const AVAHI_ENTRY_GROUP_INTERFACE: &str = "org.freedesktop.Avahi.EntryGroup";
+const AVAHI_IF_UNSPEC: i32 = -1;
+const AVAHI_PROTO_UNSPEC: i32 = -1;
+const SERVICE_TYPE: &str = "_http._tcp";
+
+pub struct Registration {
+    connection: Connection,
+    group_path: OwnedObjectPath,
+}
+
+pub fn register_service(port: u16, name: &str) -> Result<Registration, zbus::Error> {
+    let connection = Connection::system()?;
+    let server = Proxy::new(&connection, AVAHI_DESTINATION, "/", AVAHI_SERVER_INTERFACE)?;
+    let host_name: String = server.call("GetHostName", &())?;
+    let group_path: OwnedObjectPath = server.call("EntryGroupNew", &())?;
+
+    let group = Proxy::new(&connection, AVAHI_DESTINATION, group_path.clone(), AVAHI_ENTRY_GROUP_INTERFACE)?;
+    let service_name = format!("{}-{}", host_name, name);
+    let txt: Vec<Vec<u8>> = Vec::new();
+    group.call::<_, _, ()>("AddService", &(AVAHI_IF_UNSPEC, AVAHI_PROTO_UNSPEC, 0u32, &service_name, SERVICE_TYPE, "", "", port, txt))?;
+    group.call::<_, _, ()>("Commit", &())?;
+
+    info!("Service registration complete");
+    Ok(Registration { connection, group_path })
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let freed = Proxy::new(&self.connection, AVAHI_DESTINATION, self.group_path.clone(), AVAHI_ENTRY_GROUP_INTERFACE)
+            .and_then(|group| group.call::<_, _, ()>("Free", &()));
+        match freed {
+            Ok(()) => info!("Service deregistration complete"),
+            Err(err) => warn!("Couldn't withdraw the service announcement: {}", err),
+        }
+    }
+}
\ No newline at end of file
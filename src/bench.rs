@@ -0,0 +1,137 @@
+use std::{
+    convert::Infallible,
+    error,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Component, Path},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body,
+    Client,
+    Request,
+    Server,
+    StatusCode,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::runtime::Runtime;
+
+use crate::config::Settings;
+use crate::scanner::{extract_served_files, is_video, scan_directory, CatalogueItem, RelativizedPath};
+use crate::server::{self, State};
+
+const BYTES_IN_MIB: u64 = 1024 * 1024;
+
+pub fn run(folder: &Path, scans: usize, stream_duration: Duration, chunk_size: u64) -> Result<(), Box<dyn error::Error>> {
+    bench_scan(folder, scans)?;
+    Runtime::new()?.block_on(bench_streaming(folder, stream_duration, chunk_size * BYTES_IN_MIB))
+}
+
+fn bench_scan(folder: &Path, scans: usize) -> Result<(), Box<dyn error::Error>> {
+    let mut timings = Vec::with_capacity(scans);
+    let mut counts = (0, 0);
+    for _ in 0..scans.max(1) {
+        let started = Instant::now();
+        let catalogue = scan_directory(folder, folder)?;
+        timings.push(started.elapsed());
+        counts = count_items(&catalogue);
+    }
+
+    let (videos, directories) = counts;
+    let best = *timings.iter().min().unwrap();
+    let average = timings.iter().sum::<Duration>() / timings.len() as u32;
+
+    println!("Scan: {} video(s) in {} folder(s), {} run(s)", videos, directories, timings.len());
+    println!("  best {:.1} ms, average {:.1} ms, {:.0} videos/s", millis(best), millis(average), videos as f64 / average.as_secs_f64());
+    Ok(())
+}
+
+async fn bench_streaming(folder: &Path, duration: Duration, chunk_size: u64) -> Result<(), Box<dyn error::Error>> {
+    let catalogue = scan_directory(folder, folder)?;
+    let videos: Vec<(String, u64)> = extract_served_files(&catalogue)
+        .iter()
+        .filter(|file| is_video(&file.relative_path))
+        .map(|file| Ok((file_url_path(file), std::fs::metadata(file.path())?.len())))
+        .collect::<Result<_, std::io::Error>>()?;
+    let videos: Vec<_> = videos.into_iter().filter(|(_, len)| *len > 0).collect();
+    if videos.is_empty() {
+        println!("Streaming: skipped, there are no videos to stream");
+        return Ok(());
+    }
+
+    let state = State::open(&Settings::default())?;
+    state.rescan(folder)?;
+    let state = Arc::new(state);
+    let service = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| server::handle(state.clone(), request)))
+        }
+    });
+    let server = Server::try_bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?.serve(service);
+    let address = server.local_addr();
+    tokio::spawn(server);
+
+    let client = Client::new();
+    let mut cursors = vec![0; videos.len()];
+    let (mut requests, mut bytes) = (0u64, 0u64);
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let index = requests as usize % videos.len();
+        let (ref path, len) = videos[index];
+        let start = cursors[index];
+        let end = (start + chunk_size).min(len) - 1;
+        cursors[index] = if end + 1 >= len { 0 } else { end + 1 };
+
+        let request = Request::get(format!("http://{}{}", address, path))
+            .header("Range", format!("bytes={}-{}", start, end))
+            .body(Body::empty())?;
+        let response = client.request(request).await?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(format!("Requesting {} responded with {}", path, response.status()).into());
+        }
+
+        let mut body = response.into_body();
+        while let Some(chunk) = body.next().await {
+            bytes += chunk?.len() as u64;
+        }
+        requests += 1;
+    }
+    let elapsed = started.elapsed();
+
+    println!("Streaming: {} request(s) of up to {} MiB over {} video(s) in {:.1} s", requests, chunk_size / BYTES_IN_MIB, videos.len(), elapsed.as_secs_f64());
+    println!(
+        "  {:.1} MiB/s, {:.1} requests/s, {:.1} ms per request",
+        bytes as f64 / BYTES_IN_MIB as f64 / elapsed.as_secs_f64(),
+        requests as f64 / elapsed.as_secs_f64(),
+        millis(elapsed) / requests as f64
+    );
+    Ok(())
+}
+
+fn count_items(items: &[CatalogueItem]) -> (usize, usize) {
+    items.iter().fold((0, 0), |(videos, directories), item| match item {
+        CatalogueItem::Video { .. } => (videos + 1, directories),
+        CatalogueItem::Directory { items, .. } | CatalogueItem::Series { items, .. } | CatalogueItem::Season { items, .. } => {
+            let (nested_videos, nested_directories) = count_items(items);
+            (videos + nested_videos, directories + nested_directories + 1)
+        }
+    })
+}
+
+fn file_url_path(file: &RelativizedPath) -> String {
+    let components: Vec<String> = file.relative_path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(utf8_percent_encode(&name.to_string_lossy(), NON_ALPHANUMERIC).to_string()),
+            _ => None,
+        })
+        .collect();
+    format!("{}{}", server::PATH_FILE_PREFIX, components.join("/"))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
\ No newline at end of file
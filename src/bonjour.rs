@@ -0,0 +1,81 @@
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    io,
+    ptr::{null, null_mut},
+};
+
+use tracing::info;
+
+const SERVICE_TYPE: &str = "_http._tcp";
+const NO_ERROR: i32 = 0;
+
+type DnsServiceRef = *mut c_void;
+
+extern "C" {
+    fn DNSServiceRegister(
+        sd_ref: *mut DnsServiceRef,
+        flags: u32,
+        interface_index: u32,
+        name: *const c_char,
+        reg_type: *const c_char,
+        domain: *const c_char,
+        host: *const c_char,
+        port: u16,
+        txt_len: u16,
+        txt_record: *const c_void,
+        callback: *const c_void,
+        context: *mut c_void,
+    ) -> i32;
+
+    fn DNSServiceRefDeallocate(sd_ref: DnsServiceRef);
+}
+
+pub struct Registration(DnsServiceRef);
+
+unsafe impl Send for Registration {}
+
+pub fn register_service(port: u16, name: &str) -> Result<Registration, io::Error> {
+    let service_name = CString::new(format!("{}-{}", host_name()?, name)).map_err(io::Error::other)?;
+    let service_type = CString::new(SERVICE_TYPE).unwrap();
+
+    let mut service = null_mut();
+    let result = unsafe {
+        DNSServiceRegister(
+            &mut service,
+            0,
+            0,
+            service_name.as_ptr(),
+            service_type.as_ptr(),
+            null(),
+            null(),
+            port.to_be(),
+            0,
+            null(),
+            null(),
+            null_mut(),
+        )
+    };
+    if result != NO_ERROR {
+        return Err(io::Error::other(format!("DNSServiceRegister failed with {}", result)));
+    }
+
+    info!("Service registration complete");
+    Ok(Registration(service))
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        unsafe { DNSServiceRefDeallocate(self.0) };
+        info!("Service deregistration complete");
+    }
+}
+
+fn host_name() -> Result<String, io::Error> {
+    let mut buf = [0 as c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let host_name = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+    Ok(host_name.trim_end_matches(".local").to_string())
+}
\ No newline at end of file
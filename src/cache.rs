@@ -0,0 +1,61 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const CACHE_DIR_NAME: &str = "movie-nexus";
+
+const CATALOGUE_PREFIX: &str = "catalogue";
+const PROBES_PREFIX: &str = "probes";
+const THUMBNAILS_PREFIX: &str = "thumbnails";
+
+pub fn load_manifest(root_path: &Path) -> Result<Option<String>, io::Error> {
+    load(&cache_path(root_path, CATALOGUE_PREFIX)?)
+}
+
+pub fn store_manifest(root_path: &Path, manifest: &str) -> Result<(), io::Error> {
+    store(&cache_path(root_path, CATALOGUE_PREFIX)?, manifest)
+}
+
+pub fn load_probes(root_path: &Path) -> Result<Option<String>, io::Error> {
+    load(&cache_path(root_path, PROBES_PREFIX)?)
+}
+
+pub fn store_probes(root_path: &Path, probes: &str) -> Result<(), io::Error> {
+    store(&cache_path(root_path, PROBES_PREFIX)?, probes)
+}
+
+pub fn thumbnails_dir(root_path: &Path) -> Result<PathBuf, io::Error> {
+    cache_entry(root_path, THUMBNAILS_PREFIX)
+}
+
+fn load(path: &Path) -> Result<Option<String>, io::Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn store(path: &Path, contents: &str) -> Result<(), io::Error> {
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)
+}
+
+fn cache_path(root_path: &Path, prefix: &str) -> Result<PathBuf, io::Error> {
+    Ok(cache_entry(root_path, prefix)?.with_extension("json"))
+}
+
+fn cache_entry(root_path: &Path, prefix: &str) -> Result<PathBuf, io::Error> {
+    let cache_dir = dirs::cache_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No cache directory available"))?;
+    let root_path = root_path.canonicalize()?;
+    let digest = sha1_smol::Sha1::from(root_path.to_string_lossy().as_bytes()).digest().to_string();
+
+    Ok(cache_dir.join(CACHE_DIR_NAME).join(format!("{}-{}", prefix, digest)))
+}
\ No newline at end of file
@@ -0,0 +1,57 @@
+use serde_json::{json, Value};
+
+use crate::state::{self, Progress};
+
+const MILLISECONDS_IN_SECOND: f64 = 1000.0;
+const METADATA_MOVIE: u8 = 1;
+const METADATA_TV_SHOW: u8 = 2;
+
+pub fn load_request(item: &Value, progress: Option<&Progress>, file_url: &dyn Fn(&str) -> String) -> Value {
+    let path = item.get("path").and_then(Value::as_str).unwrap_or_default();
+    let url = file_url(path);
+
+    let tracks: Vec<Value> = item.get("text-tracks").and_then(Value::as_object).into_iter().flatten()
+        .filter_map(|(language, path)| Some((language, path.as_str()?)))
+        .zip(1..)
+        .map(|((language, path), id): ((&String, &str), u32)| json!({
+            "trackId": id,
+            "type": "TEXT",
+            "subtype": "SUBTITLES",
+            "trackContentId": file_url(path),
+            "trackContentType": "text/vtt",
+            "language": language,
+            "name": language,
+        }))
+        .collect();
+    let images: Vec<Value> = item.get("thumbnails").and_then(Value::as_array).into_iter().flatten()
+        .filter_map(Value::as_str)
+        .map(|path| json!({ "url": file_url(path) }))
+        .collect();
+
+    let mut metadata = json!({ "metadataType": METADATA_MOVIE, "title": item["title"], "images": images });
+    if let Some(subtitle) = item.get("subtitle").filter(|subtitle| subtitle.is_string()) {
+        metadata["subtitle"] = subtitle.clone();
+    }
+    if let Some(episode) = item.get("episode") {
+        metadata["metadataType"] = json!(METADATA_TV_SHOW);
+        metadata["seriesTitle"] = episode["show"].clone();
+        metadata["season"] = episode["season"].clone();
+        metadata["episode"] = episode["episode"].clone();
+    }
+
+    let resume_position = progress.filter(|progress| !state::is_nearly_finished(progress)).map_or(0, |progress| progress.position);
+    json!({
+        "media": {
+            "contentId": url,
+            "contentUrl": url,
+            "contentType": mime_guess::from_path(path).first_or_octet_stream().as_ref(),
+            "streamType": "BUFFERED",
+            "duration": item.get("duration").and_then(Value::as_u64).map(|duration| duration as f64 / MILLISECONDS_IN_SECOND),
+            "metadata": metadata,
+            "tracks": tracks,
+        },
+        "autoplay": true,
+        "currentTime": resume_position as f64 / MILLISECONDS_IN_SECOND,
+        "activeTrackIds": [],
+    })
+}
\ No newline at end of file
@@ -0,0 +1,333 @@
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use tracing::level_filters::LevelFilter;
+
+use crate::config::{LogFormat, LogRotation, Settings, StateBackend};
+use crate::scanner::check_library_folder;
+
+#[derive(Parser)]
+#[clap(version, about, subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[clap(help = "The library folder to serve, asked for interactively if omitted")]
+    pub folder: Option<PathBuf>,
+    #[clap(long, help = "Detach from the terminal and keep running in the background")]
+    pub daemon: bool,
+    #[clap(long, help = "Where to store the process ID of the background server", value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+    #[clap(long, hide = true)]
+    pub detached: bool,
+    #[clap(long, help = "The config file to read instead of the default one", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    #[clap(long, help = "The profile from the config file to apply", value_name = "NAME")]
+    pub profile: Option<String>,
+    #[clap(long, help = "The port to listen on [default: 5000]")]
+    pub port: Option<u16>,
+    #[clap(long, help = "An address to listen on, repeatable [default: every interface]", value_name = "ADDRESS")]
+    pub bind: Vec<IpAddr>,
+    #[clap(long, help = "The name to announce the server under on the local network [default: MovieNexus]", value_name = "NAME")]
+    pub service_name: Option<String>,
+    #[clap(long, help = "The origin allowed to make cross-origin requests [default: *]", value_name = "ORIGIN")]
+    pub cors_origin: Option<String>,
+    #[clap(long, help = "Rescan the library this often while serving it", value_name = "MINUTES")]
+    pub rescan_minutes: Option<u64>,
+    #[clap(long, help = "Serve HTTPS, with a generated self-signed certificate unless one is given")]
+    pub tls: bool,
+    #[clap(long, help = "The port to listen on for HTTPS once a certificate is set [default: 5443]")]
+    pub tls_port: Option<u16>,
+    #[clap(long, help = "The PEM certificate chain to serve HTTPS with", value_name = "PATH", requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+    #[clap(long, help = "The PEM private key of the certificate", value_name = "PATH", requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+    #[clap(long, help = "Only accept HTTPS clients presenting a certificate signed by a CA from this PEM file, redirecting plain HTTP", value_name = "PATH")]
+    pub tls_client_ca: Option<PathBuf>,
+    #[clap(long, help = "Answer plain HTTP requests with a redirect to HTTPS")]
+    pub redirect_http: bool,
+    #[clap(long, help = "Keep the catalogue in this SQLite database so it survives restarts", value_name = "PATH")]
+    pub database: Option<PathBuf>,
+    #[clap(long, value_enum, help = "Where to keep progress, playlists and the rest of the viewers' state [default: sqlite]")]
+    pub state_backend: Option<StateBackend>,
+    #[clap(long, help = "The file to keep the viewers' state in, the database by default for SQLite", value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+    #[clap(long, help = "Reject every request that would change the library, such as rescans, uploads and metadata edits")]
+    pub read_only: bool,
+    #[clap(long, help = "Don't check for new releases in the background")]
+    pub no_update_check: bool,
+    #[clap(long, help = "Don't rescan the library when its files change")]
+    pub no_watch: bool,
+    #[clap(long, help = "How long to keep the state of files that disappeared from the library [default: 30]", value_name = "DAYS")]
+    pub missing_grace_days: Option<u64>,
+    #[clap(long, help = "How much of a file to read at once while streaming it [default: 256]", value_name = "KIB")]
+    pub stream_buffer_kib: Option<usize>,
+    #[clap(long, help = "Match requested file paths regardless of their letter case")]
+    pub case_insensitive_paths: bool,
+    #[clap(long, help = "Hand out expiring signed media links and refuse unsigned file requests")]
+    pub signed_urls: bool,
+    #[clap(long, help = "How long a signed media link stays valid [default: 240]", value_name = "MINUTES")]
+    pub signed_url_minutes: Option<u64>,
+    #[clap(long, help = "Answer the Jellyfin API so that Jellyfin apps can browse and play the library")]
+    pub jellyfin: bool,
+    #[clap(long, help = "Serve the library read-only over WebDAV under /dav so it can be mounted as a network drive")]
+    pub webdav: bool,
+    #[clap(long, help = "Override the titles, genres and episodes of videos with the rows of this CSV file, keyed by a path column", value_name = "PATH")]
+    pub metadata_csv: Option<PathBuf>,
+    #[clap(long, help = "List the videos without a usable sidecar anyway, titled after their file names and with probed or unknown durations")]
+    pub fallback_metadata: bool,
+    #[clap(long, help = "Probe the videos whose sidecars leave out the duration with this ffprobe executable, reading the duration, resolution and codec", value_name = "PATH")]
+    pub ffprobe: Option<PathBuf>,
+    #[clap(long, help = "Serve HLS playlists under /hls and transcode videos under /transcode with this ffmpeg executable", value_name = "PATH")]
+    pub ffmpeg: Option<PathBuf>,
+    #[clap(long, help = "How many videos ffmpeg may transcode at once [default: 2]", value_name = "COUNT")]
+    pub max_transcodes: Option<usize>,
+    #[clap(long, help = "Extract a poster and scrub thumbnails for the videos without artwork with ffmpeg and serve them under /thumb")]
+    pub thumbnails: bool,
+    #[clap(long, help = "The most verbose level to log: off, error, warn, info, debug or trace [default: info]", value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    #[clap(long, help = "Write the log to a file instead of the console", value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    #[clap(long, help = "Rotate the log file once it grows past this many MiB", value_name = "MIB")]
+    pub log_rotate_size: Option<u64>,
+    #[clap(long, value_enum, help = "Rotate the log file every hour or day")]
+    pub log_rotate: Option<LogRotation>,
+    #[clap(long, help = "How many rotated log files to keep [default: 5]", value_name = "COUNT")]
+    pub log_keep: Option<usize>,
+    #[clap(long, value_enum, help = "Write the log as text or as one JSON object per line [default: text]")]
+    pub log_format: Option<LogFormat>,
+    #[cfg(windows)]
+    #[clap(long, help = "Install the server as a Windows service serving the folder", conflicts_with_all = &["run-as-service", "daemon"])]
+    pub install_service: bool,
+    #[cfg(windows)]
+    #[clap(long, help = "Run under the Windows service control manager", hide = true, conflicts_with = "daemon")]
+    pub run_as_service: bool,
+}
+
+impl ServeArgs {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            folder: self.folder.clone(),
+            port: self.port,
+            bind: if self.bind.is_empty() { None } else { Some(self.bind.clone()) },
+            service_name: self.service_name.clone(),
+            cors_origin: self.cors_origin.clone(),
+            rescan_minutes: self.rescan_minutes,
+            tls: if self.tls { Some(true) } else { None },
+            tls_port: self.tls_port,
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            tls_client_ca: self.tls_client_ca.clone(),
+            redirect_http: if self.redirect_http { Some(true) } else { None },
+            database: self.database.clone(),
+            state_backend: self.state_backend.clone(),
+            state_file: self.state_file.clone(),
+            read_only: if self.read_only { Some(true) } else { None },
+            check_updates: if self.no_update_check { Some(false) } else { None },
+            watch: if self.no_watch { Some(false) } else { None },
+            missing_grace_days: self.missing_grace_days,
+            stream_buffer_kib: self.stream_buffer_kib,
+            case_insensitive_paths: if self.case_insensitive_paths { Some(true) } else { None },
+            signed_urls: if self.signed_urls { Some(true) } else { None },
+            signed_url_minutes: self.signed_url_minutes,
+            url_signing_key: None,
+            jellyfin: if self.jellyfin { Some(true) } else { None },
+            webdav: if self.webdav { Some(true) } else { None },
+            metadata_csv: self.metadata_csv.clone(),
+            fallback_metadata: if self.fallback_metadata { Some(true) } else { None },
+            ffprobe: self.ffprobe.clone(),
+            ffmpeg: self.ffmpeg.clone(),
+            max_transcodes: self.max_transcodes,
+            transcode_profiles: None,
+            thumbnails: if self.thumbnails { Some(true) } else { None },
+            log_level: self.log_level,
+            log_file: self.log_file.clone(),
+            log_rotate_size: self.log_rotate_size,
+            log_rotate: self.log_rotate.clone(),
+            log_keep: self.log_keep,
+            log_format: self.log_format,
+            users: None,
+            admin_token: None,
+            api_keys: None,
+            restricted_folders: None,
+            trusted_origins: None,
+            allowed_networks: None,
+            denied_networks: None,
+            basic_auth: None,
+            accounts: None,
+            proxy_auth: None,
+            jwt_secret: None,
+            login_hours: None,
+            trakt: None,
+            opensubtitles: None,
+            s3: None,
+            share: None,
+            library: None,
+        }
+    }
+}
+
+pub fn prompt_folder() -> Result<Option<PathBuf>, io::Error> {
+    loop {
+        print!("Library folder to serve (leave empty to quit): ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim().trim_matches('"');
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let folder = PathBuf::from(line);
+        match check_library_folder(&folder) {
+            Ok(()) => return Ok(Some(folder)),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+pub enum Command {
+    #[clap(about = "Serve the library folder, the same as leaving out the subcommand")]
+    Serve(ServeArgs),
+    #[clap(about = "Stop a server running in the background")]
+    Stop {
+        #[clap(long, help = "The process ID file written by the background server", value_name = "PATH")]
+        pid_file: Option<PathBuf>,
+    },
+    #[clap(about = "Check the library for metadata problems without starting the server")]
+    Validate {
+        #[clap(help = "The library folder to check")]
+        folder: PathBuf,
+        #[clap(long, help = "Print the report as JSON")]
+        json: bool,
+        #[clap(long, help = "The config file with the metadata providers, the default one if omitted", value_name = "PATH")]
+        config: Option<PathBuf>,
+    },
+    #[clap(about = "Scan the folder and print the manifest JSON the server would serve")]
+    Scan {
+        #[clap(help = "The library folder to scan")]
+        folder: PathBuf,
+        #[clap(long, help = "Print what changed since the cached scan instead")]
+        diff: bool,
+        #[clap(long, requires = "diff", help = "Print the changes as JSON")]
+        json: bool,
+        #[clap(long, help = "The config file with the metadata providers and mounts, the default one if omitted", value_name = "PATH")]
+        config: Option<PathBuf>,
+    },
+    #[clap(about = "Write skeleton .toml sidecars for every video that doesn't have one")]
+    GenerateConfig {
+        #[clap(help = "The library folder to fill in")]
+        folder: PathBuf,
+    },
+    #[clap(about = "Write the manifest the server would serve for the folder")]
+    Export {
+        #[clap(help = "The library folder to scan")]
+        folder: PathBuf,
+        #[clap(long, value_enum, default_value = "json", help = "The manifest encoding")]
+        format: ExportFormat,
+        #[clap(long, help = "The file to write to instead of the standard output", value_name = "PATH")]
+        out: Option<PathBuf>,
+        #[clap(long, help = "The config file with the metadata providers and mounts, the default one if omitted", value_name = "PATH")]
+        config: Option<PathBuf>,
+    },
+    #[clap(about = "Write a self-contained HTML index of the library that can be browsed offline or hosted on any web server")]
+    ExportSite {
+        #[clap(help = "The library folder to scan")]
+        folder: PathBuf,
+        #[clap(help = "The folder to write the index to")]
+        out: PathBuf,
+        #[clap(long, help = "Copy the videos, text tracks and thumbnails next to the index instead of linking to the server's files")]
+        copy_media: bool,
+        #[clap(long, help = "The config file with the metadata providers and mounts, the default one if omitted", value_name = "PATH")]
+        config: Option<PathBuf>,
+    },
+    #[clap(about = "Check for a newer release and replace this executable with it")]
+    Update {
+        #[clap(long, help = "Only report whether a newer release exists")]
+        check: bool,
+    },
+    #[clap(about = "Measure how fast the library is scanned and streamed on this machine")]
+    Bench {
+        #[clap(help = "The library folder to measure, the configured one if omitted")]
+        folder: Option<PathBuf>,
+        #[clap(long, default_value = "3", help = "How many times to scan the folder")]
+        scans: usize,
+        #[clap(long, default_value = "10", help = "How long to stream for", value_name = "SECONDS")]
+        stream_seconds: u64,
+        #[clap(long, default_value = "4", help = "The size of each range request", value_name = "MIB")]
+        chunk_size: u64,
+    },
+    #[clap(about = "Print the hash of a password read from the standard input for the basic-auth section of the config file")]
+    HashPassword,
+    #[clap(about = "Manage the long-lived API tokens clients can authenticate with")]
+    Token {
+        #[clap(long, help = "The config file with the state location, the default one if omitted", value_name = "PATH")]
+        config: Option<PathBuf>,
+        #[clap(subcommand)]
+        action: TokenAction,
+    },
+    #[clap(about = "Keep secrets in the OS credential store, to be referred to as keyring:NAME in the config file")]
+    Secret {
+        #[clap(subcommand)]
+        action: SecretAction,
+    },
+    #[clap(about = "Print a shell completion script for every subcommand and flag")]
+    Completions {
+        #[clap(value_enum, help = "The shell to generate the script for")]
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TokenAction {
+    #[clap(about = "Create a token and print it")]
+    Create {
+        #[clap(help = "What the token is for, such as the device that uses it")]
+        name: String,
+        #[clap(long, help = "The user from the config file the token signs in as, the default one if omitted", value_name = "NAME")]
+        user: Option<String>,
+    },
+    #[clap(about = "List the tokens without revealing them")]
+    List,
+    #[clap(about = "Revoke a token by its ID")]
+    Revoke {
+        #[clap(help = "The ID printed by the list command")]
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SecretAction {
+    #[clap(about = "Store a secret read from the standard input, replacing any earlier one")]
+    Set {
+        #[clap(help = "The name to refer to the secret by")]
+        name: String,
+    },
+    #[clap(about = "Delete a stored secret")]
+    Delete {
+        #[clap(help = "The name the secret was stored under")]
+        name: String,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Cbor,
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::{
+    error,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process,
+};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use tokio::runtime::Runtime;
+use tracing::warn;
+
+use crate::auth;
+use crate::cache;
+use crate::cli::{Cli, ExportFormat, SecretAction, TokenAction};
+use crate::config::Settings;
+use crate::diff::diff_manifests;
+use crate::manifest;
+use crate::media_source::{self, Mount};
+use crate::metadata;
+use crate::probe::{mp4_duration, Prober};
+use crate::scanner::{format_duration, is_video, title_from_path, CatalogueItem, validate_directory, EXTENSION_NFO, EXTENSION_TOML};
+use crate::secrets;
+use crate::server;
+use crate::site;
+use crate::tokens;
+use crate::update;
+
+pub fn validate(folder: &Path, settings: &Settings, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let prober = Prober::open(folder, settings.ffprobe.as_deref());
+    let issues = validate_directory(folder, &metadata::providers(settings)?, &prober, settings.fallback_metadata())?;
+    prober.save();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&issues)?);
+    } else {
+        for issue in &issues {
+            println!("{}", issue);
+        }
+        println!("{} issue(s) found", issues.len());
+    }
+
+    if !issues.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn scan(folder: &Path, settings: &Settings, diff: bool, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let catalogue = scan_library(folder, settings, &media_source::mounts(settings)?)?;
+    let manifest = manifest::to_json(&catalogue)?;
+    if !diff {
+        println!("{}", manifest);
+        return Ok(());
+    }
+
+    let cached = match cache::load_manifest(folder)? {
+        Some(cached) => serde_json::from_str(&cached)?,
+        None => serde_json::Value::Array(Vec::new()),
+    };
+    let diff = diff_manifests(&cached, &serde_json::from_str(&manifest)?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        for (prefix, paths) in &[("+", &diff.added), ("-", &diff.removed), ("~", &diff.changed)] {
+            for path in paths.iter() {
+                println!("{} {}", prefix, path);
+            }
+        }
+        println!("{} added, {} removed, {} changed", diff.added.len(), diff.removed.len(), diff.changed.len());
+    }
+    Ok(())
+}
+
+pub fn export(folder: &Path, settings: &Settings, format: ExportFormat, out: Option<&Path>) -> Result<(), Box<dyn error::Error>> {
+    let catalogue = scan_library(folder, settings, &media_source::mounts(settings)?)?;
+    let bytes = match format {
+        ExportFormat::Json => manifest::to_json(&catalogue)?.into_bytes(),
+        ExportFormat::Cbor => manifest::to_cbor(&catalogue)?,
+    };
+
+    match out {
+        Some(path) => fs::write(path, bytes)?,
+        None => io::stdout().write_all(&bytes)?,
+    }
+    Ok(())
+}
+
+pub fn export_site(folder: &Path, settings: &Settings, out: &Path, copy_media: bool) -> Result<(), Box<dyn error::Error>> {
+    let mounts = media_source::mounts(settings)?;
+    let catalogue = scan_library(folder, settings, &mounts)?;
+    let mut items = serde_json::from_str(&manifest::to_json(&catalogue)?)?;
+    fs::create_dir_all(out)?;
+
+    if copy_media {
+        let media = out.join(server::PATH_FILE_PREFIX.trim_matches('/'));
+        for path in site::media_paths(&mut items) {
+            let target = media.join(&path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mounted = path.split_once('/').and_then(|(name, rest)| Some((mounts.iter().find(|mount| mount.name == name)?, rest)));
+            let source = match mounted {
+                Some((mount, rest)) => mount.source.local_path(Path::new(rest))
+                    .ok_or_else(|| format!("Can't copy {}: the mount {} isn't a local folder", path, mount.name))?,
+                None => folder.join(&path),
+            };
+            fs::copy(source, &target).map_err(|err| format!("Can't copy {}: {}", path, err))?;
+        }
+    }
+
+    let title = folder.canonicalize()?.file_name().map_or_else(|| folder.display().to_string(), |name| name.to_string_lossy().into_owned());
+    fs::write(out.join("index.html"), site::render(&title, &items))?;
+    Ok(())
+}
+
+fn scan_library(folder: &Path, settings: &Settings, mounts: &[Mount]) -> Result<Vec<CatalogueItem>, Box<dyn error::Error>> {
+    let prober = Prober::open(folder, settings.ffprobe.as_deref());
+    let catalogue = server::scan_library(folder, &metadata::providers(settings)?, &prober, settings.fallback_metadata(), mounts, None, &mut |_| {})?;
+    prober.save();
+    Ok(catalogue)
+}
+
+pub fn completions(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+}
+
+pub fn hash_password() -> Result<(), Box<dyn error::Error>> {
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err("The password is empty".into());
+    }
+
+    println!("{}", auth::hash_password(password)?);
+    Ok(())
+}
+
+pub fn token(settings: &Settings, action: TokenAction) -> Result<(), Box<dyn error::Error>> {
+    if settings.database.is_none() && settings.state_file.is_none() {
+        return Err("Tokens need a database or a state file to be stored in".into());
+    }
+
+    let user_state = server::open_user_state(settings)?;
+    let profiles = settings.users.clone().unwrap_or_default();
+    match action {
+        TokenAction::Create { name, user } => {
+            let token = tokens::create(user_state.as_ref(), &profiles, user.as_deref().unwrap_or_default(), &name)?;
+            println!("{}", token.token);
+        }
+        TokenAction::List => {
+            for token in tokens::list(user_state.as_ref(), &profiles)? {
+                let user = if token.profile.is_empty() { "(default)" } else { &token.profile };
+                println!("{}  {}  {}", token.id, user, token.device);
+            }
+        }
+        TokenAction::Revoke { id } => {
+            if !tokens::revoke(user_state.as_ref(), &profiles, &id)? {
+                return Err(format!("There's no token with the ID {}", id).into());
+            }
+            println!("Token {} revoked", id);
+        }
+    }
+    Ok(())
+}
+
+pub fn secret(action: SecretAction) -> Result<(), Box<dyn error::Error>> {
+    match action {
+        SecretAction::Set { name } => {
+            let mut secret = String::new();
+            io::stdin().read_to_string(&mut secret)?;
+
+            let secret = secret.trim_end_matches(['\r', '\n']);
+            if secret.is_empty() {
+                return Err("The secret is empty".into());
+            }
+            secrets::set(&name, secret)?;
+            println!("Stored the secret {}, refer to it as keyring:{}", name, name);
+        }
+        SecretAction::Delete { name } => {
+            secrets::delete(&name)?;
+            println!("Deleted the secret {}", name);
+        }
+    }
+    Ok(())
+}
+
+pub fn generate_config(folder: &Path) -> Result<(), Box<dyn error::Error>> {
+    let mut generated = 0;
+    generate_configs_in(folder, &mut generated)?;
+
+    println!("{} sidecar(s) written", generated);
+    Ok(())
+}
+
+fn generate_configs_in(folder: &Path, generated: &mut usize) -> Result<(), io::Error> {
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            generate_configs_in(&path, generated)?;
+            continue;
+        }
+        if !is_video(&path) { continue; }
+
+        let toml_path = path.with_extension(EXTENSION_TOML);
+        if toml_path.exists() || path.with_extension(EXTENSION_NFO).exists() { continue; }
+
+        let mut config = format!("title = {}\n", toml::Value::String(title_from_path(&path)));
+        match mp4_duration(&path) {
+            Ok(Some(duration)) => config.push_str(&format!("duration = \"{}\"\n", format_duration(duration))),
+            result => {
+                if let Err(err) = result {
+                    warn!("Couldn't probe {}: {}", path.display(), err);
+                }
+                config.push_str("# The duration couldn't be determined, please fill it in\nduration = \"PT0S\"\n");
+            }
+        }
+
+        fs::write(&toml_path, config)?;
+        println!("{}", toml_path.display());
+        *generated += 1;
+    }
+
+    Ok(())
+}
+
+pub fn update(check_only: bool) -> Result<(), Box<dyn error::Error>> {
+    Runtime::new()?.block_on(async {
+        let release = update::latest_release().await?;
+        if !release.is_newer() {
+            println!("Version {} is the latest one", update::CURRENT_VERSION);
+            return Ok(());
+        }
+
+        println!("Version {} is available, this is {}", release.version(), update::CURRENT_VERSION);
+        if check_only {
+            return Ok(());
+        }
+
+        update::install(&release).await?;
+        println!("Updated to {}, restart the server to use it", release.version());
+        Ok(())
+    }).map_err(|err: Box<dyn error::Error + Send + Sync>| err as Box<dyn error::Error>)
+}
\ No newline at end of file
@@ -0,0 +1,481 @@
+use std::{
+    collections::HashMap,
+    error,
+    fs,
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use tracing::level_filters::LevelFilter;
+
+use crate::logging::LogSettings;
+use crate::secrets;
+
+const CONFIG_DIR_NAME: &str = "movie-nexus";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+const DEFAULT_PORT: u16 = 5000;
+const DEFAULT_TLS_PORT: u16 = 5443;
+const DEFAULT_SERVICE_NAME: &str = "MovieNexus";
+const DEFAULT_CORS_ORIGIN: &str = "*";
+const DEFAULT_LOG_KEEP: usize = 5;
+const DEFAULT_MISSING_GRACE_DAYS: u64 = 30;
+const DEFAULT_STREAM_BUFFER_KIB: usize = 256;
+const DEFAULT_SIGNED_URL_MINUTES: u64 = 4 * 60;
+const DEFAULT_LOGIN_HOURS: u64 = 12;
+const DEFAULT_MAX_TRANSCODES: usize = 2;
+const BYTES_IN_KIB: usize = 1024;
+const BYTES_IN_MIB: u64 = 1024 * 1024;
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Settings {
+    pub folder: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub bind: Option<Vec<IpAddr>>,
+    pub service_name: Option<String>,
+    pub cors_origin: Option<String>,
+    pub rescan_minutes: Option<u64>,
+    pub tls: Option<bool>,
+    pub tls_port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub redirect_http: Option<bool>,
+    pub database: Option<PathBuf>,
+    pub state_backend: Option<StateBackend>,
+    pub state_file: Option<PathBuf>,
+    pub read_only: Option<bool>,
+    pub check_updates: Option<bool>,
+    pub watch: Option<bool>,
+    pub missing_grace_days: Option<u64>,
+    pub stream_buffer_kib: Option<usize>,
+    pub case_insensitive_paths: Option<bool>,
+    pub signed_urls: Option<bool>,
+    pub signed_url_minutes: Option<u64>,
+    pub url_signing_key: Option<String>,
+    pub jellyfin: Option<bool>,
+    pub webdav: Option<bool>,
+    pub metadata_csv: Option<PathBuf>,
+    pub fallback_metadata: Option<bool>,
+    pub ffprobe: Option<PathBuf>,
+    pub ffmpeg: Option<PathBuf>,
+    pub max_transcodes: Option<usize>,
+    pub transcode_profiles: Option<HashMap<String, TranscodeProfile>>,
+    pub thumbnails: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_level")]
+    pub log_level: Option<LevelFilter>,
+    pub log_file: Option<PathBuf>,
+    pub log_rotate_size: Option<u64>,
+    pub log_rotate: Option<LogRotation>,
+    pub log_keep: Option<usize>,
+    pub log_format: Option<LogFormat>,
+    pub users: Option<HashMap<String, UserProfile>>,
+    pub admin_token: Option<String>,
+    pub api_keys: Option<Vec<String>>,
+    pub restricted_folders: Option<HashMap<String, String>>,
+    pub trusted_origins: Option<Vec<String>>,
+    pub allowed_networks: Option<Vec<String>>,
+    pub denied_networks: Option<Vec<String>>,
+    pub basic_auth: Option<Credentials>,
+    pub accounts: Option<HashMap<String, Account>>,
+    pub proxy_auth: Option<TrustedProxy>,
+    pub jwt_secret: Option<String>,
+    pub login_hours: Option<u64>,
+    pub trakt: Option<TraktSettings>,
+    pub opensubtitles: Option<OpenSubtitlesSettings>,
+    pub s3: Option<Vec<S3Settings>>,
+    pub share: Option<Vec<ShareSettings>>,
+    pub library: Option<Vec<LibrarySettings>>,
+}
+
+impl Settings {
+    pub fn overridden_by(self, overrides: Settings) -> Settings {
+        Settings {
+            folder: overrides.folder.or(self.folder),
+            port: overrides.port.or(self.port),
+            bind: overrides.bind.or(self.bind),
+            service_name: overrides.service_name.or(self.service_name),
+            cors_origin: overrides.cors_origin.or(self.cors_origin),
+            rescan_minutes: overrides.rescan_minutes.or(self.rescan_minutes),
+            tls: overrides.tls.or(self.tls),
+            tls_port: overrides.tls_port.or(self.tls_port),
+            tls_cert: overrides.tls_cert.or(self.tls_cert),
+            tls_key: overrides.tls_key.or(self.tls_key),
+            tls_client_ca: overrides.tls_client_ca.or(self.tls_client_ca),
+            redirect_http: overrides.redirect_http.or(self.redirect_http),
+            database: overrides.database.or(self.database),
+            state_backend: overrides.state_backend.or(self.state_backend),
+            state_file: overrides.state_file.or(self.state_file),
+            read_only: overrides.read_only.or(self.read_only),
+            check_updates: overrides.check_updates.or(self.check_updates),
+            watch: overrides.watch.or(self.watch),
+            missing_grace_days: overrides.missing_grace_days.or(self.missing_grace_days),
+            stream_buffer_kib: overrides.stream_buffer_kib.or(self.stream_buffer_kib),
+            case_insensitive_paths: overrides.case_insensitive_paths.or(self.case_insensitive_paths),
+            signed_urls: overrides.signed_urls.or(self.signed_urls),
+            signed_url_minutes: overrides.signed_url_minutes.or(self.signed_url_minutes),
+            url_signing_key: overrides.url_signing_key.or(self.url_signing_key),
+            jellyfin: overrides.jellyfin.or(self.jellyfin),
+            webdav: overrides.webdav.or(self.webdav),
+            metadata_csv: overrides.metadata_csv.or(self.metadata_csv),
+            fallback_metadata: overrides.fallback_metadata.or(self.fallback_metadata),
+            ffprobe: overrides.ffprobe.or(self.ffprobe),
+            ffmpeg: overrides.ffmpeg.or(self.ffmpeg),
+            max_transcodes: overrides.max_transcodes.or(self.max_transcodes),
+            transcode_profiles: overrides.transcode_profiles.or(self.transcode_profiles),
+            thumbnails: overrides.thumbnails.or(self.thumbnails),
+            log_level: overrides.log_level.or(self.log_level),
+            log_file: overrides.log_file.or(self.log_file),
+            log_rotate_size: overrides.log_rotate_size.or(self.log_rotate_size),
+            log_rotate: overrides.log_rotate.or(self.log_rotate),
+            log_keep: overrides.log_keep.or(self.log_keep),
+            log_format: overrides.log_format.or(self.log_format),
+            users: overrides.users.or(self.users),
+            admin_token: overrides.admin_token.or(self.admin_token),
+            api_keys: overrides.api_keys.or(self.api_keys),
+            restricted_folders: overrides.restricted_folders.or(self.restricted_folders),
+            trusted_origins: overrides.trusted_origins.or(self.trusted_origins),
+            allowed_networks: overrides.allowed_networks.or(self.allowed_networks),
+            denied_networks: overrides.denied_networks.or(self.denied_networks),
+            basic_auth: overrides.basic_auth.or(self.basic_auth),
+            accounts: overrides.accounts.or(self.accounts),
+            proxy_auth: overrides.proxy_auth.or(self.proxy_auth),
+            jwt_secret: overrides.jwt_secret.or(self.jwt_secret),
+            login_hours: overrides.login_hours.or(self.login_hours),
+            trakt: overrides.trakt.or(self.trakt),
+            opensubtitles: overrides.opensubtitles.or(self.opensubtitles),
+            s3: overrides.s3.or(self.s3),
+            share: overrides.share.or(self.share),
+            library: overrides.library.or(self.library),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or(DEFAULT_SERVICE_NAME)
+    }
+
+    pub fn cors_origin(&self) -> &str {
+        self.cors_origin.as_deref().unwrap_or(DEFAULT_CORS_ORIGIN)
+    }
+
+    pub fn rescan_interval(&self) -> Option<Duration> {
+        self.rescan_minutes.filter(|minutes| *minutes > 0).map(|minutes| Duration::from_secs(minutes * 60))
+    }
+
+    pub fn tls(&self) -> bool {
+        self.tls.unwrap_or(false) || self.tls_cert.is_some() || self.tls_client_ca.is_some()
+    }
+
+    pub fn tls_port(&self) -> u16 {
+        self.tls_port.unwrap_or(DEFAULT_TLS_PORT)
+    }
+
+    pub fn redirect_http(&self) -> bool {
+        self.redirect_http.unwrap_or(false)
+    }
+
+    pub fn state_backend(&self) -> StateBackend {
+        self.state_backend.clone().unwrap_or(StateBackend::Sqlite)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.unwrap_or(false)
+    }
+
+    pub fn check_updates(&self) -> bool {
+        self.check_updates.unwrap_or(true)
+    }
+
+    pub fn watch(&self) -> bool {
+        self.watch.unwrap_or(true)
+    }
+
+    pub fn missing_grace_period(&self) -> Duration {
+        Duration::from_secs(self.missing_grace_days.unwrap_or(DEFAULT_MISSING_GRACE_DAYS) * 24 * 60 * 60)
+    }
+
+    pub fn stream_buffer_size(&self) -> usize {
+        self.stream_buffer_kib.unwrap_or(DEFAULT_STREAM_BUFFER_KIB) * BYTES_IN_KIB
+    }
+
+    pub fn case_insensitive_paths(&self) -> bool {
+        self.case_insensitive_paths.unwrap_or(false)
+    }
+
+    pub fn fallback_metadata(&self) -> bool {
+        self.fallback_metadata.unwrap_or(false)
+    }
+
+    pub fn signed_urls(&self) -> bool {
+        self.signed_urls.unwrap_or(false) || self.url_signing_key.is_some()
+    }
+
+    pub fn signed_url_lifetime(&self) -> Duration {
+        Duration::from_secs(self.signed_url_minutes.unwrap_or(DEFAULT_SIGNED_URL_MINUTES) * 60)
+    }
+
+    pub fn jellyfin(&self) -> bool {
+        self.jellyfin.unwrap_or(false)
+    }
+
+    pub fn webdav(&self) -> bool {
+        self.webdav.unwrap_or(false)
+    }
+
+    pub fn max_transcodes(&self) -> usize {
+        self.max_transcodes.unwrap_or(DEFAULT_MAX_TRANSCODES)
+    }
+
+    pub fn transcode_profiles(&self) -> HashMap<String, TranscodeProfile> {
+        self.transcode_profiles.clone().unwrap_or_else(|| {
+            [("1080p", 1080, 8000, 192), ("720p", 720, 4000, 160), ("480p", 480, 1500, 128)].iter()
+                .map(|&(name, height, video_kbps, audio_kbps)| (name.to_string(), TranscodeProfile { height, video_kbps, audio_kbps }))
+                .collect()
+        })
+    }
+
+    pub fn thumbnails(&self) -> bool {
+        self.thumbnails.unwrap_or(false)
+    }
+
+    pub fn login_lifetime(&self) -> Duration {
+        Duration::from_secs(self.login_hours.unwrap_or(DEFAULT_LOGIN_HOURS) * 60 * 60)
+    }
+
+    pub fn log_settings(&self) -> LogSettings {
+        LogSettings {
+            level: self.log_level.unwrap_or(LevelFilter::INFO),
+            file: self.log_file.clone(),
+            rotate_size: self.log_rotate_size.map(|size| size * BYTES_IN_MIB),
+            rotate_period: self.log_rotate.as_ref().map(LogRotation::period),
+            keep: self.log_keep.unwrap_or(DEFAULT_LOG_KEEP),
+            json: matches!(self.log_format, Some(LogFormat::Json)),
+        }
+    }
+
+    fn resolve_secrets(mut self) -> Result<Settings, Box<dyn error::Error>> {
+        self.admin_token = self.admin_token.map(secrets::resolve).transpose()?;
+        self.api_keys = self.api_keys.map(|keys| keys.into_iter().map(secrets::resolve).collect()).transpose()?;
+        self.jwt_secret = self.jwt_secret.map(secrets::resolve).transpose()?;
+        self.url_signing_key = self.url_signing_key.map(secrets::resolve).transpose()?;
+        if let Some(ref mut trakt) = self.trakt {
+            trakt.client_secret = secrets::resolve(std::mem::take(&mut trakt.client_secret))?;
+        }
+        if let Some(ref mut opensubtitles) = self.opensubtitles {
+            opensubtitles.api_key = secrets::resolve(std::mem::take(&mut opensubtitles.api_key))?;
+            opensubtitles.password = opensubtitles.password.take().map(secrets::resolve).transpose()?;
+        }
+        for s3 in self.s3.iter_mut().flatten() {
+            s3.secret_access_key = secrets::resolve(std::mem::take(&mut s3.secret_access_key))?;
+        }
+        for share in self.share.iter_mut().flatten() {
+            share.password = share.password.take().map(secrets::resolve).transpose()?;
+        }
+        for profile in self.users.iter_mut().flat_map(HashMap::values_mut) {
+            profile.token = profile.token.take().map(secrets::resolve).transpose()?;
+        }
+        Ok(self)
+    }
+
+    fn resolve_paths(mut self, base: &Path) -> Settings {
+        self.folder = self.folder.map(|folder| base.join(folder));
+        self.database = self.database.map(|database| base.join(database));
+        self.tls_cert = self.tls_cert.map(|tls_cert| base.join(tls_cert));
+        self.tls_key = self.tls_key.map(|tls_key| if secrets::path_reference(&tls_key).is_some() { tls_key } else { base.join(tls_key) });
+        self.tls_client_ca = self.tls_client_ca.map(|tls_client_ca| base.join(tls_client_ca));
+        self.state_file = self.state_file.map(|state_file| base.join(state_file));
+        self.metadata_csv = self.metadata_csv.map(|metadata_csv| base.join(metadata_csv));
+        self.log_file = self.log_file.map(|log_file| base.join(log_file));
+        for share in self.share.iter_mut().flatten() {
+            share.mount_point = share.mount_point.take().map(|mount_point| base.join(mount_point));
+        }
+        for library in self.library.iter_mut().flatten() {
+            library.folder = base.join(&library.folder);
+        }
+        self
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UserProfile {
+    pub token: Option<String>,
+    #[serde(default)]
+    pub hidden: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Credentials {
+    pub username: String,
+    pub password_hash: String,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Account {
+    pub password_hash: String,
+    pub role: Role,
+    pub profile: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TraktSettings {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct S3Settings {
+    pub mount: String,
+    pub bucket: String,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub prefix: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShareSettings {
+    pub mount: String,
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub mount_point: Option<PathBuf>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TranscodeProfile {
+    pub height: u32,
+    pub video_kbps: u32,
+    pub audio_kbps: u32,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibrarySettings {
+    pub mount: String,
+    pub folder: PathBuf,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OpenSubtitlesSettings {
+    pub api_key: String,
+    pub languages: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrustedProxy {
+    pub trusted_proxies: Vec<String>,
+    pub header: Option<String>,
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+#[derive(Clone, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    Sqlite,
+    Json,
+    Redb,
+}
+
+#[derive(Clone, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+}
+
+impl LogRotation {
+    fn period(&self) -> Duration {
+        match self {
+            LogRotation::Hourly => Duration::from_secs(60 * 60),
+            LogRotation::Daily => Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Default, Deserialize)]
+struct ConfigFile {
+    #[serde(flatten)]
+    settings: Settings,
+    #[serde(default)]
+    profiles: HashMap<String, Settings>,
+}
+
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    default_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Settings, Box<dyn error::Error>> {
+    let (path, required) = match path {
+        Some(path) => (Some(path.to_path_buf()), true),
+        None => (default_path(), false),
+    };
+
+    let config = match path {
+        Some(ref path) => match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|err| format!("Invalid config file {}: {}", path.display(), err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => ConfigFile::default(),
+            Err(err) => return Err(format!("Can't read the config file {}: {}", path.display(), err).into()),
+        },
+        None => ConfigFile::default(),
+    };
+
+    let mut settings = config.settings;
+    if let Some(profile) = profile {
+        let overrides = config.profiles.get(profile).ok_or_else(|| format!("There's no profile named {} in the config file", profile))?;
+        settings = settings.overridden_by(overrides.clone());
+    }
+
+    match path.as_ref().and_then(|path| path.parent()) {
+        Some(base) => settings.resolve_paths(base).resolve_secrets(),
+        None => settings.resolve_secrets(),
+    }
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<LevelFilter>, D::Error> {
+    let level = String::deserialize(deserializer)?;
+    LevelFilter::from_str(&level).map(Some).map_err(de::Error::custom)
+}
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::error;
+
+use hmac::{Hmac, Mac};
+use hyper::HeaderMap;
+use sha2::Sha256;
+
+use crate::signing::{decode_hex, encode_hex};
+
+const KEY_LENGTH: usize = 32;
+const HEADER_ORIGIN: &str = "Origin";
+const HEADER_REFERER: &str = "Referer";
+const HEADER_HOST: &str = "Host";
+const HEADER_AUTHORIZATION: &str = "Authorization";
+const HEADER_CSRF_TOKEN: &str = "X-CSRF-Token";
+const BASIC_SCHEME: &str = "Basic ";
+
+pub struct CsrfGuard {
+    key: Vec<u8>,
+    trusted_origins: Vec<String>,
+}
+
+impl CsrfGuard {
+    pub fn new(trusted_origins: &[String]) -> Result<CsrfGuard, Box<dyn error::Error>> {
+        let mut key = vec![0; KEY_LENGTH];
+        getrandom::getrandom(&mut key)?;
+        Ok(CsrfGuard {
+            key,
+            trusted_origins: trusted_origins.iter().map(|origin| origin.trim_end_matches('/').to_ascii_lowercase()).collect(),
+        })
+    }
+
+    pub fn token(&self, headers: &HeaderMap) -> String {
+        encode_hex(&self.mac(headers).finalize().into_bytes())
+    }
+
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), &'static str> {
+        let text = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let origin = text(HEADER_ORIGIN).map(str::to_string).or_else(|| text(HEADER_REFERER).and_then(origin_of));
+        if let Some(origin) = origin {
+            let origin = origin.to_ascii_lowercase();
+            let same_origin = origin.split_once("://").map(|(_, host)| host) == text(HEADER_HOST).map(str::to_ascii_lowercase).as_deref();
+            if !same_origin && !self.trusted_origins.contains(&origin) {
+                return Err("Cross-origin requests can't change anything");
+            }
+        }
+
+        if text(HEADER_AUTHORIZATION).is_some_and(|value| value.starts_with(BASIC_SCHEME)) {
+            let token = text(HEADER_CSRF_TOKEN).and_then(decode_hex).ok_or("The request needs an X-CSRF-Token header")?;
+            self.mac(headers).verify_slice(&token).map_err(|_| "Invalid CSRF token")?;
+        }
+        Ok(())
+    }
+
+    fn mac(&self, headers: &HeaderMap) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        if let Some(authorization) = headers.get(HEADER_AUTHORIZATION) {
+            mac.update(authorization.as_bytes());
+        }
+        mac
+    }
+}
+
+fn origin_of(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    Some(format!("{}://{}", scheme, host))
+}
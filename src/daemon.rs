@@ -0,0 +1,150 @@
+use std::{
+    env,
+    error,
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use daemonize::Daemonize;
+use tracing::info;
+#[cfg(windows)]
+use {
+    std::{
+        os::windows::process::CommandExt,
+        process::{self, Command, Stdio},
+        ptr::null_mut,
+        thread,
+    },
+    tokio::sync::oneshot,
+    tracing::warn,
+};
+
+#[cfg(windows)]
+use crate::bindings::windows::win32::{
+    system_services::{CreateEventW, OpenEventW, SetEvent, WaitForSingleObject},
+    windows_programming::CloseHandle,
+};
+#[cfg(windows)]
+use crate::win32::{last_error, wide};
+
+const PID_FILE_NAME: &str = "movie-nexus.pid";
+const LOG_FILE_NAME: &str = "movie-nexus.log";
+
+#[cfg(windows)]
+const DETACHED_PROCESS: u32 = 0x0000_0008;
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+#[cfg(windows)]
+const EVENT_MODIFY_STATE: u32 = 0x0002;
+#[cfg(windows)]
+const INFINITE: u32 = 0xFFFF_FFFF;
+
+pub fn default_pid_file() -> PathBuf {
+    env::temp_dir().join(PID_FILE_NAME)
+}
+
+fn log_file() -> PathBuf {
+    env::temp_dir().join(LOG_FILE_NAME)
+}
+
+#[cfg(unix)]
+pub fn detach(pid_file: &Path) -> Result<(), Box<dyn error::Error>> {
+    let log = OpenOptions::new().create(true).append(true).open(log_file())?;
+
+    Daemonize::new()
+        .pid_file(pid_file)
+        .working_directory(env::current_dir()?)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .start()?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn detach(pid_file: &Path) -> Result<(), Box<dyn error::Error>> {
+    let log = OpenOptions::new().create(true).append(true).open(log_file())?;
+
+    let child = Command::new(env::current_exe()?)
+        .args(env::args_os().skip(1).filter(|arg| arg != "--daemon"))
+        .arg("--detached")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP)
+        .spawn()?;
+    fs::write(pid_file, child.id().to_string())?;
+
+    println!("Started in the background with PID {}, logging to {}", child.id(), log_file().display());
+    Ok(())
+}
+
+pub fn stop(pid_file: &Path) -> Result<(), Box<dyn error::Error>> {
+    let pid: u32 = fs::read_to_string(pid_file)?.trim().parse()?;
+    request_stop(pid)?;
+
+    info!("Requested process {} to stop", pid);
+    Ok(())
+}
+
+pub fn remove_pid_file(pid_file: &Path) {
+    if pid_file.is_file() {
+        let _ = fs::remove_file(pid_file);
+    }
+}
+
+#[cfg(unix)]
+fn request_stop(pid: u32) -> Result<(), std::io::Error> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn request_stop(pid: u32) -> Result<(), windows::Error> {
+    let event_name = wide(stop_event_name(pid));
+
+    unsafe {
+        let event = OpenEventW(EVENT_MODIFY_STATE, false.into(), event_name.as_ptr());
+        if event.0 == 0 {
+            return Err(last_error());
+        }
+
+        let result = SetEvent(event).ok();
+        CloseHandle(event);
+        result
+    }
+}
+
+#[cfg(unix)]
+pub async fn stop_requested() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::terminate()).unwrap().recv().await;
+}
+
+#[cfg(windows)]
+pub async fn stop_requested() {
+    let event_name = wide(stop_event_name(process::id()));
+    let event = unsafe { CreateEventW(null_mut(), true.into(), false.into(), event_name.as_ptr()) };
+    if event.0 == 0 {
+        warn!("Couldn't create the stop event: {}", last_error());
+        return futures::future::pending().await;
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    thread::spawn(move || {
+        unsafe { WaitForSingleObject(event, INFINITE); }
+        let _ = sender.send(());
+    });
+
+    let _ = receiver.await;
+}
+
+#[cfg(windows)]
+fn stop_event_name(pid: u32) -> String {
+    format!("Local\\MovieNexus-Stop-{}", pid)
+}
\ No newline at end of file
@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Default, Serialize)]
+pub struct CatalogueDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+pub fn diff_manifests(old: &Value, new: &Value) -> CatalogueDiff {
+    let old_files = collect_files(old);
+    let new_files = collect_files(new);
+
+    let mut diff = CatalogueDiff::default();
+    for (path, file) in &new_files {
+        match old_files.get(path) {
+            None => diff.added.push(path.to_string()),
+            Some(old_file) if old_file != file => diff.changed.push(path.to_string()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old_files.keys()
+        .filter(|path| !new_files.contains_key(*path))
+        .map(|path| path.to_string())
+        .collect();
+
+    diff
+}
+
+fn collect_files(manifest: &Value) -> BTreeMap<&str, &Value> {
+    let mut files = BTreeMap::new();
+    if let Some(items) = manifest.as_array() {
+        for item in items {
+            match item.get("type").and_then(Value::as_str) {
+                Some("directory") | Some("series") | Some("season") => {
+                    if let Some(contents) = item.get("contents") {
+                        files.extend(collect_files(contents));
+                    }
+                }
+                Some("file") => {
+                    if let Some(path) = item.get("path").and_then(Value::as_str) {
+                        files.insert(path, item);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
\ No newline at end of file
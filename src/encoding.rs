@@ -0,0 +1,82 @@
+use std::io::{self, Write};
+
+use flate2::{write::GzEncoder, Compression};
+use hyper::{body::Bytes, http::HeaderValue, Body, HeaderMap, Response};
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+impl ContentEncoding {
+    pub fn negotiate(headers: &HeaderMap) -> ContentEncoding {
+        let accepted: Vec<_> = headers.get_all("Accept-Encoding")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|coding| {
+                let mut parameters = coding.split(';').map(str::trim);
+                let name = parameters.next()?.to_ascii_lowercase();
+                let refused = parameters.any(|parameter| {
+                    parameter.strip_prefix("q=").and_then(|quality| quality.parse::<f32>().ok()) == Some(0.0)
+                });
+                if refused { None } else { Some(name) }
+            })
+            .collect();
+
+        let accepts = |name: &str| accepted.iter().any(|coding| coding == name || coding == "*");
+        if accepts("br") {
+            ContentEncoding::Brotli
+        } else if accepts("gzip") {
+            ContentEncoding::Gzip
+        } else {
+            ContentEncoding::Identity
+        }
+    }
+
+    pub fn encode(self, bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+        self.encode_with(|writer| writer.write_all(bytes))
+    }
+
+    pub fn encode_with(self, write: impl FnOnce(&mut dyn Write) -> Result<(), io::Error>) -> Result<Vec<u8>, io::Error> {
+        match self {
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW);
+                write(&mut encoder)?;
+                Ok(encoder.into_inner())
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()
+            }
+            ContentEncoding::Identity => {
+                let mut bytes = Vec::new();
+                write(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn header(self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Brotli => Some("br"),
+            ContentEncoding::Gzip => Some("gzip"),
+            ContentEncoding::Identity => None,
+        }
+    }
+}
+
+pub fn write_encoded(bytes: Bytes, encoding: ContentEncoding, response: &mut Response<Body>) {
+    response.headers_mut().insert("Vary", HeaderValue::from_static("Accept-Encoding"));
+    if let Some(header) = encoding.header() {
+        response.headers_mut().insert("Content-Encoding", HeaderValue::from_static(header));
+    }
+    *response.body_mut() = Body::from(bytes);
+}
\ No newline at end of file
@@ -0,0 +1,55 @@
+use std::{convert::Infallible, time::Duration};
+
+use futures::stream;
+use hyper::Body;
+use serde_json::Value;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time,
+};
+
+const EVENT_BUFFER: usize = 256;
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct Event {
+    pub profile: String,
+    pub player_id: Option<String>,
+    pub name: &'static str,
+    pub data: Value,
+}
+
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Events {
+        Events { sender: broadcast::channel(EVENT_BUFFER).0 }
+    }
+}
+
+impl Events {
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self, profile: &str, player_id: Option<&str>) -> Body {
+        let seed = (self.sender.subscribe(), profile.to_string(), player_id.map(str::to_string));
+        let events = stream::unfold(seed, |(mut receiver, profile, player_id)| async move {
+            loop {
+                let message = match time::timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await {
+                    Err(_) => ": keep-alive\n\n".to_string(),
+                    Ok(Ok(event)) if event.profile == profile && (player_id.is_none() || event.player_id != player_id) => {
+                        format!("event: {}\ndata: {}\n\n", event.name, event.data)
+                    }
+                    Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+                    Ok(Err(RecvError::Closed)) => return None,
+                };
+                return Some((Ok::<_, Infallible>(message), (receiver, profile, player_id)));
+            }
+        });
+
+        Body::wrap_stream(events)
+    }
+}
\ No newline at end of file
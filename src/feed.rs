@@ -0,0 +1,58 @@
+use std::fs::Metadata;
+
+use serde_json::Value;
+
+use crate::manifest;
+use crate::xml::escape;
+
+const MILLISECONDS_IN_SECOND: u64 = 1000;
+
+pub fn render(title: &str, link: &str, items: &mut Value, file_url: &dyn Fn(&str) -> String, file_metadata: &dyn Fn(&str) -> Option<Metadata>) -> String {
+    let mut entries = String::new();
+    manifest::for_each_file(items, &mut |file| {
+        let path = match file.get("path").and_then(Value::as_str) {
+            Some(path) => path,
+            None => return,
+        };
+        let metadata = file_metadata(path);
+        let url = file_url(path);
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+        entries.push_str("<item>");
+        entries.push_str(&format!("<title>{}</title>", escape(file.get("title").and_then(Value::as_str).unwrap_or(path))));
+        if let Some(subtitle) = file.get("subtitle").and_then(Value::as_str) {
+            entries.push_str(&format!("<description>{}</description>", escape(subtitle)));
+        }
+        if let Some(id) = file.get("id").and_then(Value::as_str) {
+            entries.push_str(&format!("<guid isPermaLink=\"false\">{}</guid>", escape(id)));
+        }
+        entries.push_str(&format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>",
+            escape(&url),
+            metadata.as_ref().map_or(0, Metadata::len),
+            escape(mime.as_ref()),
+        ));
+        if let Some(modified) = metadata.and_then(|metadata| metadata.modified().ok()) {
+            entries.push_str(&format!("<pubDate>{}</pubDate>", httpdate::fmt_http_date(modified)));
+        }
+        if let Some(duration) = file.get("duration").and_then(Value::as_u64) {
+            entries.push_str(&format!("<itunes:duration>{}</itunes:duration>", duration / MILLISECONDS_IN_SECOND));
+        }
+        if let Some(artwork) = file.get("thumbnails").and_then(Value::as_array).and_then(|thumbnails| thumbnails.first()).and_then(Value::as_str) {
+            entries.push_str(&format!("<itunes:image href=\"{}\"/>", escape(&file_url(artwork))));
+        }
+        entries.push_str("</item>\n");
+    });
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n",
+            "<channel>\n<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n{}</channel>\n</rss>\n",
+        ),
+        escape(title),
+        escape(link),
+        escape(title),
+        entries,
+    )
+}
\ No newline at end of file
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::{stream, Stream, StreamExt};
+use bytes::Bytes;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::media_source::{MediaFile, MediaMetadata, MediaSource};
+use crate::scanner::is_video;
+
+const READ_AHEAD: usize = 2;
+const OPEN_FILES_CAPACITY: usize = 64;
+const POOLED_BUFFERS: usize = 64;
+const IN_FLIGHT_BYTES_PER_CONNECTION: usize = 8 * 1024 * 1024;
+const SMALL_FILE_MAX_SIZE: u64 = 1024 * 1024;
+const SMALL_FILES_CAPACITY: usize = 32 * 1024 * 1024;
+const INITIAL_CHUNK_SIZE: usize = 64 * 1024;
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const TARGET_CHUNK_DURATION: Duration = Duration::from_millis(250);
+const MIN_DRAIN_TIME: Duration = Duration::from_millis(1);
+const DRAIN_RATE_WEIGHT: f64 = 0.25;
+
+pub struct BufferPool {
+    buffer_size: usize,
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize) -> BufferPool {
+        BufferPool { buffer_size: buffer_size.max(1), buffers: Mutex::new(Vec::new()) }
+    }
+
+    fn take(&self, len: usize) -> Vec<u8> {
+        let mut buffer = self.buffers.lock().unwrap().pop().unwrap_or_else(|| Vec::with_capacity(self.buffer_size));
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < POOLED_BUFFERS && buffer.capacity() == self.buffer_size {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StreamBudget {
+    in_flight: Arc<Semaphore>,
+    drain_rate: Arc<AtomicU64>,
+}
+
+impl Default for StreamBudget {
+    fn default() -> StreamBudget {
+        StreamBudget {
+            in_flight: Arc::new(Semaphore::new(IN_FLIGHT_BYTES_PER_CONNECTION)),
+            drain_rate: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl StreamBudget {
+    fn chunk_size(&self, max_size: usize) -> usize {
+        let max_size = max_size.min(IN_FLIGHT_BYTES_PER_CONNECTION);
+        match self.drain_rate.load(Ordering::Relaxed) {
+            0 => INITIAL_CHUNK_SIZE.min(max_size),
+            rate => ((rate as f64 * TARGET_CHUNK_DURATION.as_secs_f64()) as usize).clamp(MIN_CHUNK_SIZE.min(max_size), max_size),
+        }
+    }
+
+    fn record_drain(&self, len: usize, elapsed: Duration) {
+        let rate = len as f64 / elapsed.max(MIN_DRAIN_TIME).as_secs_f64();
+        let _ = self.drain_rate.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |previous| {
+            Some(match previous {
+                0 => rate as u64,
+                previous => (previous as f64 * (1.0 - DRAIN_RATE_WEIGHT) + rate * DRAIN_RATE_WEIGHT) as u64,
+            })
+        });
+    }
+}
+
+struct PooledChunk {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsRef<[u8]> for PooledChunk {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledChunk {
+    fn drop(&mut self) {
+        self.pool.give_back(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[derive(Default)]
+pub struct OpenFiles {
+    files: Mutex<OpenFilesInner>,
+}
+
+#[derive(Default)]
+struct OpenFilesInner {
+    files: HashMap<String, (Arc<dyn MediaFile>, u64)>,
+    last_use: u64,
+}
+
+impl OpenFiles {
+    pub async fn open(&self, key: &str, source: &dyn MediaSource, path: &Path) -> Result<Arc<dyn MediaFile>, io::Error> {
+        let cached = self.files.lock().unwrap().get(key);
+        if let Some(file) = cached {
+            match source.metadata(path).await {
+                Ok(current) if current == file.metadata() => return Ok(file),
+                _ => self.files.lock().unwrap().files.remove(key),
+            };
+        }
+
+        let opened = source.open(path).await?;
+        self.files.lock().unwrap().insert(key.to_string(), opened.clone());
+        Ok(opened)
+    }
+}
+
+#[derive(Default)]
+pub struct SmallFiles {
+    files: Mutex<SmallFilesInner>,
+}
+
+#[derive(Default)]
+struct SmallFilesInner {
+    files: HashMap<String, (Bytes, Option<SystemTime>)>,
+    size: usize,
+}
+
+impl SmallFiles {
+    pub fn caches(path: &Path, len: u64) -> bool {
+        len <= SMALL_FILE_MAX_SIZE && !is_video(path)
+    }
+
+    pub async fn read(&self, key: &str, opened: &dyn MediaFile, pool: Arc<BufferPool>, budget: StreamBudget) -> Result<Bytes, io::Error> {
+        let MediaMetadata { len, modified } = opened.metadata();
+        {
+            let mut files = self.files.lock().unwrap();
+            match files.files.get(key) {
+                Some((contents, cached_modified)) if contents.len() as u64 == len && *cached_modified == modified => return Ok(contents.clone()),
+                Some(_) => {
+                    let (stale, _) = files.files.remove(key).unwrap();
+                    files.size -= stale.len();
+                }
+                None => {}
+            }
+        }
+
+        let mut contents = Vec::with_capacity(len as usize);
+        let mut chunks = opened.read_range(0..len, pool, budget);
+        while let Some(chunk) = chunks.next().await {
+            contents.extend_from_slice(&chunk?);
+        }
+        if contents.len() as u64 != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The file changed its size while it was being served"));
+        }
+        let contents = Bytes::from(contents);
+
+        let mut files = self.files.lock().unwrap();
+        if files.size + contents.len() <= SMALL_FILES_CAPACITY && !files.files.contains_key(key) {
+            files.size += contents.len();
+            files.files.insert(key.to_string(), (contents.clone(), modified));
+        }
+        Ok(contents)
+    }
+}
+
+impl OpenFilesInner {
+    fn get(&mut self, key: &str) -> Option<Arc<dyn MediaFile>> {
+        self.last_use += 1;
+        let last_use = self.last_use;
+        self.files.get_mut(key).map(|(file, used)| {
+            *used = last_use;
+            file.clone()
+        })
+    }
+
+    fn insert(&mut self, key: String, file: Arc<dyn MediaFile>) {
+        if self.files.len() >= OPEN_FILES_CAPACITY && !self.files.contains_key(&key) {
+            let least_recent = self.files.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                self.files.remove(&least_recent);
+            }
+        }
+
+        self.last_use += 1;
+        self.files.insert(key, (file, self.last_use));
+    }
+}
+
+pub fn read(file: Arc<File>, range: Range<u64>, pool: Arc<BufferPool>, budget: StreamBudget) -> impl Stream<Item=Result<Bytes, io::Error>> {
+    let mut position = range.start;
+    let chunks = {
+        let (pool, budget) = (pool.clone(), budget.clone());
+        std::iter::from_fn(move || {
+            let start = position;
+            position = (start + budget.chunk_size(pool.buffer_size) as u64).min(range.end);
+            if start < range.end { Some(start..position) } else { None }
+        })
+    };
+
+    let mut delivered: Option<(Instant, usize)> = None;
+    let drain_budget = budget.clone();
+    stream::iter(chunks)
+        .map(move |chunk| {
+            let (file, pool, in_flight) = (file.clone(), pool.clone(), budget.in_flight.clone());
+            async move {
+                let permit = in_flight.acquire_many_owned((chunk.end - chunk.start) as u32).await.map_err(io::Error::other)?;
+                tokio::task::spawn_blocking(move || {
+                    let mut buffer = pool.take((chunk.end - chunk.start) as usize);
+                    read_chunk(&file, &mut buffer, chunk.start)?;
+                    Ok(Bytes::from_owner(PooledChunk { buffer, pool, _permit: permit }))
+                }).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+            }
+        })
+        .buffered(READ_AHEAD)
+        .inspect(move |chunk| {
+            if let Some((at, len)) = delivered.take() {
+                drain_budget.record_drain(len, at.elapsed());
+            }
+            if let Ok(chunk) = chunk {
+                delivered = Some((Instant::now(), chunk.len()));
+            }
+        })
+}
+
+fn read_chunk(file: &File, buffer: &mut [u8], offset: u64) -> Result<(), io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match read_at(file, &mut buffer[filled..], offset + filled as u64)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The file got shorter while it was being served")),
+            read => filled += read,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::unix::fs::FileExt::read_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::windows::fs::FileExt::seek_read(file, buffer, offset)
+}
\ No newline at end of file
@@ -0,0 +1,77 @@
+use std::{
+    io,
+    path::Path,
+    process::Stdio,
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::{io::AsyncReadExt, process::Command};
+
+pub const PLAYLIST_NAME: &str = "playlist.m3u8";
+pub const SEGMENT_PREFIX: &str = "segment-";
+pub const SEGMENT_EXTENSION: &str = ".ts";
+
+const SEGMENT_SECONDS: u64 = 6;
+const READ_CHUNK_LEN: usize = 64 * 1024;
+const MILLISECONDS_IN_SECOND: u64 = 1000;
+
+pub fn playlist(duration: Duration, query: Option<&str>) -> String {
+    let query = query.map(|query| format!("?{}", query)).unwrap_or_default();
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        SEGMENT_SECONDS
+    );
+    for index in 0..segment_count(duration) {
+        let length = segment_length(duration, index);
+        playlist.push_str(&format!("#EXTINF:{:.3},\n{}{}{}{}\n", length.as_secs_f64(), SEGMENT_PREFIX, index, SEGMENT_EXTENSION, query));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
+pub fn segment_index(name: &str, duration: Duration) -> Option<u64> {
+    let index = name.strip_prefix(SEGMENT_PREFIX)?.strip_suffix(SEGMENT_EXTENSION)?.parse().ok()?;
+    if index < segment_count(duration) { Some(index) } else { None }
+}
+
+pub fn segment(ffmpeg: &Path, video: &Path, duration: Duration, index: u64) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, io::Error> {
+    let start = Duration::from_secs(index * SEGMENT_SECONDS);
+    let mut child = Command::new(ffmpeg)
+        .args(["-v", "error", "-ss", &format!("{:.3}", start.as_secs_f64()), "-i"])
+        .arg(video)
+        .args(["-t", &format!("{:.3}", segment_length(duration, index).as_secs_f64())])
+        .args(["-map", "0:v:0", "-map", "0:a?", "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p"])
+        .args(["-force_key_frames", &format!("expr:gte(t,n_forced*{})", SEGMENT_SECONDS), "-c:a", "aac", "-ac", "2", "-muxdelay", "0"])
+        .args(["-output_ts_offset", &format!("{:.3}", start.as_secs_f64()), "-f", "mpegts", "pipe:1"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+
+    Ok(stream::unfold(Some((child, stdout)), |process| async move {
+        let (mut child, mut stdout) = process?;
+        let mut chunk = BytesMut::with_capacity(READ_CHUNK_LEN);
+        match stdout.read_buf(&mut chunk).await {
+            Ok(0) => match child.wait().await {
+                Ok(status) if status.success() => None,
+                Ok(status) => Some((Err(io::Error::other(format!("ffmpeg failed with {}", status))), None)),
+                Err(err) => Some((Err(err), None)),
+            },
+            Ok(_) => Some((Ok(chunk.freeze()), Some((child, stdout)))),
+            Err(err) => Some((Err(err), None)),
+        }
+    }).boxed())
+}
+
+fn segment_count(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).div_ceil(SEGMENT_SECONDS * MILLISECONDS_IN_SECOND)
+}
+
+fn segment_length(duration: Duration, index: u64) -> Duration {
+    let start = Duration::from_secs(index * SEGMENT_SECONDS);
+    (duration - start.min(duration)).min(Duration::from_secs(SEGMENT_SECONDS))
+}
\ No newline at end of file
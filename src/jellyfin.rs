@@ -0,0 +1,635 @@
+use std::{collections::HashMap, error, ffi::OsStr, path::Path};
+
+use hyper::{http::request::Parts, Body, HeaderMap, Method, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::api;
+use crate::app_state::AppState;
+use crate::auth;
+use crate::file_stream::StreamBudget;
+use crate::query::JellyfinQuery;
+use crate::request_path;
+use crate::server::{self, State};
+use crate::state::Progress;
+use crate::viewer::Viewer;
+
+const SERVER_NAME: &str = "Movie Nexus";
+const PRODUCT_NAME: &str = "Jellyfin Server";
+const VERSION: &str = "10.8.13";
+const GUEST: &str = "Guest";
+const DEFAULT_DEVICE: &str = "Jellyfin client";
+const DEFAULT_PLAYER_ID: &str = "jellyfin";
+const TICKS_IN_MILLISECOND: u64 = 10_000;
+const SERVER_ID_LENGTH: usize = 32;
+const ID_HASH_LENGTH: usize = 16;
+const FILE_ID_PREFIX: &str = "0000000000000000";
+const FOLDER_ID_PREFIX: &str = "0000000000000001";
+const USER_ID_PREFIX: &str = "0000000000000002";
+const HEADER_EMBY_AUTHORIZATION: &str = "X-Emby-Authorization";
+const HEADER_AUTHORIZATION: &str = "Authorization";
+const HEADER_EMBY_TOKEN: &str = "X-Emby-Token";
+const HEADER_MEDIA_BROWSER_TOKEN: &str = "X-MediaBrowser-Token";
+const AUTHORIZATION_SCHEMES: &[&str] = &["MediaBrowser ", "Emby "];
+const QUERY_API_KEYS: &[&str] = &["api_key=", "ApiKey="];
+const ROOTS: &[&str] = &["System", "Branding", "Users", "UserViews", "UserItems", "UserPlayedItems", "UserFavoriteItems", "Items", "Videos", "Sessions"];
+const PUBLIC_PATHS: &[&str] = &["/System/Info/Public", "/System/Ping", "/Users/Public", "/Users/AuthenticateByName", "/Branding/Configuration"];
+const PATH_PLAYBACK_INFO_SUFFIX: &str = "/PlaybackInfo";
+const IMAGE_TYPES: &[&str] = &["Primary", "Thumb", "Backdrop"];
+
+pub struct Jellyfin {
+    server_id: String,
+}
+
+pub struct MediaIndex {
+    media: HashMap<String, IndexedMedia>,
+}
+
+struct IndexedMedia {
+    path: Option<String>,
+    thumbnail: Option<String>,
+    text_tracks: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Credentials {
+    username: String,
+    #[serde(default)]
+    pw: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlaybackReport {
+    item_id: String,
+    #[serde(default)]
+    position_ticks: Option<u64>,
+    #[serde(default)]
+    play_session_id: Option<String>,
+}
+
+struct Entry<'a> {
+    id: String,
+    parents: Vec<String>,
+    item: &'a Map<String, Value>,
+    folder: bool,
+}
+
+impl Entry<'_> {
+    fn parent_id(&self) -> Option<&str> {
+        self.parents.last().map(String::as_str)
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        self.item.get(key).and_then(Value::as_str)
+    }
+
+    fn thumbnail(&self) -> Option<&str> {
+        self.item.get("thumbnails").and_then(Value::as_array).and_then(|thumbnails| thumbnails.first()).and_then(Value::as_str)
+    }
+
+    fn text_tracks(&self) -> Vec<(&str, &str)> {
+        self.item.get("text-tracks")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(language, path)| Some((language.as_str(), path.as_str()?)))
+            .collect()
+    }
+
+    fn kind(&self) -> &'static str {
+        match (self.folder, self.parents.is_empty(), self.item.contains_key("episode")) {
+            (true, _, _) if self.text("type") == Some("series") => "Series",
+            (true, _, _) if self.text("type") == Some("season") => "Season",
+            (true, true, _) => "CollectionFolder",
+            (true, false, _) => "Folder",
+            (false, _, true) => "Episode",
+            (false, _, false) => "Movie",
+        }
+    }
+
+    fn resume_position(&self) -> u64 {
+        self.item.get("resume-position").and_then(Value::as_u64).unwrap_or_default()
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.item.get(key).and_then(Value::as_bool).unwrap_or_default()
+    }
+}
+
+impl MediaIndex {
+    pub fn new(manifest: &Value) -> MediaIndex {
+        let mut entries = Vec::new();
+        index(manifest, "", &[], &mut entries);
+        let media = entries.iter().map(|entry| {
+            let indexed = IndexedMedia {
+                path: entry.text("path").filter(|_| !entry.folder).map(str::to_string),
+                thumbnail: entry.thumbnail().map(str::to_string),
+                text_tracks: entry.text_tracks().into_iter().map(|(_, path)| path.to_string()).collect(),
+            };
+            (entry.id.clone(), indexed)
+        });
+        MediaIndex { media: media.collect() }
+    }
+
+    fn get(&self, id: &str) -> Option<&IndexedMedia> {
+        self.media.get(&normalize(id))
+    }
+
+    fn video(&self, id: &str) -> Option<&str> {
+        self.get(id)?.path.as_deref()
+    }
+
+    fn text_track(&self, id: &str, index: &str) -> Option<&str> {
+        self.get(id)?.text_tracks.get(index.parse::<usize>().ok()?).map(String::as_str)
+    }
+
+    fn thumbnail(&self, id: &str) -> Option<&str> {
+        self.get(id)?.thumbnail.as_deref()
+    }
+}
+
+impl Jellyfin {
+    pub fn new(folder: Option<&Path>) -> Jellyfin {
+        let folder = folder.map(|folder| folder.to_string_lossy().into_owned()).unwrap_or_default();
+        Jellyfin { server_id: hash(&folder, SERVER_ID_LENGTH) }
+    }
+}
+
+pub fn handles(path: &str) -> bool {
+    path.strip_prefix('/').and_then(|path| path.split('/').next()).is_some_and(|root| ROOTS.contains(&root))
+}
+
+pub fn is_public(path: &str) -> bool {
+    PUBLIC_PATHS.contains(&path)
+}
+
+pub fn is_query(path: &str) -> bool {
+    is_public(path) || path.ends_with(PATH_PLAYBACK_INFO_SUFFIX)
+}
+
+pub fn token<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+    let header = [HEADER_EMBY_TOKEN, HEADER_MEDIA_BROWSER_TOKEN].iter().find_map(|name| headers.get(*name)?.to_str().ok());
+    header
+        .or_else(|| authorization_parameter(headers, "Token"))
+        .or_else(|| {
+            query.into_iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|parameter| QUERY_API_KEYS.iter().find_map(|key| parameter.strip_prefix(key)))
+        })
+        .filter(|token| !token.is_empty())
+}
+
+pub async fn handle(state: &State, app: &AppState, viewer: &Viewer, attempt: &[String], parts: &Parts, body: Body, response: &mut Response<Body>) {
+    let jellyfin = match state.jellyfin {
+        Some(ref jellyfin) => jellyfin,
+        None => return *response.status_mut() = StatusCode::NOT_FOUND,
+    };
+    let segments: Vec<_> = parts.uri.path().trim_start_matches('/').split('/').collect();
+    match (&parts.method, segments.as_slice()) {
+        (&Method::GET, ["System", "Info", "Public"]) | (&Method::GET, ["System", "Info"]) => api::write_json(&system_info(jellyfin, parts), response),
+        (&Method::GET, ["System", "Ping"]) | (&Method::POST, ["System", "Ping"]) => *response.body_mut() = Body::from(PRODUCT_NAME),
+        (&Method::GET, ["Branding", "Configuration"]) => api::write_json(&json!({ "LoginDisclaimer": "", "CustomCss": "", "SplashscreenEnabled": false }), response),
+        (&Method::GET, ["Users", "Public"]) => api::write_json(&public_users(state, jellyfin), response),
+        (&Method::POST, ["Users", "AuthenticateByName"]) => authenticate(state, jellyfin, attempt, &parts.headers, body, response).await,
+        (&Method::POST, ["Sessions", "Playing"]) | (&Method::POST, ["Sessions", "Playing", _]) => {
+            report_playback(state, app, viewer, body, response).await;
+        }
+        (&Method::POST, ["Sessions", ..]) => *response.status_mut() = StatusCode::NO_CONTENT,
+        (&Method::GET, ["Users", "Me"]) | (&Method::GET, ["Users", _]) => api::write_json(&user(state, jellyfin, &viewer.profile), response),
+        (method, ["Users", _, "PlayedItems", id]) | (method, ["UserPlayedItems", id]) => {
+            match (method, file_id(id)) {
+                (&Method::POST, Some(id)) => api::update_watched(state, viewer, &id, true, response),
+                (&Method::DELETE, Some(id)) => api::update_watched(state, viewer, &id, false, response),
+                (_, None) => *response.status_mut() = StatusCode::NOT_FOUND,
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+            }
+        }
+        (method, ["Users", _, "FavoriteItems", id]) | (method, ["UserFavoriteItems", id]) => {
+            match (method, file_id(id)) {
+                (&Method::POST, Some(id)) => api::update_favorite(state, viewer, &id, true, response),
+                (&Method::DELETE, Some(id)) => api::update_favorite(state, viewer, &id, false, response),
+                (_, None) => *response.status_mut() = StatusCode::NOT_FOUND,
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+            }
+        }
+        (&Method::GET, ["Videos", id, stream]) | (&Method::GET, ["Items", id, stream @ "Download"]) if is_stream(stream) => {
+            serve_path(state, viewer, parts, app.jellyfin_media().video(id), response).await;
+        }
+        (&Method::GET, ["Videos", id, _, "Subtitles", index, ..]) => {
+            serve_path(state, viewer, parts, app.jellyfin_media().text_track(id, index), response).await;
+        }
+        (&Method::GET, ["Items", id, "Images", kind, ..]) if IMAGE_TYPES.contains(kind) => {
+            serve_path(state, viewer, parts, app.jellyfin_media().thumbnail(id), response).await;
+        }
+        (&Method::GET, _) | (&Method::POST, _) => serve_library(state, jellyfin, app, viewer, parts, &segments, response).await,
+        _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+    }
+}
+
+async fn serve_library(state: &State, jellyfin: &Jellyfin, app: &AppState, viewer: &Viewer, parts: &Parts, segments: &[&str], response: &mut Response<Body>) {
+    let mut manifest = server::visible_manifest(&app.manifest, viewer);
+    if let Err(err) = api::annotate_manifest(state, viewer, &mut manifest) {
+        return api::internal_error("Couldn't annotate the manifest", err, response);
+    }
+    let mut entries = Vec::new();
+    index(&manifest, "", &[], &mut entries);
+    let token = token(&parts.headers, parts.uri.query());
+
+    match (&parts.method, segments) {
+        (&Method::GET, ["Users", _, "Views"]) | (&Method::GET, ["UserViews"]) => {
+            let views: Vec<_> = entries.iter().filter(|entry| entry.folder && entry.parents.is_empty()).collect();
+            api::write_json(&page(jellyfin, &entries, views, 0, None), response);
+        }
+        (&Method::GET, ["Users", _, "Items", "Resume"]) | (&Method::GET, ["UserItems", "Resume"]) => {
+            let query = match JellyfinQuery::parse(parts.uri.query()) {
+                Ok(query) => query,
+                Err(err) => return api::bad_request(&err, response),
+            };
+            let resumable = entries.iter().filter(|entry| !entry.folder && entry.resume_position() > 0).collect();
+            api::write_json(&page(jellyfin, &entries, resumable, query.start_index, query.limit), response);
+        }
+        (&Method::GET, ["Users", _, "Items"]) | (&Method::GET, ["Items"]) => {
+            let query = match JellyfinQuery::parse(parts.uri.query()) {
+                Ok(query) => query,
+                Err(err) => return api::bad_request(&err, response),
+            };
+            let matching = entries.iter().filter(|entry| matches(entry, &query)).collect();
+            api::write_json(&page(jellyfin, &entries, matching, query.start_index, query.limit), response);
+        }
+        (&Method::GET, ["Users", _, "Items", id]) | (&Method::GET, ["Items", id]) => match find(&entries, id) {
+            Some(entry) => api::write_json(&item(jellyfin, &entries, entry), response),
+            None => *response.status_mut() = StatusCode::NOT_FOUND,
+        },
+        (_, ["Items", id, "PlaybackInfo"]) => match find(&entries, id).filter(|entry| !entry.folder) {
+            Some(entry) => {
+                let play_session_id = hash(&format!("{}{}", entry.id, crate::state::now()), SERVER_ID_LENGTH);
+                api::write_json(&json!({ "MediaSources": [media_source(entry, token)], "PlaySessionId": play_session_id }), response);
+            }
+            None => *response.status_mut() = StatusCode::NOT_FOUND,
+        },
+        _ => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+}
+
+fn system_info(jellyfin: &Jellyfin, parts: &Parts) -> Value {
+    json!({
+        "LocalAddress": server::origin(parts),
+        "ServerName": SERVER_NAME,
+        "Version": VERSION,
+        "ProductName": PRODUCT_NAME,
+        "OperatingSystem": std::env::consts::OS,
+        "Id": jellyfin.server_id,
+        "StartupWizardCompleted": true,
+    })
+}
+
+fn public_users(state: &State, jellyfin: &Jellyfin) -> Vec<Value> {
+    if !state.accounts.is_empty() {
+        return Vec::new();
+    }
+    if state.profiles.is_empty() {
+        return vec![user(state, jellyfin, "")];
+    }
+
+    let mut names: Vec<_> = state.profiles.keys().collect();
+    names.sort();
+    names.into_iter().map(|name| user(state, jellyfin, name)).collect()
+}
+
+fn user(state: &State, jellyfin: &Jellyfin, profile: &str) -> Value {
+    let has_password = !state.accounts.is_empty() || state.profiles.get(profile).is_some_and(|profile| profile.token.is_some());
+    json!({
+        "Name": display_name(profile),
+        "Id": user_id(profile),
+        "ServerId": jellyfin.server_id,
+        "HasPassword": has_password,
+        "HasConfiguredPassword": has_password,
+        "HasConfiguredEasyPassword": false,
+        "EnableAutoLogin": false,
+        "Policy": {
+            "IsAdministrator": false,
+            "IsHidden": false,
+            "IsDisabled": false,
+            "EnableMediaPlayback": true,
+            "EnableAllFolders": true,
+            "EnableContentDownloading": true,
+            "EnableRemoteAccess": true,
+        },
+        "Configuration": {
+            "PlayDefaultAudioTrack": true,
+            "SubtitleMode": "Default",
+            "HidePlayedInLatest": true,
+            "EnableNextEpisodeAutoPlay": true,
+            "OrderedViews": [],
+            "MyMediaExcludes": [],
+            "LatestItemsExcludes": [],
+            "GroupedFolders": [],
+        },
+    })
+}
+
+async fn authenticate(state: &State, jellyfin: &Jellyfin, attempt: &[String], headers: &HeaderMap, body: Body, response: &mut Response<Body>) {
+    let credentials = match api::read_json::<Credentials>(body).await {
+        Ok(credentials) => credentials,
+        Err(err) => return api::rejected_body("Invalid credentials", err, response),
+    };
+
+    let attempt: Vec<_> = attempt.iter().cloned().chain(Some(auth::user_key(&credentials.username))).collect();
+    if let Some(retry_after) = state.throttle.locked_for(&attempt) {
+        return auth::too_many_attempts(retry_after, response);
+    }
+
+    let profile = match sign_in(state, &credentials) {
+        Ok(Some(profile)) => profile,
+        Ok(None) => {
+            state.throttle.record_failure(&attempt);
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            *response.body_mut() = Body::from("Wrong username or password");
+            return;
+        }
+        Err(err) => return api::internal_error("Couldn't sign in", err, response),
+    };
+    state.throttle.record_success(&attempt);
+
+    let parameter = |name| authorization_parameter(headers, name).map(|value| percent_decode_str(value).decode_utf8_lossy().into_owned());
+    let (client, device, device_id) = (parameter("Client"), parameter("Device"), parameter("DeviceId"));
+    let device_name = device.clone().or_else(|| client.clone()).unwrap_or_else(|| DEFAULT_DEVICE.to_string());
+    match state.user_state.create_session(&profile, &device_name) {
+        Ok(session) => api::write_json(&json!({
+            "User": user(state, jellyfin, &profile),
+            "SessionInfo": {
+                "Id": session.id,
+                "UserId": user_id(&profile),
+                "UserName": display_name(&profile),
+                "Client": client,
+                "DeviceName": device_name,
+                "DeviceId": device_id,
+                "IsActive": true,
+                "SupportsRemoteControl": false,
+                "PlayableMediaTypes": ["Video"],
+            },
+            "AccessToken": session.token,
+            "ServerId": jellyfin.server_id,
+        }), response),
+        Err(err) => api::internal_error("Couldn't store the session", err, response),
+    }
+}
+
+fn sign_in(state: &State, credentials: &Credentials) -> Result<Option<String>, Box<dyn error::Error>> {
+    if !state.accounts.is_empty() {
+        return Ok(state.accounts.login(&credentials.username, &credentials.pw)?.map(|(_, claims)| claims.profile));
+    }
+    if state.requires_authentication() {
+        if !state.verifies_credentials(&credentials.username, &credentials.pw) {
+            return Ok(None);
+        }
+        let profile = state.profiles.get(&credentials.username).filter(|profile| profile.token.is_none());
+        return Ok(Some(profile.map(|_| credentials.username.clone()).unwrap_or_default()));
+    }
+
+    Ok(match state.profiles.get(&credentials.username) {
+        Some(profile) if profile.token.as_deref().is_none_or(|token| auth::tokens_match(&credentials.pw, token)) => Some(credentials.username.clone()),
+        Some(_) => None,
+        None if state.profiles.is_empty() => Some(String::new()),
+        None => None,
+    })
+}
+
+async fn report_playback(state: &State, app: &AppState, viewer: &Viewer, body: Body, response: &mut Response<Body>) {
+    let report = match api::read_json::<PlaybackReport>(body).await {
+        Ok(report) => report,
+        Err(err) => return api::rejected_body("Invalid playback report", err, response),
+    };
+
+    let manifest = server::visible_manifest(&app.manifest, viewer);
+    let mut entries = Vec::new();
+    index(&manifest, "", &[], &mut entries);
+    let entry = match find(&entries, &report.item_id).filter(|entry| !entry.folder) {
+        Some(entry) => entry,
+        None => return *response.status_mut() = StatusCode::NOT_FOUND,
+    };
+    let (id, position) = match (entry.text("id"), report.position_ticks) {
+        (Some(id), Some(position)) => (id, position / TICKS_IN_MILLISECOND),
+        _ => return *response.status_mut() = StatusCode::NO_CONTENT,
+    };
+
+    let progress = Progress {
+        position,
+        duration: entry.item.get("duration").and_then(Value::as_u64).unwrap_or_default(),
+        player_id: report.play_session_id.unwrap_or_else(|| DEFAULT_PLAYER_ID.to_string()),
+        updated: 0,
+        sequence: 0,
+    };
+    match api::store_progress(state, viewer, id, progress, None) {
+        Ok(_) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Err(err) => api::internal_error("Couldn't store the progress", err, response),
+    }
+}
+
+async fn serve_path(state: &State, viewer: &Viewer, parts: &Parts, path: Option<&str>, response: &mut Response<Body>) {
+    match path {
+        Some(path) => {
+            let budget = parts.extensions.get::<StreamBudget>().cloned().unwrap_or_default();
+            server::serve_file(state, viewer, &parts.method, &request_path::encode(path), parts.uri.query(), &parts.headers, budget, response).await;
+        }
+        None => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+}
+
+fn index<'a>(items: &'a Value, dir: &str, parents: &[String], entries: &mut Vec<Entry<'a>>) {
+    for item in items.as_array().into_iter().flatten().filter_map(Value::as_object) {
+        match item.get("type").and_then(Value::as_str) {
+            Some("directory") | Some("series") | Some("season") => {
+                let path = format!("{}/{}", dir, item.get("title").and_then(Value::as_str).unwrap_or_default());
+                let id = format!("{}{}", FOLDER_ID_PREFIX, hash(&path, ID_HASH_LENGTH));
+                let children: Vec<_> = parents.iter().cloned().chain(Some(id.clone())).collect();
+                entries.push(Entry { id, parents: parents.to_vec(), item, folder: true });
+                if let Some(contents) = item.get("contents") {
+                    index(contents, &path, &children, entries);
+                }
+            }
+            Some("file") => {
+                if let Some(id) = item.get("id").and_then(Value::as_str) {
+                    entries.push(Entry { id: format!("{}{}", FILE_ID_PREFIX, id), parents: parents.to_vec(), item, folder: false });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn matches(entry: &Entry, query: &JellyfinQuery) -> bool {
+    if let Some(ref ids) = query.ids {
+        return ids.iter().any(|id| normalize(id) == entry.id);
+    }
+
+    let placed = match query.parent_id {
+        Some(ref parent) if query.recursive => entry.parents.contains(&normalize(parent)),
+        Some(ref parent) => entry.parent_id() == Some(normalize(parent).as_str()),
+        None => query.recursive || entry.parents.is_empty(),
+    };
+    let typed = query.include_item_types.as_ref().is_none_or(|types| {
+        types.iter().any(|kind| kind.eq_ignore_ascii_case(entry.kind()) || (!entry.folder && kind.eq_ignore_ascii_case("Video")))
+    });
+    let searched = query.search_term.as_ref().is_none_or(|term| entry.text("title").unwrap_or_default().to_lowercase().contains(term));
+    let filtered = query.filters.iter().all(|filter| match filter.as_str() {
+        "IsResumable" => entry.resume_position() > 0,
+        "IsFavorite" => entry.flag("favorite"),
+        "IsPlayed" => entry.flag("watched"),
+        "IsUnplayed" => !entry.flag("watched"),
+        "IsFolder" => entry.folder,
+        "IsNotFolder" => !entry.folder,
+        _ => true,
+    });
+    placed && typed && searched && filtered
+}
+
+fn page(jellyfin: &Jellyfin, entries: &[Entry], matching: Vec<&Entry>, start_index: usize, limit: Option<usize>) -> Value {
+    let total = matching.len();
+    let items: Vec<_> = matching.into_iter()
+        .skip(start_index)
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|entry| item(jellyfin, entries, entry))
+        .collect();
+    json!({ "Items": items, "TotalRecordCount": total, "StartIndex": start_index })
+}
+
+fn item(jellyfin: &Jellyfin, entries: &[Entry], entry: &Entry) -> Value {
+    let mut item = Map::new();
+    item.insert("Name".to_string(), entry.text("title").unwrap_or_default().into());
+    item.insert("Id".to_string(), entry.id.clone().into());
+    item.insert("ServerId".to_string(), jellyfin.server_id.clone().into());
+    item.insert("Type".to_string(), entry.kind().into());
+    item.insert("IsFolder".to_string(), entry.folder.into());
+    item.insert("ParentId".to_string(), entry.parent_id().into());
+    item.insert("LocationType".to_string(), "FileSystem".into());
+
+    if entry.folder {
+        let children = entries.iter().filter(|child| child.parent_id() == Some(entry.id.as_str())).count();
+        item.insert("ChildCount".to_string(), children.into());
+        if let Some(season) = entry.item.get("season") {
+            item.insert("IndexNumber".to_string(), season.clone());
+        }
+        item.insert("UserData".to_string(), json!({ "Key": entry.id, "Played": false, "PlayCount": 0, "IsFavorite": false, "PlaybackPositionTicks": 0 }));
+        return Value::Object(item);
+    }
+
+    item.insert("MediaType".to_string(), "Video".into());
+    item.insert("Container".to_string(), container(entry).into());
+    if let Some(duration) = entry.item.get("duration").and_then(Value::as_u64) {
+        item.insert("RunTimeTicks".to_string(), (duration * TICKS_IN_MILLISECOND).into());
+    }
+    if let Some(overview) = entry.text("subtitle") {
+        item.insert("Overview".to_string(), overview.into());
+    }
+    item.insert("Genres".to_string(), entry.item.get("genres").cloned().unwrap_or_else(|| json!([])));
+    if let Some(episode) = entry.item.get("episode") {
+        item.insert("SeriesName".to_string(), episode.get("show").cloned().unwrap_or_default());
+        item.insert("ParentIndexNumber".to_string(), episode.get("season").cloned().unwrap_or_default());
+        item.insert("IndexNumber".to_string(), episode.get("episode").cloned().unwrap_or_default());
+    }
+    let image_tags = entry.thumbnail().map_or_else(|| json!({}), |thumbnail| json!({ "Primary": hash(thumbnail, ID_HASH_LENGTH) }));
+    item.insert("ImageTags".to_string(), image_tags);
+
+    let watched = entry.flag("watched");
+    item.insert("UserData".to_string(), json!({
+        "Key": entry.id,
+        "Played": watched,
+        "PlayCount": u64::from(watched),
+        "IsFavorite": entry.flag("favorite"),
+        "PlaybackPositionTicks": entry.resume_position() * TICKS_IN_MILLISECOND,
+    }));
+    Value::Object(item)
+}
+
+fn media_source(entry: &Entry, token: Option<&str>) -> Value {
+    let with_token = |url: String| match token {
+        Some(token) => format!("{}&api_key={}", url, token),
+        None => url,
+    };
+    let container = container(entry);
+    let subtitles: Vec<_> = entry.text_tracks()
+        .into_iter()
+        .enumerate()
+        .map(|(index, (language, _))| json!({
+            "Type": "Subtitle",
+            "Index": index,
+            "Language": language,
+            "DisplayTitle": language,
+            "Codec": "vtt",
+            "IsExternal": true,
+            "IsTextSubtitleStream": true,
+            "SupportsExternalStream": true,
+            "DeliveryMethod": "External",
+            "DeliveryUrl": with_token(format!("/Videos/{0}/{0}/Subtitles/{1}/0/Stream.vtt?static=true", entry.id, index)),
+        }))
+        .collect();
+
+    json!({
+        "Id": entry.id,
+        "Name": entry.text("title"),
+        "Path": entry.text("path"),
+        "Protocol": "File",
+        "Type": "Default",
+        "Container": container,
+        "RunTimeTicks": entry.item.get("duration").and_then(Value::as_u64).map(|duration| duration * TICKS_IN_MILLISECOND),
+        "IsRemote": false,
+        "SupportsDirectPlay": true,
+        "SupportsDirectStream": true,
+        "SupportsTranscoding": false,
+        "RequiresOpening": false,
+        "RequiresClosing": false,
+        "DirectStreamUrl": with_token(format!("/Videos/{0}/stream.{1}?static=true&mediaSourceId={0}", entry.id, container)),
+        "MediaStreams": subtitles,
+    })
+}
+
+fn container(entry: &Entry) -> String {
+    entry.text("path")
+        .and_then(|path| Path::new(path).extension())
+        .and_then(OsStr::to_str)
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+fn find<'a, 'b>(entries: &'b [Entry<'a>], id: &str) -> Option<&'b Entry<'a>> {
+    let id = normalize(id);
+    entries.iter().find(|entry| entry.id == id)
+}
+
+fn file_id(id: &str) -> Option<String> {
+    normalize(id).strip_prefix(FILE_ID_PREFIX).filter(|id| id.len() == ID_HASH_LENGTH).map(str::to_string)
+}
+
+fn is_stream(segment: &str) -> bool {
+    segment == "stream" || segment.starts_with("stream.") || segment == "Download"
+}
+
+fn authorization_parameter<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    [HEADER_EMBY_AUTHORIZATION, HEADER_AUTHORIZATION].iter()
+        .filter_map(|header| headers.get(*header)?.to_str().ok())
+        .filter_map(|value| AUTHORIZATION_SCHEMES.iter().find_map(|scheme| value.strip_prefix(scheme)))
+        .flat_map(|value| value.split(','))
+        .filter_map(|parameter| parameter.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+fn user_id(profile: &str) -> String {
+    format!("{}{}", USER_ID_PREFIX, hash(profile, ID_HASH_LENGTH))
+}
+
+fn display_name(profile: &str) -> &str {
+    if profile.is_empty() { GUEST } else { profile }
+}
+
+fn normalize(id: &str) -> String {
+    id.replace('-', "").to_ascii_lowercase()
+}
+
+fn hash(text: &str, length: usize) -> String {
+    sha1_smol::Sha1::from(text.as_bytes()).digest().to_string()[..length].to_string()
+}
\ No newline at end of file
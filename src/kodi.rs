@@ -0,0 +1,105 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+const MILLISECONDS_IN_SECOND: u64 = 1000;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Listing {
+    pub title: String,
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ListItem {
+    pub label: String,
+    pub path: String,
+    pub is_folder: bool,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub art: Map<String, Value>,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub info: Map<String, Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_time: Option<u64>,
+}
+
+pub fn listing(title: &str, dir: Option<&str>, items: &Value, folder_url: &dyn Fn(&str) -> String, file_url: &dyn Fn(&str) -> String) -> Listing {
+    let items = items.as_array().into_iter().flatten().filter_map(Value::as_object).filter_map(|item| {
+        let label = item.get("title").and_then(Value::as_str)?;
+        match item.get("type").and_then(Value::as_str)? {
+            "directory" | "series" | "season" => {
+                let path = match dir {
+                    Some(dir) => format!("{}/{}", dir, label),
+                    None => label.to_string(),
+                };
+                Some(ListItem {
+                    label: label.to_string(),
+                    path: folder_url(&path),
+                    is_folder: true,
+                    art: Map::new(),
+                    info: Map::new(),
+                    subtitles: Vec::new(),
+                    resume_time: None,
+                })
+            }
+            "file" => Some(file_item(label, item, file_url)),
+            _ => None,
+        }
+    });
+
+    Listing { title: title.to_string(), items: items.collect() }
+}
+
+fn file_item(label: &str, file: &Map<String, Value>, file_url: &dyn Fn(&str) -> String) -> ListItem {
+    let path = file.get("path").and_then(Value::as_str).unwrap_or_default();
+
+    let mut art = Map::new();
+    if let Some(thumbnail) = file.get("thumbnails").and_then(Value::as_array).and_then(|thumbnails| thumbnails.first()).and_then(Value::as_str) {
+        let url = Value::String(file_url(thumbnail));
+        art.insert("thumb".to_string(), url.clone());
+        art.insert("poster".to_string(), url.clone());
+        art.insert("fanart".to_string(), url);
+    }
+
+    let mut info = Map::new();
+    info.insert("title".to_string(), label.into());
+    if let Some(plot) = file.get("subtitle").and_then(Value::as_str) {
+        info.insert("plot".to_string(), plot.into());
+    }
+    if let Some(duration) = file.get("duration").and_then(Value::as_u64) {
+        info.insert("duration".to_string(), (duration / MILLISECONDS_IN_SECOND).into());
+    }
+    if let Some(genres) = file.get("genres") {
+        info.insert("genre".to_string(), genres.clone());
+    }
+    if let Some(watched) = file.get("watched").and_then(Value::as_bool) {
+        info.insert("playcount".to_string(), u64::from(watched).into());
+    }
+    match file.get("episode") {
+        Some(episode) => {
+            info.insert("mediatype".to_string(), "episode".into());
+            for (key, field) in [("show", "tvshowtitle"), ("season", "season"), ("episode", "episode")] {
+                if let Some(value) = episode.get(key) {
+                    info.insert(field.to_string(), value.clone());
+                }
+            }
+        }
+        None => {
+            info.insert("mediatype".to_string(), "movie".into());
+        }
+    }
+
+    let subtitles = file.get("text-tracks")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|tracks| tracks.values())
+        .filter_map(Value::as_str)
+        .map(file_url)
+        .collect();
+    let resume_time = file.get("resume-position").and_then(Value::as_u64).map(|position| position / MILLISECONDS_IN_SECOND);
+
+    ListItem { label: label.to_string(), path: file_url(path), is_folder: false, art, info, subtitles, resume_time }
+}
\ No newline at end of file
@@ -0,0 +1,139 @@
+#![recursion_limit = "256"]
+
+use std::{
+    error,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+};
+
+use crate::config::Settings;
+use crate::metadata::MetadataProvider;
+use crate::scanner::check_library_folder;
+
+mod access;
+mod accounts;
+mod api;
+mod app_state;
+mod auth;
+pub mod bench;
+pub mod byte_range;
+mod cache;
+mod cast;
+pub mod cli;
+pub mod commands;
+pub mod config;
+mod csrf;
+pub mod daemon;
+mod diff;
+mod encoding;
+mod events;
+mod feed;
+mod file_stream;
+mod hls;
+mod jellyfin;
+mod kodi;
+pub mod logging;
+mod m3u;
+mod manifest;
+mod media_source;
+pub mod metadata;
+mod network;
+mod openapi;
+mod opensubtitles;
+mod persisted_state;
+mod probe;
+mod proxy_auth;
+mod query;
+mod request_path;
+mod s3;
+pub mod scanner;
+mod secrets;
+pub mod server;
+mod share;
+mod signing;
+mod site;
+mod state;
+mod stats;
+mod store;
+mod subtitles;
+mod thumbnails;
+mod tls;
+mod tokens;
+mod trakt;
+mod transcode;
+mod update;
+mod viewer;
+mod watcher;
+mod webdav;
+mod xml;
+#[cfg(target_os = "linux")]
+mod avahi;
+#[cfg(target_os = "macos")]
+mod bonjour;
+#[cfg(windows)]
+pub mod service;
+#[cfg(target_os = "linux")]
+mod systemd;
+#[cfg(windows)]
+mod win32;
+#[cfg(windows)]
+mod windows_dns;
+
+#[cfg(windows)]
+#[allow(dead_code)]
+mod bindings {
+    ::windows::include_bindings!();
+}
+
+type Shutdown = Pin<Box<dyn Future<Output=()> + Send>>;
+
+pub struct MovieNexus;
+
+impl MovieNexus {
+    pub fn builder() -> Builder {
+        Builder { settings: Settings::default(), providers: Vec::new(), shutdown: None }
+    }
+}
+
+pub struct Builder {
+    settings: Settings,
+    providers: Vec<Arc<dyn MetadataProvider>>,
+    shutdown: Option<Shutdown>,
+}
+
+impl Builder {
+    pub fn settings(mut self, settings: Settings) -> Builder {
+        self.settings = self.settings.overridden_by(settings);
+        self
+    }
+
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Builder {
+        self.settings.folder = Some(root.into());
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Builder {
+        self.settings.port = Some(port);
+        self
+    }
+
+    pub fn metadata_provider(mut self, provider: impl MetadataProvider + 'static) -> Builder {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    pub fn shutdown(mut self, shutdown: impl Future<Output=()> + Send + 'static) -> Builder {
+        self.shutdown = Some(Box::pin(shutdown));
+        self
+    }
+
+    pub async fn serve(self) -> Result<(), Box<dyn error::Error>> {
+        let folder = self.settings.folder.clone().ok_or("No library folder given")?;
+        check_library_folder(&folder)?;
+
+        let shutdown = self.shutdown.unwrap_or_else(|| Box::pin(async { tokio::signal::ctrl_c().await.unwrap() }));
+        server::run(&folder, &self.settings, self.providers, shutdown).await
+    }
+}
\ No newline at end of file
@@ -0,0 +1,198 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::{Format, Writer},
+        time::{FormatTime, SystemTime as Timestamp},
+        FmtContext, FormatEvent, FormatFields,
+    },
+    registry::LookupSpan,
+};
+
+pub struct LogSettings {
+    pub level: LevelFilter,
+    pub file: Option<PathBuf>,
+    pub rotate_size: Option<u64>,
+    pub rotate_period: Option<Duration>,
+    pub keep: usize,
+    pub json: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> LogSettings {
+        LogSettings {
+            level: LevelFilter::INFO,
+            file: None,
+            rotate_size: None,
+            rotate_period: None,
+            keep: 0,
+            json: false,
+        }
+    }
+}
+
+pub fn init(settings: &LogSettings) -> Result<(), io::Error> {
+    let builder = tracing_subscriber::fmt().with_max_level(settings.level);
+    let format = EventFormat { json: settings.json, text: Format::default() };
+
+    match settings.file {
+        Some(ref path) => {
+            let file = RotatingFile::open(path, settings.rotate_size, settings.rotate_period, settings.keep)?;
+            builder.with_ansi(false).event_format(format).with_writer(Mutex::new(file)).init();
+        }
+        None => builder.with_ansi(io::stdout().is_terminal()).event_format(format).init(),
+    }
+    Ok(())
+}
+
+struct EventFormat {
+    json: bool,
+    text: Format,
+}
+
+impl<S, N> FormatEvent<S, N> for EventFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, context: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        if !self.json {
+            return self.text.format_event(context, writer, event);
+        }
+
+        let mut timestamp = String::new();
+        Timestamp.format_time(&mut Writer::new(&mut timestamp))?;
+        let metadata = event.metadata();
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+
+        let mut line = json!({ "timestamp": timestamp, "level": metadata.level().as_str(), "target": metadata.target() });
+        line.as_object_mut().unwrap().extend(fields.0);
+        writeln!(writer, "{}", line)
+    }
+}
+
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period_index: u64,
+    max_size: Option<u64>,
+    period: Option<Duration>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: Option<u64>, period: Option<Duration>, keep: usize) -> Result<RotatingFile, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            period_index: period_index(period),
+            max_size,
+            period,
+            keep,
+        })
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        let size_exceeded = match self.max_size {
+            Some(max_size) => self.size > 0 && self.size + incoming as u64 > max_size,
+            None => false,
+        };
+
+        size_exceeded || period_index(self.period) != self.period_index
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        for index in (1..self.keep).rev() {
+            let older = numbered_path(&self.path, index);
+            if older.exists() {
+                fs::rename(older, numbered_path(&self.path, index + 1))?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, numbered_path(&self.path, 1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        self.period_index = period_index(self.period);
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn period_index(period: Option<Duration>) -> u64 {
+    match period {
+        Some(period) => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / period.as_secs(),
+        None => 0,
+    }
+}
+
+fn numbered_path(path: &Path, index: usize) -> PathBuf {
+    let mut numbered = OsString::from(path);
+    numbered.push(format!(".{}", index));
+    PathBuf::from(numbered)
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use serde_json::Value;
+
+use crate::manifest;
+
+const HEADER: &str = "#EXTM3U";
+const MILLISECONDS_IN_SECOND: u64 = 1000;
+
+pub fn render(items: &mut Value, file_url: &dyn Fn(&str) -> String) -> String {
+    let mut playlist = format!("{}\n", HEADER);
+    manifest::for_each_file(items, &mut |file| {
+        let path = match file.get("path").and_then(Value::as_str) {
+            Some(path) => path,
+            None => return,
+        };
+        let title = file.get("title").and_then(Value::as_str).unwrap_or(path).replace(['\r', '\n'], " ");
+        let duration = file.get("duration").and_then(Value::as_u64).map_or(-1, |duration| (duration / MILLISECONDS_IN_SECOND) as i64);
+
+        playlist.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, title, file_url(path)));
+    });
+    playlist
+}
\ No newline at end of file
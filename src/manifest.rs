@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    error,
+    sync::Mutex,
+};
+
+use hyper::body::Bytes;
+use serde_json::{Map, Value};
+
+use crate::encoding::ContentEncoding;
+use crate::scanner::CatalogueItem;
+use crate::viewer::Restriction;
+
+const ENCODED_RESPONSES_CAPACITY: usize = 32;
+
+pub struct EncodedManifest {
+    pub value: Value,
+    json: Bytes,
+    gzip: Bytes,
+    brotli: Bytes,
+    digest: String,
+}
+
+impl EncodedManifest {
+    pub fn new(manifest: &str) -> Result<EncodedManifest, Box<dyn error::Error>> {
+        EncodedManifest::encode(serde_json::from_str(manifest)?, manifest)
+    }
+
+    pub fn from_value(value: Value) -> Result<EncodedManifest, Box<dyn error::Error>> {
+        let manifest = value.to_string();
+        EncodedManifest::encode(value, &manifest)
+    }
+
+    fn encode(value: Value, manifest: &str) -> Result<EncodedManifest, Box<dyn error::Error>> {
+        Ok(EncodedManifest {
+            value,
+            json: Bytes::copy_from_slice(manifest.as_bytes()),
+            gzip: ContentEncoding::Gzip.encode(manifest.as_bytes())?.into(),
+            brotli: ContentEncoding::Brotli.encode(manifest.as_bytes())?.into(),
+            digest: sha1_smol::Sha1::from(manifest).digest().to_string(),
+        })
+    }
+
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    pub fn bytes(&self, encoding: ContentEncoding) -> Bytes {
+        match encoding {
+            ContentEncoding::Brotli => self.brotli.clone(),
+            ContentEncoding::Gzip => self.gzip.clone(),
+            ContentEncoding::Identity => self.json.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EncodedResponses {
+    responses: Mutex<HashMap<String, Bytes>>,
+}
+
+impl EncodedResponses {
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.responses.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn insert(&self, key: String, bytes: Bytes) {
+        let mut responses = self.responses.lock().unwrap();
+        if responses.len() >= ENCODED_RESPONSES_CAPACITY {
+            responses.clear();
+        }
+        responses.insert(key, bytes);
+    }
+}
+
+pub fn to_json(catalogue: &[CatalogueItem]) -> Result<String, serde_json::Error> {
+    serde_json::to_string(catalogue)
+}
+
+pub fn to_cbor(catalogue: &[CatalogueItem]) -> Result<Vec<u8>, serde_cbor::Error> {
+    serde_cbor::to_vec(&catalogue)
+}
+
+pub fn for_each_file(manifest: &mut Value, action: &mut impl FnMut(&mut Map<String, Value>)) {
+    if let Some(items) = manifest.as_array_mut() {
+        for item in items {
+            let item = match item.as_object_mut() {
+                Some(item) => item,
+                None => continue,
+            };
+
+            match item.get("type").and_then(Value::as_str) {
+                Some("directory") | Some("series") | Some("season") | Some("playlist") => {
+                    if let Some(contents) = item.get_mut("contents") {
+                        for_each_file(contents, action);
+                    }
+                }
+                Some("file") => action(item),
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn directory_mut<'a>(manifest: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let mut items = manifest;
+    for name in path.split('/') {
+        items = items.as_array_mut()?
+            .iter_mut()
+            .find(|item| {
+                matches!(item.get("type").and_then(Value::as_str), Some("directory") | Some("series") | Some("season"))
+                    && item.get("title").and_then(Value::as_str) == Some(name)
+            })?
+            .get_mut("contents")?;
+    }
+    Some(items)
+}
+
+pub fn restrictions(manifest: &Value) -> Vec<Restriction> {
+    let mut restrictions = Vec::new();
+    collect_restrictions(manifest, "", &mut restrictions);
+    restrictions
+}
+
+fn collect_restrictions(items: &Value, parent: &str, restrictions: &mut Vec<Restriction>) {
+    for item in items.as_array().into_iter().flatten() {
+        if item.get("type").and_then(Value::as_str) != Some("directory") {
+            continue;
+        }
+
+        let title = item.get("title").and_then(Value::as_str).unwrap_or_default();
+        let folder = if parent.is_empty() { title.to_string() } else { format!("{}/{}", parent, title) };
+        if let Some(role) = item.get("restricted").and_then(Value::as_str) {
+            restrictions.push(Restriction { folder: folder.clone(), role: role.to_string() });
+        }
+        if let Some(contents) = item.get("contents") {
+            collect_restrictions(contents, &folder, restrictions);
+        }
+    }
+}
+
+pub fn retain_files(manifest: &mut Value, keep: &impl Fn(&Map<String, Value>) -> bool) {
+    if let Some(items) = manifest.as_array_mut() {
+        items.retain_mut(|item| {
+            let item = match item.as_object_mut() {
+                Some(item) => item,
+                None => return true,
+            };
+
+            match item.get("type").and_then(Value::as_str) {
+                Some("directory") | Some("series") | Some("season") => match item.get_mut("contents") {
+                    Some(contents) => {
+                        retain_files(contents, keep);
+                        contents.as_array().is_none_or(|contents| !contents.is_empty())
+                    }
+                    None => true,
+                },
+                Some("file") => keep(item),
+                _ => true,
+            }
+        });
+    }
+}
\ No newline at end of file
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashSet,
+    error,
+    fmt::Display,
+    fs::{self, File},
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
+use serde_json::{json, Value};
+
+use crate::config::Settings;
+use crate::file_stream::{self, BufferPool, StreamBudget};
+use crate::s3::S3Source;
+use crate::share::ShareSource;
+use crate::state;
+
+#[cfg(unix)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[libc::EIO, libc::EHOSTDOWN, libc::ESTALE];
+#[cfg(windows)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[
+    ERROR_BAD_NETPATH, ERROR_UNEXP_NET_ERR, ERROR_NETNAME_DELETED, ERROR_SEM_TIMEOUT, ERROR_NETWORK_UNREACHABLE, ERROR_CONNECTION_ABORTED,
+];
+#[cfg(windows)]
+const ERROR_BAD_NETPATH: i32 = 53;
+#[cfg(windows)]
+const ERROR_UNEXP_NET_ERR: i32 = 59;
+#[cfg(windows)]
+const ERROR_NETNAME_DELETED: i32 = 64;
+#[cfg(windows)]
+const ERROR_SEM_TIMEOUT: i32 = 121;
+#[cfg(windows)]
+const ERROR_NETWORK_UNREACHABLE: i32 = 1231;
+#[cfg(windows)]
+const ERROR_CONNECTION_ABORTED: i32 = 1236;
+
+pub struct MediaEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+#[derive(PartialEq)]
+pub struct MediaMetadata {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+pub trait MediaSource: Send + Sync {
+    fn refresh(&self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn entries(&self, dir: &Path) -> Result<Vec<MediaEntry>, io::Error>;
+    fn is_file(&self, path: &Path) -> bool;
+    fn read_to_string(&self, path: &Path) -> Result<String, io::Error>;
+
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<MediaMetadata, io::Error>>;
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Arc<dyn MediaFile>, io::Error>>;
+}
+
+pub trait MediaFile: Send + Sync {
+    fn metadata(&self) -> MediaMetadata;
+    fn read_range(&self, range: Range<u64>, pool: Arc<BufferPool>, budget: StreamBudget) -> BoxStream<'static, Result<Bytes, io::Error>>;
+}
+
+pub struct Mount {
+    pub name: String,
+    pub kind: &'static str,
+    pub source: Arc<dyn MediaSource>,
+    health: Mutex<Option<MountHealth>>,
+}
+
+struct MountHealth {
+    error: Option<String>,
+    checked: u64,
+}
+
+impl Mount {
+    fn new(name: &str, kind: &'static str, source: Arc<dyn MediaSource>) -> Mount {
+        Mount { name: name.to_string(), kind, source, health: Mutex::new(None) }
+    }
+
+    pub fn record<T, E: Display>(&self, result: &Result<T, E>) {
+        let error = result.as_ref().err().map(ToString::to_string);
+        *self.health.lock().unwrap() = Some(MountHealth { error, checked: state::now() });
+    }
+
+    pub fn health(&self) -> Value {
+        let mut health = json!({ "name": self.name, "type": self.kind, "status": "unknown" });
+        if let Some(ref checked) = *self.health.lock().unwrap() {
+            health["status"] = json!(if checked.error.is_some() { "unavailable" } else { "ok" });
+            health["checked"] = json!(checked.checked);
+            if let Some(ref error) = checked.error {
+                health["error"] = json!(error);
+            }
+        }
+        health
+    }
+}
+
+pub fn mounts(settings: &Settings) -> Result<Vec<Mount>, Box<dyn error::Error>> {
+    let mut names = HashSet::new();
+    let mut check_name = |name: &str| -> Result<(), Box<dyn error::Error>> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(format!("The mount \"{}\" has to be a single folder name", name).into());
+        }
+        if !names.insert(name.to_string()) {
+            return Err(format!("The mount \"{}\" is configured twice", name).into());
+        }
+        Ok(())
+    };
+
+    let mut mounts = Vec::new();
+    for s3 in settings.s3.iter().flatten() {
+        check_name(&s3.mount)?;
+        mounts.push(Mount::new(&s3.mount, "s3", Arc::new(S3Source::new(s3))));
+    }
+    for share in settings.share.iter().flatten() {
+        check_name(&share.mount)?;
+        let source = ShareSource::new(share)?;
+        mounts.push(Mount::new(&share.mount, source.protocol().name(), Arc::new(source)));
+    }
+    for library in settings.library.iter().flatten() {
+        check_name(&library.mount)?;
+        mounts.push(Mount::new(&library.mount, "folder", Arc::new(LocalSource::new(&library.folder))));
+    }
+    Ok(mounts)
+}
+
+pub fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::StaleNetworkFileHandle
+    ) || err.raw_os_error().is_some_and(|code| TRANSIENT_OS_ERRORS.contains(&code))
+}
+
+#[derive(Default)]
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(root: &Path) -> LocalSource {
+        LocalSource { root: root.to_path_buf() }
+    }
+}
+
+impl MediaSource for LocalSource {
+    fn refresh(&self) -> Result<(), io::Error> {
+        match self.root.metadata()? {
+            metadata if metadata.is_dir() => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't a folder", self.root.display()))),
+        }
+    }
+
+    fn entries(&self, dir: &Path) -> Result<Vec<MediaEntry>, io::Error> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.root.join(dir))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if !file_type.is_dir() && !file_type.is_file() {
+                continue;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                entries.push(MediaEntry { name, is_dir: file_type.is_dir() });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, io::Error> {
+        fs::read_to_string(self.root.join(path))
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<MediaMetadata, io::Error>> {
+        let path = self.root.join(path);
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let metadata = fs::metadata(path)?;
+                Ok(MediaMetadata { len: metadata.len(), modified: metadata.modified().ok() })
+            }).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+        }.boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Arc<dyn MediaFile>, io::Error>> {
+        let path = self.root.join(path);
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let file = File::open(path)?;
+                let metadata = file.metadata()?;
+                let opened: Arc<dyn MediaFile> = Arc::new(LocalFile { len: metadata.len(), modified: metadata.modified().ok(), file: Arc::new(file) });
+                Ok(opened)
+            }).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+        }.boxed()
+    }
+}
+
+struct LocalFile {
+    file: Arc<File>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl MediaFile for LocalFile {
+    fn metadata(&self) -> MediaMetadata {
+        MediaMetadata { len: self.len, modified: self.modified }
+    }
+
+    fn read_range(&self, range: Range<u64>, pool: Arc<BufferPool>, budget: StreamBudget) -> BoxStream<'static, Result<Bytes, io::Error>> {
+        file_stream::read(self.file.clone(), range, pool, budget).boxed()
+    }
+}
\ No newline at end of file
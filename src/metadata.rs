@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    error,
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::config::Settings;
+use crate::scanner::{universal_path, Episode, RelativizedPath};
+
+const CSV_COLUMN_PATH: &str = "path";
+const CSV_GENRE_SEPARATOR: char = ';';
+
+#[derive(Debug)]
+pub struct Metadata {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub genres: Vec<String>,
+    pub episode: Option<Episode>,
+}
+
+pub trait MetadataProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn provide(&self, video: &RelativizedPath, metadata: &mut Metadata) -> Result<(), Box<dyn error::Error>>;
+}
+
+pub fn providers(settings: &Settings) -> Result<Vec<Arc<dyn MetadataProvider>>, Box<dyn error::Error>> {
+    let mut providers: Vec<Arc<dyn MetadataProvider>> = Vec::new();
+    if let Some(ref path) = settings.metadata_csv {
+        providers.push(Arc::new(CsvProvider::open(path)?));
+    }
+    Ok(providers)
+}
+
+pub struct CsvProvider {
+    rows: HashMap<String, HashMap<String, String>>,
+}
+
+impl CsvProvider {
+    pub fn open(path: &Path) -> Result<CsvProvider, Box<dyn error::Error>> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Can't read the metadata file {}: {}", path.display(), err))?;
+        let mut records = parse_csv(&text).map_err(|err| format!("Can't parse the metadata file {}: {}", path.display(), err))?.into_iter();
+
+        let header: Vec<String> = records.next().unwrap_or_default().into_iter().map(|column| column.trim().to_lowercase()).collect();
+        let path_column = header.iter().position(|column| column == CSV_COLUMN_PATH)
+            .ok_or_else(|| format!("The metadata file {} has no {} column", path.display(), CSV_COLUMN_PATH))?;
+
+        let mut rows = HashMap::new();
+        for record in records {
+            let key = match record.get(path_column) {
+                Some(key) if !key.trim().is_empty() => key.trim().trim_start_matches('/').to_string(),
+                _ => continue,
+            };
+            let row = header.iter().cloned()
+                .zip(record)
+                .filter(|(column, value)| column != CSV_COLUMN_PATH && !value.trim().is_empty())
+                .map(|(column, value)| (column, value.trim().to_string()))
+                .collect();
+            rows.insert(key, row);
+        }
+        Ok(CsvProvider { rows })
+    }
+}
+
+impl MetadataProvider for CsvProvider {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn provide(&self, video: &RelativizedPath, metadata: &mut Metadata) -> Result<(), Box<dyn error::Error>> {
+        let row = match universal_path(&video.relative_path).and_then(|key| self.rows.get(&key)) {
+            Some(row) => row,
+            None => return Ok(()),
+        };
+
+        if let Some(title) = row.get("title") {
+            metadata.title = title.clone();
+        }
+        if let Some(subtitle) = row.get("subtitle") {
+            metadata.subtitle = Some(subtitle.clone());
+        }
+        if let Some(genres) = row.get("genres") {
+            metadata.genres = genres.split(CSV_GENRE_SEPARATOR).map(str::trim).filter(|genre| !genre.is_empty()).map(String::from).collect();
+        }
+
+        let season = row.get("season").map(|season| season.parse::<u32>().map_err(|_| format!("Bad season \"{}\"", season))).transpose()?;
+        let episode = row.get("episode").map(|episode| episode.parse::<u32>().map_err(|_| format!("Bad episode \"{}\"", episode))).transpose()?;
+        let show = row.get("show").cloned();
+        metadata.episode = match (metadata.episode.take(), show, season, episode) {
+            (Some(current), show, season, episode) => Some(Episode {
+                show: show.unwrap_or(current.show),
+                season: season.unwrap_or(current.season),
+                episode: episode.unwrap_or(current.episode),
+            }),
+            (None, Some(show), Some(season), Some(episode)) => Some(Episode { show, season, episode }),
+            (None, ..) => None,
+        };
+        Ok(())
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let (mut record, mut field) = (Vec::new(), String::new());
+    let (mut quoted, mut line, mut quote_line) = (false, 1, 1);
+
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => {
+                quoted = true;
+                quote_line = line;
+            }
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                line += 1;
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            char => {
+                if char == '\n' { line += 1; }
+                field.push(char);
+            }
+        }
+    }
+    if quoted {
+        return Err(format!("an unterminated quote on line {}", quote_line));
+    }
+
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push(record);
+    }
+    Ok(records)
+}
\ No newline at end of file
@@ -1,124 +1,91 @@
 use std::{
-    ptr::null_mut,
-    sync::{Mutex, Condvar},
+    error,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use lazy_static::lazy_static;
-use windows::ErrorCode;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
 
-use bindings::windows::win32::{
-    debug::GetLastError,
-    dns::{
-        DNS_SERVICE_REGISTER_REQUEST, DnsServiceRegister,
-    },
-    system_services::DNS_REQUEST_PENDING,
-    windows_programming::{COMPUTER_NAME_FORMAT, GetComputerNameExW},
-};
-use wrapper::DnsServiceInfo;
-
-const SERVICE_NAME: &str = "MovieNexus";
-const SERVICE_TYPE: &str = "_http._tcp.local";
-
-lazy_static! {
-    static ref REGISTRATION_MUTEX: Mutex<()> = Mutex::default();
-    static ref REGISTRATION_IN_PROGRESS_MUTEX: Mutex<bool> = Mutex::new(false);
-    static ref REGISTRATION_STATE_VAR: Condvar = Condvar::new();
-}
-
-#[allow(dead_code)]
-mod bindings {
-    ::windows::include_bindings!();
-}
+#[cfg(windows)]
+use crate::windows_dns as platform;
+#[cfg(target_os = "linux")]
+use crate::avahi as platform;
+#[cfg(target_os = "macos")]
+use crate::bonjour as platform;
 
-pub fn register_service(port: u16) -> Result<(), windows::Error> {
-    let mut buf = [0u16; 256];
-    let mut len = buf.len();
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const ROUTE_PROBES: [&str; 2] = ["192.0.2.1:9", "[2001:db8::1]:9"];
 
-    unsafe { GetComputerNameExW(COMPUTER_NAME_FORMAT::ComputerNameDnsFullyQualified, buf.as_mut_ptr() as _, &mut len as *mut _ as _).ok()?; }
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+mod platform {
+    use std::io;
 
-    let first_zero = buf.iter().position(|byte| *byte == 0).unwrap_or(buf.len());
-    let hostname = String::from_utf16(&buf[..first_zero]).unwrap();
+    use tracing::warn;
 
-    let service_name = format!("{}-{}.{}", hostname.clone(), SERVICE_NAME, SERVICE_TYPE);
-    let host_name = format!("{}.local", hostname);
+    pub struct Registration;
 
-    let service_instance = DnsServiceInfo::new(&service_name, &host_name, port);
-
-    let mut request = DNS_SERVICE_REGISTER_REQUEST {
-        version: 1,
-        interface_index: 0,
-        p_service_instance: service_instance.instance(),
-        p_register_completion_callback: Some(registration_callback),
-        p_query_context: null_mut(),
-        h_credentials: Default::default(),
-        unicast_enabled: false.into(),
-    };
-
-    {
-        let _registration_guard = REGISTRATION_MUTEX.lock().unwrap();
-        let mut state_guard = REGISTRATION_IN_PROGRESS_MUTEX.lock().unwrap();
-        *state_guard = true;
-
-        let result = unsafe { DnsServiceRegister(&mut request as *mut _, null_mut()) };
-        if result != DNS_REQUEST_PENDING as u32 {
-            return Err(ErrorCode(unsafe { GetLastError() }).into());
-        }
-
-        let _var_guard = REGISTRATION_STATE_VAR.wait_while(state_guard, |registration_in_progress| *registration_in_progress).unwrap();
+    pub fn register_service(_port: u16, _name: &str) -> Result<Registration, io::Error> {
+        warn!("Announcing the server over mDNS isn't supported on this platform");
+        Ok(Registration)
     }
-
-    Ok(())
 }
 
-extern "system" fn registration_callback() {
-    println!("Service registration complete");
-
-    *REGISTRATION_IN_PROGRESS_MUTEX.lock().unwrap() = false;
-    REGISTRATION_STATE_VAR.notify_all();
+pub struct ServiceRegistration {
+    registration: Arc<Mutex<Option<platform::Registration>>>,
+    watcher: JoinHandle<()>,
 }
 
-mod wrapper {
-    use std::ptr::null_mut;
-
-    use super::bindings::windows::win32::dns::{DNS_SERVICE_INSTANCE, DnsServiceConstructInstance, DnsServiceFreeInstance};
-
-    pub struct DnsServiceInfo {
-        instance: *mut DNS_SERVICE_INSTANCE
+impl ServiceRegistration {
+    pub fn register(port: u16, name: &str) -> Result<ServiceRegistration, Box<dyn error::Error>> {
+        let registration = Arc::new(Mutex::new(Some(platform::register_service(port, name)?)));
+        let watcher = tokio::spawn(reregister_on_network_change(registration.clone(), port, name.to_string()));
+        Ok(ServiceRegistration { registration, watcher })
     }
+}
 
-    impl DnsServiceInfo {
-        pub fn new(service_name: &str, host_name: &str, port: u16) -> DnsServiceInfo {
-            let instance = unsafe {
-                let mut service_name = (service_name.to_owned() + "\0").encode_utf16().collect::<Vec<u16>>();
-                let mut host_name = (host_name.to_owned() + "\0").encode_utf16().collect::<Vec<u16>>();
-
-                DnsServiceConstructInstance(
-                    service_name.as_mut_ptr(),
-                    host_name.as_mut_ptr(),
-                    null_mut(),
-                    null_mut(),
-                    port,
-                    0,
-                    0,
-                    0,
-                    null_mut(),
-                    null_mut(),
-                )
-            };
+impl Drop for ServiceRegistration {
+    fn drop(&mut self) {
+        self.watcher.abort();
+        self.registration.lock().unwrap().take();
+    }
+}
 
-            DnsServiceInfo {
-                instance
-            }
+async fn reregister_on_network_change(registration: Arc<Mutex<Option<platform::Registration>>>, port: u16, name: String) {
+    let mut addresses = local_addresses();
+    loop {
+        tokio::time::sleep(NETWORK_CHECK_INTERVAL).await;
+        let current = local_addresses();
+        if current == addresses {
+            continue;
         }
-
-        pub fn instance(&self) -> *mut DNS_SERVICE_INSTANCE {
-            self.instance
+        addresses = current;
+
+        info!("The network changed, announcing the server again");
+        let (registration, name) = (registration.clone(), name.clone());
+        let reregistered = tokio::task::spawn_blocking(move || {
+            let mut registration = registration.lock().unwrap();
+            registration.take();
+            *registration = Some(platform::register_service(port, &name).map_err(|err| err.to_string())?);
+            Ok::<_, String>(())
+        }).await;
+        match reregistered {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("Couldn't announce the server on the local network: {}", err),
+            Err(err) => warn!("Couldn't announce the server on the local network: {}", err),
         }
     }
+}
 
-    impl Drop for DnsServiceInfo {
-        fn drop(&mut self) {
-            unsafe { DnsServiceFreeInstance(self.instance) }
-        }
-    }
+fn local_addresses() -> Vec<Option<IpAddr>> {
+    ROUTE_PROBES.iter()
+        .map(|probe| {
+            let remote: SocketAddr = probe.parse().unwrap();
+            let local: SocketAddr = if remote.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse().unwrap();
+            let socket = UdpSocket::bind(local).ok()?;
+            socket.connect(remote).ok()?;
+            socket.local_addr().ok().map(|address| address.ip())
+        })
+        .collect()
 }
\ No newline at end of file
@@ -0,0 +1,486 @@
+use serde_json::{json, Value};
+
+use crate::update;
+
+pub fn document(server_url: &str) -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Movie Nexus",
+            "version": update::CURRENT_VERSION,
+            "description": "Serves a video library to browsers and players, tracking what each profile watched.",
+        },
+        "servers": [{ "url": server_url }],
+        "components": components(),
+        "security": [{}, { "session": [] }, { "queryToken": [] }, { "profileToken": [] }, { "basic": [] }],
+        "paths": {
+            "/": {
+                "get": {
+                    "summary": "The manifest of the library, annotated with the viewer's progress",
+                    "tags": ["Library"],
+                    "parameters": [
+                        query_parameter("format", "The format to list the library in, negotiated with Accept if omitted", json!({ "type": "string", "enum": ["json", "xml"] })),
+                        header_parameter("If-None-Match", "The ETag of a manifest the client already has"),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The directory tree",
+                            "content": {
+                                "application/json": { "schema": { "type": "array", "items": { "$ref": "#/components/schemas/CatalogueItem" } } },
+                                "application/xml": { "schema": { "type": "string" } },
+                            },
+                        },
+                        "304": { "description": "The manifest hasn't changed since the one with the given ETag" },
+                        "400": { "description": "The format is unknown" },
+                    },
+                },
+            },
+            "/catalogue": {
+                "get": {
+                    "summary": "The directory tree of the library without per-viewer annotations",
+                    "tags": ["Library"],
+                    "responses": {
+                        "200": json_response("The directory tree", json!({ "type": "array", "items": { "$ref": "#/components/schemas/CatalogueItem" } })),
+                    },
+                },
+            },
+            "/file/{path}": {
+                "get": {
+                    "summary": "Streams a video, text track or thumbnail",
+                    "tags": ["Library"],
+                    "parameters": [
+                        path_parameter("path", "The path of the file within the library"),
+                        header_parameter("Range", "The byte ranges to stream"),
+                        header_parameter("If-Range", "The ETag or Last-Modified date the ranges are only valid for, streaming the whole file otherwise"),
+                        header_parameter("If-None-Match", "The ETag of a copy the client already has"),
+                        header_parameter("If-Modified-Since", "The Last-Modified date of a copy the client already has"),
+                        query_parameter("exp", "The expiry of a signed link", json!({ "type": "integer" })),
+                        query_parameter("sig", "The signature of a signed link", json!({ "type": "string" })),
+                    ],
+                    "responses": {
+                        "200": { "description": "The whole file" },
+                        "206": { "description": "The requested range of the file, or multipart/byteranges when several were requested" },
+                        "304": { "description": "The file hasn't changed since the client's copy" },
+                        "403": { "description": "The link is unsigned or has expired" },
+                        "404": { "description": "There's no such file or the viewer can't see it" },
+                        "416": { "description": "The range is outside of the file" },
+                    },
+                },
+            },
+            "/hls/{path}/playlist.m3u8": {
+                "get": {
+                    "summary": "Lists the HLS segments of a video when the server has ffmpeg",
+                    "tags": ["Library"],
+                    "parameters": [path_parameter("path", "The path of the video within the library")],
+                    "responses": {
+                        "200": { "description": "The VOD playlist of MPEG-TS segments, carrying over the query of the request" },
+                        "404": { "description": "There's no such video, the viewer can't see it or HLS is off" },
+                    },
+                },
+            },
+            "/hls/{path}/segment-{index}.ts": {
+                "get": {
+                    "summary": "Streams one HLS segment of a video encoded into MPEG-TS",
+                    "tags": ["Library"],
+                    "parameters": [
+                        path_parameter("path", "The path of the video within the library"),
+                        path_parameter("index", "The number of the segment, starting from 0"),
+                    ],
+                    "responses": {
+                        "200": { "description": "The segment" },
+                        "404": { "description": "There's no such video or segment, or the video can't be segmented" },
+                    },
+                },
+            },
+            "/thumb/{path}/{name}": {
+                "get": {
+                    "summary": "A poster or scrub thumbnail extracted from a video with ffmpeg, when thumbnails are enabled",
+                    "tags": ["Library"],
+                    "parameters": [
+                        path_parameter("path", "The path of the video within the library"),
+                        path_parameter("name", "poster.jpg or scrub-{index}.jpg"),
+                        query_parameter("width", "The most pixels wide to scale the thumbnail to, 480 by default", json!({ "type": "integer", "maximum": 1920 })),
+                    ],
+                    "responses": {
+                        "200": { "description": "The thumbnail", "content": { "image/jpeg": {} } },
+                        "400": { "description": "The width is invalid" },
+                        "404": { "description": "There's no such video or thumbnail, or the viewer can't see it" },
+                    },
+                },
+            },
+            "/transcode/{path}": {
+                "get": {
+                    "summary": "Streams a video transcoded by ffmpeg into fragmented MP4 for clients that can't play it as it is",
+                    "tags": ["Library"],
+                    "parameters": [
+                        path_parameter("path", "The path of the video within the library"),
+                        query_parameter("profile", "The transcoding profile, needed when there's more than one", json!({ "type": "string" })),
+                        query_parameter("start", "The position to start from", json!({ "type": "number", "description": "Seconds" })),
+                        query_parameter("session", "The client's transcoding session, replacing its previous transcode, the client address by default", json!({ "type": "string" })),
+                    ],
+                    "responses": {
+                        "200": { "description": "The transcoded video" },
+                        "400": { "description": "The profile or start is invalid" },
+                        "404": { "description": "There's no such video, the viewer can't see it or transcoding is off" },
+                        "503": { "description": "Too many videos are being transcoded" },
+                    },
+                },
+            },
+            "/transcodes": {
+                "get": {
+                    "summary": "The transcoding profiles and the viewer's running transcodes",
+                    "tags": ["Library"],
+                    "responses": {
+                        "200": json_response("The profiles and sessions", json!({
+                            "type": "object",
+                            "properties": {
+                                "profiles": { "type": "object", "additionalProperties": { "type": "object" } },
+                                "sessions": { "type": "array", "items": { "type": "object" } },
+                            },
+                        })),
+                    },
+                },
+            },
+            "/transcodes/{session}": {
+                "delete": {
+                    "summary": "Stops one of the viewer's transcodes",
+                    "tags": ["Library"],
+                    "parameters": [path_parameter("session", "The transcoding session")],
+                    "responses": {
+                        "204": { "description": "The transcode got stopped" },
+                        "404": { "description": "There's no such transcode" },
+                    },
+                },
+            },
+            "/items": {
+                "get": {
+                    "summary": "Searches the library's videos",
+                    "tags": ["Search"],
+                    "parameters": [
+                        query_parameter("sort", "The order to list the videos in", json!({ "type": "string", "enum": ["title", "play-count", "last-played", "rating"] })),
+                        query_parameter("order", "The direction of the order", json!({ "type": "string", "enum": ["asc", "desc"] })),
+                        query_parameter("not-played-for", "Only the videos not played for this many days", json!({ "type": "integer" })),
+                        query_parameter("limit", "The most videos to list", json!({ "type": "integer" })),
+                    ],
+                    "responses": {
+                        "200": json_response("The matching videos", json!({ "type": "array", "items": { "$ref": "#/components/schemas/Item" } })),
+                        "400": { "description": "The query is invalid" },
+                    },
+                },
+            },
+            "/item/{id}": {
+                "get": {
+                    "summary": "A single video with the viewer's progress, bookmarks and rating",
+                    "tags": ["Search"],
+                    "parameters": [path_parameter("id", "The ID of the video")],
+                    "responses": {
+                        "200": json_response("The video", json!({ "$ref": "#/components/schemas/Item" })),
+                        "404": { "description": "There's no such video or the viewer can't see it" },
+                    },
+                },
+            },
+            "/progress": {
+                "get": {
+                    "summary": "The viewer's progress and watched state for every video they've started or watched, most recent first",
+                    "tags": ["Progress"],
+                    "responses": {
+                        "200": json_response("The progress", json!({
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": { "type": "string" },
+                                    "progress": { "allOf": [{ "$ref": "#/components/schemas/Progress" }], "nullable": true },
+                                    "watched": { "type": "boolean" },
+                                },
+                            },
+                        })),
+                    },
+                },
+            },
+            "/progress/{id}": {
+                "get": {
+                    "summary": "The viewer's playback position in a video",
+                    "tags": ["Progress"],
+                    "parameters": [path_parameter("id", "The ID of the video")],
+                    "responses": {
+                        "200": json_response("The progress", json!({ "$ref": "#/components/schemas/Progress" })),
+                        "404": { "description": "There's no such video, the viewer can't see it or hasn't started it" },
+                    },
+                },
+                "post": {
+                    "summary": "Records the viewer's playback position in a video, marking it watched once it's nearly finished",
+                    "tags": ["Progress"],
+                    "parameters": [path_parameter("id", "The ID of the video")],
+                    "requestBody": json_body(json!({ "$ref": "#/components/schemas/Progress" })),
+                    "responses": {
+                        "200": json_response("The stored progress", json!({ "$ref": "#/components/schemas/Progress" })),
+                        "400": { "description": "The progress is invalid" },
+                        "404": { "description": "There's no such video or the viewer can't see it" },
+                        "409": json_response(
+                            "The sequence is older than the stored one, which is returned instead",
+                            json!({ "$ref": "#/components/schemas/Progress" }),
+                        ),
+                    },
+                },
+            },
+            "/cast/{id}": {
+                "get": {
+                    "summary": "A Google Cast load request for a video, with its text tracks, artwork and resume position",
+                    "tags": ["Search"],
+                    "parameters": [
+                        path_parameter("id", "The ID of the video"),
+                        query_parameter("token", "The profile token to put into the media links for the receiver", json!({ "type": "string" })),
+                    ],
+                    "responses": {
+                        "200": json_response("The load request", json!({
+                            "type": "object",
+                            "properties": {
+                                "media": { "type": "object" },
+                                "autoplay": { "type": "boolean" },
+                                "currentTime": { "type": "number", "description": "Seconds" },
+                                "activeTrackIds": { "type": "array", "items": { "type": "integer" } },
+                            },
+                        })),
+                        "400": { "description": "The query is invalid" },
+                        "404": { "description": "There's no such video or the viewer can't see it" },
+                    },
+                },
+            },
+            "/health": {
+                "get": {
+                    "summary": "Whether the server is up, whether an update is available and how the mounted sources fare",
+                    "tags": ["Server"],
+                    "security": [{}],
+                    "responses": {
+                        "200": json_response("The health of the server", json!({
+                            "type": "object",
+                            "properties": {
+                                "status": { "type": "string", "enum": ["ok"] },
+                                "version": { "type": "string" },
+                                "update-available": { "type": "string" },
+                                "mounts": {
+                                    "type": "array",
+                                    "items": {
+                                        "type": "object",
+                                        "properties": {
+                                            "name": { "type": "string" },
+                                            "type": { "type": "string", "enum": ["s3", "smb", "nfs", "folder"] },
+                                            "status": { "type": "string", "enum": ["ok", "unavailable", "unknown"] },
+                                            "checked": { "type": "integer" },
+                                            "error": { "type": "string" },
+                                        },
+                                    },
+                                },
+                            },
+                        })),
+                    },
+                },
+            },
+            "/login": {
+                "post": {
+                    "summary": "Exchanges an account's credentials for a login token",
+                    "tags": ["Server"],
+                    "security": [{}],
+                    "requestBody": json_body(json!({
+                        "type": "object",
+                        "required": ["username", "password"],
+                        "properties": { "username": { "type": "string" }, "password": { "type": "string" } },
+                    })),
+                    "responses": {
+                        "200": json_response("The login token", json!({
+                            "type": "object",
+                            "properties": {
+                                "token": { "type": "string" },
+                                "role": { "type": "string", "enum": ["viewer", "editor", "admin"] },
+                                "profile": { "type": "string" },
+                                "expires": { "type": "integer" },
+                            },
+                        })),
+                        "401": { "description": "The username or the password is wrong" },
+                        "429": { "description": "Too many failed attempts" },
+                    },
+                },
+            },
+            "/admin/scans": {
+                "get": admin_operation("The history of library scans", json!({ "type": "array", "items": { "type": "object" } })),
+            },
+            "/admin/missing": {
+                "get": admin_operation("The videos that disappeared from the library", json!({ "type": "array", "items": { "$ref": "#/components/schemas/Item" } })),
+            },
+            "/admin/export-state": {
+                "get": admin_operation("A snapshot of every profile's state", json!({ "type": "object" })),
+            },
+            "/admin/import-state": {
+                "post": {
+                    "summary": "Replaces the state with a snapshot",
+                    "tags": ["Admin"],
+                    "security": [{ "adminToken": [] }, { "session": [] }],
+                    "requestBody": json_body(json!({ "type": "object" })),
+                    "responses": {
+                        "204": { "description": "The state got imported" },
+                        "400": { "description": "The snapshot is invalid" },
+                        "403": { "description": "An admin token is needed" },
+                    },
+                },
+            },
+            "/admin/tokens": {
+                "get": admin_operation("The API tokens", json!({ "type": "array", "items": { "type": "object" } })),
+                "post": {
+                    "summary": "Creates an API token",
+                    "tags": ["Admin"],
+                    "security": [{ "adminToken": [] }, { "session": [] }],
+                    "requestBody": json_body(json!({
+                        "type": "object",
+                        "required": ["name"],
+                        "properties": { "name": { "type": "string" }, "profile": { "type": "string" } },
+                    })),
+                    "responses": {
+                        "201": json_response("The token, shown only once", json!({ "type": "object" })),
+                        "403": { "description": "An admin token is needed" },
+                    },
+                },
+            },
+            "/admin/tokens/{id}": {
+                "delete": {
+                    "summary": "Revokes an API token",
+                    "tags": ["Admin"],
+                    "security": [{ "adminToken": [] }, { "session": [] }],
+                    "parameters": [path_parameter("id", "The ID of the token")],
+                    "responses": {
+                        "204": { "description": "The token got revoked" },
+                        "403": { "description": "An admin token is needed" },
+                        "404": { "description": "There's no such token" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn components() -> Value {
+    json!({
+        "securitySchemes": {
+            "session": { "type": "http", "scheme": "bearer", "description": "A session token, a login token or an API key" },
+            "queryToken": { "type": "apiKey", "in": "query", "name": "token", "description": "A session token or an API key for players that can't send headers" },
+            "profileToken": { "type": "apiKey", "in": "header", "name": "X-Profile-Token" },
+            "adminToken": { "type": "apiKey", "in": "header", "name": "X-Admin-Token" },
+            "basic": { "type": "http", "scheme": "basic" },
+        },
+        "schemas": {
+            "CatalogueItem": {
+                "oneOf": [
+                    { "$ref": "#/components/schemas/Directory" },
+                    { "$ref": "#/components/schemas/Series" },
+                    { "$ref": "#/components/schemas/Season" },
+                    { "$ref": "#/components/schemas/Item" },
+                ],
+                "discriminator": { "propertyName": "type" },
+            },
+            "Directory": {
+                "type": "object",
+                "required": ["type", "title", "contents"],
+                "properties": {
+                    "type": { "type": "string", "enum": ["directory"] },
+                    "title": { "type": "string" },
+                    "contents": { "type": "array", "items": { "$ref": "#/components/schemas/CatalogueItem" } },
+                },
+            },
+            "Series": {
+                "type": "object",
+                "required": ["type", "title", "contents"],
+                "properties": {
+                    "type": { "type": "string", "enum": ["series"] },
+                    "title": { "type": "string" },
+                    "contents": { "type": "array", "items": { "$ref": "#/components/schemas/Season" } },
+                },
+            },
+            "Season": {
+                "type": "object",
+                "required": ["type", "title", "season", "contents"],
+                "properties": {
+                    "type": { "type": "string", "enum": ["season"] },
+                    "title": { "type": "string" },
+                    "season": { "type": "integer" },
+                    "contents": { "type": "array", "items": { "$ref": "#/components/schemas/Item" } },
+                },
+            },
+            "Progress": {
+                "type": "object",
+                "required": ["position", "duration", "player-id"],
+                "properties": {
+                    "position": { "type": "integer", "description": "Milliseconds" },
+                    "duration": { "type": "integer", "description": "Milliseconds" },
+                    "player-id": { "type": "string" },
+                    "updated": { "type": "integer", "readOnly": true },
+                    "sequence": {
+                        "type": "integer",
+                        "description": "Bumped on every stored report; send the last one seen to have the report rejected if another device has moved on since",
+                    },
+                },
+            },
+            "Item": {
+                "type": "object",
+                "required": ["type", "id", "path", "title"],
+                "properties": {
+                    "type": { "type": "string", "enum": ["file"] },
+                    "id": { "type": "string" },
+                    "path": { "type": "string" },
+                    "title": { "type": "string" },
+                    "subtitle": { "type": "string", "nullable": true },
+                    "duration": { "type": "integer", "description": "Milliseconds, left out when it's unknown" },
+                    "text-tracks": { "type": "object", "additionalProperties": { "type": "string" } },
+                    "thumbnails": { "type": "array", "items": { "type": "string" } },
+                    "generated-thumbnails": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "The poster and then the scrub thumbnails extracted from the video, served under /thumb",
+                    },
+                    "genres": { "type": "array", "items": { "type": "string" } },
+                    "episode": {
+                        "type": "object",
+                        "properties": { "show": { "type": "string" }, "season": { "type": "integer" }, "episode": { "type": "integer" } },
+                    },
+                    "width": { "type": "integer" },
+                    "height": { "type": "integer" },
+                    "video-codec": { "type": "string" },
+                    "watched": { "type": "boolean" },
+                    "favorite": { "type": "boolean" },
+                    "resume-position": { "type": "integer", "nullable": true },
+                },
+            },
+        },
+    })
+}
+
+fn admin_operation(summary: &str, schema: Value) -> Value {
+    json!({
+        "summary": summary,
+        "tags": ["Admin"],
+        "security": [{ "adminToken": [] }, { "session": [] }],
+        "responses": {
+            "200": json_response(summary, schema),
+            "403": { "description": "An admin token is needed" },
+        },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "description": description, "schema": { "type": "string" } })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "description": description, "schema": schema })
+}
+
+fn header_parameter(name: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "header", "description": description, "schema": { "type": "string" } })
+}
\ No newline at end of file
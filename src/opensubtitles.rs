@@ -0,0 +1,240 @@
+use std::{
+    error,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use hyper::{body, client::HttpConnector, header, Body, Client, Method, Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::config::OpenSubtitlesSettings;
+use crate::manifest;
+use crate::scanner::language_track_path;
+use crate::server::State;
+use crate::state;
+use crate::update;
+
+const API_URL: &str = "https://api.opensubtitles.com/api/v1";
+const USER_AGENT: &str = concat!("movie-nexus v", env!("CARGO_PKG_VERSION"));
+const HASH_CHUNK_SIZE: u64 = 64 * 1024;
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+const MISS_RETRY_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+type Error = Box<dyn error::Error + Send + Sync>;
+type Candidate = (String, PathBuf, Vec<String>);
+
+enum Fetched {
+    Saved,
+    Unavailable,
+    OutOfQuota,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Search {
+    data: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    attributes: SubtitleAttributes,
+}
+
+#[derive(Deserialize)]
+struct SubtitleAttributes {
+    #[serde(default)]
+    moviehash_match: bool,
+    files: Vec<SubtitleFile>,
+}
+
+#[derive(Deserialize)]
+struct SubtitleFile {
+    file_id: u64,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    link: String,
+}
+
+pub struct OpenSubtitles {
+    settings: OpenSubtitlesSettings,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl OpenSubtitles {
+    pub fn new(settings: &OpenSubtitlesSettings) -> OpenSubtitles {
+        OpenSubtitles { settings: settings.clone(), client: Client::builder().build(HttpsConnector::new()) }
+    }
+
+    pub async fn fetch_missing(&self, state: &State, folder: &Path) -> usize {
+        let candidates = match missing_tracks(state, folder, &self.settings.languages) {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                warn!("Couldn't list the videos missing subtitles: {}", err);
+                return 0;
+            }
+        };
+        let missing = tokio::task::spawn_blocking(move || absent_tracks(candidates)).await.unwrap_or_default();
+        if missing.is_empty() {
+            return 0;
+        }
+
+        let token = match (&self.settings.username, &self.settings.password) {
+            (Some(username), Some(password)) => match self.login(username, password).await {
+                Ok(token) => Some(token),
+                Err(err) => {
+                    warn!("Couldn't log in to OpenSubtitles, downloading anonymously: {}", err);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let mut saved = 0;
+        for (id, video, language) in missing {
+            match self.fetch(&video, &language, token.as_deref()).await {
+                Ok(Fetched::Saved) => {
+                    info!("Fetched {} subtitles for {}", language, video.display());
+                    saved += 1;
+                }
+                Ok(Fetched::Unavailable) => {
+                    debug!("OpenSubtitles has no {} subtitles for {}", language, video.display());
+                    let retry_after = state::now() + MISS_RETRY_INTERVAL.as_secs();
+                    if let Err(err) = state.user_state.record_subtitle_miss(&id, &language, retry_after) {
+                        warn!("Couldn't remember that {} has no {} subtitles: {}", video.display(), language, err);
+                    }
+                }
+                Ok(Fetched::OutOfQuota) => {
+                    info!("Reached the OpenSubtitles download quota, the rest of the subtitles will be fetched on the next start");
+                    break;
+                }
+                Err(err) => warn!("Couldn't fetch {} subtitles for {}: {}", language, video.display(), err),
+            }
+            tokio::time::sleep(REQUEST_INTERVAL).await;
+        }
+        saved
+    }
+
+    async fn login(&self, username: &str, password: &str) -> Result<String, Error> {
+        let (status, bytes) = self.request(Method::POST, "/login", None, Some(json!({ "username": username, "password": password }))).await?;
+        if status != StatusCode::OK {
+            return Err(format!("OpenSubtitles responded with {}", status).into());
+        }
+        Ok(serde_json::from_slice::<Login>(&bytes)?.token)
+    }
+
+    async fn fetch(&self, video: &Path, language: &str, token: Option<&str>) -> Result<Fetched, Error> {
+        let hash = {
+            let video = video.to_path_buf();
+            tokio::task::spawn_blocking(move || movie_hash(&video)).await??
+        };
+
+        let query = format!("/subtitles?languages={}&moviehash={}", language.to_lowercase(), hash);
+        let (status, bytes) = self.request(Method::GET, &query, token, None).await?;
+        if status != StatusCode::OK {
+            return Err(format!("OpenSubtitles responded with {}", status).into());
+        }
+        let search = serde_json::from_slice::<Search>(&bytes)?;
+        let file_id = match search.data.iter().filter(|result| result.attributes.moviehash_match).flat_map(|result| &result.attributes.files).next() {
+            Some(file) => file.file_id,
+            None => return Ok(Fetched::Unavailable),
+        };
+
+        let (status, bytes) = self.request(Method::POST, "/download", token, Some(json!({ "file_id": file_id, "sub_format": "webvtt" }))).await?;
+        match status {
+            StatusCode::OK => {}
+            StatusCode::NOT_ACCEPTABLE => return Ok(Fetched::OutOfQuota),
+            status => return Err(format!("OpenSubtitles refused the download with {}", status).into()),
+        }
+        let download = serde_json::from_slice::<Download>(&bytes)?;
+
+        let subtitles = update::fetch(&download.link, "text/vtt").await?;
+        tokio::fs::write(language_track_path(video, language), &subtitles).await?;
+        Ok(Fetched::Saved)
+    }
+
+    async fn request(&self, method: Method, path: &str, token: Option<&str>, body: Option<Value>) -> Result<(StatusCode, body::Bytes), Error> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", API_URL, path))
+            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::ACCEPT, "application/json")
+            .header("Api-Key", &self.settings.api_key);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        let response = self.client.request(request.body(body)?).await?;
+        let status = response.status();
+        Ok((status, body::to_bytes(response.into_body()).await?))
+    }
+}
+
+fn movie_hash(path: &Path) -> Result<String, io::Error> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hash = size;
+    for offset in [0, size.saturating_sub(HASH_CHUNK_SIZE)] {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut chunk = Vec::with_capacity(HASH_CHUNK_SIZE as usize);
+        file.by_ref().take(HASH_CHUNK_SIZE).read_to_end(&mut chunk)?;
+        for word in chunk.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..word.len()].copy_from_slice(word);
+            hash = hash.wrapping_add(u64::from_le_bytes(bytes));
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+fn missing_tracks(state: &State, folder: &Path, languages: &[String]) -> Result<Vec<Candidate>, Box<dyn error::Error>> {
+    let mut manifest: Value = serde_json::from_str(&state.store.manifest()?)?;
+    let misses = state.user_state.subtitle_misses()?;
+    let now = state::now();
+
+    let mut candidates = Vec::new();
+    manifest::for_each_file(&mut manifest, &mut |file| {
+        let (id, path) = match (file.get("id").and_then(Value::as_str), file.get("path").and_then(Value::as_str)) {
+            (Some(id), Some(path)) => (id, path),
+            _ => return,
+        };
+        let tracks = file.get("text-tracks").and_then(Value::as_object);
+        let missing: Vec<_> = languages.iter()
+            .filter(|language| !tracks.is_some_and(|tracks| tracks.contains_key(*language)))
+            .filter(|language| misses.get(id).and_then(|misses| misses.get(*language)).is_none_or(|retry_after| *retry_after <= now))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            candidates.push((id.to_string(), folder.join(path), missing));
+        }
+    });
+    Ok(candidates)
+}
+
+fn absent_tracks(candidates: Vec<Candidate>) -> Vec<(String, PathBuf, String)> {
+    let mut absent = Vec::new();
+    for (id, video, languages) in candidates.into_iter().filter(|(_, video, _)| video.is_file()) {
+        for language in languages.into_iter().filter(|language| !language_track_path(&video, language).exists()) {
+            absent.push((id.clone(), video.clone(), language));
+        }
+    }
+    absent
+}
\ No newline at end of file
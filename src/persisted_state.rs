@@ -0,0 +1,507 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use redb::{Database, ReadableTable, TableDefinition, TableError};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::state::{
+    Bookmark, MemoryState, MetadataOverride, PlayStats, Playlist, Progress, ProgressUpdate, RatingSummary, ScanRecord, Session, StateDocument, StateSnapshot,
+    StateStore, TraktTokens, ViewingLog,
+};
+
+const STATE_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("state");
+const DOCUMENT_KEY: &str = "document";
+
+pub const JOURNAL_EXTENSION: &str = "journal";
+pub const TEMP_EXTENSION: &str = "tmp";
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const SNAPSHOT_JOURNAL_ENTRIES: u64 = 1000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum StateChange {
+    Progress { profile: String, id: String, progress: Progress },
+    Watched { profile: String, id: String, watched: bool },
+    Play { profile: String, id: String, at: u64 },
+    Playlist { playlist: Playlist },
+    PlaylistDeleted { id: u64 },
+    Favorite { profile: String, id: String, favorite: bool },
+    Bookmark { profile: String, id: String, bookmark: Bookmark },
+    BookmarkDeleted { profile: String, id: String, bookmark: u64 },
+    Rating { profile: String, id: String, rating: Option<u8> },
+    Viewing { profile: String, id: String, at: u64, milliseconds: u64 },
+    Session { token: String, session: Session },
+    SessionTouched { token: String, at: u64 },
+    SessionDeleted { profile: String, id: String },
+    TraktTokens { profile: String, tokens: Option<TraktTokens> },
+    SubtitleMiss { id: String, language: String, retry_after: u64 },
+    MetadataOverride { id: String, metadata: Option<MetadataOverride> },
+    ItemsForgotten { ids: Vec<String> },
+    Scan { scan: ScanRecord },
+    Imported { snapshot: StateSnapshot },
+}
+
+impl StateChange {
+    fn apply(self, memory: &MemoryState) -> Result<(), Box<dyn error::Error>> {
+        match self {
+            StateChange::Progress { profile, id, progress } => {
+                memory.set_progress(&profile, &id, &progress, None)?;
+            }
+            StateChange::Watched { profile, id, watched } => memory.set_watched(&profile, &id, watched)?,
+            StateChange::Play { profile, id, at } => memory.record_play(&profile, &id, at)?,
+            StateChange::Playlist { playlist } => memory.put_playlist(&playlist),
+            StateChange::PlaylistDeleted { id } => {
+                memory.delete_playlist(id)?;
+            }
+            StateChange::Favorite { profile, id, favorite } => memory.set_favorite(&profile, &id, favorite)?,
+            StateChange::Bookmark { profile, id, bookmark } => memory.put_bookmark(&profile, &id, &bookmark),
+            StateChange::BookmarkDeleted { profile, id, bookmark } => {
+                memory.delete_bookmark(&profile, &id, bookmark)?;
+            }
+            StateChange::Rating { profile, id, rating } => memory.set_rating(&profile, &id, rating)?,
+            StateChange::Viewing { profile, id, at, milliseconds } => memory.record_viewing(&profile, &id, at, milliseconds)?,
+            StateChange::Session { token, session } => memory.put_session(&Session { token, ..session }),
+            StateChange::SessionTouched { token, at } => memory.touch_session(&token, at)?,
+            StateChange::SessionDeleted { profile, id } => {
+                memory.delete_session(&profile, &id)?;
+            }
+            StateChange::TraktTokens { profile, tokens } => memory.set_trakt_tokens(&profile, tokens.as_ref())?,
+            StateChange::SubtitleMiss { id, language, retry_after } => memory.record_subtitle_miss(&id, &language, retry_after)?,
+            StateChange::MetadataOverride { id, metadata } => {
+                memory.set_metadata_override(&id, metadata.as_ref())?;
+            }
+            StateChange::ItemsForgotten { ids } => memory.forget_items(&ids)?,
+            StateChange::Scan { scan } => {
+                memory.record_scan(&scan)?;
+            }
+            StateChange::Imported { snapshot } => memory.import_state(&snapshot)?,
+        }
+        Ok(())
+    }
+}
+
+pub trait StateFile: Send + Sync {
+    fn load(&self) -> Result<MemoryState, Box<dyn error::Error>>;
+    fn record(&self, change: &StateChange, memory: &MemoryState) -> Result<(), Box<dyn error::Error>>;
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Snapshot<D> {
+    #[serde(flatten)]
+    document: D,
+    #[serde(default)]
+    journal_serial: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry<C> {
+    serial: u64,
+    #[serde(flatten)]
+    change: C,
+}
+
+struct Journal {
+    file: Option<File>,
+    serial: u64,
+    entries: u64,
+    snapshotted: Instant,
+}
+
+pub struct JsonFile {
+    path: PathBuf,
+    journal_path: PathBuf,
+    journal: Mutex<Journal>,
+}
+
+impl JsonFile {
+    pub fn new(path: &Path) -> JsonFile {
+        JsonFile {
+            path: path.to_path_buf(),
+            journal_path: path.with_extension(JOURNAL_EXTENSION),
+            journal: Mutex::new(Journal { file: None, serial: 0, entries: 0, snapshotted: Instant::now() }),
+        }
+    }
+
+    fn replay_journal(&self, memory: &MemoryState, mut serial: u64) -> Result<u64, Box<dyn error::Error>> {
+        let file = match File::open(&self.journal_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(serial),
+            Err(err) => return Err(format!("Can't read the state journal {}: {}", self.journal_path.display(), err).into()),
+        };
+
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str::<JournalEntry<StateChange>>(&line?) {
+                Ok(entry) if entry.serial > serial => {
+                    entry.change.apply(memory)?;
+                    serial = entry.serial;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Ignoring the rest of the state journal {}: {}", self.journal_path.display(), err);
+                    break;
+                }
+            }
+        }
+        Ok(serial)
+    }
+
+    fn snapshot(&self, journal: &mut Journal, memory: &MemoryState) -> Result<(), Box<dyn error::Error>> {
+        let snapshot = serde_json::to_vec(&Snapshot { document: &memory.document()?, journal_serial: journal.serial })?;
+
+        let temp_path = self.path.with_extension(TEMP_EXTENSION);
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&snapshot)?;
+        file.sync_all()?;
+        replace_durably(&temp_path, &self.path)?;
+
+        journal.file = Some(File::create(&self.journal_path)?);
+        journal.entries = 0;
+        journal.snapshotted = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn replace_durably(from: &Path, to: &Path) -> Result<(), Box<dyn error::Error>> {
+    use crate::bindings::windows::win32::file_system::MoveFileExW;
+    use crate::win32::wide;
+
+    const MOVEFILE_REPLACE_EXISTING: u32 = 1;
+    const MOVEFILE_WRITE_THROUGH: u32 = 8;
+
+    let (from, to) = (wide(from), wide(to));
+    unsafe { MoveFileExW(from.as_ptr(), to.as_ptr(), MOVEFILE_REPLACE_EXISTING | MOVEFILE_WRITE_THROUGH) }.ok()?;
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn replace_durably(from: &Path, to: &Path) -> Result<(), Box<dyn error::Error>> {
+    fs::rename(from, to)?;
+    let directory = to.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+impl StateFile for JsonFile {
+    fn load(&self) -> Result<MemoryState, Box<dyn error::Error>> {
+        let snapshot: Option<Snapshot<StateDocument>> = match fs::read(&self.path) {
+            Ok(bytes) => Some(serde_json::from_slice(&bytes).map_err(|err| format!("Invalid state file {}: {}", self.path.display(), err))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(format!("Can't read the state file {}: {}", self.path.display(), err).into()),
+        };
+
+        let (memory, serial) = match snapshot {
+            Some(snapshot) => (MemoryState::from_document(snapshot.document)?, snapshot.journal_serial),
+            None => (MemoryState::default(), 0),
+        };
+
+        let mut journal = self.journal.lock().unwrap();
+        journal.serial = self.replay_journal(&memory, serial)?;
+        self.snapshot(&mut journal, &memory)?;
+        Ok(memory)
+    }
+
+    fn record(&self, change: &StateChange, memory: &MemoryState) -> Result<(), Box<dyn error::Error>> {
+        let mut journal = self.journal.lock().unwrap();
+        let serial = journal.serial + 1;
+
+        let mut line = serde_json::to_vec(&JournalEntry { serial, change })?;
+        line.push(b'\n');
+
+        let file = journal.file.as_mut().ok_or("The state journal isn't open")?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+
+        journal.serial = serial;
+        journal.entries += 1;
+        if journal.entries >= SNAPSHOT_JOURNAL_ENTRIES || journal.snapshotted.elapsed() >= SNAPSHOT_INTERVAL {
+            self.snapshot(&mut journal, memory)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct RedbFile {
+    database: Database,
+}
+
+impl RedbFile {
+    pub fn open(path: &Path) -> Result<RedbFile, Box<dyn error::Error>> {
+        Ok(RedbFile { database: Database::create(path)? })
+    }
+}
+
+impl StateFile for RedbFile {
+    fn load(&self) -> Result<MemoryState, Box<dyn error::Error>> {
+        let transaction = self.database.begin_read()?;
+        let table = match transaction.open_table(STATE_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(MemoryState::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let document = match table.get(DOCUMENT_KEY)? {
+            Some(document) => serde_json::from_slice(document.value())?,
+            None => return Ok(MemoryState::default()),
+        };
+        MemoryState::from_document(document)
+    }
+
+    fn record(&self, _change: &StateChange, memory: &MemoryState) -> Result<(), Box<dyn error::Error>> {
+        let transaction = self.database.begin_write()?;
+        transaction.open_table(STATE_TABLE)?.insert(DOCUMENT_KEY, serde_json::to_vec(&memory.document()?)?.as_slice())?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+pub struct PersistedState<F> {
+    memory: MemoryState,
+    file: F,
+    change_lock: Mutex<()>,
+}
+
+impl<F: StateFile> PersistedState<F> {
+    pub fn open(file: F) -> Result<PersistedState<F>, Box<dyn error::Error>> {
+        Ok(PersistedState { memory: file.load()?, file, change_lock: Mutex::new(()) })
+    }
+
+    fn change<T>(&self, change: impl FnOnce(&MemoryState) -> Result<(T, Option<StateChange>), Box<dyn error::Error>>) -> Result<T, Box<dyn error::Error>> {
+        let _guard = self.change_lock.lock().unwrap();
+        let (result, change) = change(&self.memory)?;
+        if let Some(change) = change {
+            self.file.record(&change, &self.memory)?;
+        }
+        Ok(result)
+    }
+}
+
+impl<F: StateFile> StateStore for PersistedState<F> {
+    fn progress(&self, profile: &str, id: &str) -> Result<Option<Progress>, Box<dyn error::Error>> {
+        self.memory.progress(profile, id)
+    }
+
+    fn set_progress(&self, profile: &str, id: &str, progress: &Progress, base: Option<u64>) -> Result<ProgressUpdate, Box<dyn error::Error>> {
+        self.change(|memory| match memory.set_progress(profile, id, progress, base)? {
+            ProgressUpdate::Stored(progress) => Ok((
+                ProgressUpdate::Stored(progress.clone()),
+                Some(StateChange::Progress { profile: profile.to_string(), id: id.to_string(), progress }),
+            )),
+            stale => Ok((stale, None)),
+        })
+    }
+
+    fn recent_progress(&self, profile: &str) -> Result<Vec<(String, Progress)>, Box<dyn error::Error>> {
+        self.memory.recent_progress(profile)
+    }
+
+    fn watched(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        self.memory.watched(profile)
+    }
+
+    fn set_watched(&self, profile: &str, id: &str, watched: bool) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.set_watched(profile, id, watched)?;
+            Ok(((), Some(StateChange::Watched { profile: profile.to_string(), id: id.to_string(), watched })))
+        })
+    }
+
+    fn play_stats(&self, profile: &str) -> Result<HashMap<String, PlayStats>, Box<dyn error::Error>> {
+        self.memory.play_stats(profile)
+    }
+
+    fn record_play(&self, profile: &str, id: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.record_play(profile, id, at)?;
+            Ok(((), Some(StateChange::Play { profile: profile.to_string(), id: id.to_string(), at })))
+        })
+    }
+
+    fn playlists(&self) -> Result<Vec<Playlist>, Box<dyn error::Error>> {
+        self.memory.playlists()
+    }
+
+    fn create_playlist(&self, name: &str, items: &[String]) -> Result<Playlist, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let playlist = memory.create_playlist(name, items)?;
+            Ok((playlist.clone(), Some(StateChange::Playlist { playlist })))
+        })
+    }
+
+    fn update_playlist(&self, id: u64, name: Option<&str>, items: Option<&[String]>) -> Result<Option<Playlist>, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let playlist = memory.update_playlist(id, name, items)?;
+            let change = playlist.clone().map(|playlist| StateChange::Playlist { playlist });
+            Ok((playlist, change))
+        })
+    }
+
+    fn delete_playlist(&self, id: u64) -> Result<bool, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let deleted = memory.delete_playlist(id)?;
+            Ok((deleted, if deleted { Some(StateChange::PlaylistDeleted { id }) } else { None }))
+        })
+    }
+
+    fn favorites(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        self.memory.favorites(profile)
+    }
+
+    fn set_favorite(&self, profile: &str, id: &str, favorite: bool) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.set_favorite(profile, id, favorite)?;
+            Ok(((), Some(StateChange::Favorite { profile: profile.to_string(), id: id.to_string(), favorite })))
+        })
+    }
+
+    fn bookmarks(&self, profile: &str, id: &str) -> Result<Vec<Bookmark>, Box<dyn error::Error>> {
+        self.memory.bookmarks(profile, id)
+    }
+
+    fn add_bookmark(&self, profile: &str, id: &str, position: u64, label: &str) -> Result<Bookmark, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let bookmark = memory.add_bookmark(profile, id, position, label)?;
+            Ok((bookmark.clone(), Some(StateChange::Bookmark { profile: profile.to_string(), id: id.to_string(), bookmark })))
+        })
+    }
+
+    fn delete_bookmark(&self, profile: &str, id: &str, bookmark_id: u64) -> Result<bool, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let deleted = memory.delete_bookmark(profile, id, bookmark_id)?;
+            let change = StateChange::BookmarkDeleted { profile: profile.to_string(), id: id.to_string(), bookmark: bookmark_id };
+            Ok((deleted, if deleted { Some(change) } else { None }))
+        })
+    }
+
+    fn ratings(&self, profile: &str) -> Result<HashMap<String, u8>, Box<dyn error::Error>> {
+        self.memory.ratings(profile)
+    }
+
+    fn set_rating(&self, profile: &str, id: &str, rating: Option<u8>) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.set_rating(profile, id, rating)?;
+            Ok(((), Some(StateChange::Rating { profile: profile.to_string(), id: id.to_string(), rating })))
+        })
+    }
+
+    fn rating_summaries(&self) -> Result<HashMap<String, RatingSummary>, Box<dyn error::Error>> {
+        self.memory.rating_summaries()
+    }
+
+    fn viewing(&self) -> Result<HashMap<String, ViewingLog>, Box<dyn error::Error>> {
+        self.memory.viewing()
+    }
+
+    fn record_viewing(&self, profile: &str, id: &str, at: u64, milliseconds: u64) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.record_viewing(profile, id, at, milliseconds)?;
+            Ok(((), Some(StateChange::Viewing { profile: profile.to_string(), id: id.to_string(), at, milliseconds })))
+        })
+    }
+
+    fn sessions(&self, profile: &str) -> Result<Vec<Session>, Box<dyn error::Error>> {
+        self.memory.sessions(profile)
+    }
+
+    fn session(&self, token: &str) -> Result<Option<Session>, Box<dyn error::Error>> {
+        self.memory.session(token)
+    }
+
+    fn create_session(&self, profile: &str, device: &str) -> Result<Session, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let session = memory.create_session(profile, device)?;
+            Ok((session.clone(), Some(StateChange::Session { token: session.token.clone(), session })))
+        })
+    }
+
+    fn touch_session(&self, token: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.touch_session(token, at)?;
+            Ok(((), Some(StateChange::SessionTouched { token: token.to_string(), at })))
+        })
+    }
+
+    fn delete_session(&self, profile: &str, id: &str) -> Result<bool, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let deleted = memory.delete_session(profile, id)?;
+            let change = StateChange::SessionDeleted { profile: profile.to_string(), id: id.to_string() };
+            Ok((deleted, if deleted { Some(change) } else { None }))
+        })
+    }
+
+    fn trakt_tokens(&self, profile: &str) -> Result<Option<TraktTokens>, Box<dyn error::Error>> {
+        self.memory.trakt_tokens(profile)
+    }
+
+    fn set_trakt_tokens(&self, profile: &str, tokens: Option<&TraktTokens>) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.set_trakt_tokens(profile, tokens)?;
+            Ok(((), Some(StateChange::TraktTokens { profile: profile.to_string(), tokens: tokens.cloned() })))
+        })
+    }
+
+    fn subtitle_misses(&self) -> Result<HashMap<String, HashMap<String, u64>>, Box<dyn error::Error>> {
+        self.memory.subtitle_misses()
+    }
+
+    fn record_subtitle_miss(&self, id: &str, language: &str, retry_after: u64) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.record_subtitle_miss(id, language, retry_after)?;
+            Ok(((), Some(StateChange::SubtitleMiss { id: id.to_string(), language: language.to_string(), retry_after })))
+        })
+    }
+
+    fn metadata_override(&self, id: &str) -> Result<Option<MetadataOverride>, Box<dyn error::Error>> {
+        self.memory.metadata_override(id)
+    }
+
+    fn metadata_overrides(&self) -> Result<HashMap<String, MetadataOverride>, Box<dyn error::Error>> {
+        self.memory.metadata_overrides()
+    }
+
+    fn set_metadata_override(&self, id: &str, metadata: Option<&MetadataOverride>) -> Result<bool, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let changed = memory.set_metadata_override(id, metadata)?;
+            let change = StateChange::MetadataOverride { id: id.to_string(), metadata: metadata.cloned() };
+            Ok((changed, if changed { Some(change) } else { None }))
+        })
+    }
+
+    fn forget_items(&self, ids: &[String]) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.forget_items(ids)?;
+            Ok(((), if ids.is_empty() { None } else { Some(StateChange::ItemsForgotten { ids: ids.to_vec() }) }))
+        })
+    }
+
+    fn scans(&self) -> Result<Vec<ScanRecord>, Box<dyn error::Error>> {
+        self.memory.scans()
+    }
+
+    fn record_scan(&self, scan: &ScanRecord) -> Result<ScanRecord, Box<dyn error::Error>> {
+        self.change(|memory| {
+            let scan = memory.record_scan(scan)?;
+            Ok((scan.clone(), Some(StateChange::Scan { scan })))
+        })
+    }
+
+    fn export_state(&self) -> Result<StateSnapshot, Box<dyn error::Error>> {
+        self.memory.export_state()
+    }
+
+    fn import_state(&self, snapshot: &StateSnapshot) -> Result<(), Box<dyn error::Error>> {
+        self.change(|memory| {
+            memory.import_state(snapshot)?;
+            Ok(((), Some(StateChange::Imported { snapshot: snapshot.clone() })))
+        })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::cache;
+
+const BOX_HEADER_LEN: u64 = 8;
+const LARGE_BOX_HEADER_LEN: u64 = 16;
+const MILLISECONDS_IN_SECOND: f64 = 1000.0;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Probed {
+    pub duration: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct CachedProbe {
+    len: u64,
+    modified: u64,
+    #[serde(flatten)]
+    probed: Probed,
+}
+
+pub struct Prober {
+    root_path: PathBuf,
+    ffprobe: Option<PathBuf>,
+    cache: Mutex<HashMap<String, CachedProbe>>,
+    changed: AtomicBool,
+}
+
+impl Prober {
+    pub fn open(root_path: &Path, ffprobe: Option<&Path>) -> Prober {
+        let cache = match cache::load_probes(root_path) {
+            Ok(cached) => cached.and_then(|cached| serde_json::from_str(&cached).ok()).unwrap_or_default(),
+            Err(err) => {
+                warn!("Couldn't load the cached probes: {}", err);
+                HashMap::new()
+            }
+        };
+        Prober { root_path: root_path.to_path_buf(), ffprobe: ffprobe.map(Path::to_path_buf), cache: Mutex::new(cache), changed: AtomicBool::new(false) }
+    }
+
+    pub fn probe(&self, key: &str, path: &Path) -> Result<Option<Probed>, io::Error> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |modified| modified.as_secs());
+        if let Some(cached) = self.cache.lock().unwrap().get(key) {
+            if cached.len == metadata.len() && cached.modified == modified {
+                return Ok(Some(cached.probed.clone()));
+            }
+        }
+
+        let probed = match self.ffprobe {
+            Some(ref ffprobe) => ffprobe_video(ffprobe, path)?,
+            None => mp4_duration(path)?.map(|duration| Probed {
+                duration: duration.as_millis() as u64,
+                width: None,
+                height: None,
+                video_codec: None,
+            }),
+        };
+        if let Some(ref probed) = probed {
+            let cached = CachedProbe { len: metadata.len(), modified, probed: probed.clone() };
+            self.cache.lock().unwrap().insert(key.to_string(), cached);
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        Ok(probed)
+    }
+
+    pub fn save(&self) {
+        if !self.changed.load(Ordering::Relaxed) {
+            return;
+        }
+        let saved = serde_json::to_string(&*self.cache.lock().unwrap())
+            .map_err(io::Error::from)
+            .and_then(|probes| cache::store_probes(&self.root_path, &probes));
+        match saved {
+            Ok(()) => self.changed.store(false, Ordering::Relaxed),
+            Err(err) => warn!("Couldn't cache the probes: {}", err),
+        }
+    }
+}
+
+fn ffprobe_video(ffprobe: &Path, path: &Path) -> Result<Option<Probed>, io::Error> {
+    let output = Command::new(ffprobe)
+        .args(["-v", "error", "-select_streams", "v:0", "-show_entries", "format=duration:stream=width,height,codec_name", "-of", "json"])
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(format!("ffprobe failed: {}", message)));
+    }
+
+    let probed: Value = serde_json::from_slice(&output.stdout)?;
+    let seconds = probed["format"]["duration"].as_str().and_then(|duration| duration.parse::<f64>().ok());
+    let seconds = match seconds {
+        Some(seconds) if seconds.is_finite() && seconds >= 0.0 => seconds,
+        _ => return Ok(None),
+    };
+    let stream = &probed["streams"][0];
+    Ok(Some(Probed {
+        duration: (seconds * MILLISECONDS_IN_SECOND) as u64,
+        width: stream["width"].as_u64().map(|width| width as u32),
+        height: stream["height"].as_u64().map(|height| height as u32),
+        video_codec: stream["codec_name"].as_str().map(str::to_string),
+    }))
+}
+
+pub fn mp4_duration(path: &Path) -> Result<Option<Duration>, io::Error> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let (moov_start, moov_end) = match find_box(&mut file, 0, file_len, b"moov")? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+    let (mvhd_start, _) = match find_box(&mut file, moov_start, moov_end, b"mvhd")? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    file.seek(SeekFrom::Start(mvhd_start))?;
+    let version = read_array::<4>(&mut file)?[0];
+    let (timescale, duration) = if version == 1 {
+        file.seek(SeekFrom::Current(16))?;
+        let timescale = u32::from_be_bytes(read_array(&mut file)?);
+        (timescale, u64::from_be_bytes(read_array(&mut file)?))
+    } else {
+        file.seek(SeekFrom::Current(8))?;
+        let timescale = u32::from_be_bytes(read_array(&mut file)?);
+        (timescale, u32::from_be_bytes(read_array(&mut file)?) as u64)
+    };
+
+    if timescale == 0 {
+        return Ok(None);
+    }
+    let milliseconds = duration as u128 * 1000 / timescale as u128;
+    Ok(Some(Duration::from_millis(milliseconds.try_into().unwrap_or(u64::MAX))))
+}
+
+fn find_box(file: &mut File, mut offset: u64, end: u64, box_type: &[u8; 4]) -> Result<Option<(u64, u64)>, io::Error> {
+    while offset + BOX_HEADER_LEN <= end {
+        file.seek(SeekFrom::Start(offset))?;
+        let header = read_array::<8>(file)?;
+
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (BOX_HEADER_LEN, end - offset),
+            1 => (LARGE_BOX_HEADER_LEN, u64::from_be_bytes(read_array(file)?)),
+            size => (BOX_HEADER_LEN, size as u64),
+        };
+        if size < header_len {
+            return Ok(None);
+        }
+
+        if &header[4..] == box_type {
+            return Ok(Some((offset + header_len, offset + size)));
+        }
+        offset += size;
+    }
+
+    Ok(None)
+}
+
+fn read_array<const N: usize>(file: &mut File) -> Result<[u8; N], io::Error> {
+    let mut buf = [0u8; N];
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
\ No newline at end of file
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, error, net::IpAddr};
+
+use hyper::HeaderMap;
+use ipnet::IpNet;
+use tracing::warn;
+
+use crate::access;
+use crate::config::TrustedProxy;
+
+const DEFAULT_HEADERS: [&str; 2] = ["X-Remote-User", "X-Forwarded-User"];
+
+pub struct ProxyAuth {
+    trusted_proxies: Vec<IpNet>,
+    headers: Vec<String>,
+    users: HashMap<String, String>,
+}
+
+impl ProxyAuth {
+    pub fn new(config: &TrustedProxy) -> Result<ProxyAuth, Box<dyn error::Error>> {
+        if config.trusted_proxies.is_empty() {
+            return Err("Proxy authentication needs at least one trusted proxy".into());
+        }
+
+        Ok(ProxyAuth {
+            trusted_proxies: access::parse_networks(&config.trusted_proxies)?,
+            headers: match config.header {
+                Some(ref header) => vec![header.clone()],
+                None => DEFAULT_HEADERS.iter().map(|header| header.to_string()).collect(),
+            },
+            users: config.users.clone(),
+        })
+    }
+
+    pub fn profile(&self, remote: Option<IpAddr>, headers: &HeaderMap) -> Result<Option<String>, String> {
+        let user = self.headers.iter().find_map(|name| headers.get(name.as_str()));
+        let user = match user {
+            Some(user) => user.to_str().map_err(|_| "Invalid remote user header".to_string())?.trim(),
+            None => return Ok(None),
+        };
+
+        let trusted = remote.is_some_and(|ip| self.trusted_proxies.iter().any(|network| network.contains(&ip.to_canonical())));
+        if !trusted {
+            let remote = remote.map_or_else(|| "an unknown address".to_string(), |ip| ip.to_string());
+            warn!("Refused a remote user header from {}, which isn't a trusted proxy", remote);
+            return Err("Remote user headers are only accepted from trusted proxies".to_string());
+        }
+        if user.is_empty() {
+            return Err("The remote user header is empty".to_string());
+        }
+
+        Ok(Some(self.users.get(user).cloned().unwrap_or_else(|| user.to_string())))
+    }
+}
\ No newline at end of file
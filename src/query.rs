@@ -0,0 +1,280 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+use crate::state::{PlayStats, RatingSummary};
+use crate::thumbnails;
+
+const SECONDS_IN_DAY: u64 = 24 * 60 * 60;
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+pub enum SortKey {
+    Title,
+    PlayCount,
+    LastPlayed,
+    Rating,
+}
+
+pub struct ItemQuery {
+    sort: SortKey,
+    descending: bool,
+    not_played_for: Option<u64>,
+    limit: Option<usize>,
+}
+
+impl ItemQuery {
+    pub fn parse(query: Option<&str>) -> Result<ItemQuery, String> {
+        let mut sort = SortKey::Title;
+        let mut order = None;
+        let mut not_played_for = None;
+        let mut limit = None;
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "sort" => sort = match value.as_ref() {
+                    "title" => SortKey::Title,
+                    "play-count" => SortKey::PlayCount,
+                    "last-played" => SortKey::LastPlayed,
+                    "rating" => SortKey::Rating,
+                    _ => return Err(format!("Unknown sort order {}", value)),
+                },
+                "order" => order = match value.as_ref() {
+                    "asc" => Some(false),
+                    "desc" => Some(true),
+                    _ => return Err(format!("Unknown order {}, expected asc or desc", value)),
+                },
+                "not-played-for" => not_played_for = Some(value.parse::<u64>().map_err(|_| format!("Invalid day count {}", value))?),
+                "limit" => limit = Some(value.parse::<usize>().map_err(|_| format!("Invalid limit {}", value))?),
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+
+        let descending = order.unwrap_or(!matches!(sort, SortKey::Title));
+        Ok(ItemQuery { sort, descending, not_played_for, limit })
+    }
+
+    pub fn apply(&self, items: Vec<Value>, plays: &HashMap<String, PlayStats>, ratings: &HashMap<String, RatingSummary>, now: u64) -> Vec<Value> {
+        let stats_of = |item: &Value| plays.get(id_of(item)).copied().unwrap_or_default();
+        let rating_of = |item: &Value| ratings.get(id_of(item)).map(|summary| summary.average);
+
+        let mut items: Vec<Value> = match self.not_played_for {
+            Some(days) => {
+                let cutoff = now.saturating_sub(days * SECONDS_IN_DAY);
+                items.into_iter().filter(|item| stats_of(item).last_played.is_none_or(|last_played| last_played < cutoff)).collect()
+            }
+            None => items,
+        };
+
+        items.sort_by(|a, b| {
+            let ordering = match self.sort {
+                SortKey::Title => title_of(a).cmp(title_of(b)),
+                SortKey::PlayCount => stats_of(a).play_count.cmp(&stats_of(b).play_count),
+                SortKey::LastPlayed => stats_of(a).last_played.cmp(&stats_of(b).last_played),
+                SortKey::Rating => rating_of(a).partial_cmp(&rating_of(b)).unwrap_or(Ordering::Equal),
+            };
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| title_of(a).cmp(title_of(b))).then_with(|| id_of(a).cmp(id_of(b)))
+        });
+
+        if let Some(limit) = self.limit {
+            items.truncate(limit);
+        }
+        items
+    }
+}
+
+pub struct HistoryQuery {
+    pub profile: Option<String>,
+    pub limit: usize,
+}
+
+impl HistoryQuery {
+    pub fn parse(query: Option<&str>) -> Result<HistoryQuery, String> {
+        let mut history = HistoryQuery { profile: None, limit: DEFAULT_HISTORY_LIMIT };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "profile" => history.profile = Some(value.into_owned()),
+                "limit" => history.limit = value.parse::<usize>().map_err(|_| format!("Invalid limit {}", value))?,
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(history)
+    }
+}
+
+pub struct PlaylistQuery {
+    pub dir: Option<String>,
+    pub token: Option<String>,
+}
+
+impl PlaylistQuery {
+    pub fn parse(query: Option<&str>) -> Result<PlaylistQuery, String> {
+        let mut playlist = PlaylistQuery { dir: None, token: None };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "dir" => playlist.dir = Some(value.trim_matches('/').to_string()).filter(|dir| !dir.is_empty()),
+                "token" => playlist.token = Some(value.into_owned()),
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(playlist)
+    }
+}
+
+pub struct CastQuery {
+    pub token: Option<String>,
+}
+
+impl CastQuery {
+    pub fn parse(query: Option<&str>) -> Result<CastQuery, String> {
+        let mut cast = CastQuery { token: None };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "token" => cast.token = Some(value.into_owned()),
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(cast)
+    }
+}
+
+pub struct TranscodeQuery {
+    pub profile: Option<String>,
+    pub start: f64,
+    pub session: Option<String>,
+}
+
+impl TranscodeQuery {
+    pub fn parse(query: Option<&str>) -> Result<TranscodeQuery, String> {
+        let mut transcode = TranscodeQuery { profile: None, start: 0.0, session: None };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "profile" => transcode.profile = Some(value.into_owned()),
+                "start" => transcode.start = value.parse::<f64>().ok()
+                    .filter(|start| start.is_finite() && *start >= 0.0)
+                    .ok_or_else(|| format!("Invalid start {}", value))?,
+                "session" => transcode.session = Some(value.into_owned()),
+                "token" | "exp" | "sig" => {}
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(transcode)
+    }
+}
+
+pub struct ThumbnailQuery {
+    pub width: u32,
+}
+
+impl ThumbnailQuery {
+    pub fn parse(query: Option<&str>) -> Result<ThumbnailQuery, String> {
+        let mut thumbnail = ThumbnailQuery { width: thumbnails::DEFAULT_WIDTH };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+
+            match name {
+                "width" => thumbnail.width = value.parse::<u32>().ok()
+                    .filter(|width| (1..=thumbnails::MAX_WIDTH).contains(width))
+                    .ok_or_else(|| format!("Invalid width {}", value))?,
+                "token" => {}
+                _ => return Err(format!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(thumbnail)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    Json,
+    Xml,
+}
+
+pub struct ManifestQuery {
+    pub format: Option<ManifestFormat>,
+}
+
+impl ManifestQuery {
+    pub fn parse(query: Option<&str>) -> Result<ManifestQuery, String> {
+        let mut manifest = ManifestQuery { format: None };
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if name == "format" {
+                manifest.format = match percent_decode_str(value).decode_utf8_lossy().as_ref() {
+                    "json" => Some(ManifestFormat::Json),
+                    "xml" => Some(ManifestFormat::Xml),
+                    format => return Err(format!("Unknown format {}", format)),
+                };
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+#[derive(Default)]
+pub struct JellyfinQuery {
+    pub parent_id: Option<String>,
+    pub ids: Option<Vec<String>>,
+    pub recursive: bool,
+    pub search_term: Option<String>,
+    pub include_item_types: Option<Vec<String>>,
+    pub filters: Vec<String>,
+    pub start_index: usize,
+    pub limit: Option<usize>,
+}
+
+impl JellyfinQuery {
+    pub fn parse(query: Option<&str>) -> Result<JellyfinQuery, String> {
+        let mut jellyfin = JellyfinQuery::default();
+        let list = |value: &str| value.split(',').filter(|entry| !entry.is_empty()).map(str::to_string).collect::<Vec<_>>();
+
+        for pair in query.unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(&value.replace('+', " ")).decode_utf8_lossy().into_owned();
+
+            match name.to_ascii_lowercase().as_str() {
+                "parentid" => jellyfin.parent_id = Some(value).filter(|id| !id.is_empty()),
+                "ids" => jellyfin.ids = Some(list(&value)),
+                "recursive" => jellyfin.recursive = value.eq_ignore_ascii_case("true"),
+                "searchterm" => jellyfin.search_term = Some(value.to_lowercase()).filter(|term| !term.is_empty()),
+                "includeitemtypes" => jellyfin.include_item_types = Some(list(&value)).filter(|types| !types.is_empty()),
+                "filters" => jellyfin.filters = list(&value),
+                "startindex" => jellyfin.start_index = value.parse().map_err(|_| format!("Invalid start index {}", value))?,
+                "limit" => jellyfin.limit = Some(value.parse().map_err(|_| format!("Invalid limit {}", value))?),
+                _ => {}
+            }
+        }
+        Ok(jellyfin)
+    }
+}
+
+fn title_of(item: &Value) -> &str {
+    item.get("title").and_then(Value::as_str).unwrap_or_default()
+}
+
+fn id_of(item: &Value) -> &str {
+    item.get("id").and_then(Value::as_str).unwrap_or_default()
+}
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::fmt;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+const MAX_PATH_LENGTH: usize = 4096;
+const MAX_COMPONENT_LENGTH: usize = 255;
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+#[derive(Debug)]
+pub enum InvalidPath {
+    TooLong,
+    NotUtf8,
+    ControlCharacter,
+    Backslash,
+    StreamSyntax,
+    EmptyComponent,
+    Traversal,
+    EncodedTraversal,
+    TrailingDotOrSpace,
+}
+
+impl fmt::Display for InvalidPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidPath::TooLong => write!(f, "Path is too long"),
+            InvalidPath::NotUtf8 => write!(f, "Path isn't valid UTF-8"),
+            InvalidPath::ControlCharacter => write!(f, "Path contains a NUL byte or another control character"),
+            InvalidPath::Backslash => write!(f, "Path contains a backslash"),
+            InvalidPath::StreamSyntax => write!(f, "Path contains a colon"),
+            InvalidPath::EmptyComponent => write!(f, "Path has an empty component"),
+            InvalidPath::Traversal => write!(f, "Path contains a . or .. component"),
+            InvalidPath::EncodedTraversal => write!(f, "Path contains an encoded separator or traversal"),
+            InvalidPath::TrailingDotOrSpace => write!(f, "Path has a component ending with a dot or a space"),
+        }
+    }
+}
+
+pub fn validate(raw: &str) -> Result<String, InvalidPath> {
+    if raw.len() > MAX_PATH_LENGTH * 3 {
+        return Err(InvalidPath::TooLong);
+    }
+
+    let path = percent_decode_str(raw).decode_utf8().map_err(|_| InvalidPath::NotUtf8)?;
+    if path.len() > MAX_PATH_LENGTH {
+        return Err(InvalidPath::TooLong);
+    }
+
+    for component in path.split('/') {
+        validate_component(component)?;
+
+        let decoded_again = percent_decode_str(component).decode_utf8_lossy();
+        if decoded_again != component && (decoded_again.contains(['/', '\\']) || is_traversal(&decoded_again)) {
+            return Err(InvalidPath::EncodedTraversal);
+        }
+    }
+    Ok(path.into_owned())
+}
+
+pub fn encode(path: &str) -> String {
+    path.split('/').map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string()).collect::<Vec<_>>().join("/")
+}
+
+fn validate_component(component: &str) -> Result<(), InvalidPath> {
+    if component.len() > MAX_COMPONENT_LENGTH {
+        Err(InvalidPath::TooLong)
+    } else if component.chars().any(char::is_control) {
+        Err(InvalidPath::ControlCharacter)
+    } else if component.contains('\\') {
+        Err(InvalidPath::Backslash)
+    } else if cfg!(windows) && component.contains(':') {
+        Err(InvalidPath::StreamSyntax)
+    } else if component.is_empty() {
+        Err(InvalidPath::EmptyComponent)
+    } else if is_traversal(component) {
+        Err(InvalidPath::Traversal)
+    } else if cfg!(windows) && component.ends_with(['.', ' ']) {
+        Err(InvalidPath::TrailingDotOrSpace)
+    } else {
+        Ok(())
+    }
+}
+
+fn is_traversal(component: &str) -> bool {
+    component.split(['/', '\\']).any(|part| part == "." || part == "..")
+}
\ No newline at end of file
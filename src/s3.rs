@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::{self, BoxStream}, FutureExt, StreamExt, TryFutureExt, TryStreamExt};
+use hmac::{Hmac, Mac};
+use hyper::{body, client::HttpConnector, header, Body, Client, Method, Request, Response, StatusCode, Uri};
+use hyper_tls::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use crate::config::S3Settings;
+use crate::file_stream::{BufferPool, StreamBudget};
+use crate::media_source::{MediaEntry, MediaFile, MediaMetadata, MediaSource};
+use crate::scanner::universal_path;
+use crate::signing::encode_hex;
+use crate::state;
+use crate::xml::{element, elements, unescape};
+
+const DEFAULT_REGION: &str = "us-east-1";
+const SERVICE: &str = "s3";
+const SIGNING_ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const SECONDS_IN_DAY: u64 = 24 * 60 * 60;
+const URI_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+struct S3Client {
+    settings: S3Settings,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl S3Client {
+    fn region(&self) -> &str {
+        self.settings.region.as_deref().unwrap_or(DEFAULT_REGION)
+    }
+
+    fn prefix(&self) -> String {
+        match self.settings.prefix.as_deref().map(|prefix| prefix.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => format!("{}/", prefix),
+            _ => String::new(),
+        }
+    }
+
+    fn object_key(&self, path: &Path) -> Result<String, io::Error> {
+        let path = universal_path(path).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a valid object key", path.display())))?;
+        Ok(format!("{}{}", self.prefix(), path))
+    }
+
+    async fn request(&self, method: Method, key: &str, query: &[(&str, &str)], range: Option<&Range<u64>>) -> Result<Response<Body>, io::Error> {
+        let encoded_key = key.split('/').map(|segment| utf8_percent_encode(segment, URI_UNRESERVED).to_string()).collect::<Vec<_>>().join("/");
+        let (origin, path) = match self.settings.endpoint {
+            Some(ref endpoint) => (endpoint.trim_end_matches('/').to_string(), format!("/{}/{}", self.settings.bucket, encoded_key)),
+            None => (format!("https://{}.s3.{}.amazonaws.com", self.settings.bucket, self.region()), format!("/{}", encoded_key)),
+        };
+
+        let mut query: Vec<String> = query.iter()
+            .map(|(name, value)| format!("{}={}", utf8_percent_encode(name, URI_UNRESERVED), utf8_percent_encode(value, URI_UNRESERVED)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        let uri = if query.is_empty() { format!("{}{}", origin, path) } else { format!("{}{}?{}", origin, path, query) };
+        let uri = uri.parse::<Uri>().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let host = uri.authority().map(|authority| authority.as_str().to_string()).unwrap_or_default();
+
+        let (date, timestamp) = amz_date(state::now());
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, host, UNSIGNED_PAYLOAD, timestamp, SIGNED_HEADERS, UNSIGNED_PAYLOAD,
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region(), SERVICE);
+        let string_to_sign = format!("{}\n{}\n{}\n{}", SIGNING_ALGORITHM, timestamp, scope, encode_hex(&Sha256::digest(canonical_request.as_bytes())));
+
+        let mut signing_key = hmac(format!("AWS4{}", self.settings.secret_access_key).as_bytes(), date.as_bytes());
+        for part in [self.region(), SERVICE, "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let authorization = format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            SIGNING_ALGORITHM, self.settings.access_key_id, scope, SIGNED_HEADERS, encode_hex(&hmac(&signing_key, string_to_sign.as_bytes())),
+        );
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::HOST, host)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", timestamp)
+            .header(header::AUTHORIZATION, authorization);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, format!("bytes={}-{}", range.start, range.end - 1));
+        }
+        let request = request.body(Body::empty()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.client.request(request).await.map_err(io::Error::other)
+    }
+
+    async fn list(&self) -> Result<HashMap<PathBuf, u64>, io::Error> {
+        let prefix = self.prefix();
+        let mut objects = HashMap::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(ref token) = continuation {
+                query.push(("continuation-token", token));
+            }
+            let response = self.request(Method::GET, "", &query, None).await?;
+            let status = response.status();
+            let listing = body::to_bytes(response.into_body()).await.map_err(io::Error::other)?;
+            if status != StatusCode::OK {
+                return Err(io::Error::other(format!("S3 refused to list the bucket {} with {}", self.settings.bucket, status)));
+            }
+
+            let listing = String::from_utf8_lossy(&listing);
+            for object in elements(&listing, "Contents") {
+                let key = match element(object, "Key") {
+                    Some(key) => unescape(key),
+                    None => continue,
+                };
+                let size = element(object, "Size").and_then(|size| size.parse().ok()).unwrap_or(0);
+                if let Some(relative) = key.strip_prefix(&prefix).filter(|relative| !relative.is_empty() && !relative.ends_with('/')) {
+                    objects.insert(PathBuf::from(relative), size);
+                }
+            }
+
+            continuation = match (element(&listing, "IsTruncated"), element(&listing, "NextContinuationToken")) {
+                (Some("true"), Some(token)) => Some(unescape(token)),
+                _ => return Ok(objects),
+            };
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, io::Error> {
+        let response = self.request(Method::GET, key, &[], None).await?;
+        match response.status() {
+            StatusCode::OK => body::to_bytes(response.into_body()).await.map_err(io::Error::other),
+            StatusCode::NOT_FOUND => Err(io::Error::new(io::ErrorKind::NotFound, format!("There's no object {}", key))),
+            status => Err(io::Error::other(format!("S3 refused to send {} with {}", key, status))),
+        }
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, io::Error> {
+        let response = self.request(Method::HEAD, key, &[], None).await?;
+        match response.status() {
+            StatusCode::OK => response.headers().get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok()?.parse().ok())
+                .ok_or_else(|| io::Error::other(format!("S3 didn't tell the size of {}", key))),
+            StatusCode::NOT_FOUND => Err(io::Error::new(io::ErrorKind::NotFound, format!("There's no object {}", key))),
+            status => Err(io::Error::other(format!("S3 refused to describe {} with {}", key, status))),
+        }
+    }
+}
+
+pub struct S3Source {
+    client: Arc<S3Client>,
+    objects: RwLock<HashMap<PathBuf, u64>>,
+}
+
+impl S3Source {
+    pub fn new(settings: &S3Settings) -> S3Source {
+        S3Source {
+            client: Arc::new(S3Client { settings: settings.clone(), client: Client::builder().build(HttpsConnector::new()) }),
+            objects: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl MediaSource for S3Source {
+    fn refresh(&self) -> Result<(), io::Error> {
+        let objects = block_on(self.client.list())?;
+        *self.objects.write().unwrap() = objects;
+        Ok(())
+    }
+
+    fn entries(&self, dir: &Path) -> Result<Vec<MediaEntry>, io::Error> {
+        let mut entries = BTreeMap::new();
+        for path in self.objects.read().unwrap().keys() {
+            let mut components = match path.strip_prefix(dir) {
+                Ok(relative) => relative.components(),
+                Err(_) => continue,
+            };
+            if let Some(name) = components.next().and_then(|name| name.as_os_str().to_str()) {
+                *entries.entry(name.to_string()).or_insert(false) |= components.next().is_some();
+            }
+        }
+        Ok(entries.into_iter().map(|(name, is_dir)| MediaEntry { name, is_dir }).collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.objects.read().unwrap().contains_key(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, io::Error> {
+        if !self.is_file(path) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("There's no object {}", path.display())));
+        }
+        let contents = block_on(self.client.get(&self.client.object_key(path)?))?;
+        String::from_utf8(contents.to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<MediaMetadata, io::Error>> {
+        async move {
+            let listed = self.objects.read().unwrap().get(path).copied();
+            let len = match listed {
+                Some(len) => len,
+                None => self.client.size(&self.client.object_key(path)?).await?,
+            };
+            Ok(MediaMetadata { len, modified: None })
+        }.boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Arc<dyn MediaFile>, io::Error>> {
+        async move {
+            let key = self.client.object_key(path)?;
+            let listed = self.objects.read().unwrap().get(path).copied();
+            let len = match listed {
+                Some(len) => len,
+                None => self.client.size(&key).await?,
+            };
+            let opened: Arc<dyn MediaFile> = Arc::new(S3File { client: self.client.clone(), key, len });
+            Ok(opened)
+        }.boxed()
+    }
+}
+
+struct S3File {
+    client: Arc<S3Client>,
+    key: String,
+    len: u64,
+}
+
+impl MediaFile for S3File {
+    fn metadata(&self) -> MediaMetadata {
+        MediaMetadata { len: self.len, modified: None }
+    }
+
+    fn read_range(&self, range: Range<u64>, _pool: Arc<BufferPool>, _budget: StreamBudget) -> BoxStream<'static, Result<Bytes, io::Error>> {
+        if range.start >= range.end {
+            return stream::empty().boxed();
+        }
+
+        let (client, key) = (self.client.clone(), self.key.clone());
+        async move {
+            let response = client.request(Method::GET, &key, &[], Some(&range)).await?;
+            match response.status() {
+                StatusCode::OK | StatusCode::PARTIAL_CONTENT => Ok(response.into_body().map_err(io::Error::other)),
+                status => Err(io::Error::other(format!("S3 refused to stream {} with {}", key, status))),
+            }
+        }.try_flatten_stream().boxed()
+    }
+}
+
+fn block_on<T>(future: impl Future<Output=Result<T, io::Error>>) -> Result<T, io::Error> {
+    Handle::try_current().map_err(|_| io::Error::other("S3 mounts are only scanned by the server"))?.block_on(future)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn amz_date(timestamp: u64) -> (String, String) {
+    let (days, seconds) = (timestamp / SECONDS_IN_DAY, timestamp % SECONDS_IN_DAY);
+    let shifted = days + 719_468;
+    let (era, day_of_era) = (shifted / 146_097, shifted % 146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!("{}T{:02}{:02}{:02}Z", date, seconds / 3600, seconds / 60 % 60, seconds % 60);
+    (date, timestamp)
+}
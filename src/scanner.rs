@@ -1,158 +1,662 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     time::Duration,
     path::{PathBuf, Path, Component},
     io,
+    fmt,
     fs,
+    sync::{Arc, Mutex},
 };
 use serde::{Deserialize, Serialize, Serializer, ser};
 use std::collections::HashSet;
 
-const EXTENSION_MP4: &str = "mp4";
-const EXTENSION_TOML: &str = "toml";
-const EXTENSION_SUBTITLES: &str = "vtt";
+use crate::media_source::{LocalSource, MediaSource};
+use crate::metadata::{Metadata, MetadataProvider};
+use crate::probe::{Probed, Prober};
+use crate::subtitles::{self, EXTENSION_SRT, EXTENSION_VTT};
+use crate::xml;
+
+pub const EXTENSION_MP4: &str = "mp4";
+pub const EXTENSION_MKV: &str = "mkv";
+pub const EXTENSION_TOML: &str = "toml";
+pub const EXTENSION_NFO: &str = "nfo";
+const NFO_ROOTS: [&str; 3] = ["movie", "episodedetails", "musicvideo"];
+const NFO_ARTWORK: [&str; 3] = ["thumb", "poster", "fanart"];
+const DIRECTORY_CONFIG_NAME: &str = ".directory.toml";
 
 const DEFAULT_LANGUAGE: &str = "en";
 
-#[derive(Debug, Serialize)]
+const ITEM_ID_LENGTH: usize = 16;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum CatalogueItem {
     #[serde(rename = "directory")]
     Directory {
         #[serde(rename = "title")] name: String,
         #[serde(rename = "contents")] items: Vec<CatalogueItem>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        restricted: Option<String>,
+    },
+    #[serde(rename = "series")]
+    Series {
+        #[serde(rename = "title")] name: String,
+        #[serde(rename = "contents")] items: Vec<CatalogueItem>,
+    },
+    #[serde(rename = "season")]
+    Season {
+        #[serde(rename = "season")] number: u32,
+        #[serde(rename = "title")] name: String,
+        #[serde(rename = "contents")] items: Vec<CatalogueItem>,
     },
     #[serde(rename = "file")]
     Video {
+        id: String,
         path: RelativizedPath,
         title: String,
         subtitle: Option<String>,
-        #[serde(serialize_with = "serialize_duration")]
-        duration: Duration,
+        #[serde(serialize_with = "serialize_duration", skip_serializing_if = "Option::is_none")]
+        duration: Option<Duration>,
         #[serde(rename = "text-tracks", skip_serializing_if = "HashMap::is_empty")]
         text_tracks: HashMap<String, RelativizedPath>,
         #[serde(skip_serializing_if = "Vec::is_empty")]
-        thumbnails: Vec<PathBuf>,
+        thumbnails: Vec<RelativizedPath>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        genres: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        episode: Option<Episode>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        width: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        height: Option<u32>,
+        #[serde(rename = "video-codec", skip_serializing_if = "Option::is_none")]
+        video_codec: Option<String>,
     },
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct Episode {
+    pub show: String,
+    pub season: u32,
+    pub episode: u32,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct RelativizedPath {
-    pub path: PathBuf,
+    pub root: Arc<Path>,
     pub relative_path: PathBuf,
 }
 
 impl RelativizedPath {
-    fn new(root_path: impl AsRef<Path>, path: impl AsRef<Path>) -> RelativizedPath {
-        RelativizedPath {
-            path: PathBuf::from(path.as_ref()),
-            relative_path: path.as_ref().strip_prefix(root_path).unwrap().to_path_buf(),
-        }
+    fn new(root_path: &Arc<Path>, relative_path: PathBuf) -> RelativizedPath {
+        RelativizedPath { root: root_path.clone(), relative_path }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.root.join(&self.relative_path)
     }
 }
 
 impl Serialize for RelativizedPath {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let universal_path = self.relative_path.components()
-            .map(|component| {
-                match component {
-                    Component::Normal(str) => Ok(str.to_str().unwrap()),
-                    _ => {
-                        let message = format!("Path {} does not consist only of normal components", self.relative_path.to_string_lossy());
-                        Err(ser::Error::custom(message))
-                    }
-                }
-            })
-            .collect::<Result<Vec<&str>, S::Error>>()?
-            .join("/");
+        let universal_path = universal_path(&self.relative_path).ok_or_else(|| {
+            let message = format!("Path {} does not consist only of normal components", self.relative_path.to_string_lossy());
+            ser::Error::custom(message)
+        })?;
 
         serializer.serialize_str(&universal_path)
     }
 }
 
-fn serialize_duration<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
-    serializer.serialize_u64(duration.as_millis() as u64)
+pub fn universal_path(path: &Path) -> Option<String> {
+    path.components()
+        .map(|component| {
+            match component {
+                Component::Normal(str) => Some(str.to_str().unwrap()),
+                _ => None
+            }
+        })
+        .collect::<Option<Vec<&str>>>()
+        .map(|components| components.join("/"))
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let total_seconds = duration.as_secs();
+    format!(
+        "PT{}H{}M{}.{:03}S",
+        total_seconds / 3600,
+        total_seconds / 60 % 60,
+        total_seconds % 60,
+        duration.subsec_millis()
+    )
+}
+
+fn serialize_duration<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_u64(duration.as_millis() as u64),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn item_id(relative_path: &Path) -> String {
+    let key = universal_path(relative_path).unwrap_or_else(|| relative_path.to_string_lossy().into_owned());
+    sha1_smol::Sha1::from(key.as_bytes()).digest().to_string()[..ITEM_ID_LENGTH].to_string()
+}
+
+pub fn title_from_path(path: &Path) -> String {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let spaced = stem.replace(['.', '_'], " ");
+    let words = spaced.split_whitespace().collect::<Vec<_>>();
+    let end = words.iter().rposition(|word| is_year_tag(word)).filter(|end| *end > 0).unwrap_or(words.len());
+    let title = words[..end].join(" ");
+    match title.trim_end_matches(['-', ' ']) {
+        "" => spaced.trim().to_string(),
+        title => title.to_string(),
+    }
+}
+
+fn is_year_tag(word: &str) -> bool {
+    let year = word.trim_start_matches(['(', '[']).trim_end_matches([')', ']']);
+    year.len() == 4 && year.parse::<u32>().is_ok_and(|year| (1900..2100).contains(&year))
+}
+
+pub fn check_library_folder(path: &Path) -> Result<(), io::Error> {
+    let describe = |err: io::Error| io::Error::new(err.kind(), format!("Can't read the library folder {}: {}", path.display(), err));
+
+    if !fs::metadata(path).map_err(describe)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a folder", path.display())));
+    }
+    fs::read_dir(path).map_err(describe)?;
+    Ok(())
 }
 
 pub fn scan_directory(root_path: &Path, path: &Path) -> Result<Vec<CatalogueItem>, io::Error> {
+    let prober = Prober::open(root_path, None);
+    let catalogue = scan_directory_with(root_path, path, &[], &prober, false, None, &mut |_| {});
+    prober.save();
+    catalogue
+}
+
+pub fn scan_directory_with(
+    root_path: &Path,
+    path: &Path,
+    providers: &[Arc<dyn MetadataProvider>],
+    prober: &Prober,
+    fallback: bool,
+    cache: Option<&ScanCache>,
+    on_video: &mut dyn FnMut(&CatalogueItem),
+) -> Result<Vec<CatalogueItem>, io::Error> {
+    let dir = path.strip_prefix(root_path).unwrap();
+    scan(&LocalSource::new(root_path), &Arc::from(root_path), Path::new(""), dir, providers, prober, fallback, cache, &mut Vec::new(), on_video)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn scan_source(
+    source: &dyn MediaSource,
+    root_path: &Path,
+    mount: &Path,
+    providers: &[Arc<dyn MetadataProvider>],
+    prober: &Prober,
+    fallback: bool,
+    cache: Option<&ScanCache>,
+    on_video: &mut dyn FnMut(&CatalogueItem),
+) -> Result<Vec<CatalogueItem>, io::Error> {
+    source.refresh()?;
+    scan(source, &Arc::from(root_path), mount, Path::new(""), providers, prober, fallback, cache, &mut Vec::new(), on_video)
+}
+
+pub fn validate_directory(root_path: &Path, providers: &[Arc<dyn MetadataProvider>], prober: &Prober, fallback: bool) -> Result<Vec<Issue>, io::Error> {
+    let mut issues = Vec::new();
+    let source = LocalSource::new(root_path);
+    scan(&source, &Arc::from(root_path), Path::new(""), Path::new(""), providers, prober, fallback, None, &mut issues, &mut |_| {})?;
+    Ok(issues)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn scan(
+    source: &dyn MediaSource,
+    root_path: &Arc<Path>,
+    mount: &Path,
+    dir: &Path,
+    providers: &[Arc<dyn MetadataProvider>],
+    prober: &Prober,
+    fallback: bool,
+    cache: Option<&ScanCache>,
+    issues: &mut Vec<Issue>,
+    on_video: &mut dyn FnMut(&CatalogueItem),
+) -> Result<Vec<CatalogueItem>, io::Error> {
+    if let Some(items) = cache.and_then(|cache| cache.get(mount, dir)) {
+        return Ok(items);
+    }
+    let relativized = |path: &Path| RelativizedPath::new(root_path, mount.join(path));
+
+    let entries = source.entries(dir)?;
+    let mut language_tracks: HashMap<PathBuf, Vec<(String, PathBuf)>> = HashMap::new();
+    for entry in &entries {
+        let path = dir.join(&entry.name);
+        if let Some((video, language)) = language_track(source, &path) {
+            language_tracks.entry(video).or_default().push((language, path));
+        }
+    }
+    for tracks in language_tracks.values_mut() {
+        tracks.sort_by_key(|(_, path)| !subtitles::is_srt(path));
+    }
+
     let mut items: Vec<CatalogueItem> = Vec::new();
-    for child_path in fs::read_dir(path)? {
-        let entry = child_path?;
-        let file_name = entry.file_name().into_string().unwrap();
-        let file_type = entry.file_type()?;
-
-        let path = entry.path();
-
-        if file_type.is_dir() {
-            items.push(CatalogueItem::Directory {
-                name: file_name,
-                items: scan_directory(root_path, &path)?,
-            })
-        } else if file_type.is_file() {
-            if let Some(extension) = path.extension() {
-                if extension != EXTENSION_MP4 { continue; }
-
-                let mut toml_path = path.clone();
-                toml_path.set_extension(EXTENSION_TOML);
-                if !toml_path.is_file() { continue; }
-
-                let config = match toml::from_str::<Config>(&fs::read_to_string(toml_path)?) {
-                    Ok(file) => file,
-                    Err(_) => continue,
-                };
-
-                let duration = iso8601::duration(&config.duration);
-                let duration = if let Ok(iso8601::Duration::YMDHMS { hour, minute, second, millisecond, .. }) = duration {
-                    let milliseconds_total = hour as u64 * 60 * 60 * 1000 + minute as u64 * 60 * 1000 + second as u64 * 1000 + millisecond as u64;
-                    Duration::from_millis(milliseconds_total)
-                } else {
+    for entry in entries {
+        let path = dir.join(&entry.name);
+
+        if entry.is_dir {
+            let restricted = match directory_config(source, &path) {
+                Ok(config) => config.restricted,
+                Err(error) => {
+                    issues.push(Issue::UnparsableSidecar { sidecar: relativized(&path.join(DIRECTORY_CONFIG_NAME)), error });
                     continue;
-                };
+                }
+            };
+            let contents = match restricted {
+                Some(_) => scan(source, root_path, mount, &path, providers, prober, fallback, cache, issues, &mut |_| {})?,
+                None => scan(source, root_path, mount, &path, providers, prober, fallback, cache, issues, on_video)?,
+            };
+            items.push(CatalogueItem::Directory { name: entry.name, items: contents, restricted })
+        } else {
+            if subtitles::is_subtitles(&path) {
+                if sibling_video(source, &path).is_none() && language_track(source, &path).is_none() {
+                    issues.push(Issue::OrphanedSubtitles { subtitles: relativized(&path) });
+                }
+                continue;
+            }
+            if !is_video(&path) { continue; }
+
+            let sidecar_path = [EXTENSION_TOML, EXTENSION_NFO].iter().map(|extension| path.with_extension(extension)).find(|path| source.is_file(path));
+            let config = match sidecar_path {
+                Some(ref sidecar_path) => {
+                    let text = source.read_to_string(sidecar_path)?;
+                    let config = if sidecar_path.extension().is_some_and(|extension| extension == EXTENSION_NFO) {
+                        nfo_config(&text)
+                    } else {
+                        toml::from_str::<Config>(&text).map_err(|err| err.to_string())
+                    };
+                    config.map_err(|error| Issue::UnparsableSidecar { sidecar: relativized(sidecar_path), error })
+                }
+                None => Err(Issue::MissingSidecar { video: relativized(&path) }),
+            };
+            let config = match config {
+                Ok(file) => file,
+                Err(issue) => {
+                    issues.push(issue);
+                    if !fallback { continue; }
+                    Config { title: title_from_path(&path), ..Config::default() }
+                }
+            };
+            let sidecar_path = sidecar_path.unwrap_or_else(|| path.clone());
+
+            let (duration, probed) = match config.duration {
+                Some(duration) => match iso8601::duration(&duration) {
+                    Ok(iso8601::Duration::YMDHMS { hour, minute, second, millisecond, .. }) => {
+                        let milliseconds_total = hour as u64 * 60 * 60 * 1000 + minute as u64 * 60 * 1000 + second as u64 * 1000 + millisecond as u64;
+                        (Some(Duration::from_millis(milliseconds_total)), None)
+                    }
+                    _ => {
+                        issues.push(Issue::BadDuration { sidecar: relativized(&sidecar_path), duration });
+                        if !fallback { continue; }
+                        (None, None)
+                    }
+                },
+                None => match probe(source, prober, &mount.join(&path), &path) {
+                    Ok(probed) => (Some(Duration::from_millis(probed.duration)), Some(probed)),
+                    Err(error) => {
+                        issues.push(Issue::UnprobedDuration { sidecar: relativized(&sidecar_path), error });
+                        if !fallback { continue; }
+                        (None, None)
+                    }
+                },
+            };
 
-                let mut text_tracks: HashMap<String, RelativizedPath> = HashMap::new();
+            let mut text_tracks: HashMap<String, RelativizedPath> = language_tracks.remove(&path)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(language, track)| (language, relativized(&track)))
+                .collect();
 
-                let mut subtitle_path = path.clone();
-                subtitle_path.set_extension(EXTENSION_SUBTITLES);
-                if subtitle_path.is_file() {
-                    let language = config.text_track_language.unwrap_or(DEFAULT_LANGUAGE.into());
-                    text_tracks.insert(language, RelativizedPath::new(root_path, subtitle_path));
+            let subtitle_path = [EXTENSION_VTT, EXTENSION_SRT].iter().map(|extension| path.with_extension(extension)).find(|path| source.is_file(path));
+            if let Some(subtitle_path) = subtitle_path {
+                let language = config.text_track_language.unwrap_or(DEFAULT_LANGUAGE.into());
+                text_tracks.entry(language).or_insert_with(|| relativized(&subtitle_path));
+            }
+
+            let mut thumbnails = Vec::new();
+            for thumbnail in config.thumbnails {
+                let thumbnail_path = path.parent().unwrap().join(&thumbnail);
+                if source.is_file(&thumbnail_path) {
+                    thumbnails.push(relativized(&thumbnail_path));
+                } else {
+                    issues.push(Issue::MissingArtwork { sidecar: relativized(&sidecar_path), artwork: thumbnail });
                 }
+            }
 
-                items.push(CatalogueItem::Video {
-                    path: RelativizedPath::new(root_path, path),
-                    title: config.title,
-                    subtitle: config.subtitle,
-                    duration,
-                    text_tracks,
-                    thumbnails: Vec::new(),
-                })
+            let episode = episode_of(&root_path.join(mount).join(&path), config.show, config.season, config.episode);
+            let path = relativized(&path);
+            let mut metadata = Metadata { title: config.title, subtitle: config.subtitle, genres: config.genres, episode };
+            for provider in providers {
+                if let Err(err) = provider.provide(&path, &mut metadata) {
+                    issues.push(Issue::ProviderFailed { video: path.clone(), provider: provider.name().to_string(), error: err.to_string() });
+                }
             }
+            let video = CatalogueItem::Video {
+                id: item_id(&path.relative_path),
+                path,
+                title: metadata.title,
+                subtitle: metadata.subtitle,
+                duration,
+                text_tracks,
+                thumbnails,
+                genres: metadata.genres,
+                episode: metadata.episode,
+                width: probed.as_ref().and_then(|probed| probed.width),
+                height: probed.as_ref().and_then(|probed| probed.height),
+                video_codec: probed.and_then(|probed| probed.video_codec),
+            };
+            on_video(&video);
+            items.push(video);
         }
     }
 
+    let items = group_episodes(items);
+    if let Some(cache) = cache {
+        cache.insert(mount, dir, &items);
+    }
     Ok(items)
 }
 
-#[derive(Deserialize)]
+#[derive(Default)]
+pub struct ScanCache {
+    directories: Mutex<HashMap<(PathBuf, PathBuf), Vec<CatalogueItem>>>,
+}
+
+impl ScanCache {
+    pub fn clear(&self) {
+        self.directories.lock().unwrap().clear();
+    }
+
+    pub fn invalidate(&self, mount: &Path, path: &Path) {
+        self.directories.lock().unwrap()
+            .retain(|(cached_mount, dir), _| cached_mount != mount || !(path.starts_with(dir) || dir.starts_with(path)));
+    }
+
+    fn get(&self, mount: &Path, dir: &Path) -> Option<Vec<CatalogueItem>> {
+        self.directories.lock().unwrap().get(&(mount.to_path_buf(), dir.to_path_buf())).cloned()
+    }
+
+    fn insert(&self, mount: &Path, dir: &Path, items: &[CatalogueItem]) {
+        self.directories.lock().unwrap().insert((mount.to_path_buf(), dir.to_path_buf()), items.to_vec());
+    }
+}
+
+fn group_episodes(items: Vec<CatalogueItem>) -> Vec<CatalogueItem> {
+    let mut grouped = Vec::new();
+    let mut series: Vec<(String, BTreeMap<u32, Vec<CatalogueItem>>)> = Vec::new();
+    let mut add_episode = |show: &str, season: u32, video: CatalogueItem| {
+        let position = match series.iter().position(|(name, _)| name == show) {
+            Some(position) => position,
+            None => {
+                series.push((show.to_string(), BTreeMap::new()));
+                series.len() - 1
+            }
+        };
+        series[position].1.entry(season).or_default().push(video);
+    };
+
+    for item in items {
+        match item {
+            CatalogueItem::Video { episode: Some(ref episode), .. } => {
+                let (show, season) = (episode.show.clone(), episode.season);
+                add_episode(&show, season, item);
+            }
+            CatalogueItem::Directory { restricted: None, ref name, ref items } if is_show_wrapper(name, items) => {
+                if let CatalogueItem::Directory { items, .. } = item {
+                    for show in items {
+                        if let CatalogueItem::Series { name, items: seasons } = show {
+                            for season in seasons {
+                                if let CatalogueItem::Season { number, items: episodes, .. } = season {
+                                    episodes.into_iter().for_each(|episode| add_episode(&name, number, episode));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            item => grouped.push(item),
+        }
+    }
+
+    grouped.extend(series.into_iter().map(|(name, seasons)| CatalogueItem::Series {
+        name,
+        items: seasons.into_iter().map(|(number, mut episodes)| {
+            episodes.sort_by_key(|episode| match episode {
+                CatalogueItem::Video { episode: Some(episode), .. } => episode.episode,
+                _ => 0,
+            });
+            CatalogueItem::Season { number, name: format!("Season {}", number), items: episodes }
+        }).collect(),
+    }));
+    grouped
+}
+
+fn is_show_wrapper(name: &str, items: &[CatalogueItem]) -> bool {
+    !items.is_empty() && items.iter().all(|item| match item {
+        CatalogueItem::Series { name: show, .. } => is_season_folder(name) || folder_name(show) == folder_name(name),
+        _ => false,
+    })
+}
+
+fn is_season_folder(name: &str) -> bool {
+    let name = folder_name(name);
+    let number = name.strip_prefix("season").map(str::trim_start).or_else(|| name.strip_prefix('s')).unwrap_or_default();
+    !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn folder_name(name: &str) -> String {
+    name.replace(['.', '_', '-'], " ").split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+#[derive(Default, Deserialize)]
 struct Config {
     title: String,
     subtitle: Option<String>,
-    duration: String,
+    duration: Option<String>,
     #[serde(rename = "text-track-language")]
     text_track_language: Option<String>,
+    #[serde(default)]
+    thumbnails: Vec<String>,
+    #[serde(default)]
+    genres: Vec<String>,
+    show: Option<String>,
+    season: Option<u32>,
+    episode: Option<u32>,
+}
+
+fn nfo_config(text: &str) -> Result<Config, String> {
+    let root = NFO_ROOTS.iter().find_map(|root| xml::element(text, root)).ok_or("there's no movie or episodedetails element")?;
+    let mut details = root.to_string();
+    for actor in xml::elements(root, "actor") {
+        details = details.replacen(actor, "", 1);
+    }
+
+    let field = |name: &str| xml::element(&details, name).map(xml::text).filter(|value| !value.is_empty());
+    let number = |name: &str| field(name).and_then(|value| value.parse::<u64>().ok()).filter(|number| *number > 0);
+
+    let duration = number("durationinseconds")
+        .or_else(|| number("runtime").map(|minutes| minutes * 60))
+        .map(|seconds| format_duration(Duration::from_secs(seconds)));
+    let mut thumbnails: Vec<String> = Vec::new();
+    for artwork in NFO_ARTWORK.iter().flat_map(|name| xml::elements(&details, name)).map(xml::text) {
+        let is_local = !artwork.contains("://")
+            && !artwork.contains('<')
+            && Path::new(&artwork).components().all(|component| matches!(component, Component::Normal(_)));
+        if !artwork.is_empty() && is_local && !thumbnails.contains(&artwork) {
+            thumbnails.push(artwork);
+        }
+    }
+    let genres = xml::elements(&details, "genre").into_iter()
+        .map(xml::text)
+        .flat_map(|genres| genres.split('/').map(|genre| genre.trim().to_string()).collect::<Vec<_>>())
+        .filter(|genre| !genre.is_empty())
+        .collect();
+
+    Ok(Config {
+        title: field("title").ok_or("there's no title")?,
+        subtitle: field("plot").or_else(|| field("outline")),
+        duration,
+        text_track_language: None,
+        thumbnails,
+        genres,
+        show: field("showtitle"),
+        season: field("season").and_then(|season| season.parse().ok()),
+        episode: field("episode").and_then(|episode| episode.parse().ok()),
+    })
+}
+
+fn probe(source: &dyn MediaSource, prober: &Prober, key: &Path, path: &Path) -> Result<Probed, String> {
+    let local_path = source.local_path(path).ok_or("videos on this mount can't be probed")?;
+    let key = universal_path(key).ok_or("the path can't be used as a cache key")?;
+    match prober.probe(&key, &local_path) {
+        Ok(Some(probed)) => Ok(probed),
+        Ok(None) => Err("the video doesn't state its duration".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[derive(Default, Deserialize)]
+struct DirectoryConfig {
+    restricted: Option<String>,
+}
+
+fn directory_config(source: &dyn MediaSource, path: &Path) -> Result<DirectoryConfig, String> {
+    match source.read_to_string(&path.join(DIRECTORY_CONFIG_NAME)) {
+        Ok(text) => toml::from_str(&text).map_err(|err| err.to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(DirectoryConfig::default()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub fn language_track_path(video: &Path, language: &str) -> PathBuf {
+    let stem = video.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    video.with_file_name(format!("{}.{}.{}", stem, language, EXTENSION_VTT))
+}
+
+fn language_track(source: &dyn MediaSource, path: &Path) -> Option<(PathBuf, String)> {
+    if !subtitles::is_subtitles(path) || sibling_video(source, path).is_some() {
+        return None;
+    }
+
+    let (stem, language) = path.file_stem()?.to_str()?.rsplit_once('.')?;
+    let (primary, region) = match language.split_once('-') {
+        Some((primary, region)) => (primary, Some(region)),
+        None => (language, None),
+    };
+    let is_language = (2..=3).contains(&primary.len())
+        && primary.chars().all(|char| char.is_ascii_alphabetic())
+        && region.is_none_or(|region| (1..=4).contains(&region.len()) && region.chars().all(|char| char.is_ascii_alphanumeric()));
+    if !is_language {
+        return None;
+    }
+    video_with_stem(source, &path.with_file_name(stem)).map(|video| (video, language.to_string()))
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == EXTENSION_MP4 || extension == EXTENSION_MKV)
+}
+
+fn sibling_video(source: &dyn MediaSource, path: &Path) -> Option<PathBuf> {
+    video_with_stem(source, &path.with_file_name(path.file_stem()?))
+}
+
+fn video_with_stem(source: &dyn MediaSource, stem: &Path) -> Option<PathBuf> {
+    [EXTENSION_MP4, EXTENSION_MKV].iter()
+        .map(|extension| {
+            let mut video = stem.as_os_str().to_os_string();
+            video.push(format!(".{}", extension));
+            PathBuf::from(video)
+        })
+        .find(|video| source.is_file(video))
+}
+
+fn episode_of(path: &Path, show: Option<String>, season: Option<u32>, episode: Option<u32>) -> Option<Episode> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let marker = find_episode_marker(&stem);
+    let (season, episode) = match (season, episode, marker) {
+        (Some(season), Some(episode), _) => (season, episode),
+        (season, episode, Some((_, marker_season, marker_episode))) => (season.unwrap_or(marker_season), episode.unwrap_or(marker_episode)),
+        _ => return None,
+    };
+
+    let show = show.or_else(|| {
+        let prefix = stem[..marker.map_or(0, |(start, ..)| start)].replace(['.', '_'], " ");
+        Some(prefix.trim().trim_end_matches('-').trim().to_string()).filter(|prefix| !prefix.is_empty())
+    });
+    let show = show.or_else(|| path.parent()?.file_name().map(|name| name.to_string_lossy().into_owned()))?;
+    Some(Episode { show, season, episode })
+}
+
+fn find_episode_marker(stem: &str) -> Option<(usize, u32, u32)> {
+    let bytes = stem.as_bytes();
+    for start in 0..bytes.len() {
+        if !bytes[start].eq_ignore_ascii_case(&b's') || (start > 0 && bytes[start - 1].is_ascii_alphanumeric()) { continue; }
+
+        let season_end = start + 1 + bytes[start + 1..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+        if season_end == start + 1 || !bytes.get(season_end).is_some_and(|byte| byte.eq_ignore_ascii_case(&b'e')) { continue; }
+
+        let episode_end = season_end + 1 + bytes[season_end + 1..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+        if episode_end == season_end + 1 { continue; }
+
+        if let (Ok(season), Ok(episode)) = (stem[start + 1..season_end].parse(), stem[season_end + 1..episode_end].parse()) {
+            return Some((start, season, episode));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "issue", rename_all = "kebab-case")]
+pub enum Issue {
+    MissingSidecar { video: RelativizedPath },
+    UnparsableSidecar { sidecar: RelativizedPath, error: String },
+    BadDuration { sidecar: RelativizedPath, duration: String },
+    UnprobedDuration { sidecar: RelativizedPath, error: String },
+    OrphanedSubtitles { subtitles: RelativizedPath },
+    MissingArtwork { sidecar: RelativizedPath, artwork: String },
+    ProviderFailed { video: RelativizedPath, provider: String, error: String },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::MissingSidecar { video } => write!(f, "{}: no .{} or .{} sidecar", video.relative_path.display(), EXTENSION_TOML, EXTENSION_NFO),
+            Issue::UnparsableSidecar { sidecar, error } => write!(f, "{}: can't be parsed: {}", sidecar.relative_path.display(), error.trim_end()),
+            Issue::BadDuration { sidecar, duration } => write!(f, "{}: bad duration \"{}\"", sidecar.relative_path.display(), duration),
+            Issue::UnprobedDuration { sidecar, error } => write!(f, "{}: no duration and it couldn't be probed: {}", sidecar.relative_path.display(), error),
+            Issue::OrphanedSubtitles { subtitles } => write!(f, "{}: no matching video", subtitles.relative_path.display()),
+            Issue::MissingArtwork { sidecar, artwork } => write!(f, "{}: artwork {} doesn't exist", sidecar.relative_path.display(), artwork),
+            Issue::ProviderFailed { video, provider, error } => write!(f, "{}: the {} metadata provider failed: {}", video.relative_path.display(), provider, error),
+        }
+    }
 }
 
-pub fn extract_served_files(catalogue: &Vec<CatalogueItem>) -> HashSet<RelativizedPath> {
+pub fn extract_served_files(catalogue: &[CatalogueItem]) -> HashSet<RelativizedPath> {
     catalogue.iter()
         .flat_map(|item| {
             match item {
-                CatalogueItem::Video { path, text_tracks, .. } => text_tracks
+                CatalogueItem::Video { path, text_tracks, thumbnails, .. } => text_tracks
                     .values()
-                    .map(|path| path.clone())
+                    .chain(thumbnails)
+                    .cloned()
                     .chain(Some(path.clone()))
-                    .into_iter()
                     .collect(),
-                CatalogueItem::Directory { items, .. } => extract_served_files(items)
+                CatalogueItem::Directory { items, .. }
+                | CatalogueItem::Series { items, .. }
+                | CatalogueItem::Season { items, .. } => extract_served_files(items)
             }
         })
         .collect()
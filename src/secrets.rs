@@ -0,0 +1,35 @@
+use std::{error, path::Path};
+
+use keyring::Entry;
+
+const SERVICE: &str = "movie-nexus";
+const REFERENCE_PREFIX: &str = "keyring:";
+
+pub fn path_reference(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix(REFERENCE_PREFIX)
+}
+
+pub fn resolve(value: String) -> Result<String, Box<dyn error::Error>> {
+    match value.strip_prefix(REFERENCE_PREFIX) {
+        Some(name) => get(name),
+        None => Ok(value),
+    }
+}
+
+pub fn get(name: &str) -> Result<String, Box<dyn error::Error>> {
+    Entry::new(SERVICE, name)
+        .and_then(|entry| entry.get_password())
+        .map_err(|err| format!("Can't read the secret {} from the credential store: {}", name, err).into())
+}
+
+pub fn set(name: &str, secret: &str) -> Result<(), Box<dyn error::Error>> {
+    Entry::new(SERVICE, name)
+        .and_then(|entry| entry.set_password(secret))
+        .map_err(|err| format!("Can't store the secret {} in the credential store: {}", name, err).into())
+}
+
+pub fn delete(name: &str) -> Result<(), Box<dyn error::Error>> {
+    Entry::new(SERVICE, name)
+        .and_then(|entry| entry.delete_password())
+        .map_err(|err| format!("Can't delete the secret {} from the credential store: {}", name, err).into())
+}
\ No newline at end of file
@@ -0,0 +1,1972 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    convert::TryInto,
+    error,
+    fs,
+    future::Future,
+    io::Error,
+    net::{
+        IpAddr::{self, V4, V6},
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwapOption;
+use bytes::Bytes;
+use futures::{future, stream::{self, BoxStream}, FutureExt, StreamExt};
+use hyper::{
+    body::HttpBody,
+    Body,
+    HeaderMap,
+    http::{request::Parts, HeaderValue},
+    Method,
+    Request,
+    Response,
+    server::{
+        conn::{AddrIncoming, AddrStream},
+        Builder,
+        Server,
+    },
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    StatusCode,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use socket2::{Domain, Socket, Type};
+use tracing::{debug, error, info, warn};
+
+use crate::app_state::{AppState, PartialCatalogue};
+use crate::auth::{self, ApiKeys, BasicAuth, Throttle};
+use crate::byte_range::{ByteRange, parse_range};
+use crate::cache;
+use crate::config::{Role, Settings, StateBackend, UserProfile};
+use crate::csrf::CsrfGuard;
+use crate::encoding::{self, ContentEncoding};
+use crate::feed;
+use crate::hls;
+use crate::kodi;
+use crate::jellyfin::{self, Jellyfin};
+use crate::m3u;
+use crate::openapi;
+use crate::opensubtitles::OpenSubtitles;
+use crate::manifest::{self, EncodedManifest};
+use crate::media_source::{self, LocalSource, MediaSource, Mount};
+use crate::network::ServiceRegistration;
+use crate::proxy_auth::ProxyAuth;
+use crate::query::{CastQuery, HistoryQuery, ManifestFormat, ManifestQuery, PlaylistQuery, ThumbnailQuery, TranscodeQuery};
+use crate::request_path;
+use crate::access::AccessList;
+use crate::accounts::Accounts;
+use crate::api;
+use crate::metadata::{self, MetadataProvider};
+use crate::probe::Prober;
+use crate::thumbnails::Thumbnails;
+use crate::transcode::{self, TranscodeError, Transcodes};
+use crate::watcher;
+use crate::scanner::{is_video, item_id, scan_directory_with, scan_source, CatalogueItem, ScanCache};
+use crate::signing::{encode_hex, UrlSigner};
+use crate::diff::diff_manifests;
+use crate::events::Events;
+use crate::file_stream::{BufferPool, SmallFiles, StreamBudget};
+use crate::persisted_state::{JsonFile, PersistedState, RedbFile};
+use crate::state::{self, MemoryState, ScanRecord, SqliteState, StateStore};
+use crate::subtitles;
+use crate::stats::{self, ViewingStats};
+use crate::store::{CatalogueStore, MemoryStore, SqliteStore};
+use crate::update;
+use crate::viewer::{self, Restriction, Viewer};
+use crate::webdav;
+use crate::xml;
+#[cfg(target_os = "linux")]
+use crate::systemd;
+use crate::tls;
+use crate::trakt::{self, Trakt};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+const PATH_MANIFEST: &str = "/";
+const PATH_CATALOGUE: &str = "/catalogue";
+const PATH_HEALTH: &str = "/health";
+const PATH_OPENAPI: &str = "/openapi.json";
+const PATH_CSRF_TOKEN: &str = "/csrf-token";
+const PATH_LOGIN: &str = "/login";
+const PATH_PLAYLIST_M3U: &str = "/playlist.m3u";
+const PATH_PLAYLIST_M3U8: &str = "/playlist.m3u8";
+const PATH_FEED: &str = "/feed.xml";
+const PATH_KODI: &str = "/kodi";
+const PATH_WEBDAV: &str = "/dav";
+const PATH_WEBDAV_PREFIX: &str = "/dav/";
+const PATH_HLS_PREFIX: &str = "/hls/";
+const PATH_TRANSCODE_PREFIX: &str = "/transcode/";
+const PATH_THUMBNAIL_PREFIX: &str = "/thumb/";
+const PATH_TRANSCODES: &str = "/transcodes";
+const PATH_TRANSCODE_SESSION_PREFIX: &str = "/transcodes/";
+pub const PATH_FILE_PREFIX: &str = "/file/";
+const PATH_PROGRESS: &str = "/progress";
+const PATH_PROGRESS_PREFIX: &str = "/progress/";
+const PATH_EVENTS: &str = "/events";
+const PATH_CONTINUE_WATCHING: &str = "/continue-watching";
+const PATH_UP_NEXT: &str = "/up-next";
+const PATH_HISTORY: &str = "/history";
+const PATH_VIEWING_STATS: &str = "/stats/viewing";
+const PATH_WATCHED_PREFIX: &str = "/watched/";
+const PATH_ITEM_PREFIX: &str = "/item/";
+const PATH_ITEMS: &str = "/items";
+const PATH_CAST_PREFIX: &str = "/cast/";
+const PATH_FAVORITES: &str = "/favorites";
+const PATH_FAVORITE_PREFIX: &str = "/favorites/";
+const PATH_BOOKMARKS_PREFIX: &str = "/bookmarks/";
+const PATH_RATING_PREFIX: &str = "/ratings/";
+const PATH_METADATA_PREFIX: &str = "/metadata/";
+const PATH_SESSIONS: &str = "/sessions";
+const PATH_SESSION_PREFIX: &str = "/sessions/";
+const PATH_TRAKT: &str = "/trakt";
+const PATH_ADMIN_PREFIX: &str = "/admin/";
+const PATH_ADMIN_EXPORT_STATE: &str = "/admin/export-state";
+const PATH_ADMIN_IMPORT_STATE: &str = "/admin/import-state";
+const PATH_ADMIN_SCANS: &str = "/admin/scans";
+const PATH_ADMIN_MISSING: &str = "/admin/missing";
+const PATH_ADMIN_TOKENS: &str = "/admin/tokens";
+const PATH_ADMIN_TOKEN_PREFIX: &str = "/admin/tokens/";
+const PATH_PLAYLISTS: &str = "/playlists";
+const PATH_PLAYLIST_PREFIX: &str = "/playlists/";
+
+const SAFE_METHODS: &str = "GET, HEAD, OPTIONS";
+
+const ALLOWED_ORIGIN: &str = "*";
+const MAX_AGE: u32 = 48 * 60 * 60;
+const SHARE_RETRY_AFTER: &str = "30";
+const TRANSCODE_RETRY_AFTER: &str = "10";
+const ETAG_LENGTH: usize = 16;
+const VTT_CONTENT_TYPE: &str = "text/vtt; charset=utf-8";
+const MULTIPART_BOUNDARY_LEN: usize = 16;
+const MAX_BYTE_RANGES: usize = 16;
+
+const LIBRARY_TITLE: &str = "Movie Nexus";
+
+#[derive(Clone, Copy)]
+enum Export {
+    M3u,
+    Feed,
+    Kodi,
+}
+
+pub struct State {
+    pub store: Box<dyn CatalogueStore>,
+    pub user_state: Box<dyn StateStore>,
+    pub events: Events,
+    pub viewing_stats: Mutex<Option<ViewingStats>>,
+    app: ArcSwapOption<AppState>,
+    pub available_update: Mutex<Option<String>>,
+    pub profiles: HashMap<String, UserProfile>,
+    restricted_folders: Vec<Restriction>,
+    admin_token: Option<String>,
+    missing_grace_period: Duration,
+    buffer_pool: Arc<BufferPool>,
+    case_insensitive_paths: bool,
+    read_only: bool,
+    basic_auth: Option<BasicAuth>,
+    api_keys: ApiKeys,
+    pub url_signer: Option<UrlSigner>,
+    access: AccessList,
+    csrf: CsrfGuard,
+    pub throttle: Throttle,
+    pub accounts: Accounts,
+    proxy_auth: Option<ProxyAuth>,
+    pub jellyfin: Option<Jellyfin>,
+    pub trakt: Option<Trakt>,
+    webdav: bool,
+    ffmpeg: Option<PathBuf>,
+    pub transcodes: Option<Transcodes>,
+    pub thumbnails: Option<Thumbnails>,
+    metadata_providers: Vec<Arc<dyn MetadataProvider>>,
+    ffprobe: Option<PathBuf>,
+    fallback_metadata: bool,
+    local_files: LocalSource,
+    mounts: Vec<Mount>,
+    cors_origin: HeaderValue,
+    scanning: Mutex<()>,
+    scan_cache: ScanCache,
+}
+
+impl State {
+    pub fn open(settings: &Settings) -> Result<State, Box<dyn error::Error>> {
+        let store: Box<dyn CatalogueStore> = match settings.database {
+            Some(ref path) => Box::new(SqliteStore::open(path)?),
+            None => Box::new(MemoryStore::default()),
+        };
+        let user_state = open_user_state(settings)?;
+
+        Ok(State {
+            store,
+            user_state,
+            events: Events::default(),
+            viewing_stats: Mutex::new(None),
+            app: ArcSwapOption::empty(),
+            available_update: Mutex::new(None),
+            profiles: settings.users.clone().unwrap_or_default(),
+            restricted_folders: settings.restricted_folders.iter()
+                .flatten()
+                .map(|(folder, role)| Restriction { folder: folder.clone(), role: role.clone() })
+                .collect(),
+            admin_token: settings.admin_token.clone(),
+            missing_grace_period: settings.missing_grace_period(),
+            buffer_pool: Arc::new(BufferPool::new(settings.stream_buffer_size())),
+            case_insensitive_paths: settings.case_insensitive_paths(),
+            read_only: settings.read_only(),
+            basic_auth: settings.basic_auth.as_ref().map(BasicAuth::new).transpose()?,
+            api_keys: ApiKeys::new(settings.api_keys.as_deref().unwrap_or_default())?,
+            url_signer: if settings.signed_urls() {
+                Some(UrlSigner::new(settings.url_signing_key.as_deref(), settings.signed_url_lifetime())?)
+            } else {
+                None
+            },
+            access: AccessList::new(
+                settings.allowed_networks.as_deref().unwrap_or_default(),
+                settings.denied_networks.as_deref().unwrap_or_default(),
+            )?,
+            csrf: CsrfGuard::new(settings.trusted_origins.as_deref().unwrap_or_default())?,
+            throttle: Throttle::default(),
+            proxy_auth: settings.proxy_auth.as_ref().map(ProxyAuth::new).transpose()?,
+            accounts: Accounts::new(settings.accounts.clone().unwrap_or_default(), settings.jwt_secret.as_deref(), settings.login_lifetime())?,
+            jellyfin: if settings.jellyfin() { Some(Jellyfin::new(settings.folder.as_deref())) } else { None },
+            trakt: settings.trakt.as_ref().map(Trakt::new),
+            webdav: settings.webdav(),
+            ffmpeg: settings.ffmpeg.clone(),
+            transcodes: settings.ffmpeg.as_ref()
+                .map(|ffmpeg| Transcodes::new(ffmpeg, settings.transcode_profiles(), settings.max_transcodes())),
+            thumbnails: match (settings.thumbnails(), &settings.ffmpeg, &settings.folder) {
+                (true, Some(ffmpeg), Some(folder)) => Some(Thumbnails::new(ffmpeg, cache::thumbnails_dir(folder)?)),
+                _ => None,
+            },
+            metadata_providers: metadata::providers(settings)?,
+            ffprobe: settings.ffprobe.clone(),
+            fallback_metadata: settings.fallback_metadata(),
+            local_files: LocalSource::default(),
+            mounts: media_source::mounts(settings)?,
+            cors_origin: HeaderValue::from_str(settings.cors_origin())
+                .map_err(|_| format!("The CORS origin {} isn't a valid header value", settings.cors_origin()))?,
+            scanning: Mutex::new(()),
+            scan_cache: ScanCache::default(),
+        })
+    }
+
+    fn app_state(&self) -> Result<Arc<AppState>, Box<dyn error::Error>> {
+        if let Some(app) = self.app.load_full() {
+            return Ok(app);
+        }
+
+        let app = Some(self.prepare(AppState::load(&*self.store)?));
+        let published = self.app.compare_and_swap(&None::<Arc<AppState>>, app.clone());
+        Ok(published.clone().or(app).unwrap())
+    }
+
+    fn prepare(&self, mut app: AppState) -> Arc<AppState> {
+        app.restrictions.extend(self.restricted_folders.iter().cloned());
+        Arc::new(if self.case_insensitive_paths { app.with_case_insensitive_paths() } else { app })
+    }
+
+    fn mount<'k>(&self, key: &'k str) -> Option<(&Mount, &'k str)> {
+        let (mount, rest) = key.split_once('/').unwrap_or((key, ""));
+        self.mounts.iter().find(|candidate| candidate.name == mount).map(|mount| (mount, rest))
+    }
+
+    pub fn media_source(&self, key: &str, path: &Path) -> (&dyn MediaSource, PathBuf) {
+        match self.mount(key) {
+            Some((mount, rest)) => (&*mount.source, PathBuf::from(rest)),
+            None => (&self.local_files, path.to_path_buf()),
+        }
+    }
+
+    pub fn requires_authentication(&self) -> bool {
+        self.basic_auth.is_some() || !self.api_keys.is_empty()
+    }
+
+    pub fn verifies_credentials(&self, username: &str, password: &str) -> bool {
+        self.basic_auth.as_ref().is_some_and(|basic_auth| basic_auth.verifies(username, password)) || self.api_keys.contains(password)
+    }
+
+    pub fn permits_link(&self, path: &str, query: Option<&str>) -> bool {
+        self.url_signer.as_ref().is_none_or(|signer| signer.permits(&request_path::encode(path), query))
+    }
+
+    pub fn has_local_files(&self, key: &str) -> bool {
+        self.mount(key).is_none_or(|(mount, _)| mount.source.local_path(Path::new("")).is_some())
+    }
+
+    pub fn mount_folders(&self) -> Vec<PathBuf> {
+        self.mounts.iter().filter_map(|mount| mount.source.local_path(Path::new(""))).collect()
+    }
+
+    pub fn rescan(&self, folder: &Path) -> Result<(), Box<dyn error::Error>> {
+        self.rescan_changes(folder, None)
+    }
+
+    pub fn rescan_changes(&self, folder: &Path, changed: Option<&[PathBuf]>) -> Result<(), Box<dyn error::Error>> {
+        let _scanning = self.scanning.lock().unwrap();
+        match changed {
+            Some(changed) => changed.iter().for_each(|path| self.forget_scanned(folder, path)),
+            None => self.scan_cache.clear(),
+        }
+        let (started, start) = (state::now(), Instant::now());
+        let has_catalogue = self.store.has_catalogue()?;
+        let previous = if has_catalogue {
+            serde_json::from_str(&self.store.manifest()?)?
+        } else {
+            serde_json::Value::Array(Vec::new())
+        };
+
+        let mut partial = PartialCatalogue::new();
+        let mut on_video = |video: &CatalogueItem| {
+            if has_catalogue { return; }
+            if let Err(err) = partial.add(video) {
+                warn!("Couldn't add a scanned item to the partial catalogue: {}", err);
+            }
+            if partial.due() {
+                match partial.publish() {
+                    Ok(app) => self.app.store(Some(self.prepare(app))),
+                    Err(err) => warn!("Couldn't publish the partial catalogue: {}", err),
+                }
+            }
+        };
+        let prober = Prober::open(folder, self.ffprobe.as_deref());
+        let catalogue = scan_library(folder, &self.metadata_providers, &prober, self.fallback_metadata, &self.mounts, Some(&self.scan_cache), &mut on_video)?;
+        prober.save();
+        self.store.update(&catalogue, started)?;
+
+        let purged = self.store.purge_missing(started.saturating_sub(self.missing_grace_period.as_secs()))?;
+        if !purged.is_empty() {
+            info!("Forgetting {} item(s) missing for longer than the grace period", purged.len());
+            self.user_state.forget_items(&purged)?;
+        }
+
+        let manifest = self.store.manifest()?;
+        if let Err(err) = cache::store_manifest(folder, &manifest) {
+            warn!("Couldn't update the catalogue cache: {}", err);
+        }
+
+        let app = AppState::new(EncodedManifest::new(&manifest)?, self.store.served_files()?);
+        let diff = diff_manifests(&previous, &app.manifest.value);
+        self.app.store(Some(self.prepare(app)));
+
+        let scan = ScanRecord {
+            id: 0,
+            started,
+            duration_ms: start.elapsed().as_millis() as u64,
+            added: diff.added,
+            removed: diff.removed,
+            changed: diff.changed,
+        };
+        if let Err(err) = self.user_state.record_scan(&scan) {
+            warn!("Couldn't record the scan: {}", err);
+        }
+        Ok(())
+    }
+
+    fn forget_scanned(&self, folder: &Path, path: &Path) {
+        if let Ok(relative) = path.strip_prefix(folder) {
+            self.scan_cache.invalidate(Path::new(""), relative);
+        }
+        for mount in &self.mounts {
+            let relative = mount.source.local_path(Path::new("")).and_then(|root| Some(path.strip_prefix(root).ok()?.to_path_buf()));
+            if let Some(relative) = relative {
+                self.scan_cache.invalidate(Path::new(&mount.name), &relative);
+            }
+        }
+    }
+}
+
+pub fn open_user_state(settings: &Settings) -> Result<Box<dyn StateStore>, Box<dyn error::Error>> {
+    Ok(match (settings.state_backend(), &settings.state_file, &settings.database) {
+        (StateBackend::Sqlite, Some(path), _) | (StateBackend::Sqlite, None, Some(path)) => Box::new(SqliteState::open(path)?),
+        (StateBackend::Sqlite, None, None) => Box::new(MemoryState::default()),
+        (StateBackend::Json, Some(path), _) => Box::new(PersistedState::open(JsonFile::new(path))?),
+        (StateBackend::Redb, Some(path), _) => Box::new(PersistedState::open(RedbFile::open(path)?)?),
+        (_, None, _) => return Err("The JSON and redb state backends need a state file".into()),
+    })
+}
+
+pub fn scan_library(
+    folder: &Path,
+    providers: &[Arc<dyn MetadataProvider>],
+    prober: &Prober,
+    fallback: bool,
+    mounts: &[Mount],
+    cache: Option<&ScanCache>,
+    on_video: &mut dyn FnMut(&CatalogueItem),
+) -> Result<Vec<CatalogueItem>, Error> {
+    let mut catalogue = scan_directory_with(folder, folder, providers, prober, fallback, cache, on_video)?;
+    for mount in mounts {
+        let scanned = scan_source(&*mount.source, folder, Path::new(&mount.name), providers, prober, fallback, cache, on_video);
+        mount.record(&scanned);
+        let items = match scanned {
+            Ok(items) => items,
+            Err(err) => {
+                warn!("Couldn't scan the mount {}: {}", mount.name, err);
+                continue;
+            }
+        };
+
+        let hidden = catalogue.len();
+        catalogue.retain(|item| !matches!(item, CatalogueItem::Directory { name, .. } if *name == mount.name));
+        if catalogue.len() != hidden {
+            warn!("The mount {} hides the library folder of the same name", mount.name);
+        }
+        catalogue.push(CatalogueItem::Directory { name: mount.name.clone(), items, restricted: None });
+    }
+    Ok(catalogue)
+}
+
+pub async fn rescan_in_background(state: &Arc<State>, folder: &Path) {
+    rescan_changes_in_background(state, folder, None).await
+}
+
+pub async fn rescan_changes_in_background(state: &Arc<State>, folder: &Path, changed: Option<Vec<PathBuf>>) {
+    let (state, folder) = (state.clone(), folder.to_path_buf());
+    let rescanned = tokio::task::spawn_blocking(move || state.rescan_changes(&folder, changed.as_deref()).map_err(|err| err.to_string())).await;
+    match rescanned {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => warn!("Couldn't rescan the library: {}", err),
+        Err(err) => warn!("Couldn't rescan the library: {}", err),
+    }
+}
+
+pub async fn run(
+    folder: &Path,
+    settings: &Settings,
+    providers: Vec<Arc<dyn MetadataProvider>>,
+    shutdown: impl Future<Output=()> + Send + 'static,
+) -> Result<(), Box<dyn error::Error>> {
+    let port = settings.port();
+    let _registration = match ServiceRegistration::register(port, settings.service_name()) {
+        Ok(registration) => Some(registration),
+        Err(err) => {
+            warn!("Couldn't announce the server on the local network: {}", err);
+            None
+        }
+    };
+
+    let tls = match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key, settings.tls_client_ca.as_deref())?),
+        (None, None) if settings.tls() => {
+            let (cert, key) = tls::self_signed()?;
+            Some(tls::acceptor(&cert, &key, settings.tls_client_ca.as_deref())?)
+        }
+        (None, None) => None,
+        _ => return Err("HTTPS needs both a certificate and a key".into()),
+    };
+    let redirect = settings.redirect_http() || settings.tls_client_ca.is_some();
+    let redirect_port = if redirect && tls.is_some() { Some(settings.tls_port()) } else { None };
+
+    let mut state = State::open(settings)?;
+    state.metadata_providers.extend(providers);
+    let state = Arc::new(state);
+    if state.store.has_catalogue()? {
+        info!("Serving the stored catalogue while the library is rescanned");
+    } else {
+        info!("Serving the catalogue as the library is scanned");
+    }
+
+    {
+        let (state, folder) = (state.clone(), folder.to_path_buf());
+        let subtitles = settings.opensubtitles.as_ref().map(OpenSubtitles::new);
+        tokio::spawn(async move {
+            rescan_in_background(&state, &folder).await;
+            if let Some(subtitles) = subtitles {
+                if subtitles.fetch_missing(&state, &folder).await > 0 {
+                    rescan_in_background(&state, &folder).await;
+                }
+            }
+        });
+    }
+
+    if let Some(interval) = settings.rescan_interval() {
+        let (state, folder) = (state.clone(), folder.to_path_buf());
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                rescan_in_background(&state, &folder).await;
+            }
+        });
+    }
+
+    if settings.watch() {
+        let ignored = [&settings.database, &settings.state_file, &settings.log_file].iter().copied().flatten().cloned().collect();
+        if let Err(err) = watcher::start(state.clone(), folder.to_path_buf(), ignored) {
+            warn!("Couldn't watch the library for changes: {}", err);
+        }
+    }
+
+    tokio::spawn(stats::aggregate_periodically(state.clone()));
+    if state.transcodes.is_some() {
+        tokio::spawn(transcode::reap_abandoned(state.clone()));
+    }
+    if state.trakt.is_some() {
+        tokio::spawn(trakt::deliver(state.clone()));
+    }
+
+    if settings.check_updates() {
+        tokio::spawn(update::watch_releases(state.clone()));
+    }
+
+    let service_state = state.clone();
+    let service = make_service_fn(move |conn: &AddrStream| {
+        let (state, budget, remote) = (service_state.clone(), StreamBudget::default(), conn.remote_addr());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(budget.clone());
+                request.extensions_mut().insert(remote);
+                handle(state.clone(), request)
+            }))
+        }
+    });
+
+    let shutdown = async move {
+        shutdown.await;
+
+        #[cfg(target_os = "linux")]
+        systemd::notify_stopping();
+    }.shared();
+
+    let addresses = bind_addresses(settings);
+    let mut handles: Vec<_> = bind_servers(&addresses, port)?
+        .into_iter()
+        .map(|builder| match redirect_port {
+            Some(tls_port) => {
+                let state = state.clone();
+                let redirect = make_service_fn(move |conn: &AddrStream| {
+                    let permitted = state.access.permits(conn.remote_addr().ip());
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                            future::ok::<_, Infallible>(if permitted { tls::redirect(&request, tls_port) } else { forbidden_address() })
+                        }))
+                    }
+                });
+                tokio::spawn(builder.serve(redirect).with_graceful_shutdown(shutdown.clone()))
+            }
+            None => tokio::spawn(builder.serve(service.clone()).with_graceful_shutdown(shutdown.clone())),
+        })
+        .collect();
+
+    if let Some(acceptor) = tls {
+        for ip_addr in &addresses {
+            let listener = tokio::net::TcpListener::from_std(bind_listener(SocketAddr::from((*ip_addr, settings.tls_port())))?)?;
+            let served = tls::serve(listener, acceptor.clone(), state.clone(), shutdown.clone());
+            handles.push(tokio::spawn(served.map(Ok)));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    systemd::notify_ready();
+
+    if let (Err(e), ..) = future::select_all(handles).await {
+        error!("Server error: {}", e);
+    }
+
+    Ok(())
+}
+
+struct RequestLog {
+    method: Method,
+    path: String,
+    range: Option<String>,
+    client: Option<IpAddr>,
+    started: Instant,
+    status: StatusCode,
+    bytes: u64,
+}
+
+impl RequestLog {
+    fn new(request: &Request<Body>) -> RequestLog {
+        RequestLog {
+            method: request.method().clone(),
+            path: request.uri().path().to_string(),
+            range: request.headers().get("Range").and_then(|value| value.to_str().ok()).map(str::to_string),
+            client: request.extensions().get::<SocketAddr>().map(|remote| remote.ip().to_canonical()),
+            started: Instant::now(),
+            status: StatusCode::OK,
+            bytes: 0,
+        }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for RequestLog {
+    fn drop(&mut self) {
+        info!(
+            method = %self.method,
+            path = %self.path,
+            status = self.status.as_u16(),
+            range = self.range.as_deref(),
+            bytes = self.bytes,
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            client = self.client.map(|client| client.to_string()).as_deref(),
+            "{} {} {}", self.method, self.path, self.status.as_u16()
+        );
+    }
+}
+
+pub async fn handle(state: Arc<State>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let cors_origin = state.cors_origin.clone();
+    let mut log = RequestLog::new(&request);
+    let mut response = respond(state, request).await?;
+    if response.headers().contains_key("Access-Control-Allow-Origin") {
+        response.headers_mut().insert("Access-Control-Allow-Origin", cors_origin);
+    }
+
+    log.status = response.status();
+    match response.body().size_hint().exact() {
+        Some(bytes) => log.bytes = bytes,
+        None => {
+            let body = std::mem::take(response.body_mut());
+            *response.body_mut() = Body::wrap_stream(body.inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    log.record(chunk.len());
+                }
+            }));
+        }
+    }
+    Ok(response)
+}
+
+async fn respond(state: Arc<State>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let remote = request.extensions().get::<SocketAddr>().copied();
+    if let Some(remote) = remote {
+        if !state.access.permits(remote.ip()) {
+            debug!("Refused a request from {}", remote);
+            return Ok(forbidden_address());
+        }
+    }
+
+    let mut response = Response::new(Body::empty());
+    let attempt = auth::attempt_keys(remote.map(|remote| remote.ip()), request.headers());
+    if request.method() != Method::OPTIONS {
+        if let Some(retry_after) = state.throttle.locked_for(&attempt) {
+            add_common_cors_headers(&mut response);
+            auth::too_many_attempts(retry_after, &mut response);
+            return Ok(response);
+        }
+    }
+
+    let signed = request.uri().path().strip_prefix(PATH_FILE_PREFIX)
+        .zip(state.url_signer.as_ref())
+        .is_some_and(|(path, signer)| signer.permits(path, request.uri().query()));
+
+    let account = match state.accounts.authenticate(request.headers()) {
+        Ok(account) => account,
+        Err(err) => {
+            state.throttle.record_failure(&attempt);
+            add_common_cors_headers(&mut response);
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            *response.body_mut() = Body::from(err);
+            return Ok(response);
+        }
+    };
+    let proxied = match state.proxy_auth.as_ref().map_or(Ok(None), |proxy_auth| proxy_auth.profile(remote.map(|remote| remote.ip()), request.headers())) {
+        Ok(proxied) => proxied,
+        Err(err) => {
+            add_common_cors_headers(&mut response);
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            *response.body_mut() = Body::from(err);
+            return Ok(response);
+        }
+    };
+    let jellyfin_path = state.jellyfin.is_some() && jellyfin::handles(request.uri().path());
+    let logging_in = request.uri().path() == PATH_LOGIN || (jellyfin_path && jellyfin::is_public(request.uri().path()));
+    let querying = jellyfin_path && jellyfin::is_query(request.uri().path());
+
+    let by_api_key = state.api_keys.permits(request.headers(), request.uri().query());
+    if state.requires_authentication() {
+        let by_password = state.basic_auth.as_ref().is_some_and(|basic_auth| basic_auth.permits(request.headers()));
+        if by_password || by_api_key {
+            state.throttle.record_success(&attempt);
+        }
+        let permitted = signed
+            || logging_in
+            || account.is_some()
+            || proxied.is_some()
+            || by_password
+            || by_api_key
+            || viewer::has_valid_session(state.user_state.as_ref(), request.headers(), request.uri().query());
+        if request.method() != Method::OPTIONS && !permitted {
+            if viewer::presents_credentials(request.headers(), request.uri().query()) {
+                state.throttle.record_failure(&attempt);
+            }
+            add_common_cors_headers(&mut response);
+            match state.basic_auth {
+                Some(_) => auth::challenge(&mut response),
+                None => auth::bearer_challenge(&mut response),
+            }
+            return Ok(response);
+        }
+    }
+
+    if state.read_only && !is_safe_method(request.method()) && !logging_in && !querying {
+        add_common_cors_headers(&mut response);
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        response.headers_mut().insert("Allow", HeaderValue::from_static(SAFE_METHODS));
+        *response.body_mut() = Body::from("The server is in read-only mode");
+        return Ok(response);
+    }
+
+    if !is_safe_method(request.method()) {
+        if let Err(err) = state.csrf.check(request.headers()) {
+            add_common_cors_headers(&mut response);
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            *response.body_mut() = Body::from(err);
+            return Ok(response);
+        }
+    }
+
+    let (parts, body) = request.into_parts();
+    let app = match state.app_state() {
+        Ok(app) => app,
+        Err(err) => {
+            add_common_cors_headers(&mut response);
+            api::internal_error("Couldn't read the catalogue", err, &mut response);
+            return Ok(response);
+        }
+    };
+    let viewer = match (&account, &proxied) {
+        (Some(account), _) => Viewer::for_account(&state.profiles, &app.restrictions, &account.profile),
+        (None, Some(profile)) => Viewer::for_account(&state.profiles, &app.restrictions, profile),
+        (None, None) if by_api_key => Viewer::for_account(&state.profiles, &app.restrictions, ""),
+        (None, None) => Viewer::resolve(&state.profiles, &app.restrictions, state.user_state.as_ref(), &parts.headers, parts.uri.query()),
+    };
+    let viewer = match viewer {
+        Ok(viewer) => viewer,
+        Err(err) => {
+            state.throttle.record_failure(&attempt);
+            add_common_cors_headers(&mut response);
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            *response.body_mut() = Body::from(err);
+            return Ok(response);
+        }
+    };
+
+    let admin = viewer::is_admin(state.admin_token.as_deref(), &parts.headers) || account.as_ref().is_some_and(|account| account.role == Role::Admin);
+    if !state.accounts.is_empty() {
+        if let Some(role) = required_role(&parts.method, parts.uri.path()) {
+            if !admin && !account.as_ref().is_some_and(|account| account.role >= role) {
+                add_common_cors_headers(&mut response);
+                *response.status_mut() = if account.is_some() { StatusCode::FORBIDDEN } else { StatusCode::UNAUTHORIZED };
+                *response.body_mut() = Body::from("Your role doesn't allow this");
+                return Ok(response);
+            }
+        }
+    }
+
+    match (&parts.method, parts.uri.path()) {
+        (&Method::GET, PATH_MANIFEST) | (&Method::HEAD, PATH_MANIFEST) => serve_manifest(&state, &viewer, &parts, &mut response),
+        (&Method::GET, PATH_CATALOGUE) | (&Method::HEAD, PATH_CATALOGUE) => {
+            add_common_cors_headers(&mut response);
+            serve_catalogue(&state, &viewer, &parts.headers, &mut response);
+        }
+        (&Method::GET, PATH_HEALTH) => serve_health(&state.available_update, &state.mounts, &mut response),
+        (&Method::GET, PATH_OPENAPI) => {
+            add_common_cors_headers(&mut response);
+            api::write_json(&openapi::document(&origin(&parts)), &mut response);
+        }
+        (&Method::OPTIONS, PATH_LOGIN) => {
+            add_common_cors_headers(&mut response);
+            add_preflight_headers("POST", &mut response);
+        }
+        (&Method::POST, PATH_LOGIN) => {
+            add_common_cors_headers(&mut response);
+            api::login(&state, &attempt, body, &mut response).await;
+        }
+        (&Method::GET, PATH_PLAYLIST_M3U) | (&Method::GET, PATH_PLAYLIST_M3U8) => {
+            add_common_cors_headers(&mut response);
+            serve_export(&state, &app, &viewer, &parts, Export::M3u, &mut response);
+        }
+        (&Method::GET, PATH_FEED) => {
+            add_common_cors_headers(&mut response);
+            serve_export(&state, &app, &viewer, &parts, Export::Feed, &mut response);
+        }
+        (&Method::GET, PATH_KODI) => {
+            add_common_cors_headers(&mut response);
+            serve_export(&state, &app, &viewer, &parts, Export::Kodi, &mut response);
+        }
+        (method, _) if jellyfin_path => {
+            add_common_cors_headers(&mut response);
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, POST, DELETE", &mut response),
+                _ => jellyfin::handle(&state, &app, &viewer, &attempt, &parts, body, &mut response).await,
+            }
+        }
+        (&Method::GET, path) if state.ffmpeg.is_some() && path.starts_with(PATH_HLS_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            serve_hls(&state, &app, &viewer, &parts, path.strip_prefix(PATH_HLS_PREFIX).unwrap(), &mut response);
+        }
+        (&Method::GET, path) if state.thumbnails.is_some() && path.starts_with(PATH_THUMBNAIL_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            serve_thumbnail(&state, &app, &viewer, &parts, path.strip_prefix(PATH_THUMBNAIL_PREFIX).unwrap(), &mut response).await;
+        }
+        (&Method::GET, path) if state.transcodes.is_some() && path.starts_with(PATH_TRANSCODE_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            serve_transcode(&state, &app, &viewer, &parts, path.strip_prefix(PATH_TRANSCODE_PREFIX).unwrap(), &mut response);
+        }
+        (method, PATH_TRANSCODES) if state.transcodes.is_some() => {
+            add_common_cors_headers(&mut response);
+
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET", &mut response),
+                Method::GET => api::serve_transcodes(&state, &viewer, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if state.transcodes.is_some() && path.starts_with(PATH_TRANSCODE_SESSION_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let session = path.strip_prefix(PATH_TRANSCODE_SESSION_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("DELETE", &mut response),
+                Method::DELETE => api::stop_transcode(&state, &viewer, session, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (_, path) if state.webdav && (path == PATH_WEBDAV || path.starts_with(PATH_WEBDAV_PREFIX)) => {
+            add_common_cors_headers(&mut response);
+            serve_webdav(&state, &app, &viewer, &parts, &mut response).await;
+        }
+        (&Method::GET, PATH_CSRF_TOKEN) => {
+            response.headers_mut().insert("Cache-Control", HeaderValue::from_static("no-store"));
+            api::write_json(&serde_json::json!({ "token": state.csrf.token(&parts.headers) }), &mut response);
+        }
+        (&Method::GET, PATH_ITEMS) => {
+            add_common_cors_headers(&mut response);
+            api::serve_items(&state, &viewer, parts.uri.query(), &mut response);
+        }
+        (&Method::GET, path) if path.starts_with(PATH_ITEM_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            api::serve_item(&state, &viewer, path.strip_prefix(PATH_ITEM_PREFIX).unwrap(), &mut response);
+        }
+        (&Method::GET, path) if path.starts_with(PATH_CAST_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            serve_cast(&state, &viewer, &parts, path.strip_prefix(PATH_CAST_PREFIX).unwrap(), &mut response);
+        }
+        (&Method::GET, PATH_EVENTS) => {
+            add_common_cors_headers(&mut response);
+            serve_events(&state, &viewer, parts.uri.query(), &mut response);
+        }
+        (&Method::GET, PATH_HISTORY) => {
+            add_common_cors_headers(&mut response);
+            serve_history(&state, &viewer, &app.restrictions, parts.uri.query(), &mut response);
+        }
+        (&Method::GET, PATH_VIEWING_STATS) => {
+            add_common_cors_headers(&mut response);
+            api::serve_viewing_stats(&state, &viewer, &mut response);
+        }
+        (&Method::GET, PATH_UP_NEXT) => {
+            add_common_cors_headers(&mut response);
+            api::serve_up_next(&state, &viewer, &mut response);
+        }
+        (&Method::GET, PATH_CONTINUE_WATCHING) => {
+            add_common_cors_headers(&mut response);
+            api::serve_continue_watching(&state, &viewer, &mut response);
+        }
+        (&Method::GET, PATH_PROGRESS) => {
+            add_common_cors_headers(&mut response);
+            api::serve_all_progress(&state, &viewer, &mut response);
+        }
+        (method, path) if path.starts_with(PATH_PROGRESS_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_PROGRESS_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, POST", &mut response),
+                Method::GET => api::serve_progress(&state, &viewer, id, &mut response),
+                Method::POST => api::update_progress(&state, &viewer, id, body, &mut response).await,
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_WATCHED_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_WATCHED_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("PUT, DELETE", &mut response),
+                Method::PUT => api::update_watched(&state, &viewer, id, true, &mut response),
+                Method::DELETE => api::update_watched(&state, &viewer, id, false, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (&Method::GET, PATH_FAVORITES) => {
+            add_common_cors_headers(&mut response);
+            api::serve_favorites(&state, &viewer, &mut response);
+        }
+        (method, path) if path.starts_with(PATH_FAVORITE_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_FAVORITE_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("PUT, DELETE", &mut response),
+                Method::PUT => api::update_favorite(&state, &viewer, id, true, &mut response),
+                Method::DELETE => api::update_favorite(&state, &viewer, id, false, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_BOOKMARKS_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_BOOKMARKS_PREFIX).unwrap();
+            match (method, id.split_once('/')) {
+                (&Method::OPTIONS, None) => add_preflight_headers("GET, POST", &mut response),
+                (&Method::GET, None) => api::serve_bookmarks(&state, &viewer, id, &mut response),
+                (&Method::POST, None) => api::create_bookmark(&state, &viewer, id, body, &mut response).await,
+                (&Method::OPTIONS, Some(_)) => add_preflight_headers("DELETE", &mut response),
+                (&Method::DELETE, Some((id, bookmark_id))) => api::delete_bookmark(&state, &viewer, id, bookmark_id, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_RATING_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_RATING_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("PUT, DELETE", &mut response),
+                Method::PUT => api::update_rating(&state, &viewer, id, body, &mut response).await,
+                Method::DELETE => api::delete_rating(&state, &viewer, id, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_METADATA_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_METADATA_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, PUT, DELETE", &mut response),
+                Method::GET => api::serve_metadata(&state, &viewer, id, &mut response),
+                Method::PUT => api::update_metadata(&state, &viewer, id, body, &mut response).await,
+                Method::DELETE => api::delete_metadata(&state, &viewer, id, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, PATH_SESSIONS) => {
+            add_common_cors_headers(&mut response);
+
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, POST", &mut response),
+                Method::GET => api::serve_sessions(&state, &viewer, &mut response),
+                Method::POST => api::create_session(&state, &viewer, body, &mut response).await,
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_SESSION_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_SESSION_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("DELETE", &mut response),
+                Method::DELETE => api::delete_session(&state, &viewer, id, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, PATH_TRAKT) if state.trakt.is_some() => {
+            add_common_cors_headers(&mut response);
+
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, POST, DELETE", &mut response),
+                Method::GET => trakt::serve_status(&state, &viewer, &mut response),
+                Method::POST => trakt::connect(state.clone(), &viewer, &mut response).await,
+                Method::DELETE => trakt::disconnect(&state, &viewer, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (&Method::OPTIONS, path) if path.starts_with(PATH_ADMIN_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            add_preflight_headers("GET, POST, DELETE", &mut response);
+        }
+        (_, path) if path.starts_with(PATH_ADMIN_PREFIX) && !admin => {
+            if parts.headers.contains_key(viewer::HEADER_ADMIN_TOKEN) {
+                state.throttle.record_failure(&attempt);
+            }
+            add_common_cors_headers(&mut response);
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            *response.body_mut() = Body::from("An admin token is needed");
+        }
+        (&Method::GET, PATH_ADMIN_EXPORT_STATE) => {
+            add_common_cors_headers(&mut response);
+            api::export_state(&state, &mut response);
+        }
+        (&Method::GET, PATH_ADMIN_SCANS) => {
+            add_common_cors_headers(&mut response);
+            api::serve_scans(&state, &mut response);
+        }
+        (&Method::GET, PATH_ADMIN_MISSING) => {
+            add_common_cors_headers(&mut response);
+            api::serve_missing(&state, &mut response);
+        }
+        (&Method::POST, PATH_ADMIN_IMPORT_STATE) => {
+            add_common_cors_headers(&mut response);
+            api::import_state(&state, body, &mut response).await;
+        }
+        (&Method::GET, PATH_ADMIN_TOKENS) => {
+            add_common_cors_headers(&mut response);
+            api::serve_api_tokens(&state, &state.profiles, &mut response);
+        }
+        (&Method::POST, PATH_ADMIN_TOKENS) => {
+            add_common_cors_headers(&mut response);
+            api::create_api_token(&state, &state.profiles, body, &mut response).await;
+        }
+        (&Method::DELETE, path) if path.starts_with(PATH_ADMIN_TOKEN_PREFIX) => {
+            add_common_cors_headers(&mut response);
+            api::revoke_api_token(&state, &state.profiles, path.strip_prefix(PATH_ADMIN_TOKEN_PREFIX).unwrap(), &mut response);
+        }
+        (method, PATH_PLAYLISTS) => {
+            add_common_cors_headers(&mut response);
+
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, POST", &mut response),
+                Method::GET => api::serve_playlists(&state, &viewer, &mut response),
+                Method::POST => api::create_playlist(&state, body, &mut response).await,
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method, path) if path.starts_with(PATH_PLAYLIST_PREFIX) => {
+            add_common_cors_headers(&mut response);
+
+            let id = path.strip_prefix(PATH_PLAYLIST_PREFIX).unwrap();
+            match *method {
+                Method::OPTIONS => add_preflight_headers("GET, PATCH, DELETE", &mut response),
+                Method::GET => api::serve_playlist(&state, &viewer, id, &mut response),
+                Method::PATCH => api::update_playlist(&state, id, body, &mut response).await,
+                Method::DELETE => api::delete_playlist(&state, id, &mut response),
+                _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED
+            }
+        }
+        (method @ &Method::GET, path) | (method @ &Method::HEAD, path) | (method @ &Method::OPTIONS, path) if path.starts_with(PATH_FILE_PREFIX) => {
+            response.headers_mut().insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+            add_common_cors_headers(&mut response);
+
+            match method {
+                &Method::OPTIONS => add_preflight_headers("GET, HEAD", &mut response),
+                &Method::GET | &Method::HEAD => {
+                    serve_file(
+                        &state,
+                        &viewer,
+                        method,
+                        path.strip_prefix(PATH_FILE_PREFIX).unwrap(),
+                        parts.uri.query(),
+                        &parts.headers,
+                        parts.extensions.get::<StreamBudget>().cloned().unwrap_or_default(),
+                        &mut response,
+                    ).await;
+                }
+                _ => panic!("Unhandled method: {}", method)
+            }
+        }
+        (&Method::OPTIONS, _) => {
+            add_common_cors_headers(&mut response);
+            add_preflight_headers("GET", &mut response);
+        }
+        _ => *response.status_mut() = StatusCode::NOT_FOUND
+    }
+
+    if parts.method == Method::HEAD {
+        strip_body(&mut response);
+    }
+    Ok(response)
+}
+
+fn strip_body(response: &mut Response<Body>) {
+    let body = std::mem::take(response.body_mut());
+    if let Some(len) = body.size_hint().exact() {
+        if !response.headers().contains_key("Content-Length") {
+            response.headers_mut().insert("Content-Length", len.into());
+        }
+    }
+}
+
+fn forbidden_address() -> Response<Body> {
+    let mut response = Response::new(Body::from("Requests from this address aren't allowed"));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response
+}
+
+fn bind_addresses(settings: &Settings) -> Vec<IpAddr> {
+    match settings.bind {
+        Some(ref addresses) if !addresses.is_empty() => addresses.clone(),
+        _ => vec![V4(Ipv4Addr::from(0)), V6(Ipv6Addr::from(0))],
+    }
+}
+
+fn bind_servers(addresses: &[IpAddr], port: u16) -> Result<Vec<Builder<AddrIncoming>>, Box<dyn error::Error>> {
+    #[cfg(target_os = "linux")]
+    {
+        let listeners = systemd::take_listeners()?;
+        if !listeners.is_empty() {
+            return Ok(listeners.into_iter().map(Server::from_tcp).collect::<Result<_, _>>()?);
+        }
+    }
+
+    addresses.iter()
+        .map(|ip_addr| bind(SocketAddr::from((*ip_addr, port))))
+        .collect()
+}
+
+fn bind(addr: SocketAddr) -> Result<Builder<AddrIncoming>, Box<dyn error::Error>> {
+    Ok(Server::from_tcp(bind_listener(addr)?)?)
+}
+
+fn bind_listener(addr: SocketAddr) -> Result<std::net::TcpListener, Box<dyn error::Error>> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+fn required_role(method: &Method, path: &str) -> Option<Role> {
+    let library = path.starts_with(PATH_METADATA_PREFIX) || path == PATH_PLAYLISTS || path.starts_with(PATH_PLAYLIST_PREFIX);
+    if *method == Method::OPTIONS {
+        None
+    } else if path.starts_with(PATH_ADMIN_PREFIX) || (library && *method == Method::DELETE) {
+        Some(Role::Admin)
+    } else if library && !is_safe_method(method) {
+        Some(Role::Editor)
+    } else {
+        None
+    }
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || method.as_str() == webdav::METHOD_PROPFIND
+}
+
+fn serve_manifest(state: &State, viewer: &Viewer, parts: &Parts, response: &mut Response<Body>) {
+    let format = match ManifestQuery::parse(parts.uri.query()) {
+        Ok(query) => query.format.unwrap_or_else(|| if accepts_xml(&parts.headers) { ManifestFormat::Xml } else { ManifestFormat::Json }),
+        Err(err) => return api::bad_request(&err, response),
+    };
+
+    response.headers_mut().insert("Vary", HeaderValue::from_static("Accept, Accept-Encoding"));
+
+    let loaded = state.app_state()
+        .and_then(|app| Ok((app, api::playlist_entries(state, viewer)?, api::ManifestAnnotations::load(state, viewer)?)));
+    let (app, playlists, annotations) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => return api::internal_error("Couldn't read the catalogue", err, response),
+    };
+
+    let (content_type, format_name) = match format {
+        ManifestFormat::Json => ("application/json", "json"),
+        ManifestFormat::Xml => ("application/xml; charset=utf-8", "xml"),
+    };
+    let encoding = ContentEncoding::negotiate(&parts.headers);
+    let mut cache_key = None;
+    if state.url_signer.is_none() {
+        let variant = format!("{}\n{}\n{}", format_name, annotations.fingerprint(), serde_json::to_string(&playlists).unwrap_or_default());
+        let etag = manifest_etag(&app.manifest, viewer, &variant);
+        if send_etag(&etag, &parts.headers, response) {
+            return;
+        }
+
+        let key = format!("{} {}", etag, encoding.header().unwrap_or_default());
+        if let Some(bytes) = app.manifest_responses.get(&key) {
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static(content_type));
+            encoding::write_encoded(bytes, encoding, response);
+            response.headers_mut().insert("Vary", HeaderValue::from_static("Accept, Accept-Encoding"));
+            return;
+        }
+        cache_key = Some(key);
+    }
+
+    let mut manifest = visible_manifest(&app.manifest, viewer);
+    if let Some(items) = manifest.as_array_mut() {
+        items.extend(playlists);
+    }
+    annotations.apply(state, &mut manifest);
+
+    let body = match format {
+        ManifestFormat::Json => encoding.encode_with(|writer| serde_json::to_writer(writer, &manifest).map_err(Error::from)),
+        ManifestFormat::Xml => encoding.encode(xml::catalogue(&manifest).as_bytes()),
+    };
+    match body {
+        Ok(bytes) => {
+            let bytes = Bytes::from(bytes);
+            if let Some(key) = cache_key {
+                app.manifest_responses.insert(key, bytes.clone());
+            }
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static(content_type));
+            encoding::write_encoded(bytes, encoding, response);
+            response.headers_mut().insert("Vary", HeaderValue::from_static("Accept, Accept-Encoding"));
+        }
+        Err(err) => api::internal_error("Couldn't compress the catalogue", err.into(), response),
+    }
+}
+
+fn manifest_etag(manifest: &EncodedManifest, viewer: &Viewer, variant: &str) -> String {
+    let mut hasher = sha1_smol::Sha1::from(manifest.digest());
+    for folder in viewer.hidden() {
+        hasher.update(b"\n");
+        hasher.update(folder.as_bytes());
+    }
+    hasher.update(b"\n");
+    hasher.update(variant.as_bytes());
+    format!("W/\"{}\"", &hasher.digest().to_string()[..ETAG_LENGTH])
+}
+
+fn send_etag(etag: &str, headers: &HeaderMap, response: &mut Response<Body>) -> bool {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert("ETag", value);
+    }
+    let matches = etag_matches(headers, etag);
+    if matches {
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+    }
+    matches
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers.get_all("If-None-Match")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+fn accepts_xml(headers: &HeaderMap) -> bool {
+    let accepted: Vec<_> = headers.get_all("Accept")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut parameters = range.split(';').map(str::trim);
+            let name = parameters.next()?.to_ascii_lowercase();
+            let refused = parameters.any(|parameter| {
+                parameter.strip_prefix("q=").and_then(|quality| quality.parse::<f32>().ok()) == Some(0.0)
+            });
+            if refused { None } else { Some(name) }
+        })
+        .collect();
+
+    let accepts = |name: &str| accepted.iter().any(|range| range == name);
+    (accepts("application/xml") || accepts("text/xml")) && !accepts("application/json")
+}
+
+fn serve_catalogue(state: &State, viewer: &Viewer, headers: &HeaderMap, response: &mut Response<Body>) {
+    let app = match state.app_state() {
+        Ok(app) => app,
+        Err(err) => return api::internal_error("Couldn't read the catalogue", err, response),
+    };
+
+    if state.url_signer.is_none() && send_etag(&manifest_etag(&app.manifest, viewer, ""), headers, response) {
+        return;
+    }
+
+    let encoding = ContentEncoding::negotiate(headers);
+    if viewer.sees_everything() && state.url_signer.is_none() {
+        response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+        encoding::write_encoded(app.manifest.bytes(encoding), encoding, response);
+    } else {
+        let mut manifest = visible_manifest(&app.manifest, viewer);
+        if let Some(ref signer) = state.url_signer {
+            manifest::for_each_file(&mut manifest, &mut |file| signer.sign_file(PATH_FILE_PREFIX, file));
+        }
+        write_manifest(&manifest, encoding, response);
+    }
+}
+
+pub fn visible_manifest(manifest: &EncodedManifest, viewer: &Viewer) -> serde_json::Value {
+    let mut manifest = manifest.value.clone();
+    if !viewer.sees_everything() {
+        manifest::retain_files(&mut manifest, &|file| {
+            file.get("path").and_then(serde_json::Value::as_str).is_some_and(|path| viewer.can_see(path))
+        });
+    }
+    manifest
+}
+
+fn write_manifest(manifest: &serde_json::Value, encoding: ContentEncoding, response: &mut Response<Body>) {
+    match encoding.encode_with(|writer| serde_json::to_writer(writer, manifest).map_err(Error::from)) {
+        Ok(bytes) => {
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+            encoding::write_encoded(bytes.into(), encoding, response);
+        }
+        Err(err) => api::internal_error("Couldn't compress the catalogue", err.into(), response),
+    }
+}
+
+pub fn origin(parts: &Parts) -> String {
+    let host = parts.uri.authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| parts.headers.get("Host").and_then(|host| host.to_str().ok()))
+        .unwrap_or("localhost");
+    let scheme = if parts.extensions.get::<tls::Secure>().is_some() { "https" } else { "http" };
+    format!("{}://{}", scheme, host)
+}
+
+fn serve_export(state: &State, app: &AppState, viewer: &Viewer, parts: &Parts, export: Export, response: &mut Response<Body>) {
+    let query = match PlaylistQuery::parse(parts.uri.query()) {
+        Ok(query) => query,
+        Err(err) => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err);
+            return;
+        }
+    };
+
+    let mut manifest = visible_manifest(&app.manifest, viewer);
+    if let Export::Kodi = export {
+        if let Err(err) = api::annotate_manifest(state, viewer, &mut manifest) {
+            return api::internal_error("Couldn't annotate the manifest", err, response);
+        }
+    }
+    let items = match query.dir {
+        Some(ref dir) => match manifest::directory_mut(&mut manifest, dir) {
+            Some(items) => items,
+            None => {
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return;
+            }
+        },
+        None => &mut manifest,
+    };
+
+    let origin = origin(parts);
+    let with_token = |url: String, separator: char| match query.token {
+        Some(ref token) => format!("{}{}token={}", url, separator, utf8_percent_encode(token, NON_ALPHANUMERIC)),
+        None => url,
+    };
+    let file_url = |path: &str| match state.url_signer {
+        Some(ref signer) => format!("{}{}", origin, signer.sign(PATH_FILE_PREFIX, path)),
+        None => with_token(format!("{}{}{}", origin, PATH_FILE_PREFIX, request_path::encode(path)), '?'),
+    };
+    let title = query.dir.as_deref().and_then(|dir| dir.rsplit('/').next()).unwrap_or(LIBRARY_TITLE);
+
+    let (content_type, body) = match export {
+        Export::M3u => ("audio/x-mpegurl; charset=utf-8", m3u::render(items, &file_url)),
+        Export::Feed => {
+            let file_metadata = |path: &str| app.served_file(path).and_then(|(_, path)| fs::metadata(path).ok());
+            let link = format!("{}{}", origin, PATH_MANIFEST);
+            ("application/rss+xml; charset=utf-8", feed::render(title, &link, items, &file_url, &file_metadata))
+        }
+        Export::Kodi => {
+            let folder_url = |dir: &str| {
+                with_token(format!("{}{}?dir={}", origin, PATH_KODI, utf8_percent_encode(dir, NON_ALPHANUMERIC)), '&')
+            };
+            return api::write_json(&kodi::listing(title, query.dir.as_deref(), items, &folder_url, &file_url), response);
+        }
+    };
+    response.headers_mut().insert("Content-Type", HeaderValue::from_static(content_type));
+    *response.body_mut() = Body::from(body);
+}
+
+fn serve_cast(state: &State, viewer: &Viewer, parts: &Parts, id: &str, response: &mut Response<Body>) {
+    let query = match CastQuery::parse(parts.uri.query()) {
+        Ok(query) => query,
+        Err(err) => return api::bad_request(&err, response),
+    };
+
+    let origin = origin(parts);
+    let file_url = |path: &str| match (&state.url_signer, &query.token) {
+        (Some(signer), _) => format!("{}{}", origin, signer.sign(PATH_FILE_PREFIX, path)),
+        (None, Some(token)) => format!("{}{}{}?token={}", origin, PATH_FILE_PREFIX, request_path::encode(path), utf8_percent_encode(token, NON_ALPHANUMERIC)),
+        (None, None) => format!("{}{}{}", origin, PATH_FILE_PREFIX, request_path::encode(path)),
+    };
+    api::serve_cast(state, viewer, id, &file_url, response);
+}
+
+fn serve_hls(state: &State, app: &AppState, viewer: &Viewer, parts: &Parts, path: &str, response: &mut Response<Body>) {
+    let (video, name) = match path.rsplit_once('/').map(|(video, name)| (request_path::validate(video), name)) {
+        Some((Ok(video), name)) => (video, name),
+        Some((Err(err), _)) => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err.to_string());
+            return;
+        }
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+
+    if !state.permits_link(&video, parts.uri.query()) {
+        return refuse_unsigned(response);
+    }
+
+    let (key, file) = match app.served_file(&video) {
+        Some((key, file)) if viewer.can_see(key) => (key, file),
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let duration = match video_duration(state, key) {
+        Ok(Some(duration)) => duration,
+        Ok(None) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+        Err(err) => {
+            error!("Couldn't look up {}: {}", key, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return;
+        }
+    };
+
+    if name == hls::PLAYLIST_NAME {
+        response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/vnd.apple.mpegurl"));
+        *response.body_mut() = Body::from(hls::playlist(duration, parts.uri.query()));
+        return;
+    }
+
+    let index = match hls::segment_index(name, duration) {
+        Some(index) => index,
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let (source, source_path) = state.media_source(key, file);
+    let local_path = match source.local_path(&source_path) {
+        Some(local_path) => local_path,
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            *response.body_mut() = Body::from("Videos on this mount can't be segmented");
+            return;
+        }
+    };
+    match state.transcodes.as_ref().unwrap().segment(&local_path, duration, index) {
+        Ok(segment) => {
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("video/mp2t"));
+            *response.body_mut() = Body::wrap_stream(segment);
+        }
+        Err(err @ TranscodeError::Busy) => {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response.headers_mut().insert("Retry-After", HeaderValue::from_static(TRANSCODE_RETRY_AFTER));
+            *response.body_mut() = Body::from(err.to_string());
+        }
+        Err(err) => {
+            error!("Couldn't start ffmpeg for {}: {}", key, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+}
+
+fn video_duration(state: &State, key: &str) -> Result<Option<Duration>, Box<dyn error::Error>> {
+    let item = state.store.item(&item_id(Path::new(key)))?;
+    Ok(item.and_then(|item| item.get("duration").and_then(serde_json::Value::as_u64)).map(Duration::from_millis))
+}
+
+async fn serve_thumbnail(state: &State, app: &AppState, viewer: &Viewer, parts: &Parts, path: &str, response: &mut Response<Body>) {
+    let query = match ThumbnailQuery::parse(parts.uri.query()) {
+        Ok(query) => query,
+        Err(err) => return api::bad_request(&err, response),
+    };
+    let (video, name) = match path.rsplit_once('/').map(|(video, name)| (request_path::validate(video), name)) {
+        Some((Ok(video), name)) => (video, name),
+        Some((Err(err), _)) => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err.to_string());
+            return;
+        }
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+
+    let (key, file) = match app.served_file(&video) {
+        Some((key, file)) if viewer.can_see(key) && is_video(Path::new(key)) => (key, file),
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let (source, source_path) = state.media_source(key, file);
+    let (duration, local_path) = match (video_duration(state, key), source.local_path(&source_path)) {
+        (Ok(Some(duration)), Some(local_path)) => (duration, local_path),
+        (Err(err), _) => {
+            error!("Couldn't look up {}: {}", key, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return;
+        }
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+
+    let thumbnails = state.thumbnails.as_ref().unwrap();
+    match thumbnails.thumbnail(key, &local_path, duration, name, query.width).await {
+        Ok(Some(thumbnail)) => {
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("image/jpeg"));
+            *response.body_mut() = Body::from(thumbnail);
+        }
+        Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+        Err(err) => {
+            error!("Couldn't extract the thumbnail {} of {}: {}", name, key, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+}
+
+fn serve_transcode(state: &State, app: &AppState, viewer: &Viewer, parts: &Parts, path: &str, response: &mut Response<Body>) {
+    let transcodes = state.transcodes.as_ref().unwrap();
+    let query = match TranscodeQuery::parse(parts.uri.query()) {
+        Ok(query) => query,
+        Err(err) => return api::bad_request(&err, response),
+    };
+    let requested_path = match request_path::validate(path) {
+        Ok(path) => path,
+        Err(err) => return api::bad_request(&err.to_string(), response),
+    };
+    if !state.permits_link(&requested_path, parts.uri.query()) {
+        return refuse_unsigned(response);
+    }
+
+    let (key, file) = match app.served_file(&requested_path) {
+        Some((key, file)) if viewer.can_see(key) && is_video(Path::new(key)) => (key, file),
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let (source, source_path) = state.media_source(key, file);
+    let local_path = match source.local_path(&source_path) {
+        Some(local_path) => local_path,
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            *response.body_mut() = Body::from("Videos on this mount can't be transcoded");
+            return;
+        }
+    };
+
+    let session = match query.session {
+        Some(session) => session,
+        None => parts.extensions.get::<SocketAddr>().map(|remote| remote.ip().to_string()).unwrap_or_default(),
+    };
+    match transcodes.start(&viewer.profile, &session, key, &local_path, query.profile.as_deref(), query.start) {
+        Ok(output) => {
+            if query.start == 0.0 {
+                api::record_play(state, viewer, key);
+            }
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("video/mp4"));
+            response.headers_mut().insert("Cache-Control", HeaderValue::from_static("no-store"));
+            *response.body_mut() = Body::wrap_stream(output);
+        }
+        Err(err @ (TranscodeError::NoProfile | TranscodeError::UnknownProfile(_))) => api::bad_request(&err.to_string(), response),
+        Err(err @ TranscodeError::Busy) => {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response.headers_mut().insert("Retry-After", HeaderValue::from_static(TRANSCODE_RETRY_AFTER));
+            *response.body_mut() = Body::from(err.to_string());
+        }
+        Err(err @ TranscodeError::Spawn(_)) => {
+            error!("Couldn't transcode {}: {}", key, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+}
+
+async fn serve_webdav(state: &State, app: &AppState, viewer: &Viewer, parts: &Parts, response: &mut Response<Body>) {
+    let raw = parts.uri.path().strip_prefix(PATH_WEBDAV).unwrap().trim_matches('/');
+    let path = match raw {
+        "" => String::new(),
+        raw => match request_path::validate(raw) {
+            Ok(path) => path,
+            Err(err) => {
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                *response.body_mut() = Body::from(err.to_string());
+                return;
+            }
+        },
+    };
+
+    match parts.method.as_str() {
+        "OPTIONS" => {
+            response.headers_mut().insert("DAV", HeaderValue::from_static("1"));
+            response.headers_mut().insert("Allow", HeaderValue::from_static(webdav::ALLOWED_METHODS));
+            add_preflight_headers(webdav::ALLOWED_METHODS, response);
+        }
+        webdav::METHOD_PROPFIND => {
+            let depth = match webdav::Depth::parse(parts.headers.get("Depth").and_then(|depth| depth.to_str().ok())) {
+                Ok(depth) => depth,
+                Err(err) => {
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    *response.body_mut() = Body::from(err);
+                    return;
+                }
+            };
+
+            response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/xml; charset=utf-8"));
+            if depth == webdav::Depth::Infinity {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                *response.body_mut() = Body::from(webdav::finite_depth_required());
+                return;
+            }
+            match webdav::propfind(state, app, viewer, PATH_WEBDAV, &path, depth).await {
+                Some(multistatus) => {
+                    *response.status_mut() = StatusCode::MULTI_STATUS;
+                    *response.body_mut() = Body::from(multistatus);
+                }
+                None => {
+                    response.headers_mut().remove("Content-Type");
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                }
+            }
+        }
+        "GET" | "HEAD" => {
+            response.headers_mut().insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+            let budget = parts.extensions.get::<StreamBudget>().cloned().unwrap_or_default();
+            serve_file(state, viewer, &parts.method, raw, parts.uri.query(), &parts.headers, budget, response).await;
+        }
+        _ => {
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            response.headers_mut().insert("Allow", HeaderValue::from_static(webdav::ALLOWED_METHODS));
+        }
+    }
+}
+
+fn serve_history(state: &State, viewer: &Viewer, restrictions: &[Restriction], query: Option<&str>, response: &mut Response<Body>) {
+    let query = match HistoryQuery::parse(query) {
+        Ok(query) => query,
+        Err(err) => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err);
+            return;
+        }
+    };
+
+    match query.profile {
+        Some(ref profile) if *profile != viewer.profile => match Viewer::named(&state.profiles, restrictions, profile) {
+            Ok(named) => api::serve_history(state, &named, query.limit, response),
+            Err(err) => {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                *response.body_mut() = Body::from(err);
+            }
+        },
+        _ => api::serve_history(state, viewer, query.limit, response),
+    }
+}
+
+fn serve_events(state: &State, viewer: &Viewer, query: Option<&str>, response: &mut Response<Body>) {
+    let player_id = query.unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("player-id="))
+        .map(|value| percent_decode_str(value).decode_utf8_lossy().into_owned())
+        .next();
+
+    response.headers_mut().insert("Content-Type", HeaderValue::from_static("text/event-stream"));
+    response.headers_mut().insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    *response.body_mut() = state.events.subscribe(&viewer.profile, player_id.as_deref());
+}
+
+fn serve_health(available_update: &Mutex<Option<String>>, mounts: &[Mount], response: &mut Response<Body>) {
+    let mut health = serde_json::json!({
+        "status": "ok",
+        "version": update::CURRENT_VERSION,
+    });
+    if !mounts.is_empty() {
+        health["mounts"] = mounts.iter().map(Mount::health).collect();
+    }
+    if let Some(ref version) = *available_update.lock().unwrap() {
+        health["update-available"] = serde_json::Value::String(version.clone());
+    }
+
+    response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+    *response.body_mut() = Body::from(health.to_string());
+}
+
+fn refuse_unsigned(response: &mut Response<Body>) {
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    *response.body_mut() = Body::from("The link is unsigned or has expired");
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_file(
+    state: &State,
+    viewer: &Viewer,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap<HeaderValue>,
+    budget: StreamBudget,
+    response: &mut Response<Body>,
+) {
+    let range_data = headers
+        .get("Range")
+        .map(|it| {
+            it.to_str()
+        });
+
+    let range_data = match range_data {
+        None => None,
+        Some(Ok(data)) => Some(data),
+        Some(Err(err)) => {
+            warn!("Invalid range: {}", err);
+
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from("Invalid range");
+            return;
+        }
+    };
+
+    let requested_path = match request_path::validate(path) {
+        Ok(path) => path,
+        Err(err) => {
+            debug!("Rejected the file path {}: {}", path, err);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from(err.to_string());
+            return;
+        }
+    };
+    if !state.permits_link(&requested_path, query) {
+        return refuse_unsigned(response);
+    }
+
+    let app = match state.app_state() {
+        Ok(app) => app,
+        Err(err) => {
+            error!("Couldn't look up {}: {}", requested_path, err);
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return;
+        }
+    };
+
+    let (requested_path, path) = match app.served_file(&requested_path) {
+        Some((key, path)) if viewer.can_see(key) => (key, path),
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+
+    let ranges = if let Some(range_data) = range_data {
+        match parse_range::<()>(range_data) {
+            Ok((_, ranges)) => ranges,
+            Err(_) => {
+                warn!("Error while parsing the byte range: {}", range_data);
+
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                return;
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    if *method != Method::HEAD && api::starts_playback(path, &ranges) {
+        api::record_play(state, viewer, requested_path);
+    }
+
+    match serve_file_range(state, &app, requested_path, path, &ranges, headers, budget, response).await {
+        Ok(()) => {}
+        Err(err) if media_source::is_transient(&err) => {
+            warn!("The share holding {} is unavailable: {}", requested_path, err);
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response.headers_mut().insert("Retry-After", HeaderValue::from_static(SHARE_RETRY_AFTER));
+            *response.body_mut() = Body::from("The file is temporarily unavailable");
+        }
+        Err(_) => {
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            *response.body_mut() = Body::from("Couldn't read the file");
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve_file_range(
+    state: &State,
+    app: &AppState,
+    key: &str,
+    path: &Path,
+    ranges: &[ByteRange],
+    headers: &HeaderMap,
+    budget: StreamBudget,
+    response: &mut Response<Body>,
+) -> Result<(), Error> {
+    let (source, source_path) = state.media_source(key, path);
+    let opened = app.open_files.open(key, source, &source_path).await;
+    if let Some((mount, _)) = state.mount(key) {
+        mount.record(&opened);
+    }
+    let opened = opened?;
+    let metadata = opened.metadata();
+    let pool = state.buffer_pool.clone();
+    let converted = if subtitles::is_srt(path) {
+        let srt = app.small_files.read(key, &*opened, pool.clone(), budget.clone()).await?;
+        Some(Bytes::from(subtitles::srt_to_vtt(&subtitles::decode(&srt))))
+    } else {
+        None
+    };
+    let file_len = converted.as_ref().map_or(metadata.len, |converted| converted.len() as u64);
+
+    let validators = metadata.modified.map(|modified| Validators::new(file_len, modified));
+    if let Some(ref validators) = validators {
+        if let Ok(etag) = HeaderValue::from_str(&validators.etag) {
+            response.headers_mut().insert("ETag", etag);
+        }
+        response.headers_mut().insert("Last-Modified", httpdate::fmt_http_date(validators.modified).parse().unwrap());
+        if validators.not_modified(headers) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(());
+        }
+    }
+    let ranges = match headers.get("If-Range") {
+        Some(_) if !validators.as_ref().is_some_and(|validators| validators.range_applies(headers)) => &[],
+        _ if ranges.len() > MAX_BYTE_RANGES => &[],
+        _ => ranges,
+    };
+
+    let mut satisfiable: Vec<Range<u64>> = ranges.iter().filter_map(|range| match *range {
+        ByteRange::StartingAt(start) if start < file_len => Some(start..file_len),
+        ByteRange::Last(len) if len <= file_len => Some(file_len - len..file_len),
+        ByteRange::FromToIncluding(start, end) if start < file_len && end < file_len && start <= end => Some(start..end + 1),
+        _ => None,
+    }).collect();
+    satisfiable.sort_by_key(|range| range.start);
+    let mut served: Vec<Range<u64>> = Vec::with_capacity(satisfiable.len());
+    for range in satisfiable {
+        match served.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => served.push(range),
+        }
+    }
+    if !ranges.is_empty() {
+        if served.is_empty() {
+            *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            response.headers_mut().insert("Content-Range", format!("bytes */{}", file_len).parse().unwrap());
+            return Ok(());
+        }
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    }
+
+    let mime = if converted.is_some() { Some(VTT_CONTENT_TYPE.to_string()) } else { content_type(path) };
+    let contents = if converted.is_some() {
+        converted
+    } else if SmallFiles::caches(path, file_len) {
+        Some(app.small_files.read(key, &*opened, pool.clone(), budget.clone()).await?)
+    } else {
+        None
+    };
+    let read = |range: Range<u64>| -> BoxStream<'static, Result<Bytes, Error>> {
+        match contents {
+            Some(ref contents) => stream::once(future::ready(Ok(contents.slice(range.start as usize..range.end as usize)))).boxed(),
+            None => opened.read_range(range, pool.clone(), budget.clone()),
+        }
+    };
+
+    let body_of = |range: Range<u64>| match contents {
+        Some(ref contents) => Body::from(contents.slice(range.start as usize..range.end as usize)),
+        None => Body::wrap_stream(read(range)),
+    };
+
+    let body = match served.as_slice() {
+        [] => {
+            response.headers_mut().insert("Content-Length", file_len.into());
+            body_of(0..file_len)
+        }
+        [range] => {
+            response.headers_mut().insert("Content-Range", content_range(range, file_len).parse().unwrap());
+            response.headers_mut().insert("Content-Length", (range.end - range.start).into());
+            body_of(range.clone())
+        }
+        parts => {
+            let boundary = multipart_boundary()?;
+            let part_type = mime.clone().unwrap_or_else(|| String::from("application/octet-stream"));
+            let mut body = Vec::new();
+            let mut len = 0;
+            for range in parts {
+                let header = format!("--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n", boundary, part_type, content_range(range, file_len));
+                len += header.len() as u64 + (range.end - range.start) + 2;
+                body.push(stream::once(future::ready(Ok(Bytes::from(header)))).boxed());
+                body.push(read(range.clone()));
+                body.push(stream::once(future::ready(Ok(Bytes::from_static(b"\r\n")))).boxed());
+            }
+            let closing = format!("--{}--\r\n", boundary);
+            len += closing.len() as u64;
+            body.push(stream::once(future::ready(Ok(Bytes::from(closing)))).boxed());
+
+            response.headers_mut().insert("Content-Type", format!("multipart/byteranges; boundary={}", boundary).parse().unwrap());
+            response.headers_mut().insert("Content-Length", len.into());
+            *response.body_mut() = Body::wrap_stream(stream::iter(body).flatten());
+            return Ok(());
+        }
+    };
+
+    if let Some(mime) = mime {
+        response.headers_mut().insert("Content-Type", mime.try_into().unwrap());
+    }
+
+    *response.body_mut() = body;
+    Ok(())
+}
+
+struct Validators {
+    etag: String,
+    modified: SystemTime,
+}
+
+impl Validators {
+    fn new(len: u64, modified: SystemTime) -> Validators {
+        let seconds = modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        Validators { etag: format!("\"{:x}-{:x}\"", len, seconds), modified: UNIX_EPOCH + Duration::from_secs(seconds) }
+    }
+
+    fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if headers.contains_key("If-None-Match") {
+            return etag_matches(headers, &self.etag);
+        }
+        header_date(headers, "If-Modified-Since").is_some_and(|since| self.modified <= since)
+    }
+
+    fn range_applies(&self, headers: &HeaderMap) -> bool {
+        match headers.get("If-Range").and_then(|value| value.to_str().ok()).map(str::trim) {
+            Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == self.etag,
+            Some(_) => header_date(headers, "If-Range") == Some(self.modified),
+            None => true,
+        }
+    }
+}
+
+fn header_date(headers: &HeaderMap, name: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(name)?.to_str().ok()?.trim()).ok()
+}
+
+fn content_type(path: &Path) -> Option<String> {
+    let mime = mime_guess::from_path(path).first()?;
+    let utf8 = path.extension().is_some_and(|extension| extension == subtitles::EXTENSION_VTT);
+    Some(if utf8 { format!("{}; charset=utf-8", mime) } else { mime.to_string() })
+}
+
+fn content_range(range: &Range<u64>, file_len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, file_len)
+}
+
+fn multipart_boundary() -> Result<String, Error> {
+    let mut bytes = [0u8; MULTIPART_BOUNDARY_LEN];
+    getrandom::getrandom(&mut bytes).map_err(Error::other)?;
+    Ok(encode_hex(&bytes))
+}
+
+fn add_preflight_headers(methods: &'static str, response: &mut Response<Body>) {
+    response.headers_mut().insert("Access-Control-Allow-Methods", HeaderValue::from_static(methods));
+    response.headers_mut().insert("Access-Control-Allow-Headers", HeaderValue::from_static(viewer::ALLOWED_HEADERS));
+}
+
+fn add_common_cors_headers(response: &mut Response<Body>) {
+    response.headers_mut().insert("Access-Control-Allow-Origin", HeaderValue::from_static(ALLOWED_ORIGIN));
+    response.headers_mut().insert("Access-Control-Expose-Headers", HeaderValue::from_static("Content-Type, Accept-Encoding, Range"));
+    response.headers_mut().insert("Access-Control-Max-Age", HeaderValue::from(MAX_AGE));
+}
\ No newline at end of file
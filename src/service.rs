@@ -0,0 +1,378 @@
+use std::{
+    error,
+    ffi::c_void,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    ptr::{null, null_mut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use tokio::{runtime::Runtime, sync::Notify};
+use tracing::{error, info, level_filters::LevelFilter, Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::bindings::windows::win32::{
+    security::{
+        CloseServiceHandle, OpenSCManagerW, RegisterServiceCtrlHandlerExW, SERVICE_STATUS, SERVICE_TABLE_ENTRYW, SetServiceStatus,
+        StartServiceCtrlDispatcherW,
+    },
+    services::CreateServiceW,
+    system_services::{HANDLE, RegisterEventSourceW, ReportEventW},
+};
+use crate::cli::ServeArgs;
+use crate::config::Settings;
+use crate::secrets;
+use crate::server;
+use crate::win32::{last_error, wide};
+
+const SERVICE_NAME: &str = "MovieNexus";
+const SERVICE_DISPLAY_NAME: &str = "Movie Nexus";
+
+const SC_MANAGER_CREATE_SERVICE: u32 = 0x0002;
+const SERVICE_ALL_ACCESS: u32 = 0xF01FF;
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_AUTO_START: u32 = 2;
+const SERVICE_ERROR_NORMAL: u32 = 1;
+
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_START_PENDING: u32 = 2;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+
+const SERVICE_ACCEPT_STOP: u32 = 0x1;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+
+const PENDING_WAIT_HINT_MS: u32 = 10_000;
+
+const EVENTLOG_ERROR_TYPE: u16 = 0x1;
+const EVENTLOG_WARNING_TYPE: u16 = 0x2;
+const EVENTLOG_INFORMATION_TYPE: u16 = 0x4;
+
+lazy_static! {
+    static ref SERVICE_SETUP: Mutex<Option<(PathBuf, Settings)>> = Mutex::default();
+    static ref STOP_REQUESTED: Notify = Notify::new();
+}
+
+static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn install(folder: &Path, cli: &ServeArgs) -> Result<(), Box<dyn error::Error>> {
+    let executable = std::env::current_exe()?;
+    let folder = folder.canonicalize()?;
+    let mut command = format!("\"{}\" --run-as-service \"{}\"", executable.display(), folder.display());
+    if let Some(ref config) = cli.config {
+        command.push_str(&format!(" --config \"{}\"", config.canonicalize()?.display()));
+    }
+    if let Some(port) = cli.port {
+        command.push_str(&format!(" --port {}", port));
+    }
+    for address in &cli.bind {
+        command.push_str(&format!(" --bind {}", address));
+    }
+    if let Some(ref service_name) = cli.service_name {
+        command.push_str(&format!(" --service-name \"{}\"", service_name));
+    }
+    if let Some(ref cors_origin) = cli.cors_origin {
+        command.push_str(&format!(" --cors-origin \"{}\"", cors_origin));
+    }
+    if let Some(minutes) = cli.rescan_minutes {
+        command.push_str(&format!(" --rescan-minutes {}", minutes));
+    }
+    if cli.tls {
+        command.push_str(" --tls");
+    }
+    if let Some(port) = cli.tls_port {
+        command.push_str(&format!(" --tls-port {}", port));
+    }
+    if let Some(ref tls_cert) = cli.tls_cert {
+        command.push_str(&format!(" --tls-cert \"{}\"", tls_cert.canonicalize()?.display()));
+    }
+    if let Some(ref tls_key) = cli.tls_key {
+        let tls_key = if secrets::path_reference(tls_key).is_some() { tls_key.clone() } else { tls_key.canonicalize()? };
+        command.push_str(&format!(" --tls-key \"{}\"", tls_key.display()));
+    }
+    if let Some(ref tls_client_ca) = cli.tls_client_ca {
+        command.push_str(&format!(" --tls-client-ca \"{}\"", tls_client_ca.canonicalize()?.display()));
+    }
+    if cli.redirect_http {
+        command.push_str(" --redirect-http");
+    }
+    if let Some(ref database) = cli.database {
+        command.push_str(&format!(" --database \"{}\"", std::env::current_dir()?.join(database).display()));
+    }
+    if let Some(ref backend) = cli.state_backend {
+        command.push_str(&format!(" --state-backend {}", backend.to_possible_value().unwrap().get_name()));
+    }
+    if let Some(ref state_file) = cli.state_file {
+        command.push_str(&format!(" --state-file \"{}\"", std::env::current_dir()?.join(state_file).display()));
+    }
+    if let Some(ref profile) = cli.profile {
+        command.push_str(&format!(" --profile \"{}\"", profile));
+    }
+    if cli.read_only {
+        command.push_str(" --read-only");
+    }
+    if cli.no_update_check {
+        command.push_str(" --no-update-check");
+    }
+    if cli.no_watch {
+        command.push_str(" --no-watch");
+    }
+    if let Some(days) = cli.missing_grace_days {
+        command.push_str(&format!(" --missing-grace-days {}", days));
+    }
+    if let Some(size) = cli.stream_buffer_kib {
+        command.push_str(&format!(" --stream-buffer-kib {}", size));
+    }
+    if cli.case_insensitive_paths {
+        command.push_str(" --case-insensitive-paths");
+    }
+    if cli.signed_urls {
+        command.push_str(" --signed-urls");
+    }
+    if let Some(minutes) = cli.signed_url_minutes {
+        command.push_str(&format!(" --signed-url-minutes {}", minutes));
+    }
+    if cli.jellyfin {
+        command.push_str(" --jellyfin");
+    }
+    if cli.webdav {
+        command.push_str(" --webdav");
+    }
+    if let Some(ref metadata_csv) = cli.metadata_csv {
+        command.push_str(&format!(" --metadata-csv \"{}\"", metadata_csv.canonicalize()?.display()));
+    }
+    if cli.fallback_metadata {
+        command.push_str(" --fallback-metadata");
+    }
+    if let Some(ref ffprobe) = cli.ffprobe {
+        command.push_str(&format!(" --ffprobe \"{}\"", tool_path(ffprobe)?.display()));
+    }
+    if let Some(ref ffmpeg) = cli.ffmpeg {
+        command.push_str(&format!(" --ffmpeg \"{}\"", tool_path(ffmpeg)?.display()));
+    }
+    if let Some(count) = cli.max_transcodes {
+        command.push_str(&format!(" --max-transcodes {}", count));
+    }
+    if cli.thumbnails {
+        command.push_str(" --thumbnails");
+    }
+    if let Some(level) = cli.log_level {
+        command.push_str(&format!(" --log-level {}", level));
+    }
+    if let Some(ref log_file) = cli.log_file {
+        command.push_str(&format!(" --log-file \"{}\"", std::env::current_dir()?.join(log_file).display()));
+    }
+    if let Some(size) = cli.log_rotate_size {
+        command.push_str(&format!(" --log-rotate-size {}", size));
+    }
+    if let Some(ref rotation) = cli.log_rotate {
+        command.push_str(&format!(" --log-rotate {}", rotation.to_possible_value().unwrap().get_name()));
+    }
+    if let Some(count) = cli.log_keep {
+        command.push_str(&format!(" --log-keep {}", count));
+    }
+    if let Some(format) = cli.log_format {
+        command.push_str(&format!(" --log-format {}", format.to_possible_value().unwrap().get_name()));
+    }
+
+    let service_name = wide(SERVICE_NAME);
+    let display_name = wide(SERVICE_DISPLAY_NAME);
+    let command = wide(&command);
+
+    unsafe {
+        let manager = OpenSCManagerW(null(), null(), SC_MANAGER_CREATE_SERVICE);
+        if manager.is_null() {
+            return Err(last_error().into());
+        }
+
+        let service = CreateServiceW(
+            manager,
+            service_name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            command.as_ptr(),
+            null(),
+            null_mut(),
+            null(),
+            null(),
+            null(),
+        );
+        let result = if service.is_null() {
+            Err(last_error())
+        } else {
+            CloseServiceHandle(service);
+            Ok(())
+        };
+        CloseServiceHandle(manager);
+        result?;
+    }
+
+    info!("Service {} installed for {}", SERVICE_NAME, folder.display());
+    Ok(())
+}
+
+fn tool_path(path: &Path) -> Result<PathBuf, io::Error> {
+    if path.parent().is_none_or(|parent| parent.as_os_str().is_empty()) {
+        return Ok(path.to_path_buf());
+    }
+    path.canonicalize()
+}
+
+pub fn run(folder: PathBuf, settings: Settings) -> Result<(), Box<dyn error::Error>> {
+    *SERVICE_SETUP.lock().unwrap() = Some((folder, settings));
+
+    let mut service_name = wide(SERVICE_NAME);
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lp_service_name: service_name.as_mut_ptr(),
+            lp_service_proc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+
+    unsafe { StartServiceCtrlDispatcherW(table.as_ptr()).ok()?; }
+    Ok(())
+}
+
+extern "system" fn service_main(_arg_count: u32, _args: *mut *mut u16) {
+    let service_name = wide(SERVICE_NAME);
+    let handle = unsafe { RegisterServiceCtrlHandlerExW(service_name.as_ptr(), Some(control_handler), null_mut()) };
+    if handle.is_null() {
+        error!("Couldn't register the service control handler: {}", last_error());
+        return;
+    }
+    STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+
+    set_status(SERVICE_START_PENDING, NO_ERROR);
+
+    let (folder, settings) = SERVICE_SETUP.lock().unwrap().take().unwrap();
+    let result = Runtime::new()
+        .map_err(Into::into)
+        .and_then(|runtime| {
+            set_status(SERVICE_RUNNING, NO_ERROR);
+            runtime.block_on(server::run(&folder, &settings, Vec::new(), async { STOP_REQUESTED.notified().await }))
+        });
+
+    match result {
+        Ok(()) => {
+            info!("Service stopped");
+            set_status(SERVICE_STOPPED, NO_ERROR);
+        }
+        Err(err) => {
+            error!("Service failed: {}", err);
+            set_status(SERVICE_STOPPED, ERROR_SERVICE_SPECIFIC_ERROR);
+        }
+    }
+}
+
+extern "system" fn control_handler(control: u32, _event_type: u32, _event_data: *mut c_void, _context: *mut c_void) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            set_status(SERVICE_STOP_PENDING, NO_ERROR);
+            STOP_REQUESTED.notify_one();
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED
+    }
+}
+
+fn set_status(state: u32, exit_code: u32) {
+    let pending = state == SERVICE_START_PENDING || state == SERVICE_STOP_PENDING;
+    let mut status = SERVICE_STATUS {
+        dw_service_type: SERVICE_WIN32_OWN_PROCESS,
+        dw_current_state: state,
+        dw_controls_accepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+        dw_win32_exit_code: exit_code,
+        dw_service_specific_exit_code: if exit_code == ERROR_SERVICE_SPECIFIC_ERROR { 1 } else { 0 },
+        dw_check_point: 0,
+        dw_wait_hint: if pending { PENDING_WAIT_HINT_MS } else { 0 },
+    };
+
+    unsafe { SetServiceStatus(STATUS_HANDLE.load(Ordering::SeqCst) as *mut _, &mut status); }
+}
+
+pub fn init_event_log(level: LevelFilter) {
+    let source_name = wide(SERVICE_NAME);
+    let source = unsafe { RegisterEventSourceW(null(), source_name.as_ptr()) };
+
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(false)
+        .without_time()
+        .with_writer(EventLog(source))
+        .init();
+}
+
+struct EventLog(HANDLE);
+
+impl<'a> MakeWriter<'a> for EventLog {
+    type Writer = EventLogEntry;
+
+    fn make_writer(&'a self) -> EventLogEntry {
+        EventLogEntry::new(self.0, EVENTLOG_INFORMATION_TYPE)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> EventLogEntry {
+        let event_type = match *meta.level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE
+        };
+
+        EventLogEntry::new(self.0, event_type)
+    }
+}
+
+struct EventLogEntry {
+    source: HANDLE,
+    event_type: u16,
+    message: Vec<u8>,
+}
+
+impl EventLogEntry {
+    fn new(source: HANDLE, event_type: u16) -> EventLogEntry {
+        EventLogEntry {
+            source,
+            event_type,
+            message: Vec::new(),
+        }
+    }
+}
+
+impl Write for EventLogEntry {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventLogEntry {
+    fn drop(&mut self) {
+        if self.message.is_empty() { return; }
+
+        let mut message = wide(String::from_utf8_lossy(&self.message).trim_end());
+        let mut strings = [message.as_mut_ptr()];
+        unsafe { ReportEventW(self.source, self.event_type, 0, 0, null_mut(), 1, 0, strings.as_mut_ptr(), null_mut()); }
+    }
+}
\ No newline at end of file
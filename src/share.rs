@@ -0,0 +1,261 @@
+use std::{
+    fs::{self, File},
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::{self, BoxStream}, FutureExt, StreamExt};
+use tracing::debug;
+
+use crate::config::ShareSettings;
+use crate::file_stream::{self, BufferPool, StreamBudget};
+use crate::media_source::{self, LocalSource, MediaEntry, MediaFile, MediaMetadata, MediaSource};
+
+const RETRY_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Smb,
+    Nfs,
+}
+
+impl Protocol {
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Smb => "smb",
+            Protocol::Nfs => "nfs",
+        }
+    }
+}
+
+struct Share {
+    settings: ShareSettings,
+    root: PathBuf,
+}
+
+impl Share {
+    #[cfg(windows)]
+    fn connect(&self) -> Result<(), io::Error> {
+        use std::ptr::null;
+
+        use crate::bindings::windows::win32::{security::NETRESOURCEW, windows_networking::WNetAddConnection2W};
+        use crate::win32::wide;
+
+        const RESOURCETYPE_DISK: u32 = 1;
+        const CONNECT_TEMPORARY: u32 = 4;
+        const NO_ERROR: u32 = 0;
+        const ERROR_SESSION_CREDENTIAL_CONFLICT: u32 = 1219;
+
+        if self.settings.mount_point.is_some() {
+            return Ok(());
+        }
+        let (_, host, path) = parse_url(&self.settings.url).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let share = path.split('/').next().unwrap_or_default();
+        let mut remote_name = wide(format!(r"\\{}\{}", host, share));
+        let mut resource = NETRESOURCEW { dw_type: RESOURCETYPE_DISK, lp_remote_name: remote_name.as_mut_ptr(), ..Default::default() };
+        let username = self.settings.username.as_ref().map(wide);
+        let password = self.settings.password.as_ref().map(wide);
+
+        let result = unsafe {
+            WNetAddConnection2W(
+                &mut resource,
+                password.as_ref().map_or(null(), |password| password.as_ptr()),
+                username.as_ref().map_or(null(), |username| username.as_ptr()),
+                CONNECT_TEMPORARY,
+            )
+        };
+        match result {
+            NO_ERROR | ERROR_SESSION_CREDENTIAL_CONFLICT => Ok(()),
+            code => Err(io::Error::from_raw_os_error(code as i32)),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn connect(&self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn retrying<T>(&self, mut operation: impl FnMut() -> Result<T, io::Error>) -> Result<T, io::Error> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(err) if attempt < RETRY_ATTEMPTS && media_source::is_transient(&err) => {
+                    thread::sleep(backoff);
+                    if let Err(err) = self.connect() {
+                        debug!("Couldn't reconnect to {}: {}", self.settings.url, err);
+                    }
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> Result<fs::Metadata, io::Error> {
+        let path = self.root.join(path);
+        self.retrying(|| fs::metadata(&path))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<File>, io::Error> {
+        let path = self.root.join(path);
+        self.retrying(|| File::open(&path)).map(Arc::new)
+    }
+}
+
+pub struct ShareSource {
+    share: Arc<Share>,
+    local: LocalSource,
+    protocol: Protocol,
+}
+
+impl ShareSource {
+    pub fn new(settings: &ShareSettings) -> Result<ShareSource, String> {
+        let (protocol, host, path) = parse_url(&settings.url)?;
+        let root = match settings.mount_point {
+            Some(ref mount_point) => mount_point.clone(),
+            None if cfg!(windows) => PathBuf::from(format!(r"\\{}\{}", host, path.replace('/', r"\"))),
+            None => return Err(format!("The share {} needs a mount-point where the system has mounted it", settings.url)),
+        };
+
+        Ok(ShareSource {
+            share: Arc::new(Share { settings: settings.clone(), root: root.clone() }),
+            local: LocalSource::new(&root),
+            protocol,
+        })
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+}
+
+impl MediaSource for ShareSource {
+    fn refresh(&self) -> Result<(), io::Error> {
+        self.share.connect()?;
+        let root = &self.share.root;
+        match self.share.retrying(|| root.metadata())? {
+            metadata if metadata.is_dir() => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't a folder", root.display()))),
+        }
+    }
+
+    fn entries(&self, dir: &Path) -> Result<Vec<MediaEntry>, io::Error> {
+        self.share.retrying(|| self.local.entries(dir))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.local.is_file(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, io::Error> {
+        self.share.retrying(|| self.local.read_to_string(path))
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        self.local.local_path(path)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<MediaMetadata, io::Error>> {
+        let (share, path) = (self.share.clone(), path.to_path_buf());
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let metadata = share.metadata(&path)?;
+                Ok(MediaMetadata { len: metadata.len(), modified: metadata.modified().ok() })
+            }).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+        }.boxed()
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Arc<dyn MediaFile>, io::Error>> {
+        let (share, path) = (self.share.clone(), path.to_path_buf());
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let file = share.open(&path)?;
+                let metadata = file.metadata()?;
+                let opened: Arc<dyn MediaFile> = Arc::new(ShareFile { len: metadata.len(), modified: metadata.modified().ok(), share, path, file });
+                Ok(opened)
+            }).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+        }.boxed()
+    }
+}
+
+struct ShareFile {
+    share: Arc<Share>,
+    path: PathBuf,
+    file: Arc<File>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+struct Resumable {
+    chunks: BoxStream<'static, Result<Bytes, io::Error>>,
+    position: u64,
+    resumed: u32,
+}
+
+impl MediaFile for ShareFile {
+    fn metadata(&self) -> MediaMetadata {
+        MediaMetadata { len: self.len, modified: self.modified }
+    }
+
+    fn read_range(&self, range: Range<u64>, pool: Arc<BufferPool>, budget: StreamBudget) -> BoxStream<'static, Result<Bytes, io::Error>> {
+        let (share, path, end) = (self.share.clone(), self.path.clone(), range.end);
+        let chunks = file_stream::read(self.file.clone(), range.clone(), pool.clone(), budget.clone()).boxed();
+        let initial = Resumable { chunks, position: range.start, resumed: 0 };
+
+        stream::unfold(Some(initial), move |state| {
+            let (share, path, pool, budget) = (share.clone(), path.clone(), pool.clone(), budget.clone());
+            async move {
+                let mut state = state?;
+                loop {
+                    match state.chunks.next().await? {
+                        Ok(chunk) => {
+                            state.position += chunk.len() as u64;
+                            return Some((Ok(chunk), Some(state)));
+                        }
+                        Err(err) if state.resumed < RETRY_ATTEMPTS && media_source::is_transient(&err) => {
+                            debug!("Reopening {} after {}", path.display(), err);
+                            let reopened = {
+                                let (share, path) = (share.clone(), path.clone());
+                                tokio::task::spawn_blocking(move || share.open(&path)).await.unwrap_or_else(|err| Err(io::Error::other(err)))
+                            };
+                            let file = match reopened {
+                                Ok(file) => file,
+                                Err(err) => return Some((Err(err), None)),
+                            };
+                            state.chunks = file_stream::read(file, state.position..end, pool.clone(), budget.clone()).boxed();
+                            state.resumed += 1;
+                        }
+                        Err(err) => return Some((Err(err), None)),
+                    }
+                }
+            }
+        }).boxed()
+    }
+}
+
+fn parse_url(url: &str) -> Result<(Protocol, String, String), String> {
+    let (protocol, rest) = if let Some(rest) = url.strip_prefix("smb://") {
+        (Protocol::Smb, rest.to_string())
+    } else if let Some(rest) = url.strip_prefix("nfs://") {
+        (Protocol::Nfs, rest.to_string())
+    } else if let Some(rest) = url.strip_prefix(r"\\") {
+        (Protocol::Smb, rest.replace('\\', "/"))
+    } else {
+        return Err(format!("The share {} has to be an smb:// or nfs:// URL", url));
+    };
+
+    let (host, path) = rest.split_once('/').unwrap_or((&rest, ""));
+    let path = path.trim_matches('/');
+    if host.is_empty() || path.is_empty() {
+        return Err(format!("The share {} has to name a host and a shared folder", url));
+    }
+    Ok((protocol, host.to_string(), path.to_string()))
+}
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::{error, time::Duration};
+
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+use crate::request_path;
+use crate::state;
+
+const KEY_LENGTH: usize = 32;
+const QUERY_EXPIRY: &str = "exp=";
+const QUERY_SIGNATURE: &str = "sig=";
+
+pub struct UrlSigner {
+    key: Vec<u8>,
+    lifetime: Duration,
+}
+
+impl UrlSigner {
+    pub fn new(key: Option<&str>, lifetime: Duration) -> Result<UrlSigner, Box<dyn error::Error>> {
+        let key = match key {
+            Some(key) if !key.is_empty() => key.as_bytes().to_vec(),
+            Some(_) => return Err("The URL signing key can't be empty".into()),
+            None => {
+                let mut key = vec![0; KEY_LENGTH];
+                getrandom::getrandom(&mut key)?;
+                key
+            }
+        };
+        Ok(UrlSigner { key, lifetime })
+    }
+
+    pub fn sign(&self, prefix: &str, path: &str) -> String {
+        let expires = state::now() + self.lifetime.as_secs();
+        format!("{}{}?{}{}&{}{}", prefix, request_path::encode(path), QUERY_EXPIRY, expires, QUERY_SIGNATURE, self.signature(path, expires))
+    }
+
+    pub fn sign_file(&self, prefix: &str, file: &mut Map<String, Value>) {
+        if let Some(path) = file.get("path").and_then(Value::as_str) {
+            let url = self.sign(prefix, path);
+            file.insert("url".to_string(), Value::String(url));
+        }
+
+        let text_tracks = file.get("text-tracks").and_then(Value::as_object).map(|tracks| {
+            tracks.iter()
+                .filter_map(|(language, path)| Some((language.clone(), Value::String(self.sign(prefix, path.as_str()?)))))
+                .collect::<Map<_, _>>()
+        });
+        if let Some(text_tracks) = text_tracks {
+            file.insert("text-track-urls".to_string(), Value::Object(text_tracks));
+        }
+    }
+
+    pub fn permits(&self, path: &str, query: Option<&str>) -> bool {
+        let parameter = |name: &str| query.into_iter().flat_map(|query| query.split('&')).find_map(|parameter| parameter.strip_prefix(name));
+        let (expires, signature) = match (parameter(QUERY_EXPIRY).and_then(|expires| expires.parse::<u64>().ok()), parameter(QUERY_SIGNATURE)) {
+            (Some(expires), Some(signature)) => (expires, signature),
+            _ => return false,
+        };
+        let (path, signature) = match (percent_decode_str(path).decode_utf8(), decode_hex(signature)) {
+            (Ok(path), Some(signature)) => (path, signature),
+            _ => return false,
+        };
+
+        expires >= state::now() && self.mac(&path, expires).verify_slice(&signature).is_ok()
+    }
+
+    fn signature(&self, path: &str, expires: u64) -> String {
+        encode_hex(&self.mac(path, expires).finalize().into_bytes())
+    }
+
+    fn mac(&self, path: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|start| u8::from_str_radix(text.get(start..start + 2)?, 16).ok()).collect()
+}
\ No newline at end of file
@@ -0,0 +1,118 @@
+use serde_json::Value;
+
+use crate::manifest;
+use crate::xml::escape;
+
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body { font-family: sans-serif; margin: 0; display: flex; min-height: 100vh; }
+nav { flex: 0 0 24rem; overflow-y: auto; padding: 1rem; border-right: 1px solid #ccc; }
+main { flex: 1; padding: 1rem; }
+ul { list-style: none; padding-left: 1rem; margin: 0.25rem 0; }
+li { margin: 0.25rem 0; }
+summary { cursor: pointer; font-weight: bold; }
+small { color: #666; }
+video { max-width: 100%; max-height: 80vh; }
+</style>
+</head>
+<body>
+<nav><h1>{title}</h1><div id="catalogue"></div></nav>
+<main id="player"></main>
+<script type="application/json" id="items">{items}</script>
+<script>
+function fileUrl(path) {
+  return "file/" + path.split("/").map(encodeURIComponent).join("/");
+}
+
+function formatDuration(milliseconds) {
+  const minutes = Math.round(milliseconds / 60000);
+  return minutes >= 60 ? Math.floor(minutes / 60) + " h " + minutes % 60 + " min" : minutes + " min";
+}
+
+function play(item) {
+  const player = document.getElementById("player");
+  const heading = document.createElement("h2");
+  heading.textContent = item.title;
+  const video = document.createElement("video");
+  video.controls = true;
+  video.src = fileUrl(item.path);
+  if (item.thumbnails && item.thumbnails.length) {
+    video.poster = fileUrl(item.thumbnails[0]);
+  }
+  for (const [language, path] of Object.entries(item["text-tracks"] || {})) {
+    const track = document.createElement("track");
+    track.kind = "subtitles";
+    track.srclang = language;
+    track.label = language;
+    track.src = fileUrl(path);
+    video.append(track);
+  }
+  player.replaceChildren(heading, video);
+  if (item.subtitle) {
+    const subtitle = document.createElement("p");
+    subtitle.textContent = item.subtitle;
+    player.append(subtitle);
+  }
+  video.play();
+}
+
+function render(items, parent) {
+  const list = document.createElement("ul");
+  for (const item of items) {
+    const entry = document.createElement("li");
+    if (item.type === "file") {
+      const link = document.createElement("a");
+      link.href = fileUrl(item.path);
+      link.textContent = item.title;
+      link.addEventListener("click", event => {
+        event.preventDefault();
+        play(item);
+      });
+      entry.append(link);
+      if (item.duration) {
+        const duration = document.createElement("small");
+        duration.textContent = " " + formatDuration(item.duration);
+        entry.append(duration);
+      }
+    } else {
+      const details = document.createElement("details");
+      const summary = document.createElement("summary");
+      summary.textContent = item.title;
+      details.append(summary);
+      render(item.contents || [], details);
+      entry.append(details);
+    }
+    list.append(entry);
+  }
+  parent.append(list);
+}
+
+render(JSON.parse(document.getElementById("items").textContent), document.getElementById("catalogue"));
+</script>
+</body>
+</html>
+"#;
+
+pub fn render(title: &str, items: &Value) -> String {
+    let (head, tail) = TEMPLATE.split_once("{items}").unwrap();
+    format!("{}{}{}", head.replace("{title}", &escape(title)), items.to_string().replace('<', "\\u003c"), tail)
+}
+
+pub fn media_paths(items: &mut Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    manifest::for_each_file(items, &mut |file| {
+        paths.extend(file.get("path").and_then(Value::as_str).map(String::from));
+        if let Some(tracks) = file.get("text-tracks").and_then(Value::as_object) {
+            paths.extend(tracks.values().filter_map(Value::as_str).map(String::from));
+        }
+        if let Some(thumbnails) = file.get("thumbnails").and_then(Value::as_array) {
+            paths.extend(thumbnails.iter().filter_map(Value::as_str).map(String::from));
+        }
+    });
+    paths
+}
\ No newline at end of file
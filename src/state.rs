@@ -0,0 +1,1254 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    error,
+    path::Path,
+    sync::{Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+const WATCHED_THRESHOLD: f64 = 0.9;
+const PLAY_SESSION_GAP: u64 = 30 * 60;
+const VIEWING_GAP: u64 = 5 * 60;
+const SECONDS_IN_HOUR: u64 = 60 * 60;
+
+const SESSION_TOKEN_BYTES: usize = 16;
+const SESSION_ID_LENGTH: usize = 16;
+const SESSION_TOUCH_INTERVAL: u64 = 60;
+
+pub const MIN_RATING: u8 = 1;
+pub const MAX_RATING: u8 = 5;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Progress {
+    pub position: u64,
+    pub duration: u64,
+    pub player_id: String,
+    #[serde(default)]
+    pub updated: u64,
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+pub enum ProgressUpdate {
+    Stored(Progress),
+    Stale(Progress),
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlayStats {
+    pub play_count: u64,
+    pub last_played: Option<u64>,
+}
+
+impl PlayStats {
+    fn played_at(self, at: u64) -> PlayStats {
+        let new_session = self.last_played.is_none_or(|last_played| at.saturating_sub(last_played) >= PLAY_SESSION_GAP);
+        PlayStats {
+            play_count: if new_session { self.play_count + 1 } else { self.play_count },
+            last_played: Some(at),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RatingSummary {
+    pub average: f64,
+    pub count: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Playlist {
+    pub id: u64,
+    pub name: String,
+    pub items: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Bookmark {
+    pub id: u64,
+    pub position: u64,
+    pub label: String,
+    pub created: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Session {
+    pub id: String,
+    #[serde(skip_serializing, default)]
+    pub token: String,
+    pub profile: String,
+    pub device: String,
+    pub created: u64,
+    pub last_seen: u64,
+}
+
+impl Session {
+    fn new(profile: &str, device: &str) -> Result<Session, Box<dyn error::Error>> {
+        let mut bytes = [0; SESSION_TOKEN_BYTES];
+        getrandom::getrandom(&mut bytes)?;
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let id = sha1_smol::Sha1::from(token.as_bytes()).digest().to_string()[..SESSION_ID_LENGTH].to_string();
+
+        let created = now();
+        Ok(Session { id, token, profile: profile.to_string(), device: device.to_string(), created, last_seen: created })
+    }
+
+    pub fn needs_touch(&self, at: u64) -> bool {
+        at.saturating_sub(self.last_seen) >= SESSION_TOUCH_INTERVAL
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TraktTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MetadataOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScanRecord {
+    pub id: u64,
+    pub started: u64,
+    pub duration_ms: u64,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+pub type ViewingLog = HashMap<String, BTreeMap<u64, u64>>;
+
+pub trait StateStore: Send + Sync {
+    fn progress(&self, profile: &str, id: &str) -> Result<Option<Progress>, Box<dyn error::Error>>;
+    fn set_progress(&self, profile: &str, id: &str, progress: &Progress, base: Option<u64>) -> Result<ProgressUpdate, Box<dyn error::Error>>;
+    fn recent_progress(&self, profile: &str) -> Result<Vec<(String, Progress)>, Box<dyn error::Error>>;
+    fn watched(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>>;
+    fn set_watched(&self, profile: &str, id: &str, watched: bool) -> Result<(), Box<dyn error::Error>>;
+    fn play_stats(&self, profile: &str) -> Result<HashMap<String, PlayStats>, Box<dyn error::Error>>;
+    fn record_play(&self, profile: &str, id: &str, at: u64) -> Result<(), Box<dyn error::Error>>;
+    fn playlists(&self) -> Result<Vec<Playlist>, Box<dyn error::Error>>;
+    fn create_playlist(&self, name: &str, items: &[String]) -> Result<Playlist, Box<dyn error::Error>>;
+    fn update_playlist(&self, id: u64, name: Option<&str>, items: Option<&[String]>) -> Result<Option<Playlist>, Box<dyn error::Error>>;
+    fn delete_playlist(&self, id: u64) -> Result<bool, Box<dyn error::Error>>;
+    fn favorites(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>>;
+    fn set_favorite(&self, profile: &str, id: &str, favorite: bool) -> Result<(), Box<dyn error::Error>>;
+    fn bookmarks(&self, profile: &str, id: &str) -> Result<Vec<Bookmark>, Box<dyn error::Error>>;
+    fn add_bookmark(&self, profile: &str, id: &str, position: u64, label: &str) -> Result<Bookmark, Box<dyn error::Error>>;
+    fn delete_bookmark(&self, profile: &str, id: &str, bookmark_id: u64) -> Result<bool, Box<dyn error::Error>>;
+    fn ratings(&self, profile: &str) -> Result<HashMap<String, u8>, Box<dyn error::Error>>;
+    fn set_rating(&self, profile: &str, id: &str, rating: Option<u8>) -> Result<(), Box<dyn error::Error>>;
+    fn rating_summaries(&self) -> Result<HashMap<String, RatingSummary>, Box<dyn error::Error>>;
+    fn viewing(&self) -> Result<HashMap<String, ViewingLog>, Box<dyn error::Error>>;
+    fn record_viewing(&self, profile: &str, id: &str, at: u64, milliseconds: u64) -> Result<(), Box<dyn error::Error>>;
+    fn sessions(&self, profile: &str) -> Result<Vec<Session>, Box<dyn error::Error>>;
+    fn session(&self, token: &str) -> Result<Option<Session>, Box<dyn error::Error>>;
+    fn create_session(&self, profile: &str, device: &str) -> Result<Session, Box<dyn error::Error>>;
+    fn touch_session(&self, token: &str, at: u64) -> Result<(), Box<dyn error::Error>>;
+    fn delete_session(&self, profile: &str, id: &str) -> Result<bool, Box<dyn error::Error>>;
+    fn trakt_tokens(&self, profile: &str) -> Result<Option<TraktTokens>, Box<dyn error::Error>>;
+    fn set_trakt_tokens(&self, profile: &str, tokens: Option<&TraktTokens>) -> Result<(), Box<dyn error::Error>>;
+    fn subtitle_misses(&self) -> Result<HashMap<String, HashMap<String, u64>>, Box<dyn error::Error>>;
+    fn record_subtitle_miss(&self, id: &str, language: &str, retry_after: u64) -> Result<(), Box<dyn error::Error>>;
+    fn metadata_override(&self, id: &str) -> Result<Option<MetadataOverride>, Box<dyn error::Error>>;
+    fn metadata_overrides(&self) -> Result<HashMap<String, MetadataOverride>, Box<dyn error::Error>>;
+    fn set_metadata_override(&self, id: &str, metadata: Option<&MetadataOverride>) -> Result<bool, Box<dyn error::Error>>;
+    fn forget_items(&self, ids: &[String]) -> Result<(), Box<dyn error::Error>>;
+    fn scans(&self) -> Result<Vec<ScanRecord>, Box<dyn error::Error>>;
+    fn record_scan(&self, scan: &ScanRecord) -> Result<ScanRecord, Box<dyn error::Error>>;
+    fn export_state(&self) -> Result<StateSnapshot, Box<dyn error::Error>>;
+    fn import_state(&self, snapshot: &StateSnapshot) -> Result<(), Box<dyn error::Error>>;
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProfileState {
+    pub progress: HashMap<String, Progress>,
+    pub watched: HashSet<String>,
+    pub plays: HashMap<String, PlayStats>,
+    pub favorites: HashSet<String>,
+    pub bookmarks: HashMap<String, Vec<Bookmark>>,
+    pub ratings: HashMap<String, u8>,
+    pub viewing: ViewingLog,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct StateSnapshot {
+    pub profiles: HashMap<String, ProfileState>,
+    pub playlists: Vec<Playlist>,
+    pub metadata_overrides: HashMap<String, MetadataOverride>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct StateDocument {
+    #[serde(flatten)]
+    pub snapshot: StateSnapshot,
+    pub sessions: HashMap<String, Session>,
+    pub scans: Vec<ScanRecord>,
+    pub trakt_tokens: HashMap<String, TraktTokens>,
+    pub subtitle_misses: HashMap<String, HashMap<String, u64>>,
+}
+
+#[derive(Default)]
+pub struct MemoryState {
+    profiles: RwLock<HashMap<String, ProfileState>>,
+    playlists: RwLock<(u64, BTreeMap<u64, Playlist>)>,
+    last_bookmark_id: Mutex<u64>,
+    sessions: RwLock<HashMap<String, Session>>,
+    scans: RwLock<Vec<ScanRecord>>,
+    metadata_overrides: RwLock<HashMap<String, MetadataOverride>>,
+    trakt_tokens: RwLock<HashMap<String, TraktTokens>>,
+    subtitle_misses: RwLock<HashMap<String, HashMap<String, u64>>>,
+}
+
+impl MemoryState {
+    pub fn from_document(document: StateDocument) -> Result<MemoryState, Box<dyn error::Error>> {
+        let state = MemoryState::default();
+        state.import_state(&document.snapshot)?;
+        *state.sessions.write().unwrap() = document.sessions
+            .into_iter()
+            .map(|(token, session)| (token.clone(), Session { token, ..session }))
+            .collect();
+        *state.scans.write().unwrap() = document.scans;
+        *state.trakt_tokens.write().unwrap() = document.trakt_tokens;
+        *state.subtitle_misses.write().unwrap() = document.subtitle_misses;
+        Ok(state)
+    }
+
+    pub fn document(&self) -> Result<StateDocument, Box<dyn error::Error>> {
+        Ok(StateDocument {
+            snapshot: self.export_state()?,
+            sessions: self.sessions.read().unwrap().clone(),
+            scans: self.scans.read().unwrap().clone(),
+            trakt_tokens: self.trakt_tokens.read().unwrap().clone(),
+            subtitle_misses: self.subtitle_misses.read().unwrap().clone(),
+        })
+    }
+
+    pub fn put_playlist(&self, playlist: &Playlist) {
+        let mut playlists = self.playlists.write().unwrap();
+        playlists.0 = playlists.0.max(playlist.id);
+        playlists.1.insert(playlist.id, playlist.clone());
+    }
+
+    pub fn put_bookmark(&self, profile: &str, id: &str, bookmark: &Bookmark) {
+        {
+            let mut last_bookmark_id = self.last_bookmark_id.lock().unwrap();
+            *last_bookmark_id = (*last_bookmark_id).max(bookmark.id);
+        }
+
+        self.write(profile, |state| {
+            let bookmarks = state.bookmarks.entry(id.to_string()).or_default();
+            bookmarks.retain(|existing| existing.id != bookmark.id);
+            bookmarks.push(bookmark.clone());
+            bookmarks.sort_by_key(|bookmark| bookmark.position);
+        });
+    }
+
+    pub fn put_session(&self, session: &Session) {
+        self.sessions.write().unwrap().insert(session.token.clone(), session.clone());
+    }
+
+    fn read<T>(&self, profile: &str, read: impl FnOnce(&ProfileState) -> T) -> T {
+        match self.profiles.read().unwrap().get(profile) {
+            Some(state) => read(state),
+            None => read(&ProfileState::default()),
+        }
+    }
+
+    fn write(&self, profile: &str, write: impl FnOnce(&mut ProfileState)) {
+        write(self.profiles.write().unwrap().entry(profile.to_string()).or_default());
+    }
+}
+
+impl StateStore for MemoryState {
+    fn progress(&self, profile: &str, id: &str) -> Result<Option<Progress>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.progress.get(id).cloned()))
+    }
+
+    fn set_progress(&self, profile: &str, id: &str, progress: &Progress, base: Option<u64>) -> Result<ProgressUpdate, Box<dyn error::Error>> {
+        let mut update = None;
+        self.write(profile, |state| match state.progress.get(id) {
+            Some(current) if base.is_some_and(|base| base < current.sequence) => update = Some(ProgressUpdate::Stale(current.clone())),
+            current => {
+                let stored = Progress { sequence: current.map_or(0, |current| current.sequence) + 1, ..progress.clone() };
+                state.progress.insert(id.to_string(), stored.clone());
+                update = Some(ProgressUpdate::Stored(stored));
+            }
+        });
+        Ok(update.unwrap())
+    }
+
+    fn recent_progress(&self, profile: &str) -> Result<Vec<(String, Progress)>, Box<dyn error::Error>> {
+        let mut recent: Vec<_> = self.read(profile, |state| {
+            state.progress.iter().map(|(id, progress)| (id.clone(), progress.clone())).collect()
+        });
+        recent.sort_by_key(|(_, progress)| Reverse(progress.updated));
+        Ok(recent)
+    }
+
+    fn watched(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.watched.clone()))
+    }
+
+    fn set_watched(&self, profile: &str, id: &str, watched: bool) -> Result<(), Box<dyn error::Error>> {
+        self.write(profile, |state| {
+            if watched {
+                state.watched.insert(id.to_string());
+            } else {
+                state.watched.remove(id);
+            }
+        });
+        Ok(())
+    }
+
+    fn play_stats(&self, profile: &str) -> Result<HashMap<String, PlayStats>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.plays.clone()))
+    }
+
+    fn record_play(&self, profile: &str, id: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        self.write(profile, |state| {
+            let stats = state.plays.entry(id.to_string()).or_default();
+            *stats = stats.played_at(at);
+        });
+        Ok(())
+    }
+
+    fn playlists(&self) -> Result<Vec<Playlist>, Box<dyn error::Error>> {
+        Ok(self.playlists.read().unwrap().1.values().cloned().collect())
+    }
+
+    fn create_playlist(&self, name: &str, items: &[String]) -> Result<Playlist, Box<dyn error::Error>> {
+        let mut playlists = self.playlists.write().unwrap();
+        playlists.0 += 1;
+
+        let playlist = Playlist { id: playlists.0, name: name.to_string(), items: items.to_vec() };
+        playlists.1.insert(playlist.id, playlist.clone());
+        Ok(playlist)
+    }
+
+    fn update_playlist(&self, id: u64, name: Option<&str>, items: Option<&[String]>) -> Result<Option<Playlist>, Box<dyn error::Error>> {
+        let mut playlists = self.playlists.write().unwrap();
+        Ok(playlists.1.get_mut(&id).map(|playlist| {
+            if let Some(name) = name {
+                playlist.name = name.to_string();
+            }
+            if let Some(items) = items {
+                playlist.items = items.to_vec();
+            }
+            playlist.clone()
+        }))
+    }
+
+    fn delete_playlist(&self, id: u64) -> Result<bool, Box<dyn error::Error>> {
+        Ok(self.playlists.write().unwrap().1.remove(&id).is_some())
+    }
+
+    fn favorites(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.favorites.clone()))
+    }
+
+    fn set_favorite(&self, profile: &str, id: &str, favorite: bool) -> Result<(), Box<dyn error::Error>> {
+        self.write(profile, |state| {
+            if favorite {
+                state.favorites.insert(id.to_string());
+            } else {
+                state.favorites.remove(id);
+            }
+        });
+        Ok(())
+    }
+
+    fn bookmarks(&self, profile: &str, id: &str) -> Result<Vec<Bookmark>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.bookmarks.get(id).cloned().unwrap_or_default()))
+    }
+
+    fn add_bookmark(&self, profile: &str, id: &str, position: u64, label: &str) -> Result<Bookmark, Box<dyn error::Error>> {
+        let bookmark_id = {
+            let mut last_bookmark_id = self.last_bookmark_id.lock().unwrap();
+            *last_bookmark_id += 1;
+            *last_bookmark_id
+        };
+
+        let bookmark = Bookmark { id: bookmark_id, position, label: label.to_string(), created: now() };
+        self.put_bookmark(profile, id, &bookmark);
+        Ok(bookmark)
+    }
+
+    fn delete_bookmark(&self, profile: &str, id: &str, bookmark_id: u64) -> Result<bool, Box<dyn error::Error>> {
+        let mut deleted = false;
+        self.write(profile, |state| {
+            if let Some(bookmarks) = state.bookmarks.get_mut(id) {
+                let count = bookmarks.len();
+                bookmarks.retain(|bookmark| bookmark.id != bookmark_id);
+                deleted = bookmarks.len() != count;
+            }
+        });
+        Ok(deleted)
+    }
+
+    fn ratings(&self, profile: &str) -> Result<HashMap<String, u8>, Box<dyn error::Error>> {
+        Ok(self.read(profile, |state| state.ratings.clone()))
+    }
+
+    fn set_rating(&self, profile: &str, id: &str, rating: Option<u8>) -> Result<(), Box<dyn error::Error>> {
+        self.write(profile, |state| match rating {
+            Some(rating) => {
+                state.ratings.insert(id.to_string(), rating);
+            }
+            None => {
+                state.ratings.remove(id);
+            }
+        });
+        Ok(())
+    }
+
+    fn rating_summaries(&self) -> Result<HashMap<String, RatingSummary>, Box<dyn error::Error>> {
+        let mut totals = HashMap::<String, (u64, u64)>::new();
+        for state in self.profiles.read().unwrap().values() {
+            for (id, &rating) in &state.ratings {
+                let total = totals.entry(id.clone()).or_default();
+                total.0 += rating as u64;
+                total.1 += 1;
+            }
+        }
+
+        Ok(totals
+            .into_iter()
+            .map(|(id, (sum, count))| (id, RatingSummary { average: sum as f64 / count as f64, count }))
+            .collect())
+    }
+
+    fn viewing(&self) -> Result<HashMap<String, ViewingLog>, Box<dyn error::Error>> {
+        Ok(self.profiles.read().unwrap().iter().map(|(profile, state)| (profile.clone(), state.viewing.clone())).collect())
+    }
+
+    fn record_viewing(&self, profile: &str, id: &str, at: u64, milliseconds: u64) -> Result<(), Box<dyn error::Error>> {
+        self.write(profile, |state| {
+            *state.viewing.entry(id.to_string()).or_default().entry(hour_of(at)).or_default() += milliseconds;
+        });
+        Ok(())
+    }
+
+    fn sessions(&self, profile: &str) -> Result<Vec<Session>, Box<dyn error::Error>> {
+        let mut sessions: Vec<_> = self.sessions.read().unwrap().values().filter(|session| session.profile == profile).cloned().collect();
+        sessions.sort_by_key(|session| Reverse(session.last_seen));
+        Ok(sessions)
+    }
+
+    fn session(&self, token: &str) -> Result<Option<Session>, Box<dyn error::Error>> {
+        Ok(self.sessions.read().unwrap().get(token).cloned())
+    }
+
+    fn create_session(&self, profile: &str, device: &str) -> Result<Session, Box<dyn error::Error>> {
+        let session = Session::new(profile, device)?;
+        self.put_session(&session);
+        Ok(session)
+    }
+
+    fn touch_session(&self, token: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        if let Some(session) = self.sessions.write().unwrap().get_mut(token) {
+            session.last_seen = at;
+        }
+        Ok(())
+    }
+
+    fn delete_session(&self, profile: &str, id: &str) -> Result<bool, Box<dyn error::Error>> {
+        let mut sessions = self.sessions.write().unwrap();
+        let count = sessions.len();
+        sessions.retain(|_, session| session.profile != profile || session.id != id);
+        Ok(sessions.len() != count)
+    }
+
+    fn trakt_tokens(&self, profile: &str) -> Result<Option<TraktTokens>, Box<dyn error::Error>> {
+        Ok(self.trakt_tokens.read().unwrap().get(profile).cloned())
+    }
+
+    fn set_trakt_tokens(&self, profile: &str, tokens: Option<&TraktTokens>) -> Result<(), Box<dyn error::Error>> {
+        let mut trakt_tokens = self.trakt_tokens.write().unwrap();
+        match tokens {
+            Some(tokens) => trakt_tokens.insert(profile.to_string(), tokens.clone()),
+            None => trakt_tokens.remove(profile),
+        };
+        Ok(())
+    }
+
+    fn subtitle_misses(&self) -> Result<HashMap<String, HashMap<String, u64>>, Box<dyn error::Error>> {
+        Ok(self.subtitle_misses.read().unwrap().clone())
+    }
+
+    fn record_subtitle_miss(&self, id: &str, language: &str, retry_after: u64) -> Result<(), Box<dyn error::Error>> {
+        self.subtitle_misses.write().unwrap().entry(id.to_string()).or_default().insert(language.to_string(), retry_after);
+        Ok(())
+    }
+
+    fn metadata_override(&self, id: &str) -> Result<Option<MetadataOverride>, Box<dyn error::Error>> {
+        Ok(self.metadata_overrides.read().unwrap().get(id).cloned())
+    }
+
+    fn metadata_overrides(&self) -> Result<HashMap<String, MetadataOverride>, Box<dyn error::Error>> {
+        Ok(self.metadata_overrides.read().unwrap().clone())
+    }
+
+    fn set_metadata_override(&self, id: &str, metadata: Option<&MetadataOverride>) -> Result<bool, Box<dyn error::Error>> {
+        let mut overrides = self.metadata_overrides.write().unwrap();
+        Ok(match metadata {
+            Some(metadata) => {
+                overrides.insert(id.to_string(), metadata.clone());
+                true
+            }
+            None => overrides.remove(id).is_some(),
+        })
+    }
+
+    fn forget_items(&self, ids: &[String]) -> Result<(), Box<dyn error::Error>> {
+        for state in self.profiles.write().unwrap().values_mut() {
+            for id in ids {
+                state.progress.remove(id);
+                state.watched.remove(id);
+                state.plays.remove(id);
+                state.favorites.remove(id);
+                state.bookmarks.remove(id);
+                state.ratings.remove(id);
+                state.viewing.remove(id);
+            }
+        }
+        for playlist in self.playlists.write().unwrap().1.values_mut() {
+            playlist.items.retain(|id| !ids.contains(id));
+        }
+
+        let mut overrides = self.metadata_overrides.write().unwrap();
+        let mut subtitle_misses = self.subtitle_misses.write().unwrap();
+        for id in ids {
+            overrides.remove(id);
+            subtitle_misses.remove(id);
+        }
+        Ok(())
+    }
+
+    fn scans(&self) -> Result<Vec<ScanRecord>, Box<dyn error::Error>> {
+        Ok(self.scans.read().unwrap().iter().rev().cloned().collect())
+    }
+
+    fn record_scan(&self, scan: &ScanRecord) -> Result<ScanRecord, Box<dyn error::Error>> {
+        let mut scans = self.scans.write().unwrap();
+        let scan = ScanRecord { id: scans.len() as u64 + 1, ..scan.clone() };
+        scans.push(scan.clone());
+        Ok(scan)
+    }
+
+    fn export_state(&self) -> Result<StateSnapshot, Box<dyn error::Error>> {
+        Ok(StateSnapshot {
+            profiles: self.profiles.read().unwrap().clone(),
+            playlists: self.playlists()?,
+            metadata_overrides: self.metadata_overrides()?,
+        })
+    }
+
+    fn import_state(&self, snapshot: &StateSnapshot) -> Result<(), Box<dyn error::Error>> {
+        let last_bookmark_id = snapshot.profiles.values()
+            .flat_map(|state| state.bookmarks.values().flatten())
+            .map(|bookmark| bookmark.id)
+            .max()
+            .unwrap_or_default();
+        let last_playlist_id = snapshot.playlists.iter().map(|playlist| playlist.id).max().unwrap_or_default();
+
+        *self.profiles.write().unwrap() = snapshot.profiles.clone();
+        *self.playlists.write().unwrap() = (
+            last_playlist_id,
+            snapshot.playlists.iter().map(|playlist| (playlist.id, playlist.clone())).collect(),
+        );
+        *self.last_bookmark_id.lock().unwrap() = last_bookmark_id;
+        *self.metadata_overrides.write().unwrap() = snapshot.metadata_overrides.clone();
+        Ok(())
+    }
+}
+
+pub struct SqliteState {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteState {
+    pub fn open(path: &Path) -> Result<SqliteState, Box<dyn error::Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS progress (
+                profile TEXT NOT NULL,
+                id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                player_id TEXT NOT NULL,
+                updated INTEGER NOT NULL,
+                sequence INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (profile, id)
+            );
+            CREATE INDEX IF NOT EXISTS progress_updated ON progress (profile, updated);
+            CREATE TABLE IF NOT EXISTS watched (profile TEXT NOT NULL, id TEXT NOT NULL, PRIMARY KEY (profile, id));
+            CREATE TABLE IF NOT EXISTS plays (
+                profile TEXT NOT NULL,
+                id TEXT NOT NULL,
+                play_count INTEGER NOT NULL,
+                last_played INTEGER,
+                PRIMARY KEY (profile, id)
+            );
+            CREATE TABLE IF NOT EXISTS playlists (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, items TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS favorites (profile TEXT NOT NULL, id TEXT NOT NULL, PRIMARY KEY (profile, id));
+            CREATE TABLE IF NOT EXISTS bookmarks (
+                bookmark_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                profile TEXT NOT NULL,
+                id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                label TEXT NOT NULL,
+                created INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS bookmarks_item ON bookmarks (profile, id);
+            CREATE TABLE IF NOT EXISTS ratings (profile TEXT NOT NULL, id TEXT NOT NULL, rating INTEGER NOT NULL, PRIMARY KEY (profile, id));
+            CREATE TABLE IF NOT EXISTS sessions (
+                token TEXT PRIMARY KEY,
+                id TEXT NOT NULL,
+                profile TEXT NOT NULL,
+                device TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS scans (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                added TEXT NOT NULL,
+                removed TEXT NOT NULL,
+                changed TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS metadata_overrides (id TEXT PRIMARY KEY, metadata TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS trakt_tokens (
+                profile TEXT PRIMARY KEY,
+                access_token TEXT NOT NULL,
+                refresh_token TEXT NOT NULL,
+                expires INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS subtitle_misses (
+                id TEXT NOT NULL,
+                language TEXT NOT NULL,
+                retry_after INTEGER NOT NULL,
+                PRIMARY KEY (id, language)
+            );
+            CREATE TABLE IF NOT EXISTS viewing (
+                profile TEXT NOT NULL,
+                id TEXT NOT NULL,
+                hour INTEGER NOT NULL,
+                milliseconds INTEGER NOT NULL,
+                PRIMARY KEY (profile, id, hour)
+            );"
+        )?;
+        add_missing_column(&connection, "progress", "sequence", "INTEGER NOT NULL DEFAULT 0")?;
+
+        Ok(SqliteState { connection: Mutex::new(connection) })
+    }
+}
+
+impl StateStore for SqliteState {
+    fn progress(&self, profile: &str, id: &str) -> Result<Option<Progress>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let progress = connection
+            .query_row(
+                "SELECT position, duration, player_id, updated, sequence FROM progress WHERE profile = ?1 AND id = ?2",
+                [profile, id],
+                |row| progress_from_row(row, 0),
+            )
+            .optional()?;
+        Ok(progress)
+    }
+
+    fn set_progress(&self, profile: &str, id: &str, progress: &Progress, base: Option<u64>) -> Result<ProgressUpdate, Box<dyn error::Error>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let current = transaction
+            .query_row(
+                "SELECT position, duration, player_id, updated, sequence FROM progress WHERE profile = ?1 AND id = ?2",
+                [profile, id],
+                |row| progress_from_row(row, 0),
+            )
+            .optional()?;
+        let sequence = match current {
+            Some(current) if base.is_some_and(|base| base < current.sequence) => return Ok(ProgressUpdate::Stale(current)),
+            current => current.map_or(0, |current| current.sequence),
+        };
+
+        let stored = Progress { sequence: sequence + 1, ..progress.clone() };
+        transaction.execute(
+            "INSERT OR REPLACE INTO progress (profile, id, position, duration, player_id, updated, sequence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![profile, id, stored.position, stored.duration, stored.player_id, stored.updated, stored.sequence],
+        )?;
+        transaction.commit()?;
+        Ok(ProgressUpdate::Stored(stored))
+    }
+
+    fn recent_progress(&self, profile: &str) -> Result<Vec<(String, Progress)>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let recent = connection
+            .prepare("SELECT id, position, duration, player_id, updated, sequence FROM progress WHERE profile = ?1 ORDER BY updated DESC")?
+            .query_map([profile], |row| Ok((row.get(0)?, progress_from_row(row, 1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(recent)
+    }
+
+    fn watched(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let watched = connection
+            .prepare("SELECT id FROM watched WHERE profile = ?1")?
+            .query_map([profile], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(watched)
+    }
+
+    fn set_watched(&self, profile: &str, id: &str, watched: bool) -> Result<(), Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        if watched {
+            connection.execute("INSERT OR IGNORE INTO watched (profile, id) VALUES (?1, ?2)", [profile, id])?;
+        } else {
+            connection.execute("DELETE FROM watched WHERE profile = ?1 AND id = ?2", [profile, id])?;
+        }
+        Ok(())
+    }
+
+    fn play_stats(&self, profile: &str) -> Result<HashMap<String, PlayStats>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let stats = connection
+            .prepare("SELECT id, play_count, last_played FROM plays WHERE profile = ?1")?
+            .query_map([profile], |row| Ok((row.get(0)?, PlayStats { play_count: row.get(1)?, last_played: row.get(2)? })))?
+            .collect::<Result<_, _>>()?;
+        Ok(stats)
+    }
+
+    fn record_play(&self, profile: &str, id: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let stats = transaction
+            .query_row("SELECT play_count, last_played FROM plays WHERE profile = ?1 AND id = ?2", [profile, id], |row| {
+                Ok(PlayStats { play_count: row.get(0)?, last_played: row.get(1)? })
+            })
+            .optional()?
+            .unwrap_or_default()
+            .played_at(at);
+        transaction.execute(
+            "INSERT OR REPLACE INTO plays (profile, id, play_count, last_played) VALUES (?1, ?2, ?3, ?4)",
+            params![profile, id, stats.play_count, stats.last_played],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn playlists(&self) -> Result<Vec<Playlist>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let rows = connection
+            .prepare("SELECT id, name, items FROM playlists ORDER BY id")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?)))?
+            .collect::<Result<Vec<(u64, String, String)>, _>>()?;
+
+        let mut playlists = Vec::with_capacity(rows.len());
+        for (id, name, items) in rows {
+            playlists.push(Playlist { id, name, items: serde_json::from_str(&items)? });
+        }
+        Ok(playlists)
+    }
+
+    fn create_playlist(&self, name: &str, items: &[String]) -> Result<Playlist, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("INSERT INTO playlists (name, items) VALUES (?1, ?2)", [name, &serde_json::to_string(items)?])?;
+        Ok(Playlist { id: connection.last_insert_rowid() as u64, name: name.to_string(), items: items.to_vec() })
+    }
+
+    fn update_playlist(&self, id: u64, name: Option<&str>, items: Option<&[String]>) -> Result<Option<Playlist>, Box<dyn error::Error>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let stored = transaction
+            .query_row("SELECT name, items FROM playlists WHERE id = ?1", [id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .optional()?;
+        let (stored_name, stored_items) = match stored {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+
+        let playlist = Playlist {
+            id,
+            name: name.map_or(stored_name, str::to_string),
+            items: match items {
+                Some(items) => items.to_vec(),
+                None => serde_json::from_str(&stored_items)?,
+            },
+        };
+        transaction.execute(
+            "UPDATE playlists SET name = ?1, items = ?2 WHERE id = ?3",
+            params![playlist.name, serde_json::to_string(&playlist.items)?, id],
+        )?;
+        transaction.commit()?;
+        Ok(Some(playlist))
+    }
+
+    fn delete_playlist(&self, id: u64) -> Result<bool, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.execute("DELETE FROM playlists WHERE id = ?1", [id])? > 0)
+    }
+
+    fn favorites(&self, profile: &str) -> Result<HashSet<String>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let favorites = connection
+            .prepare("SELECT id FROM favorites WHERE profile = ?1")?
+            .query_map([profile], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(favorites)
+    }
+
+    fn set_favorite(&self, profile: &str, id: &str, favorite: bool) -> Result<(), Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        if favorite {
+            connection.execute("INSERT OR IGNORE INTO favorites (profile, id) VALUES (?1, ?2)", [profile, id])?;
+        } else {
+            connection.execute("DELETE FROM favorites WHERE profile = ?1 AND id = ?2", [profile, id])?;
+        }
+        Ok(())
+    }
+
+    fn bookmarks(&self, profile: &str, id: &str) -> Result<Vec<Bookmark>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let bookmarks = connection
+            .prepare("SELECT bookmark_id, position, label, created FROM bookmarks WHERE profile = ?1 AND id = ?2 ORDER BY position")?
+            .query_map([profile, id], |row| Ok(Bookmark { id: row.get(0)?, position: row.get(1)?, label: row.get(2)?, created: row.get(3)? }))?
+            .collect::<Result<_, _>>()?;
+        Ok(bookmarks)
+    }
+
+    fn add_bookmark(&self, profile: &str, id: &str, position: u64, label: &str) -> Result<Bookmark, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let created = now();
+        connection.execute(
+            "INSERT INTO bookmarks (profile, id, position, label, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![profile, id, position, label, created],
+        )?;
+        Ok(Bookmark { id: connection.last_insert_rowid() as u64, position, label: label.to_string(), created })
+    }
+
+    fn delete_bookmark(&self, profile: &str, id: &str, bookmark_id: u64) -> Result<bool, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let deleted = connection.execute(
+            "DELETE FROM bookmarks WHERE profile = ?1 AND id = ?2 AND bookmark_id = ?3",
+            params![profile, id, bookmark_id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn ratings(&self, profile: &str) -> Result<HashMap<String, u8>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let ratings = connection
+            .prepare("SELECT id, rating FROM ratings WHERE profile = ?1")?
+            .query_map([profile], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(ratings)
+    }
+
+    fn set_rating(&self, profile: &str, id: &str, rating: Option<u8>) -> Result<(), Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        match rating {
+            Some(rating) => connection.execute("INSERT OR REPLACE INTO ratings (profile, id, rating) VALUES (?1, ?2, ?3)", params![profile, id, rating])?,
+            None => connection.execute("DELETE FROM ratings WHERE profile = ?1 AND id = ?2", [profile, id])?,
+        };
+        Ok(())
+    }
+
+    fn rating_summaries(&self) -> Result<HashMap<String, RatingSummary>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let summaries = connection
+            .prepare("SELECT id, AVG(rating), COUNT(*) FROM ratings GROUP BY id")?
+            .query_map([], |row| Ok((row.get(0)?, RatingSummary { average: row.get(1)?, count: row.get(2)? })))?
+            .collect::<Result<_, _>>()?;
+        Ok(summaries)
+    }
+
+    fn viewing(&self) -> Result<HashMap<String, ViewingLog>, Box<dyn error::Error>> {
+        Ok(viewing_from(&self.connection.lock().unwrap())?)
+    }
+
+    fn record_viewing(&self, profile: &str, id: &str, at: u64, milliseconds: u64) -> Result<(), Box<dyn error::Error>> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO viewing (profile, id, hour, milliseconds) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (profile, id, hour) DO UPDATE SET milliseconds = milliseconds + excluded.milliseconds",
+            params![profile, id, hour_of(at), milliseconds],
+        )?;
+        Ok(())
+    }
+
+    fn sessions(&self, profile: &str) -> Result<Vec<Session>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let sessions = connection
+            .prepare("SELECT token, id, profile, device, created, last_seen FROM sessions WHERE profile = ?1 ORDER BY last_seen DESC")?
+            .query_map([profile], session_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(sessions)
+    }
+
+    fn session(&self, token: &str) -> Result<Option<Session>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let session = connection
+            .query_row("SELECT token, id, profile, device, created, last_seen FROM sessions WHERE token = ?1", [token], session_from_row)
+            .optional()?;
+        Ok(session)
+    }
+
+    fn create_session(&self, profile: &str, device: &str) -> Result<Session, Box<dyn error::Error>> {
+        let session = Session::new(profile, device)?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO sessions (token, id, profile, device, created, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![session.token, session.id, session.profile, session.device, session.created, session.last_seen],
+        )?;
+        Ok(session)
+    }
+
+    fn touch_session(&self, token: &str, at: u64) -> Result<(), Box<dyn error::Error>> {
+        self.connection.lock().unwrap().execute("UPDATE sessions SET last_seen = ?1 WHERE token = ?2", params![at, token])?;
+        Ok(())
+    }
+
+    fn delete_session(&self, profile: &str, id: &str) -> Result<bool, Box<dyn error::Error>> {
+        let deleted = self.connection.lock().unwrap().execute("DELETE FROM sessions WHERE profile = ?1 AND id = ?2", [profile, id])?;
+        Ok(deleted > 0)
+    }
+
+    fn trakt_tokens(&self, profile: &str) -> Result<Option<TraktTokens>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let tokens = connection
+            .query_row("SELECT access_token, refresh_token, expires FROM trakt_tokens WHERE profile = ?1", [profile], |row| {
+                Ok(TraktTokens { access_token: row.get(0)?, refresh_token: row.get(1)?, expires: row.get(2)? })
+            })
+            .optional()?;
+        Ok(tokens)
+    }
+
+    fn set_trakt_tokens(&self, profile: &str, tokens: Option<&TraktTokens>) -> Result<(), Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        match tokens {
+            Some(tokens) => connection.execute(
+                "INSERT OR REPLACE INTO trakt_tokens (profile, access_token, refresh_token, expires) VALUES (?1, ?2, ?3, ?4)",
+                params![profile, tokens.access_token, tokens.refresh_token, tokens.expires],
+            )?,
+            None => connection.execute("DELETE FROM trakt_tokens WHERE profile = ?1", [profile])?,
+        };
+        Ok(())
+    }
+
+    fn subtitle_misses(&self) -> Result<HashMap<String, HashMap<String, u64>>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let rows = connection
+            .prepare("SELECT id, language, retry_after FROM subtitle_misses")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(String, String, u64)>, _>>()?;
+
+        let mut misses: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for (id, language, retry_after) in rows {
+            misses.entry(id).or_default().insert(language, retry_after);
+        }
+        Ok(misses)
+    }
+
+    fn record_subtitle_miss(&self, id: &str, language: &str, retry_after: u64) -> Result<(), Box<dyn error::Error>> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO subtitle_misses (id, language, retry_after) VALUES (?1, ?2, ?3)",
+            params![id, language, retry_after],
+        )?;
+        Ok(())
+    }
+
+    fn metadata_override(&self, id: &str) -> Result<Option<MetadataOverride>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let metadata = connection
+            .query_row("SELECT metadata FROM metadata_overrides WHERE id = ?1", [id], |row| row.get::<_, String>(0))
+            .optional()?;
+        Ok(metadata.map(|metadata| serde_json::from_str(&metadata)).transpose()?)
+    }
+
+    fn metadata_overrides(&self) -> Result<HashMap<String, MetadataOverride>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let rows = connection
+            .prepare("SELECT id, metadata FROM metadata_overrides")?
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+
+        let mut overrides = HashMap::with_capacity(rows.len());
+        for (id, metadata) in rows {
+            overrides.insert(id, serde_json::from_str(&metadata)?);
+        }
+        Ok(overrides)
+    }
+
+    fn set_metadata_override(&self, id: &str, metadata: Option<&MetadataOverride>) -> Result<bool, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let changed = match metadata {
+            Some(metadata) => connection.execute(
+                "INSERT OR REPLACE INTO metadata_overrides (id, metadata) VALUES (?1, ?2)",
+                [id, &serde_json::to_string(metadata)?],
+            )?,
+            None => connection.execute("DELETE FROM metadata_overrides WHERE id = ?1", [id])?,
+        };
+        Ok(changed > 0)
+    }
+
+    fn forget_items(&self, ids: &[String]) -> Result<(), Box<dyn error::Error>> {
+        let playlists = self.playlists()?;
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        for id in ids {
+            for table in &["progress", "watched", "plays", "favorites", "bookmarks", "ratings", "viewing", "metadata_overrides", "subtitle_misses"] {
+                transaction.execute(&format!("DELETE FROM {} WHERE id = ?1", table), [id])?;
+            }
+        }
+        for playlist in playlists.iter().filter(|playlist| playlist.items.iter().any(|id| ids.contains(id))) {
+            let items: Vec<_> = playlist.items.iter().filter(|id| !ids.contains(id)).collect();
+            transaction.execute("UPDATE playlists SET items = ?1 WHERE id = ?2", params![serde_json::to_string(&items)?, playlist.id])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn scans(&self) -> Result<Vec<ScanRecord>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let rows = connection
+            .prepare("SELECT id, started, duration_ms, added, removed, changed FROM scans ORDER BY id DESC")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))?
+            .collect::<Result<Vec<(u64, u64, u64, String, String, String)>, _>>()?;
+
+        let mut scans = Vec::with_capacity(rows.len());
+        for (id, started, duration_ms, added, removed, changed) in rows {
+            scans.push(ScanRecord {
+                id,
+                started,
+                duration_ms,
+                added: serde_json::from_str(&added)?,
+                removed: serde_json::from_str(&removed)?,
+                changed: serde_json::from_str(&changed)?,
+            });
+        }
+        Ok(scans)
+    }
+
+    fn record_scan(&self, scan: &ScanRecord) -> Result<ScanRecord, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO scans (started, duration_ms, added, removed, changed) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                scan.started,
+                scan.duration_ms,
+                serde_json::to_string(&scan.added)?,
+                serde_json::to_string(&scan.removed)?,
+                serde_json::to_string(&scan.changed)?,
+            ],
+        )?;
+        Ok(ScanRecord { id: connection.last_insert_rowid() as u64, ..scan.clone() })
+    }
+
+    fn export_state(&self) -> Result<StateSnapshot, Box<dyn error::Error>> {
+        let playlists = self.playlists()?;
+        let metadata_overrides = self.metadata_overrides()?;
+        let connection = self.connection.lock().unwrap();
+        let mut profiles = HashMap::<String, ProfileState>::new();
+
+        let mut statement = connection.prepare("SELECT profile, id, position, duration, player_id, updated, sequence FROM progress")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let progress = progress_from_row(row, 2)?;
+            profiles.entry(row.get(0)?).or_default().progress.insert(row.get(1)?, progress);
+        }
+
+        let mut statement = connection.prepare("SELECT profile, id FROM watched")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            profiles.entry(row.get(0)?).or_default().watched.insert(row.get(1)?);
+        }
+
+        let mut statement = connection.prepare("SELECT profile, id, play_count, last_played FROM plays")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let stats = PlayStats { play_count: row.get(2)?, last_played: row.get(3)? };
+            profiles.entry(row.get(0)?).or_default().plays.insert(row.get(1)?, stats);
+        }
+
+        let mut statement = connection.prepare("SELECT profile, id FROM favorites")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            profiles.entry(row.get(0)?).or_default().favorites.insert(row.get(1)?);
+        }
+
+        let mut statement = connection.prepare("SELECT profile, id, bookmark_id, position, label, created FROM bookmarks ORDER BY position")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let bookmark = Bookmark { id: row.get(2)?, position: row.get(3)?, label: row.get(4)?, created: row.get(5)? };
+            profiles.entry(row.get(0)?).or_default().bookmarks.entry(row.get(1)?).or_default().push(bookmark);
+        }
+
+        let mut statement = connection.prepare("SELECT profile, id, rating FROM ratings")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            profiles.entry(row.get(0)?).or_default().ratings.insert(row.get(1)?, row.get(2)?);
+        }
+
+        for (profile, viewing) in viewing_from(&connection)? {
+            profiles.entry(profile).or_default().viewing = viewing;
+        }
+
+        Ok(StateSnapshot { profiles, playlists, metadata_overrides })
+    }
+
+    fn import_state(&self, snapshot: &StateSnapshot) -> Result<(), Box<dyn error::Error>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(
+            "DELETE FROM progress;
+            DELETE FROM watched;
+            DELETE FROM plays;
+            DELETE FROM favorites;
+            DELETE FROM bookmarks;
+            DELETE FROM ratings;
+            DELETE FROM viewing;
+            DELETE FROM playlists;
+            DELETE FROM metadata_overrides;"
+        )?;
+
+        for (profile, state) in &snapshot.profiles {
+            for (id, progress) in &state.progress {
+                transaction.execute(
+                    "INSERT INTO progress (profile, id, position, duration, player_id, updated, sequence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![profile, id, progress.position, progress.duration, progress.player_id, progress.updated, progress.sequence],
+                )?;
+            }
+            for id in &state.watched {
+                transaction.execute("INSERT INTO watched (profile, id) VALUES (?1, ?2)", [profile, id])?;
+            }
+            for (id, stats) in &state.plays {
+                transaction.execute(
+                    "INSERT INTO plays (profile, id, play_count, last_played) VALUES (?1, ?2, ?3, ?4)",
+                    params![profile, id, stats.play_count, stats.last_played],
+                )?;
+            }
+            for id in &state.favorites {
+                transaction.execute("INSERT INTO favorites (profile, id) VALUES (?1, ?2)", [profile, id])?;
+            }
+            for (id, bookmarks) in &state.bookmarks {
+                for bookmark in bookmarks {
+                    transaction.execute(
+                        "INSERT INTO bookmarks (bookmark_id, profile, id, position, label, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![bookmark.id, profile, id, bookmark.position, bookmark.label, bookmark.created],
+                    )?;
+                }
+            }
+            for (id, rating) in &state.ratings {
+                transaction.execute("INSERT INTO ratings (profile, id, rating) VALUES (?1, ?2, ?3)", params![profile, id, rating])?;
+            }
+            for (id, hours) in &state.viewing {
+                for (hour, milliseconds) in hours {
+                    transaction.execute(
+                        "INSERT INTO viewing (profile, id, hour, milliseconds) VALUES (?1, ?2, ?3, ?4)",
+                        params![profile, id, hour, milliseconds],
+                    )?;
+                }
+            }
+        }
+
+        for playlist in &snapshot.playlists {
+            transaction.execute(
+                "INSERT INTO playlists (id, name, items) VALUES (?1, ?2, ?3)",
+                params![playlist.id, playlist.name, serde_json::to_string(&playlist.items)?],
+            )?;
+        }
+        for (id, metadata) in &snapshot.metadata_overrides {
+            transaction.execute("INSERT INTO metadata_overrides (id, metadata) VALUES (?1, ?2)", [id, &serde_json::to_string(metadata)?])?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn add_missing_column(connection: &Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let columns = connection
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !columns.iter().any(|existing| existing == column) {
+        connection.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+    }
+    Ok(())
+}
+
+fn viewing_from(connection: &Connection) -> rusqlite::Result<HashMap<String, ViewingLog>> {
+    let mut viewing = HashMap::<String, ViewingLog>::new();
+    let mut statement = connection.prepare("SELECT profile, id, hour, milliseconds FROM viewing")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        viewing.entry(row.get(0)?).or_default().entry(row.get(1)?).or_default().insert(row.get(2)?, row.get(3)?);
+    }
+    Ok(viewing)
+}
+
+fn progress_from_row(row: &Row, first: usize) -> rusqlite::Result<Progress> {
+    Ok(Progress {
+        position: row.get(first)?,
+        duration: row.get(first + 1)?,
+        player_id: row.get(first + 2)?,
+        updated: row.get(first + 3)?,
+        sequence: row.get(first + 4)?,
+    })
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        token: row.get(0)?,
+        id: row.get(1)?,
+        profile: row.get(2)?,
+        device: row.get(3)?,
+        created: row.get(4)?,
+        last_seen: row.get(5)?,
+    })
+}
+
+pub fn is_nearly_finished(progress: &Progress) -> bool {
+    progress.position as f64 >= progress.duration as f64 * WATCHED_THRESHOLD
+}
+
+pub fn viewing_time(previous: &Progress, current: &Progress) -> u64 {
+    let elapsed = current.updated.saturating_sub(previous.updated);
+    if previous.player_id != current.player_id || elapsed > VIEWING_GAP {
+        return 0;
+    }
+    current.position.saturating_sub(previous.position).min((elapsed + 1) * 1000)
+}
+
+fn hour_of(at: u64) -> u64 {
+    at - at % SECONDS_IN_HOUR
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
\ No newline at end of file
@@ -0,0 +1,148 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    error,
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::server::State;
+use crate::state;
+use crate::viewer::Viewer;
+
+const AGGREGATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SECONDS_IN_HOUR: u64 = 60 * 60;
+const HOURS_IN_DAY: u64 = 24;
+const MILLISECONDS_IN_HOUR: f64 = (SECONDS_IN_HOUR * 1000) as f64;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ViewingStats {
+    pub updated: u64,
+    pub profiles: BTreeMap<String, ProfileStats>,
+    pub titles: Vec<TitleStats>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileStats {
+    pub hours_watched: f64,
+    pub titles_watched: usize,
+    pub genres: Vec<GenreStats>,
+    pub busiest_hours: Vec<HourStats>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GenreStats {
+    pub genre: String,
+    pub hours_watched: f64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HourStats {
+    pub hour: u64,
+    pub hours_watched: f64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TitleStats {
+    pub id: String,
+    pub title: String,
+    #[serde(skip)]
+    pub path: String,
+    pub hours_watched: f64,
+    pub viewers: usize,
+}
+
+impl ViewingStats {
+    pub fn visible_to(&self, viewer: &Viewer) -> ViewingStats {
+        ViewingStats {
+            titles: self.titles.iter().filter(|title| viewer.can_see(&title.path)).cloned().collect(),
+            ..self.clone()
+        }
+    }
+}
+
+pub async fn aggregate_periodically(state: Arc<State>) {
+    loop {
+        let aggregated = {
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || aggregate(&state).map_err(|err| err.to_string())).await
+        };
+
+        match aggregated {
+            Ok(Ok(stats)) => *state.viewing_stats.lock().unwrap() = Some(stats),
+            Ok(Err(err)) => warn!("Couldn't aggregate the viewing statistics: {}", err),
+            Err(err) => warn!("Couldn't aggregate the viewing statistics: {}", err),
+        }
+
+        tokio::time::sleep(AGGREGATION_INTERVAL).await;
+    }
+}
+
+fn aggregate(state: &State) -> Result<ViewingStats, Box<dyn error::Error>> {
+    let items: HashMap<String, Value> = state.store.items()?
+        .into_iter()
+        .filter_map(|item| Some((item.get("id")?.as_str()?.to_string(), item)))
+        .collect();
+    let text = |item: &Value, key| item.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+
+    let mut profiles = BTreeMap::new();
+    let mut titles = HashMap::<String, (u64, HashSet<String>)>::new();
+    for (profile, viewing) in state.user_state.viewing()? {
+        let (mut total, mut genres, mut hours) = (0, HashMap::<String, u64>::new(), BTreeMap::<u64, u64>::new());
+        for (id, watched) in &viewing {
+            let watched_for: u64 = watched.values().sum();
+            total += watched_for;
+            for (hour, milliseconds) in watched {
+                *hours.entry(hour / SECONDS_IN_HOUR % HOURS_IN_DAY).or_default() += milliseconds;
+            }
+
+            if let Some(item) = items.get(id) {
+                let item_genres = item.get("genres").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str);
+                for genre in item_genres {
+                    *genres.entry(genre.to_string()).or_default() += watched_for;
+                }
+
+                let title = titles.entry(id.clone()).or_default();
+                title.0 += watched_for;
+                title.1.insert(profile.clone());
+            }
+        }
+
+        let mut genres: Vec<_> = genres.into_iter().collect();
+        genres.sort_by_key(|(genre, milliseconds)| (Reverse(*milliseconds), genre.clone()));
+        let mut hours: Vec<_> = hours.into_iter().collect();
+        hours.sort_by_key(|(hour, milliseconds)| (Reverse(*milliseconds), *hour));
+
+        profiles.insert(profile, ProfileStats {
+            hours_watched: hours_of(total),
+            titles_watched: viewing.len(),
+            genres: genres.into_iter().map(|(genre, milliseconds)| GenreStats { genre, hours_watched: hours_of(milliseconds) }).collect(),
+            busiest_hours: hours.into_iter().map(|(hour, milliseconds)| HourStats { hour, hours_watched: hours_of(milliseconds) }).collect(),
+        });
+    }
+
+    let mut titles: Vec<_> = titles
+        .into_iter()
+        .map(|(id, (milliseconds, viewers))| {
+            let item = &items[&id];
+            let (title, path) = (text(item, "title"), text(item, "path"));
+            (milliseconds, TitleStats { id, title, path, hours_watched: hours_of(milliseconds), viewers: viewers.len() })
+        })
+        .collect();
+    titles.sort_by_key(|(milliseconds, title)| (Reverse(*milliseconds), title.id.clone()));
+
+    Ok(ViewingStats { updated: state::now(), profiles, titles: titles.into_iter().map(|(_, title)| title).collect() })
+}
+
+fn hours_of(milliseconds: u64) -> f64 {
+    milliseconds as f64 / MILLISECONDS_IN_HOUR
+}
\ No newline at end of file
@@ -0,0 +1,274 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+
+use crate::manifest;
+use crate::scanner::{extract_served_files, universal_path, CatalogueItem};
+
+const EMPTY_MANIFEST: &str = "[]";
+
+pub trait CatalogueStore: Send + Sync {
+    fn has_catalogue(&self) -> Result<bool, Box<dyn error::Error>>;
+    fn manifest(&self) -> Result<String, Box<dyn error::Error>>;
+    fn item(&self, id: &str) -> Result<Option<Value>, Box<dyn error::Error>>;
+    fn items(&self) -> Result<Vec<Value>, Box<dyn error::Error>>;
+    fn served_files(&self) -> Result<HashMap<String, PathBuf>, Box<dyn error::Error>>;
+    fn update(&self, catalogue: &[CatalogueItem], at: u64) -> Result<(), Box<dyn error::Error>>;
+    fn missing_items(&self) -> Result<Vec<Value>, Box<dyn error::Error>>;
+    fn purge_missing(&self, missing_before: u64) -> Result<Vec<String>, Box<dyn error::Error>>;
+}
+
+struct Snapshot {
+    manifest: String,
+    items: HashMap<String, Value>,
+    files: HashMap<String, PathBuf>,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    snapshot: RwLock<Option<Snapshot>>,
+    missing: RwLock<HashMap<String, (Value, u64)>>,
+}
+
+impl CatalogueStore for MemoryStore {
+    fn has_catalogue(&self) -> Result<bool, Box<dyn error::Error>> {
+        Ok(self.snapshot.read().unwrap().is_some())
+    }
+
+    fn manifest(&self) -> Result<String, Box<dyn error::Error>> {
+        Ok(self.snapshot.read().unwrap().as_ref().map_or(EMPTY_MANIFEST, |snapshot| &snapshot.manifest).to_string())
+    }
+
+    fn item(&self, id: &str) -> Result<Option<Value>, Box<dyn error::Error>> {
+        let item = self.snapshot.read().unwrap().as_ref().and_then(|snapshot| snapshot.items.get(id).cloned());
+        Ok(item.or_else(|| self.missing.read().unwrap().get(id).map(|(item, since)| mark_missing(item.clone(), *since))))
+    }
+
+    fn items(&self) -> Result<Vec<Value>, Box<dyn error::Error>> {
+        Ok(self.snapshot.read().unwrap().as_ref().map_or_else(Vec::new, |snapshot| snapshot.items.values().cloned().collect()))
+    }
+
+    fn served_files(&self) -> Result<HashMap<String, PathBuf>, Box<dyn error::Error>> {
+        Ok(self.snapshot.read().unwrap().as_ref().map(|snapshot| snapshot.files.clone()).unwrap_or_default())
+    }
+
+    fn update(&self, catalogue: &[CatalogueItem], at: u64) -> Result<(), Box<dyn error::Error>> {
+        let snapshot = Snapshot {
+            manifest: manifest::to_json(catalogue)?,
+            items: items_by_id(catalogue)?,
+            files: served_files_by_key(catalogue),
+        };
+
+        let mut stored = self.snapshot.write().unwrap();
+        let mut missing = self.missing.write().unwrap();
+        missing.retain(|id, _| !snapshot.items.contains_key(id));
+        if let Some(ref stored) = *stored {
+            for (id, item) in stored.items.iter().filter(|(id, _)| !snapshot.items.contains_key(*id)) {
+                missing.entry(id.clone()).or_insert_with(|| (item.clone(), at));
+            }
+        }
+
+        *stored = Some(snapshot);
+        Ok(())
+    }
+
+    fn missing_items(&self) -> Result<Vec<Value>, Box<dyn error::Error>> {
+        Ok(self.missing.read().unwrap().values().map(|(item, since)| mark_missing(item.clone(), *since)).collect())
+    }
+
+    fn purge_missing(&self, missing_before: u64) -> Result<Vec<String>, Box<dyn error::Error>> {
+        let mut missing = self.missing.write().unwrap();
+        let purged: Vec<_> = missing.iter().filter(|(_, (_, since))| *since < missing_before).map(|(id, _)| id.clone()).collect();
+        for id in &purged {
+            missing.remove(id);
+        }
+        Ok(purged)
+    }
+}
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<SqliteStore, Box<dyn error::Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS catalogue (id INTEGER PRIMARY KEY CHECK (id = 0), manifest TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS items (id TEXT PRIMARY KEY, item TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS served_files (relative_path TEXT PRIMARY KEY, path TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS missing_items (id TEXT PRIMARY KEY, item TEXT NOT NULL, since INTEGER NOT NULL);"
+        )?;
+
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+}
+
+impl CatalogueStore for SqliteStore {
+    fn has_catalogue(&self) -> Result<bool, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row("SELECT COUNT(*) FROM catalogue", [], |row| row.get::<_, i64>(0))? > 0)
+    }
+
+    fn manifest(&self) -> Result<String, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let manifest = connection.query_row("SELECT manifest FROM catalogue WHERE id = 0", [], |row| row.get(0)).optional()?;
+        Ok(manifest.unwrap_or_else(|| EMPTY_MANIFEST.to_string()))
+    }
+
+    fn item(&self, id: &str) -> Result<Option<Value>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let item = connection.query_row("SELECT item FROM items WHERE id = ?1", [id], |row| row.get::<_, String>(0)).optional()?;
+        if let Some(item) = item {
+            return Ok(Some(serde_json::from_str(&item)?));
+        }
+
+        let missing = connection
+            .query_row("SELECT item, since FROM missing_items WHERE id = ?1", [id], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+            .optional()?;
+        match missing {
+            Some((item, since)) => Ok(Some(mark_missing(serde_json::from_str(&item)?, since))),
+            None => Ok(None),
+        }
+    }
+
+    fn items(&self) -> Result<Vec<Value>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let items = connection
+            .prepare("SELECT item FROM items")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items.iter().map(|item| serde_json::from_str(item)).collect::<Result<_, _>>()?)
+    }
+
+    fn served_files(&self) -> Result<HashMap<String, PathBuf>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let files = connection
+            .prepare("SELECT relative_path, path FROM served_files")?
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, PathBuf::from(row.get::<_, String>(1)?))))?
+            .collect::<Result<_, _>>()?;
+        Ok(files)
+    }
+
+    fn update(&self, catalogue: &[CatalogueItem], at: u64) -> Result<(), Box<dyn error::Error>> {
+        let manifest = manifest::to_json(catalogue)?;
+        let items: HashMap<_, _> = items_by_id(catalogue)?
+            .into_iter()
+            .map(|(id, item)| (id, item.to_string()))
+            .collect();
+        let files = served_files_by_key(catalogue)
+            .into_iter()
+            .map(|(relative_path, path)| (relative_path, path.to_string_lossy().into_owned()))
+            .collect();
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute("INSERT OR REPLACE INTO catalogue (id, manifest) VALUES (0, ?1)", [&manifest])?;
+        track_missing(&transaction, &items, at)?;
+        sync_table(&transaction, "items", "id", "item", &items)?;
+        sync_table(&transaction, "served_files", "relative_path", "path", &files)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn missing_items(&self) -> Result<Vec<Value>, Box<dyn error::Error>> {
+        let connection = self.connection.lock().unwrap();
+        let rows = connection
+            .prepare("SELECT item, since FROM missing_items ORDER BY since")?
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u64)>, _>>()?;
+
+        let mut missing = Vec::with_capacity(rows.len());
+        for (item, since) in rows {
+            missing.push(mark_missing(serde_json::from_str(&item)?, since));
+        }
+        Ok(missing)
+    }
+
+    fn purge_missing(&self, missing_before: u64) -> Result<Vec<String>, Box<dyn error::Error>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let purged = transaction
+            .prepare("SELECT id FROM missing_items WHERE since < ?1")?
+            .query_map([missing_before], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        transaction.execute("DELETE FROM missing_items WHERE since < ?1", [missing_before])?;
+        transaction.commit()?;
+        Ok(purged)
+    }
+}
+
+fn track_missing(transaction: &Transaction, items: &HashMap<String, String>, at: u64) -> Result<(), rusqlite::Error> {
+    let missing: HashSet<String> = transaction
+        .prepare("SELECT id FROM missing_items")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for id in missing.iter().filter(|id| items.contains_key(*id)) {
+        transaction.execute("DELETE FROM missing_items WHERE id = ?1", [id])?;
+    }
+
+    let stored: Vec<(String, String)> = transaction
+        .prepare("SELECT id, item FROM items")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, item) in stored.iter().filter(|(id, _)| !items.contains_key(id)) {
+        transaction.execute("INSERT OR IGNORE INTO missing_items (id, item, since) VALUES (?1, ?2, ?3)", params![id, item, at])?;
+    }
+    Ok(())
+}
+
+fn sync_table(transaction: &Transaction, table: &str, key: &str, value: &str, rows: &HashMap<String, String>) -> Result<(), rusqlite::Error> {
+    let stored: HashMap<String, String> = transaction
+        .prepare(&format!("SELECT {}, {} FROM {}", key, value, table))?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let delete = format!("DELETE FROM {} WHERE {} = ?1", table, key);
+    for stored_key in stored.keys().filter(|stored_key| !rows.contains_key(*stored_key)) {
+        transaction.execute(&delete, [stored_key])?;
+    }
+
+    let upsert = format!("INSERT OR REPLACE INTO {} ({}, {}) VALUES (?1, ?2)", table, key, value);
+    for (row_key, row_value) in rows {
+        if stored.get(row_key) != Some(row_value) {
+            transaction.execute(&upsert, params![row_key, row_value])?;
+        }
+    }
+    Ok(())
+}
+
+fn mark_missing(mut item: Value, since: u64) -> Value {
+    if let Some(fields) = item.as_object_mut() {
+        fields.insert("missing-since".to_string(), since.into());
+    }
+    item
+}
+
+fn items_by_id(catalogue: &[CatalogueItem]) -> Result<HashMap<String, Value>, serde_json::Error> {
+    let mut items = HashMap::new();
+    for item in catalogue {
+        match item {
+            CatalogueItem::Video { id, .. } => {
+                items.insert(id.clone(), serde_json::to_value(item)?);
+            }
+            CatalogueItem::Directory { items: children, .. }
+            | CatalogueItem::Series { items: children, .. }
+            | CatalogueItem::Season { items: children, .. } => items.extend(items_by_id(children)?),
+        }
+    }
+    Ok(items)
+}
+
+pub fn served_files_by_key(catalogue: &[CatalogueItem]) -> HashMap<String, PathBuf> {
+    extract_served_files(catalogue)
+        .into_iter()
+        .filter_map(|file| universal_path(&file.relative_path).map(|key| (key, file.path())))
+        .collect()
+}
\ No newline at end of file
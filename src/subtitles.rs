@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+pub const EXTENSION_VTT: &str = "vtt";
+pub const EXTENSION_SRT: &str = "srt";
+
+const VTT_HEADER: &str = "WEBVTT\n";
+const CUE_TIMING_SEPARATOR: &str = "-->";
+
+pub fn is_subtitles(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == EXTENSION_VTT || extension == EXTENSION_SRT)
+}
+
+pub fn is_srt(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == EXTENSION_SRT)
+}
+
+pub fn decode(subtitles: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(subtitles) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => Cow::Owned(subtitles.iter().map(|&byte| byte as char).collect()),
+    }
+}
+
+pub fn srt_to_vtt(srt: &str) -> String {
+    let srt = srt.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+
+    let mut vtt = String::from(VTT_HEADER);
+    for cue in srt.split("\n\n").map(str::trim).filter(|cue| !cue.is_empty()) {
+        let mut lines = cue.lines().skip_while(|line| !line.contains(CUE_TIMING_SEPARATOR));
+        let (start, end) = match lines.next().and_then(|timing| timing.split_once(CUE_TIMING_SEPARATOR)) {
+            Some(timing) => timing,
+            None => continue,
+        };
+
+        vtt.push_str(&format!("\n{} {} {}\n", timestamp(start), CUE_TIMING_SEPARATOR, timestamp(end)));
+        for line in lines.filter(|line| !line.contains(CUE_TIMING_SEPARATOR)) {
+            vtt.push_str(line);
+            vtt.push('\n');
+        }
+    }
+    vtt
+}
+
+fn timestamp(srt: &str) -> String {
+    srt.split_whitespace().next().unwrap_or_default().replace(',', ".")
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+use std::{io, net::TcpListener, time::Duration};
+
+use listenfd::ListenFd;
+use sd_notify::NotifyState;
+use tracing::{info, warn};
+
+pub fn take_listeners() -> Result<Vec<TcpListener>, io::Error> {
+    let mut listen_fd = ListenFd::from_env();
+    let listeners = (0..listen_fd.len())
+        .filter_map(|index| listen_fd.take_tcp_listener(index).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !listeners.is_empty() {
+        info!("Using {} socket(s) passed by systemd", listeners.len());
+    }
+    Ok(listeners)
+}
+
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Couldn't notify systemd about readiness: {}", err);
+    }
+
+    let mut watchdog_usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut watchdog_usec) {
+        let period = Duration::from_micros(watchdog_usec / 2);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                    warn!("Couldn't ping the systemd watchdog: {}", err);
+                }
+            }
+        });
+    }
+}
+
+pub fn notify_stopping() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Stopping]) {
+        warn!("Couldn't notify systemd about stopping: {}", err);
+    }
+}
\ No newline at end of file
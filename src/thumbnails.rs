@@ -0,0 +1,97 @@
+use std::{
+    io,
+    iter,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tokio::{fs, process::Command};
+
+use crate::scanner::item_id;
+
+pub const DEFAULT_WIDTH: u32 = 480;
+pub const MAX_WIDTH: u32 = 1920;
+
+const POSTER_NAME: &str = "poster";
+const SCRUB_PREFIX: &str = "scrub-";
+const EXTENSION: &str = ".jpg";
+const SCRUB_COUNT: u32 = 4;
+const POSTER_FRACTION: f64 = 0.1;
+
+pub struct Thumbnails {
+    ffmpeg: PathBuf,
+    cache: PathBuf,
+    next_temp: AtomicU64,
+}
+
+impl Thumbnails {
+    pub fn new(ffmpeg: &Path, cache: PathBuf) -> Thumbnails {
+        Thumbnails { ffmpeg: ffmpeg.to_path_buf(), cache, next_temp: AtomicU64::new(0) }
+    }
+
+    pub fn names() -> Vec<String> {
+        iter::once(format!("{}{}", POSTER_NAME, EXTENSION))
+            .chain((0..SCRUB_COUNT).map(|index| format!("{}{}{}", SCRUB_PREFIX, index, EXTENSION)))
+            .collect()
+    }
+
+    pub async fn thumbnail(&self, key: &str, video: &Path, duration: Duration, name: &str, width: u32) -> Result<Option<Vec<u8>>, io::Error> {
+        let (stem, position) = match position(name, duration) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let path = self.cache.join(item_id(Path::new(key))).join(format!("{}-{}{}", stem, width, EXTENSION));
+        if is_fresh(&path, video).await {
+            return fs::read(&path).await.map(Some);
+        }
+        fs::create_dir_all(path.parent().unwrap()).await?;
+
+        let temp_path = path.with_extension(format!("{}.tmp", self.next_temp.fetch_add(1, Ordering::Relaxed)));
+        let status = Command::new(&self.ffmpeg)
+            .args(["-v", "error", "-ss", &format!("{:.3}", position.as_secs_f64()), "-i"])
+            .arg(video)
+            .args(["-frames:v", "1", "-vf", &format!("scale='min({},iw)':-2", width), "-c:v", "mjpeg", "-f", "image2", "-y"])
+            .arg(&temp_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await?;
+        if !status.success() {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(io::Error::other(format!("ffmpeg failed with {}", status)));
+        }
+
+        fs::rename(&temp_path, &path).await?;
+        fs::read(&path).await.map(Some)
+    }
+}
+
+fn position(name: &str, duration: Duration) -> Option<(String, Duration)> {
+    let stem = name.strip_suffix(EXTENSION)?;
+    if stem == POSTER_NAME {
+        return Some((stem.to_string(), duration.mul_f64(POSTER_FRACTION)));
+    }
+
+    let index: u32 = stem.strip_prefix(SCRUB_PREFIX)?.parse().ok()?;
+    if index >= SCRUB_COUNT {
+        return None;
+    }
+    Some((stem.to_string(), duration.mul_f64(f64::from(index + 1) / f64::from(SCRUB_COUNT + 1))))
+}
+
+async fn is_fresh(path: &Path, video: &Path) -> bool {
+    let modified = |path: &Path| {
+        let path = path.to_path_buf();
+        async move { fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok() }
+    };
+    match (modified(path).await, modified(video).await) {
+        (Some(cached), Some(video)) => cached >= video,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+use std::{
+    error,
+    fs::{self, File},
+    future::Future,
+    io::{BufRead, BufReader, Cursor},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use hyper::{
+    http::{uri::Authority, HeaderValue},
+    server::conn::Http,
+    service::service_fn,
+    Body,
+    Request,
+    Response,
+    StatusCode,
+};
+use rustls_pemfile::Item;
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig},
+    TlsAcceptor,
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::config;
+use crate::secrets;
+
+use crate::file_stream::StreamBudget;
+use crate::server::{self, State};
+
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_HTTPS_PORT: u16 = 443;
+const SELF_SIGNED_DIR_NAME: &str = "tls";
+const SELF_SIGNED_CERT_NAME: &str = "self-signed.pem";
+const SELF_SIGNED_KEY_NAME: &str = "self-signed.key";
+const SELF_SIGNED_NAMES: [&str; 2] = ["localhost", "movie-nexus"];
+
+#[derive(Clone, Copy)]
+pub struct Secure;
+
+pub fn acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor, Box<dyn error::Error>> {
+    let certs = certificates(cert)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca in certificates(client_ca)? {
+                roots.add(&ca).map_err(|err| format!("Invalid client CA certificate in {}: {}", client_ca.display(), err))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, private_key(key)?)?;
+    config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub fn self_signed() -> Result<(PathBuf, PathBuf), Box<dyn error::Error>> {
+    let dir = config::default_dir().ok_or("There's no config folder to keep the self-signed certificate in")?.join(SELF_SIGNED_DIR_NAME);
+    let (cert_path, key_path) = (dir.join(SELF_SIGNED_CERT_NAME), dir.join(SELF_SIGNED_KEY_NAME));
+
+    if !cert_path.is_file() || !key_path.is_file() {
+        let cert = rcgen::generate_simple_self_signed(SELF_SIGNED_NAMES.iter().map(|name| name.to_string()).collect::<Vec<_>>())?;
+        fs::create_dir_all(&dir)?;
+        write_private(&key_path, &cert.serialize_private_key_pem())?;
+        fs::write(&cert_path, cert.serialize_pem()?)?;
+        info!("Generated a self-signed certificate in {}", cert_path.display());
+    }
+
+    let certs = rustls_pemfile::certs(&mut open(&cert_path, "certificate")?)?;
+    if let Some(cert) = certs.first() {
+        let fingerprint: Vec<_> = Sha256::digest(cert).iter().map(|byte| format!("{:02X}", byte)).collect();
+        info!("Serving HTTPS with a self-signed certificate, SHA-256 fingerprint {}", fingerprint.join(":"));
+    }
+    Ok((cert_path, key_path))
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> Result<(), std::io::Error> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> Result<(), std::io::Error> {
+    fs::write(path, contents)
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, Box<dyn error::Error>> {
+    let certs: Vec<_> = rustls_pemfile::certs(&mut open(path, "certificate")?)?.into_iter().map(Certificate).collect();
+    if certs.is_empty() {
+        return Err(format!("There are no certificates in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, Box<dyn error::Error>> {
+    let mut reader: Box<dyn BufRead> = match secrets::path_reference(path) {
+        Some(name) => Box::new(Cursor::new(secrets::get(name)?.into_bytes())),
+        None => Box::new(open(path, "key")?),
+    };
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(Item::RSAKey(key)) | Some(Item::PKCS8Key(key)) | Some(Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(format!("There's no private key in {}", path.display()).into()),
+        }
+    }
+}
+
+fn open(path: &Path, what: &str) -> Result<BufReader<File>, String> {
+    File::open(path).map(BufReader::new).map_err(|err| format!("Can't read the TLS {} {}: {}", what, path.display(), err))
+}
+
+pub async fn serve(listener: TcpListener, acceptor: TlsAcceptor, state: Arc<State>, shutdown: impl Future<Output=()> + Clone + Send + 'static) {
+    loop {
+        let (tcp, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("Couldn't accept an HTTPS connection: {}", err);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            },
+            _ = shutdown.clone() => return,
+        };
+
+        let (acceptor, state, shutdown) = (acceptor.clone(), state.clone(), shutdown.clone());
+        tokio::spawn(async move {
+            let tls = match acceptor.accept(tcp).await {
+                Ok(tls) => tls,
+                Err(err) => return debug!("TLS handshake failed: {}", err),
+            };
+
+            let budget = StreamBudget::default();
+            let connection = Http::new().serve_connection(tls, service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(budget.clone());
+                request.extensions_mut().insert(remote);
+                request.extensions_mut().insert(Secure);
+                server::handle(state.clone(), request)
+            }));
+            tokio::pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(err) = result {
+                debug!("HTTPS connection failed: {}", err);
+            }
+        });
+    }
+}
+
+pub fn redirect(request: &Request<Body>, tls_port: u16) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let host = request.headers()
+        .get("Host")
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| Authority::from_str(host).ok());
+    let host = match host {
+        Some(host) => host,
+        None => {
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            *response.body_mut() = Body::from("HTTPS is required");
+            return response;
+        }
+    };
+
+    let port = if tls_port == DEFAULT_HTTPS_PORT { String::new() } else { format!(":{}", tls_port) };
+    let path = request.uri().path_and_query().map_or("/", |path| path.as_str());
+    match HeaderValue::from_str(&format!("https://{}{}{}", host.host(), port, path)) {
+        Ok(location) => {
+            *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+            response.headers_mut().insert("Location", location);
+        }
+        Err(_) => *response.status_mut() = StatusCode::BAD_REQUEST,
+    }
+    response
+}
\ No newline at end of file
@@ -0,0 +1,33 @@
+use std::{collections::HashMap, error};
+
+use crate::config::UserProfile;
+use crate::state::{Session, StateStore};
+
+pub fn list(user_state: &dyn StateStore, profiles: &HashMap<String, UserProfile>) -> Result<Vec<Session>, Box<dyn error::Error>> {
+    let mut tokens = Vec::new();
+    for profile in profile_names(profiles) {
+        tokens.extend(user_state.sessions(profile)?);
+    }
+    tokens.sort_by_key(|token| token.created);
+    Ok(tokens)
+}
+
+pub fn create(user_state: &dyn StateStore, profiles: &HashMap<String, UserProfile>, profile: &str, name: &str) -> Result<Session, Box<dyn error::Error>> {
+    if !profile.is_empty() && !profiles.contains_key(profile) {
+        return Err(format!("There's no profile named {}", profile).into());
+    }
+    user_state.create_session(profile, name)
+}
+
+pub fn revoke(user_state: &dyn StateStore, profiles: &HashMap<String, UserProfile>, id: &str) -> Result<bool, Box<dyn error::Error>> {
+    for profile in profile_names(profiles) {
+        if user_state.delete_session(profile, id)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn profile_names(profiles: &HashMap<String, UserProfile>) -> impl Iterator<Item=&str> {
+    Some("").into_iter().chain(profiles.keys().map(String::as_str))
+}
\ No newline at end of file
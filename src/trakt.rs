@@ -0,0 +1,271 @@
+use std::{
+    error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::{body, client::HttpConnector, header, Body, Client, Request, Response, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::api;
+use crate::config::TraktSettings;
+use crate::server::State;
+use crate::state::{self, Progress, TraktTokens};
+use crate::viewer::Viewer;
+
+const API_URL: &str = "https://api.trakt.tv";
+const API_VERSION: &str = "2";
+const REDIRECT_URI: &str = "urn:ietf:wg:oauth:2.0:oob";
+const USER_AGENT: &str = concat!("movie-nexus/", env!("CARGO_PKG_VERSION"));
+const REFRESH_MARGIN: u64 = 24 * 60 * 60;
+
+type Error = Box<dyn error::Error + Send + Sync>;
+
+#[derive(Clone, Copy)]
+pub enum Action {
+    Start,
+    Stop,
+}
+
+impl Action {
+    fn path(self) -> &'static str {
+        match self {
+            Action::Start => "/scrobble/start",
+            Action::Stop => "/scrobble/stop",
+        }
+    }
+}
+
+struct Scrobble {
+    profile: String,
+    action: Action,
+    media: Value,
+    progress: f64,
+}
+
+#[derive(Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_url: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_in: u64,
+    created_at: u64,
+}
+
+impl From<TokenResponse> for TraktTokens {
+    fn from(response: TokenResponse) -> TraktTokens {
+        TraktTokens {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            expires: response.created_at + response.expires_in,
+        }
+    }
+}
+
+pub struct Trakt {
+    client_id: String,
+    client_secret: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+    scrobbles: UnboundedSender<Scrobble>,
+    pending: Mutex<Option<UnboundedReceiver<Scrobble>>>,
+}
+
+impl Trakt {
+    pub fn new(settings: &TraktSettings) -> Trakt {
+        let (scrobbles, pending) = mpsc::unbounded_channel();
+        Trakt {
+            client_id: settings.client_id.clone(),
+            client_secret: settings.client_secret.clone(),
+            client: Client::builder().build(HttpsConnector::new()),
+            scrobbles,
+            pending: Mutex::new(Some(pending)),
+        }
+    }
+
+    pub fn scrobble(&self, profile: &str, action: Action, item: &Value, progress: &Progress) {
+        let percent = if progress.duration > 0 { progress.position as f64 * 100.0 / progress.duration as f64 } else { 0.0 };
+        let scrobble = Scrobble { profile: profile.to_string(), action, media: media(item), progress: percent.clamp(0.0, 100.0) };
+        let _ = self.scrobbles.send(scrobble);
+    }
+
+    async fn send(&self, state: &State, scrobble: &Scrobble) -> Result<(), Error> {
+        let tokens = match state.user_state.trakt_tokens(&scrobble.profile).map_err(|err| err.to_string())? {
+            Some(tokens) if tokens.expires <= state::now() + REFRESH_MARGIN => self.refresh(state, &scrobble.profile, &tokens).await?,
+            Some(tokens) => tokens,
+            None => return Ok(()),
+        };
+
+        let mut body = scrobble.media.clone();
+        body["progress"] = json!(scrobble.progress);
+        match self.post(scrobble.action.path(), Some(&tokens.access_token), &body).await?.0 {
+            StatusCode::OK | StatusCode::CREATED | StatusCode::CONFLICT => Ok(()),
+            StatusCode::NOT_FOUND => Err("Trakt couldn't identify the video".into()),
+            status => Err(format!("Trakt responded with {}", status).into()),
+        }
+    }
+
+    async fn refresh(&self, state: &State, profile: &str, tokens: &TraktTokens) -> Result<TraktTokens, Error> {
+        let body = json!({
+            "refresh_token": tokens.refresh_token,
+            "client_id": self.client_id,
+            "client_secret": self.client_secret,
+            "redirect_uri": REDIRECT_URI,
+            "grant_type": "refresh_token",
+        });
+        let (status, bytes) = self.post("/oauth/token", None, &body).await?;
+        if status != StatusCode::OK {
+            return Err(format!("Trakt refused to refresh the token with {}", status).into());
+        }
+
+        let tokens = TraktTokens::from(serde_json::from_slice::<TokenResponse>(&bytes)?);
+        state.user_state.set_trakt_tokens(profile, Some(&tokens)).map_err(|err| err.to_string())?;
+        Ok(tokens)
+    }
+
+    async fn device_code(&self) -> Result<DeviceCode, Error> {
+        let (status, bytes) = self.post("/oauth/device/code", None, &json!({ "client_id": self.client_id })).await?;
+        if status != StatusCode::OK {
+            return Err(format!("Trakt responded with {}", status).into());
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn post(&self, path: &str, access_token: Option<&str>, body: &Value) -> Result<(StatusCode, body::Bytes), Error> {
+        let mut request = Request::post(format!("{}{}", API_URL, path))
+            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("trakt-api-version", API_VERSION)
+            .header("trakt-api-key", &self.client_id);
+        if let Some(access_token) = access_token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", access_token));
+        }
+
+        let response = self.client.request(request.body(Body::from(body.to_string()))?).await?;
+        let status = response.status();
+        Ok((status, body::to_bytes(response.into_body()).await?))
+    }
+}
+
+pub fn crossed_threshold(previous: Option<&Progress>, current: &Progress) -> Option<Action> {
+    let was_finished = previous.is_some_and(state::is_nearly_finished);
+    let was_started = previous.is_some_and(|previous| previous.position > 0) && !was_finished;
+    if state::is_nearly_finished(current) {
+        if was_finished { None } else { Some(Action::Stop) }
+    } else if current.position > 0 && !was_started {
+        Some(Action::Start)
+    } else {
+        None
+    }
+}
+
+pub async fn deliver(state: Arc<State>) {
+    let trakt = match state.trakt {
+        Some(ref trakt) => trakt,
+        None => return,
+    };
+    let pending = trakt.pending.lock().unwrap().take();
+    let mut scrobbles = match pending {
+        Some(scrobbles) => scrobbles,
+        None => return,
+    };
+
+    while let Some(scrobble) = scrobbles.recv().await {
+        if let Err(err) = trakt.send(&state, &scrobble).await {
+            warn!("Couldn't scrobble to Trakt for \"{}\": {}", scrobble.profile, err);
+        }
+    }
+}
+
+pub fn serve_status(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    match state.user_state.trakt_tokens(&viewer.profile) {
+        Ok(tokens) => api::write_json(&json!({ "connected": tokens.is_some() }), response),
+        Err(err) => api::internal_error("Couldn't read the Trakt tokens", err, response),
+    }
+}
+
+pub async fn connect(state: Arc<State>, viewer: &Viewer, response: &mut Response<Body>) {
+    let trakt = match state.trakt {
+        Some(ref trakt) => trakt,
+        None => return *response.status_mut() = StatusCode::NOT_FOUND,
+    };
+
+    let code = match trakt.device_code().await {
+        Ok(code) => code,
+        Err(err) => {
+            warn!("Couldn't start the Trakt login: {}", err);
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            return;
+        }
+    };
+
+    api::write_json(&json!({
+        "user-code": code.user_code,
+        "verification-url": code.verification_url,
+        "expires-in": code.expires_in,
+    }), response);
+    tokio::spawn(poll_login(state.clone(), viewer.profile.clone(), code));
+}
+
+pub fn disconnect(state: &State, viewer: &Viewer, response: &mut Response<Body>) {
+    match state.user_state.set_trakt_tokens(&viewer.profile, None) {
+        Ok(()) => *response.status_mut() = StatusCode::NO_CONTENT,
+        Err(err) => api::internal_error("Couldn't forget the Trakt tokens", err, response),
+    }
+}
+
+async fn poll_login(state: Arc<State>, profile: String, code: DeviceCode) {
+    let trakt = match state.trakt {
+        Some(ref trakt) => trakt,
+        None => return,
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(code.expires_in);
+    let mut interval = Duration::from_secs(code.interval.max(1));
+    while Instant::now() < deadline {
+        tokio::time::sleep(interval).await;
+
+        let body = json!({ "code": code.device_code, "client_id": trakt.client_id, "client_secret": trakt.client_secret });
+        match trakt.post("/oauth/device/token", None, &body).await {
+            Ok((StatusCode::OK, bytes)) => {
+                let stored = serde_json::from_slice::<TokenResponse>(&bytes)
+                    .map_err(|err| err.to_string())
+                    .and_then(|tokens| state.user_state.set_trakt_tokens(&profile, Some(&tokens.into())).map_err(|err| err.to_string()));
+                match stored {
+                    Ok(()) => info!("Connected the profile \"{}\" to Trakt", profile),
+                    Err(err) => warn!("Couldn't store the Trakt tokens of \"{}\": {}", profile, err),
+                }
+                return;
+            }
+            Ok((StatusCode::BAD_REQUEST, _)) => {}
+            Ok((StatusCode::TOO_MANY_REQUESTS, _)) => interval += Duration::from_secs(1),
+            Ok((status, _)) => {
+                info!("The Trakt login of \"{}\" ended with {}", profile, status);
+                return;
+            }
+            Err(err) => warn!("Couldn't poll the Trakt login of \"{}\": {}", profile, err),
+        }
+    }
+    info!("The Trakt login of \"{}\" expired", profile);
+}
+
+fn media(item: &Value) -> Value {
+    match item.get("episode") {
+        Some(episode) => json!({
+            "show": { "title": episode["show"] },
+            "episode": { "season": episode["season"], "number": episode["episode"] },
+        }),
+        None => json!({ "movie": { "title": item["title"] } }),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+use tokio::{
+    io::AsyncReadExt,
+    process::{Child, ChildStdout, Command},
+};
+use tracing::info;
+
+use crate::config::TranscodeProfile;
+use crate::hls;
+use crate::server::State;
+use crate::state;
+
+const READ_CHUNK_LEN: usize = 64 * 1024;
+const IDLE_TIMEOUT_SECONDS: u64 = 60;
+const REAP_INTERVAL: Duration = Duration::from_secs(15);
+
+pub enum TranscodeError {
+    NoProfile,
+    UnknownProfile(String),
+    Busy,
+    Spawn(io::Error),
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::NoProfile => write!(f, "A transcoding profile has to be picked"),
+            TranscodeError::UnknownProfile(profile) => write!(f, "There's no transcoding profile {}", profile),
+            TranscodeError::Busy => write!(f, "Too many videos are being transcoded"),
+            TranscodeError::Spawn(err) => write!(f, "Couldn't start ffmpeg: {}", err),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TranscodeSession {
+    pub session: String,
+    pub path: String,
+    pub profile: String,
+    pub started: u64,
+    pub last_active: u64,
+}
+
+struct Session {
+    owner: String,
+    info: TranscodeSession,
+    generation: u64,
+    last_active: Arc<AtomicU64>,
+    child: Arc<Mutex<Child>>,
+}
+
+type Sessions = Arc<Mutex<HashMap<(String, String), Session>>>;
+
+pub struct Transcodes {
+    ffmpeg: PathBuf,
+    profiles: HashMap<String, TranscodeProfile>,
+    max: usize,
+    sessions: Sessions,
+    segments: Arc<AtomicUsize>,
+    generations: AtomicU64,
+}
+
+impl Transcodes {
+    pub fn new(ffmpeg: &Path, profiles: HashMap<String, TranscodeProfile>, max: usize) -> Transcodes {
+        Transcodes {
+            ffmpeg: ffmpeg.to_path_buf(),
+            profiles,
+            max,
+            sessions: Sessions::default(),
+            segments: Arc::new(AtomicUsize::new(0)),
+            generations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn profiles(&self) -> &HashMap<String, TranscodeProfile> {
+        &self.profiles
+    }
+
+    pub fn sessions(&self, owner: &str) -> Vec<TranscodeSession> {
+        self.sessions.lock().unwrap().values()
+            .filter(|session| session.owner == owner)
+            .map(|session| TranscodeSession {
+                last_active: session.last_active.load(Ordering::Relaxed),
+                session: session.info.session.clone(),
+                path: session.info.path.clone(),
+                profile: session.info.profile.clone(),
+                started: session.info.started,
+            })
+            .collect()
+    }
+
+    pub fn stop(&self, owner: &str, session: &str) -> bool {
+        let stopped = self.sessions.lock().unwrap().remove(&(owner.to_string(), session.to_string()));
+        match stopped {
+            Some(stopped) => {
+                kill(&stopped);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn start(
+        &self,
+        owner: &str,
+        session: &str,
+        key: &str,
+        video: &Path,
+        profile: Option<&str>,
+        start: f64,
+    ) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, TranscodeError> {
+        let (profile_name, profile) = match profile {
+            Some(name) => self.profiles.get_key_value(name).ok_or_else(|| TranscodeError::UnknownProfile(name.to_string()))?,
+            None if self.profiles.len() == 1 => self.profiles.iter().next().unwrap(),
+            None => return Err(TranscodeError::NoProfile),
+        };
+
+        let id = (owner.to_string(), session.to_string());
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(replaced) = sessions.remove(&id) {
+            kill(&replaced);
+        }
+        if sessions.len() + self.segments.load(Ordering::Relaxed) >= self.max {
+            return Err(TranscodeError::Busy);
+        }
+
+        let mut child = self.command(video, profile, start).spawn().map_err(TranscodeError::Spawn)?;
+        let stdout = child.stdout.take().unwrap();
+        let now = state::now();
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let last_active = Arc::new(AtomicU64::new(now));
+        let child = Arc::new(Mutex::new(child));
+        sessions.insert(id.clone(), Session {
+            owner: owner.to_string(),
+            info: TranscodeSession { session: session.to_string(), path: key.to_string(), profile: profile_name.clone(), started: now, last_active: now },
+            generation,
+            last_active: last_active.clone(),
+            child: child.clone(),
+        });
+        info!("Transcoding {} with the {} profile", key, profile_name);
+
+        let output = Output { stdout, _child: child, last_active, _guard: Guard { sessions: self.sessions.clone(), id, generation } };
+        Ok(stream::unfold(Some(output), |output| async move {
+            let mut output = output?;
+            let mut chunk = BytesMut::with_capacity(READ_CHUNK_LEN);
+            match output.stdout.read_buf(&mut chunk).await {
+                Ok(0) => None,
+                Ok(_) => {
+                    output.last_active.store(state::now(), Ordering::Relaxed);
+                    Some((Ok(chunk.freeze()), Some(output)))
+                }
+                Err(err) => Some((Err(err), None)),
+            }
+        }).boxed())
+    }
+
+    pub fn segment(&self, video: &Path, duration: Duration, index: u64) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, TranscodeError> {
+        let guard = {
+            let sessions = self.sessions.lock().unwrap();
+            if sessions.len() + self.segments.load(Ordering::Relaxed) >= self.max {
+                return Err(TranscodeError::Busy);
+            }
+            self.segments.fetch_add(1, Ordering::Relaxed);
+            SegmentGuard { segments: self.segments.clone() }
+        };
+
+        let segment = hls::segment(&self.ffmpeg, video, duration, index).map_err(TranscodeError::Spawn)?;
+        Ok(segment.map(move |chunk| {
+            let _running = &guard;
+            chunk
+        }).boxed())
+    }
+
+    pub fn reap(&self) {
+        let idle_since = state::now().saturating_sub(IDLE_TIMEOUT_SECONDS);
+        self.sessions.lock().unwrap().retain(|_, session| {
+            let idle = session.last_active.load(Ordering::Relaxed) < idle_since;
+            if idle {
+                info!("Stopping the abandoned transcode of {}", session.info.path);
+                kill(session);
+            }
+            !idle
+        });
+    }
+
+    fn command(&self, video: &Path, profile: &TranscodeProfile, start: f64) -> Command {
+        let mut command = Command::new(&self.ffmpeg);
+        command
+            .args(["-v", "error", "-ss", &format!("{:.3}", start), "-i"])
+            .arg(video)
+            .args(["-map", "0:v:0", "-map", "0:a:0?", "-vf", &format!("scale=-2:min({}\\,ih)", profile.height)])
+            .args(["-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p"])
+            .args(["-b:v", &format!("{}k", profile.video_kbps), "-maxrate", &format!("{}k", profile.video_kbps)])
+            .args(["-bufsize", &format!("{}k", profile.video_kbps * 2)])
+            .args(["-c:a", "aac", "-ac", "2", "-b:a", &format!("{}k", profile.audio_kbps)])
+            .args(["-f", "mp4", "-movflags", "frag_keyframe+empty_moov+default_base_moof", "pipe:1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        command
+    }
+}
+
+struct Output {
+    stdout: ChildStdout,
+    _child: Arc<Mutex<Child>>,
+    last_active: Arc<AtomicU64>,
+    _guard: Guard,
+}
+
+struct Guard {
+    sessions: Sessions,
+    id: (String, String),
+    generation: u64,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(&self.id).is_some_and(|session| session.generation == self.generation) {
+            sessions.remove(&self.id);
+        }
+    }
+}
+
+struct SegmentGuard {
+    segments: Arc<AtomicUsize>,
+}
+
+impl Drop for SegmentGuard {
+    fn drop(&mut self) {
+        self.segments.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn kill(session: &Session) {
+    let _ = session.child.lock().unwrap().start_kill();
+}
+
+pub async fn reap_abandoned(state: Arc<State>) {
+    loop {
+        tokio::time::sleep(REAP_INTERVAL).await;
+        if let Some(ref transcodes) = state.transcodes {
+            transcodes.reap();
+        }
+    }
+}
\ No newline at end of file
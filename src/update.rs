@@ -0,0 +1,174 @@
+use std::{
+    env::{self, consts},
+    error,
+    fs,
+    io,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use hyper::{body, header, Body, Client, Request, StatusCode, Uri};
+use hyper_tls::HttpsConnector;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::server::State;
+use crate::signing::{decode_hex, encode_hex};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/DrMetallius/movie-nexus/releases/latest";
+const USER_AGENT: &str = concat!("movie-nexus/", env!("CARGO_PKG_VERSION"));
+const MAX_REDIRECTS: usize = 5;
+const CHECKSUM_SUFFIX: &str = ".sha256";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+type Error = Box<dyn error::Error + Send + Sync>;
+
+#[derive(Deserialize)]
+pub struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    pub fn is_newer(&self) -> bool {
+        is_newer(self.version(), CURRENT_VERSION)
+    }
+}
+
+pub async fn latest_release() -> Result<Release, Error> {
+    let bytes = fetch(LATEST_RELEASE_URL, "application/vnd.github+json").await?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+pub async fn install(release: &Release) -> Result<(), Error> {
+    let asset_name = asset_name();
+    let find_asset = |name: &str| release.assets.iter()
+        .find(|asset| asset.name == name)
+        .ok_or_else(|| format!("Release {} has no file named {}", release.version(), name));
+    let asset = find_asset(&asset_name)?;
+    let checksum_asset = find_asset(&format!("{}{}", asset_name, CHECKSUM_SUFFIX))?;
+
+    let checksum = fetch(&checksum_asset.browser_download_url, "text/plain").await?;
+    let checksum = String::from_utf8_lossy(&checksum).split_whitespace().next().and_then(decode_hex)
+        .ok_or_else(|| format!("{} isn't a SHA-256 checksum", checksum_asset.name))?;
+    let bytes = fetch(&asset.browser_download_url, "application/octet-stream").await?;
+    let digest = Sha256::digest(&bytes);
+    if digest.as_slice() != checksum.as_slice() {
+        return Err(format!("The downloaded {} has the SHA-256 checksum {} instead of the published {}", asset_name, encode_hex(&digest), encode_hex(&checksum)).into());
+    }
+
+    replace_executable(&env::current_exe()?, &bytes)?;
+    Ok(())
+}
+
+pub async fn watch_releases(state: Arc<State>) {
+    loop {
+        match latest_release().await {
+            Ok(release) if release.is_newer() => {
+                info!("Version {} is available, run the update command to install it", release.version());
+                *state.available_update.lock().unwrap() = Some(release.version().to_string());
+            }
+            Ok(_) => (),
+            Err(err) => debug!("Couldn't check for updates: {}", err),
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+pub async fn fetch(url: &str, accept: &'static str) -> Result<body::Bytes, Error> {
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+
+    let mut url = url.to_string();
+    for _ in 0..MAX_REDIRECTS {
+        let request = Request::get(&url)
+            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())?;
+        let response = client.request(request).await?;
+
+        if response.status().is_redirection() {
+            url = response.headers()
+                .get(header::LOCATION)
+                .ok_or("Redirect without a location")?
+                .to_str()?
+                .to_string();
+            if url.parse::<Uri>()?.scheme_str() != Some("https") {
+                return Err(format!("Refusing to follow the redirect to {}", url).into());
+            }
+            continue;
+        }
+        if response.status() != StatusCode::OK {
+            return Err(format!("{} responded with {}", url, response.status()).into());
+        }
+
+        return Ok(body::to_bytes(response.into_body()).await?);
+    }
+
+    Err(format!("Too many redirects fetching {}", url).into())
+}
+
+fn asset_name() -> String {
+    format!("movie-nexus-{}-{}{}", consts::OS, consts::ARCH, consts::EXE_SUFFIX)
+}
+
+fn replace_executable(executable: &Path, bytes: &[u8]) -> Result<(), io::Error> {
+    let new_executable = executable.with_extension("new");
+    fs::write(&new_executable, bytes)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new_executable, fs::Permissions::from_mode(0o755))?;
+    }
+
+    #[cfg(windows)]
+    let old_executable = executable.with_extension("old");
+    #[cfg(windows)]
+    {
+        if old_executable.exists() {
+            fs::remove_file(&old_executable)?;
+        }
+        fs::rename(executable, &old_executable)?;
+    }
+
+    let replaced = fs::rename(&new_executable, executable);
+    #[cfg(windows)]
+    if replaced.is_err() {
+        let _ = fs::rename(&old_executable, executable);
+    }
+    replaced
+}
+
+fn is_newer(version: &str, current: &str) -> bool {
+    let (mut version, mut current) = (parse_version(version), parse_version(current));
+    let len = version.len().max(current.len());
+    version.resize(len, 0);
+    current.resize(len, 0);
+    version > current
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
\ No newline at end of file
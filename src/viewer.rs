@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use hyper::HeaderMap;
+use tracing::warn;
+
+use crate::auth;
+use crate::config::UserProfile;
+use crate::jellyfin;
+use crate::state::{self, StateStore};
+
+const HEADER_PROFILE: &str = "X-Profile";
+const HEADER_PROFILE_TOKEN: &str = "X-Profile-Token";
+const HEADER_SESSION: &str = "X-Session";
+pub const HEADER_ADMIN_TOKEN: &str = "X-Admin-Token";
+const HEADER_AUTHORIZATION: &str = "Authorization";
+const BEARER_SCHEME: &str = "Bearer ";
+const QUERY_TOKEN: &str = "token=";
+
+pub const ALLOWED_HEADERS: &str = "Authorization, Content-Type, Range, X-Profile, X-Profile-Token, X-Session, X-Admin-Token, X-CSRF-Token, X-Emby-Authorization, X-Emby-Token, Depth";
+
+#[derive(Clone)]
+pub struct Restriction {
+    pub folder: String,
+    pub role: String,
+}
+
+#[derive(Default)]
+pub struct Viewer {
+    pub profile: String,
+    hidden: Vec<String>,
+    roles: Vec<String>,
+}
+
+impl Viewer {
+    pub fn resolve(
+        profiles: &HashMap<String, UserProfile>,
+        restrictions: &[Restriction],
+        user_state: &dyn StateStore,
+        headers: &HeaderMap,
+        query: Option<&str>,
+    ) -> Result<Viewer, String> {
+        Ok(Viewer::identify(profiles, user_state, headers, query)?.restricted_by(restrictions))
+    }
+
+    pub fn named(profiles: &HashMap<String, UserProfile>, restrictions: &[Restriction], name: &str) -> Result<Viewer, String> {
+        Ok(Viewer::unauthenticated(profiles, name)?.restricted_by(restrictions))
+    }
+
+    pub fn for_account(profiles: &HashMap<String, UserProfile>, restrictions: &[Restriction], name: &str) -> Result<Viewer, String> {
+        let viewer = match profiles.get(name) {
+            Some(profile) => Viewer::authenticated(name, profile),
+            None if name.is_empty() => Viewer::default(),
+            None => return Err(format!("There's no profile named {}", name)),
+        };
+        Ok(viewer.restricted_by(restrictions))
+    }
+
+    fn identify(profiles: &HashMap<String, UserProfile>, user_state: &dyn StateStore, headers: &HeaderMap, query: Option<&str>) -> Result<Viewer, String> {
+        if let Some(token) = session_token(headers, query)? {
+            let session = user_state.session(token)
+                .map_err(|err| format!("Couldn't look up the session: {}", err))?
+                .ok_or_else(|| "Unknown session".to_string())?;
+
+            let now = state::now();
+            if session.needs_touch(now) {
+                if let Err(err) = user_state.touch_session(token, now) {
+                    warn!("Couldn't update the session of {}: {}", session.device, err);
+                }
+            }
+
+            return match profiles.get(&session.profile) {
+                Some(profile) => Ok(Viewer::authenticated(&session.profile, profile)),
+                None if session.profile.is_empty() => Ok(Viewer::default()),
+                None => Err(format!("There's no profile named {}", session.profile)),
+            };
+        }
+
+        if let Some(token) = header(headers, HEADER_PROFILE_TOKEN)? {
+            return profiles.iter()
+                .find(|(_, profile)| profile.token.as_deref().is_some_and(|expected| auth::tokens_match(token, expected)))
+                .map(|(name, profile)| Viewer::authenticated(name, profile))
+                .ok_or_else(|| "Unknown profile token".to_string());
+        }
+
+        match header(headers, HEADER_PROFILE)? {
+            Some(name) => Viewer::unauthenticated(profiles, name),
+            None => Ok(Viewer::default()),
+        }
+    }
+
+    fn unauthenticated(profiles: &HashMap<String, UserProfile>, name: &str) -> Result<Viewer, String> {
+        match profiles.get(name) {
+            Some(profile) if profile.token.is_none() => Ok(Viewer::new(name, profile)),
+            Some(_) => Err(format!("Profile {} needs a token", name)),
+            None => Err(format!("There's no profile named {}", name)),
+        }
+    }
+
+    fn new(name: &str, profile: &UserProfile) -> Viewer {
+        Viewer {
+            profile: name.to_string(),
+            hidden: profile.hidden.iter().map(|folder| folder.trim_matches('/').to_string()).collect(),
+            roles: Vec::new(),
+        }
+    }
+
+    fn authenticated(name: &str, profile: &UserProfile) -> Viewer {
+        Viewer { roles: profile.roles.clone(), ..Viewer::new(name, profile) }
+    }
+
+    fn restricted_by(mut self, restrictions: &[Restriction]) -> Viewer {
+        let restricted = restrictions.iter()
+            .filter(|restriction| !self.roles.contains(&restriction.role))
+            .map(|restriction| restriction.folder.trim_matches('/').to_string())
+            .collect::<Vec<_>>();
+        self.hidden.extend(restricted);
+        self
+    }
+
+    pub fn sees_everything(&self) -> bool {
+        self.hidden.is_empty()
+    }
+
+    pub fn hidden(&self) -> &[String] {
+        &self.hidden
+    }
+
+    pub fn can_see(&self, path: &str) -> bool {
+        !self.hidden.iter().any(|folder| {
+            path.strip_prefix(folder.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+pub fn has_valid_session(user_state: &dyn StateStore, headers: &HeaderMap, query: Option<&str>) -> bool {
+    match session_token(headers, query) {
+        Ok(Some(token)) => user_state.session(token).is_ok_and(|session| session.is_some()),
+        _ => false,
+    }
+}
+
+pub fn presents_credentials(headers: &HeaderMap, query: Option<&str>) -> bool {
+    headers.contains_key(HEADER_AUTHORIZATION) || !matches!(session_token(headers, query), Ok(None))
+}
+
+fn session_token<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Result<Option<&'a str>, String> {
+    if let Some(token) = header(headers, HEADER_SESSION)? {
+        return Ok(Some(token));
+    }
+    if let Some(token) = header(headers, HEADER_AUTHORIZATION)?.and_then(|value| value.strip_prefix(BEARER_SCHEME)) {
+        return Ok(Some(token.trim()));
+    }
+    if let Some(token) = jellyfin::token(headers, query) {
+        return Ok(Some(token));
+    }
+    Ok(query.into_iter().flat_map(|query| query.split('&')).find_map(|parameter| parameter.strip_prefix(QUERY_TOKEN)))
+}
+
+pub fn is_admin(admin_token: Option<&str>, headers: &HeaderMap) -> bool {
+    match (admin_token, header(headers, HEADER_ADMIN_TOKEN)) {
+        (Some(expected), Ok(Some(token))) => auth::tokens_match(token, expected),
+        _ => false,
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, String> {
+    headers.get(name)
+        .map(|value| value.to_str().map_err(|_| format!("Invalid {} header", name)))
+        .transpose()
+}
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashSet,
+    path::{self, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{info, warn};
+
+use crate::persisted_state::{JOURNAL_EXTENSION, TEMP_EXTENSION};
+use crate::server::{self, State};
+
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+const SIDECAR_SUFFIXES: [&str; 3] = ["-journal", "-wal", "-shm"];
+
+pub fn start(state: Arc<State>, folder: PathBuf, ignored: Vec<PathBuf>) -> Result<(), notify::Error> {
+    let ignored: Vec<PathBuf> = ignored.iter().filter_map(|path| path::absolute(path).ok()).collect();
+    let (sender, changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+            let changed: Vec<PathBuf> = event.paths.into_iter().filter(|path| !is_ignored(path, &ignored)).collect();
+            if !changed.is_empty() {
+                let _ = sender.send(changed);
+            }
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Couldn't watch the library for changes: {}", err),
+    })?;
+
+    watcher.watch(&folder, RecursiveMode::Recursive)?;
+    for mount_folder in state.mount_folders() {
+        if let Err(err) = watcher.watch(&mount_folder, RecursiveMode::Recursive) {
+            warn!("Couldn't watch {} for changes: {}", mount_folder.display(), err);
+        }
+    }
+
+    tokio::spawn(rescan_on_changes(state, folder, watcher, changes));
+    Ok(())
+}
+
+fn is_ignored(path: &Path, ignored: &[PathBuf]) -> bool {
+    let path = match path::absolute(path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    ignored.iter().any(|ignored| {
+        if path == *ignored || path == ignored.with_extension(JOURNAL_EXTENSION) || path == ignored.with_extension(TEMP_EXTENSION) {
+            return true;
+        }
+        let suffix = match (path.parent() == ignored.parent(), path.file_name().and_then(|name| name.to_str()), ignored.file_name().and_then(|name| name.to_str())) {
+            (true, Some(name), Some(ignored)) => name.strip_prefix(ignored),
+            _ => None,
+        };
+        suffix.is_some_and(|suffix| {
+            SIDECAR_SUFFIXES.contains(&suffix) || suffix.strip_prefix('.').is_some_and(|number| number.parse::<u32>().is_ok())
+        })
+    })
+}
+
+async fn rescan_on_changes(state: Arc<State>, folder: PathBuf, _watcher: RecommendedWatcher, mut changes: UnboundedReceiver<Vec<PathBuf>>) {
+    while let Some(paths) = changes.recv().await {
+        let mut changed: HashSet<PathBuf> = paths.into_iter().collect();
+        loop {
+            match tokio::time::timeout(SETTLE_DELAY, changes.recv()).await {
+                Ok(Some(paths)) => changed.extend(paths),
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        info!("{} path(s) in the library changed, rescanning them", changed.len());
+        server::rescan_changes_in_background(&state, &folder, Some(changed.into_iter().collect())).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use futures::future;
+
+use crate::app_state::AppState;
+use crate::media_source::MediaMetadata;
+use crate::request_path;
+use crate::server::State;
+use crate::viewer::Viewer;
+use crate::xml::escape;
+
+pub const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+pub const METHOD_PROPFIND: &str = "PROPFIND";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Depth {
+    Zero,
+    One,
+    Infinity,
+}
+
+impl Depth {
+    pub fn parse(header: Option<&str>) -> Result<Depth, String> {
+        match header.map(str::trim) {
+            Some("0") => Ok(Depth::Zero),
+            Some("1") => Ok(Depth::One),
+            Some(depth) if depth.eq_ignore_ascii_case("infinity") => Ok(Depth::Infinity),
+            None => Ok(Depth::Infinity),
+            Some(depth) => Err(format!("Invalid depth {}", depth)),
+        }
+    }
+}
+
+pub async fn propfind(state: &State, app: &AppState, viewer: &Viewer, prefix: &str, path: &str, depth: Depth) -> Option<String> {
+    let visible: Vec<_> = app.files.iter().filter(|(key, _)| viewer.can_see(key)).collect();
+
+    let mut listing: Vec<(String, Option<&PathBuf>)> = Vec::new();
+    if let Some((_, file)) = visible.iter().find(|(key, _)| key.as_str() == path) {
+        listing.push((path.to_string(), Some(*file)));
+    } else {
+        let dir = if path.is_empty() { String::new() } else { format!("{}/", path) };
+        let mut children = BTreeMap::new();
+        for (key, file) in &visible {
+            if let Some(rest) = key.strip_prefix(dir.as_str()) {
+                match rest.split_once('/') {
+                    Some((name, _)) => children.insert(name, None),
+                    None => children.insert(rest, Some(*file)),
+                };
+            }
+        }
+        if children.is_empty() && !path.is_empty() {
+            return None;
+        }
+
+        listing.push((path.to_string(), None));
+        if depth != Depth::Zero {
+            listing.extend(children.into_iter().map(|(name, file)| (format!("{}{}", dir, name), file)));
+        }
+    }
+
+    let metadata = future::join_all(listing.iter().map(|(key, file)| async move {
+        let (source, source_path) = state.media_source(key, (*file)?);
+        source.metadata(&source_path).await.ok()
+    })).await;
+
+    let mut responses = String::new();
+    for ((key, file), metadata) in listing.iter().zip(metadata) {
+        match file {
+            Some(_) => responses.push_str(&file_response(prefix, key, metadata.as_ref())),
+            None => responses.push_str(&collection_response(prefix, key)),
+        }
+    }
+
+    Some(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n{}</D:multistatus>\n", responses))
+}
+
+pub fn finite_depth_required() -> String {
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n".to_string()
+}
+
+fn collection_response(prefix: &str, path: &str) -> String {
+    let href = if path.is_empty() { format!("{}/", prefix) } else { format!("{}/{}/", prefix, request_path::encode(path)) };
+    let name = path.rsplit('/').next().unwrap_or_default();
+    response(&href, &format!("<D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>", escape(name)))
+}
+
+fn file_response(prefix: &str, key: &str, metadata: Option<&MediaMetadata>) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let mut properties = format!(
+        "<D:displayname>{}</D:displayname><D:resourcetype/><D:getcontenttype>{}</D:getcontenttype>",
+        escape(name),
+        escape(mime_guess::from_path(key).first_or_octet_stream().as_ref()),
+    );
+    if let Some(metadata) = metadata {
+        properties.push_str(&format!("<D:getcontentlength>{}</D:getcontentlength>", metadata.len));
+        if let Some(modified) = metadata.modified {
+            properties.push_str(&format!("<D:getlastmodified>{}</D:getlastmodified>", httpdate::fmt_http_date(modified)));
+        }
+    }
+    response(&format!("{}/{}", prefix, request_path::encode(key)), &properties)
+}
+
+fn response(href: &str, properties: &str) -> String {
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape(href),
+        properties,
+    )
+}
\ No newline at end of file
@@ -0,0 +1,13 @@
+use std::{ffi::OsStr, os::windows::ffi::OsStrExt};
+
+use windows::ErrorCode;
+
+use crate::bindings::windows::win32::debug::GetLastError;
+
+pub fn wide(value: impl AsRef<OsStr>) -> Vec<u16> {
+    value.as_ref().encode_wide().chain(Some(0)).collect()
+}
+
+pub fn last_error() -> windows::Error {
+    ErrorCode(unsafe { GetLastError() }).into()
+}
\ No newline at end of file
@@ -0,0 +1,174 @@
+use serde_json::{Map, Value};
+
+const CATALOGUE_NAMESPACE: &str = "urn:movie-nexus:catalogue:1";
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            character if character.is_control() && !matches!(character, '\t' | '\n' | '\r') => {}
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            name => name.strip_prefix("#x").map(|code| u32::from_str_radix(code, 16))
+                .or_else(|| name.strip_prefix('#').map(|code| code.parse::<u32>()))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        });
+        match (character, entity) {
+            (Some(character), Some((_, end))) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+pub fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", name), format!("</{}>", name));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let tag_end = match after.find('>') {
+            Some(tag_end) => tag_end,
+            None => break,
+        };
+        let attributes = &after[..tag_end];
+        let content = &after[tag_end + 1..];
+        if !(attributes.is_empty() || attributes.starts_with(char::is_whitespace)) || attributes.ends_with('/') {
+            rest = content;
+            continue;
+        }
+        match content.find(&close) {
+            Some(end) => {
+                found.push(&content[..end]);
+                rest = &content[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+pub fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    elements(xml, name).into_iter().next()
+}
+
+pub fn text(content: &str) -> String {
+    let content = content.trim();
+    match content.strip_prefix("<![CDATA[").and_then(|content| content.strip_suffix("]]>")) {
+        Some(data) => data.trim().to_string(),
+        None => unescape(content),
+    }
+}
+
+pub fn catalogue(manifest: &Value) -> String {
+    let mut xml = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<catalogue xmlns=\"{}\">\n", CATALOGUE_NAMESPACE);
+    for item in manifest.as_array().into_iter().flatten() {
+        write_item(&mut xml, item, 1);
+    }
+    xml.push_str("</catalogue>\n");
+    xml
+}
+
+fn write_item(xml: &mut String, item: &Value, depth: usize) {
+    let item = match item.as_object() {
+        Some(item) => item,
+        None => return,
+    };
+    let indent = "  ".repeat(depth);
+
+    let (element, attributes) = match item.get("type").and_then(Value::as_str) {
+        Some("directory") => ("directory", attributes(item, &["title", "restricted"])),
+        Some("series") => ("series", attributes(item, &["title"])),
+        Some("season") => ("season", attributes(item, &["title", "season"])),
+        Some("playlist") => ("playlist", attributes(item, &["id", "title"])),
+        Some("file") => return write_file(xml, item, &indent),
+        _ => return,
+    };
+    xml.push_str(&format!("{}<{}{}>\n", indent, element, attributes));
+    for child in item.get("contents").and_then(Value::as_array).into_iter().flatten() {
+        write_item(xml, child, depth + 1);
+    }
+    xml.push_str(&format!("{}</{}>\n", indent, element));
+}
+
+fn write_file(xml: &mut String, file: &Map<String, Value>, indent: &str) {
+    let file_attributes = attributes(file, &[
+        "id", "path", "url", "duration", "width", "height", "video-codec", "watched", "favorite", "resume-position", "rating", "average-rating", "rating-count",
+    ]);
+    xml.push_str(&format!("{}<file{}>\n", indent, file_attributes));
+
+    for name in ["title", "subtitle"] {
+        if let Some(text) = file.get(name).and_then(Value::as_str) {
+            xml.push_str(&format!("{}  <{}>{}</{}>\n", indent, name, escape(text), name));
+        }
+    }
+
+    let urls = file.get("text-track-urls").and_then(Value::as_object);
+    for (language, path) in file.get("text-tracks").and_then(Value::as_object).into_iter().flatten() {
+        let mut track = Map::new();
+        track.insert("language".to_string(), Value::String(language.clone()));
+        track.insert("path".to_string(), path.clone());
+        if let Some(url) = urls.and_then(|urls| urls.get(language)) {
+            track.insert("url".to_string(), url.clone());
+        }
+        xml.push_str(&format!("{}  <text-track{}/>\n", indent, attributes(&track, &["language", "path", "url"])));
+    }
+    for thumbnail in file.get("thumbnails").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+        xml.push_str(&format!("{}  <thumbnail path=\"{}\"/>\n", indent, escape(thumbnail)));
+    }
+    for thumbnail in file.get("generated-thumbnails").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+        xml.push_str(&format!("{}  <generated-thumbnail path=\"{}\"/>\n", indent, escape(thumbnail)));
+    }
+    for genre in file.get("genres").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+        xml.push_str(&format!("{}  <genre>{}</genre>\n", indent, escape(genre)));
+    }
+    if let Some(episode) = file.get("episode").and_then(Value::as_object) {
+        xml.push_str(&format!("{}  <episode{}/>\n", indent, attributes(episode, &["show", "season", "episode"])));
+    }
+
+    xml.push_str(&format!("{}</file>\n", indent));
+}
+
+fn attributes(item: &Map<String, Value>, names: &[&str]) -> String {
+    let mut attributes = String::new();
+    for name in names {
+        let value = match item.get(*name) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+            _ => continue,
+        };
+        attributes.push_str(&format!(" {}=\"{}\"", name, escape(&value)));
+    }
+    attributes
+}
\ No newline at end of file